@@ -0,0 +1,171 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::error::CliWalletError;
+use std::path::PathBuf;
+use tari_comms::connection::NetAddress;
+use tari_core::types::PublicKey;
+use tari_utilities::hex::Hex;
+
+/// The help text listing the commands of the wallet
+pub const HELP: &str = "Commands:
+  create                                    Create a new wallet and show its mnemonic
+  restore <words>...                        Restore a wallet from its mnemonic
+  import <file>                             Restore a wallet from a backup file encrypted with the passphrase
+  identity                                  Show the public key and node id to receive transactions on
+  send <public key> <amount> [fee per gram] Send an amount, in uT, to the wallet with the public key
+  transactions                              List the transactions of the wallet
+  utxos                                     List the outputs owned by the wallet
+  balance                                   Show the balance of the wallet
+  export <file>                             Write a backup of the wallet, encrypted with the passphrase, to a file
+  help                                      Show this help
+  exit                                      Leave the wallet";
+
+/// A command that can be entered interactively, or read from a script
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Create,
+    Restore(Vec<String>),
+    Import(PathBuf),
+    Identity,
+    Send {
+        public_key: String,
+        amount: u64,
+        fee_per_gram: Option<u64>,
+    },
+    Transactions,
+    Utxos,
+    Balance,
+    Export(PathBuf),
+    Help,
+    Exit,
+}
+
+impl Command {
+    /// Parse a command from a line of input. Returns `None` for empty lines and comments starting with `#`.
+    pub fn parse(line: &str) -> Result<Option<Command>, CliWalletError> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let command = match (name, args.as_slice()) {
+            ("create", []) => Command::Create,
+            ("restore", words) if !words.is_empty() => Command::Restore(words.iter().map(|w| w.to_string()).collect()),
+            ("import", [file]) => Command::Import(PathBuf::from(file)),
+            ("identity", []) => Command::Identity,
+            ("send", [public_key, amount]) => Command::Send {
+                public_key: public_key.to_string(),
+                amount: parse_number(amount, "amount")?,
+                fee_per_gram: None,
+            },
+            ("send", [public_key, amount, fee_per_gram]) => Command::Send {
+                public_key: public_key.to_string(),
+                amount: parse_number(amount, "amount")?,
+                fee_per_gram: Some(parse_number(fee_per_gram, "fee per gram")?),
+            },
+            ("transactions", []) => Command::Transactions,
+            ("utxos", []) => Command::Utxos,
+            ("balance", []) => Command::Balance,
+            ("export", [file]) => Command::Export(PathBuf::from(file)),
+            ("help", []) => Command::Help,
+            ("exit", []) | ("quit", []) => Command::Exit,
+            _ => {
+                return Err(CliWalletError::InvalidCommand(format!(
+                    "Unknown command or wrong arguments: {}",
+                    line
+                )))
+            },
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Parse a peer given as `<public key>@<net address>`, where the public key is in hex
+pub fn parse_peer(peer: &str) -> Result<(PublicKey, NetAddress), CliWalletError> {
+    let mut parts = peer.splitn(2, '@');
+    match (parts.next(), parts.next()) {
+        (Some(public_key), Some(net_address)) => {
+            Ok((PublicKey::from_hex(public_key)?, net_address.parse::<NetAddress>()?))
+        },
+        _ => Err(CliWalletError::InvalidCommand(format!(
+            "Invalid peer, expected <public key>@<net address>: {}",
+            peer
+        ))),
+    }
+}
+
+fn parse_number(value: &str, name: &str) -> Result<u64, CliWalletError> {
+    value
+        .parse::<u64>()
+        .map_err(|_| CliWalletError::InvalidCommand(format!("Invalid {}: {}", name, value)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tari_crypto::keys::PublicKey as PK;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::parse("  balance ").unwrap(), Some(Command::Balance));
+        assert_eq!(Command::parse("").unwrap(), None);
+        assert_eq!(Command::parse("# a comment").unwrap(), None);
+        assert_eq!(
+            Command::parse("restore abandon ability").unwrap(),
+            Some(Command::Restore(vec!["abandon".to_string(), "ability".to_string()]))
+        );
+        assert_eq!(
+            Command::parse("send abcd 1000").unwrap(),
+            Some(Command::Send {
+                public_key: "abcd".to_string(),
+                amount: 1000,
+                fee_per_gram: None
+            })
+        );
+        assert_eq!(
+            Command::parse("send abcd 1000 20").unwrap(),
+            Some(Command::Send {
+                public_key: "abcd".to_string(),
+                amount: 1000,
+                fee_per_gram: Some(20)
+            })
+        );
+        assert!(Command::parse("send abcd lots").is_err());
+        assert!(Command::parse("restore").is_err());
+        assert!(Command::parse("balance now").is_err());
+        assert!(Command::parse("mine").is_err());
+    }
+
+    #[test]
+    fn parse_peers() {
+        let (_, public_key) = PublicKey::random_keypair(&mut rand::OsRng::new().unwrap());
+        let (parsed_public_key, net_address) = parse_peer(&format!("{}@127.0.0.1:18189", public_key.to_hex())).unwrap();
+        assert_eq!(parsed_public_key, public_key);
+        assert_eq!(net_address, "127.0.0.1:18189".parse::<NetAddress>().unwrap());
+        assert!(parse_peer(&public_key.to_hex()).is_err());
+        assert!(parse_peer(&format!("{}@nowhere", public_key.to_hex())).is_err());
+        assert!(parse_peer("zz@127.0.0.1:18189").is_err());
+    }
+}
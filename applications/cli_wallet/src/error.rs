@@ -0,0 +1,82 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use derive_error::Error;
+use keymanager::{file_backup::FileError, keymanager::KeyManagerError, mnemonic::MnemonicError};
+use std::io;
+use tari_comms::{
+    connection::{ConnectionError, NetAddressError},
+    connection_manager::connection_manager::ConnectionManagerError,
+    peer_manager::{manager::PeerManagerError, node_id::NodeIdError},
+};
+use tari_storage::keyvalue_store::DatastoreError;
+use tari_utilities::{byte_array::ByteArrayError, hex::HexError};
+use wallet::{
+    transaction_manager::TransactionManagerError,
+    transaction_service::TransactionServiceError,
+    wallet_db::WalletDbError,
+};
+
+#[derive(Debug, Error)]
+pub enum CliWalletError {
+    // The wallet directory could not be created
+    IoError(io::Error),
+    // The wallet keys or a backup could not be saved or loaded, e.g. because the passphrase is wrong
+    FileError(FileError),
+    // The wallet keys could not be restored from the mnemonic
+    KeyManagerError(KeyManagerError),
+    // The mnemonic of the master key could not be generated
+    MnemonicError(MnemonicError),
+    // A key could not be derived or parsed
+    ByteArrayError(ByteArrayError),
+    // A public key could not be parsed from hex
+    HexError(HexError),
+    // The wallet database could not be opened
+    DatastoreError(DatastoreError),
+    // The wallet database could not be read or written
+    WalletDbError(WalletDbError),
+    // The transactions of the wallet could not be loaded
+    TransactionManagerError(TransactionManagerError),
+    // A transaction could not be sent
+    TransactionServiceError(TransactionServiceError),
+    // A node id could not be derived from a public key
+    NodeIdError(NodeIdError),
+    // The inbound message service or the outbound message pool could not be started
+    ConnectionError(ConnectionError),
+    // The wallet could not listen for peers on its listen address
+    ConnectionManagerError(ConnectionManagerError),
+    // The peer database of the wallet could not be created or updated
+    PeerManagerError(PeerManagerError),
+    // A net address could not be parsed or added to a peer
+    NetAddressError(NetAddressError),
+    // The operating system random number generator could not be created
+    RandomError(rand::Error),
+    // The command could not be parsed
+    #[error(msg_embedded, no_from, non_std)]
+    InvalidCommand(String),
+    // A wallet already exists in the wallet directory
+    WalletExists,
+    // There is no wallet in the wallet directory, create or restore one first
+    WalletNotFound,
+    // The wallet is being used by another thread, which panicked
+    LockError,
+}
@@ -0,0 +1,160 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use clap::{App, Arg};
+use cli_wallet::{commands::parse_peer, CliWalletError, Command, Session, WalletConfig};
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    process,
+};
+use tari_comms::connection::NetAddress;
+
+/// The directory the wallet is stored in if none is given
+const DEFAULT_WALLET_DIR: &str = "tari_wallet";
+/// The environment variable the passphrase can be given in, instead of on the command line
+const PASSPHRASE_ENV: &str = "TARI_WALLET_PASSPHRASE";
+
+fn main() {
+    let matches = App::new("Tari wallet")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("A command-line Tari wallet")
+        .arg(
+            Arg::with_name("wallet-dir")
+                .long("wallet-dir")
+                .short("d")
+                .takes_value(true)
+                .default_value(DEFAULT_WALLET_DIR)
+                .help("The directory the wallet is stored in"),
+        )
+        .arg(
+            Arg::with_name("passphrase")
+                .long("passphrase")
+                .short("p")
+                .takes_value(true)
+                .env(PASSPHRASE_ENV)
+                .help("The passphrase the wallet keys and backups are encrypted with. Asked for if not given"),
+        )
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .short("l")
+                .takes_value(true)
+                .help("The net address to listen for peers on, e.g. 127.0.0.1:18189. Needed to receive transactions"),
+        )
+        .arg(
+            Arg::with_name("peer")
+                .long("peer")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("A peer to send transactions to, as <public key>@<net address>. Can be given more than once"),
+        )
+        .arg(
+            Arg::with_name("script")
+                .long("script")
+                .short("s")
+                .takes_value(true)
+                .conflicts_with("command")
+                .help("Run the commands in this file, one per line, instead of reading them from the terminal"),
+        )
+        .arg(
+            Arg::with_name("command")
+                .long("command")
+                .short("c")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Run this command instead of reading commands from the terminal. Can be given more than once"),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("Wallet failed: {:?}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &clap::ArgMatches) -> Result<(), CliWalletError> {
+    let passphrase = match matches.value_of("passphrase") {
+        Some(passphrase) => passphrase.to_string(),
+        None => read_passphrase()?,
+    };
+    let mut config = WalletConfig::new(matches.value_of("wallet-dir").unwrap(), &passphrase);
+    if let Some(listen_address) = matches.value_of("listen") {
+        config = config.with_listen_address(listen_address.parse::<NetAddress>()?);
+    }
+    for peer in matches.values_of("peer").into_iter().flatten() {
+        let (public_key, net_address) = parse_peer(peer)?;
+        config = config.with_peer(public_key, net_address);
+    }
+    let mut session = Session::new(config)?;
+    if let Some(script) = matches.value_of("script") {
+        let script = fs::read_to_string(script)?;
+        return run_script(&mut session, script.lines());
+    }
+    if let Some(commands) = matches.values_of("command") {
+        return run_script(&mut session, commands);
+    }
+
+    println!("{}", cli_wallet::commands::HELP);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+        // Mistakes are reported and the user can simply try again
+        match Command::parse(&line) {
+            Ok(Some(Command::Exit)) => return Ok(()),
+            Ok(Some(command)) => match session.execute(&command) {
+                Ok(output) => println!("{}", output),
+                Err(e) => println!("Error: {:?}", e),
+            },
+            Ok(None) => {},
+            Err(e) => println!("Error: {:?}", e),
+        }
+    }
+}
+
+fn read_passphrase() -> Result<String, CliWalletError> {
+    print!("Passphrase: ");
+    io::stdout().flush()?;
+    let mut passphrase = String::new();
+    io::stdin().read_line(&mut passphrase)?;
+    Ok(passphrase.trim_end_matches(|c| c == '\r' || c == '\n').to_string())
+}
+
+/// Run commands non-interactively, stopping at the first command that fails
+fn run_script<'a, I: Iterator<Item = &'a str>>(session: &mut Session, lines: I) -> Result<(), CliWalletError> {
+    for line in lines {
+        match Command::parse(line)? {
+            Some(Command::Exit) => return Ok(()),
+            Some(command) => println!("{}", session.execute(&command)?),
+            None => {},
+        }
+    }
+    Ok(())
+}
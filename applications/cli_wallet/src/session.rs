@@ -0,0 +1,82 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{
+    commands::{Command, HELP},
+    error::CliWalletError,
+    wallet::{CliWallet, WalletConfig},
+};
+
+/// A sequence of commands entered by the user or read from a script. The session holds the wallet in the configured
+/// directory once it has been created, restored, imported or opened.
+pub struct Session {
+    config: WalletConfig,
+    wallet: Option<CliWallet>,
+}
+
+impl Session {
+    /// Start a session, opening the wallet in the wallet directory if there is one
+    pub fn new(config: WalletConfig) -> Result<Session, CliWalletError> {
+        let wallet = if CliWallet::exists(&config.wallet_dir) {
+            Some(CliWallet::open(config.clone())?)
+        } else {
+            None
+        };
+        Ok(Session { config, wallet })
+    }
+
+    /// The wallet of the session, if one has been created or opened
+    pub fn wallet(&self) -> Option<&CliWallet> {
+        self.wallet.as_ref()
+    }
+
+    /// Execute a command
+    /// # Returns
+    /// The text to show the user
+    pub fn execute(&mut self, command: &Command) -> Result<String, CliWalletError> {
+        match (command, &self.wallet) {
+            (Command::Help, _) => Ok(HELP.to_string()),
+            (Command::Exit, _) => Ok(String::new()),
+            (Command::Create, None) => {
+                let (wallet, mnemonic) = CliWallet::create(self.config.clone())?;
+                self.wallet = Some(wallet);
+                Ok(format!(
+                    "Created a new wallet. Write down its mnemonic to restore it:\n{}",
+                    mnemonic.join(" ")
+                ))
+            },
+            (Command::Restore(mnemonic), None) => {
+                self.wallet = Some(CliWallet::restore(self.config.clone(), mnemonic)?);
+                Ok("Restored the wallet from its mnemonic".to_string())
+            },
+            (Command::Import(file), None) => {
+                self.wallet = Some(CliWallet::import(self.config.clone(), file)?);
+                Ok(format!("Imported the wallet from {}", file.display()))
+            },
+            (Command::Create, Some(_)) | (Command::Restore(_), Some(_)) | (Command::Import(_), Some(_)) => {
+                Err(CliWalletError::WalletExists)
+            },
+            (_, None) => Err(CliWalletError::WalletNotFound),
+            (command, Some(wallet)) => wallet.execute(command),
+        }
+    }
+}
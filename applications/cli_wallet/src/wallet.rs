@@ -0,0 +1,427 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{commands::Command, error::CliWalletError};
+use digest::Digest;
+use keymanager::{
+    file_backup::FileBackup,
+    keymanager::{KeyBranch, KeyManager, KeyPath},
+    mnemonic::{self, Mnemonic, MnemonicLanguage},
+};
+use rand::OsRng;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tari_comms::{
+    connection::{net_address::net_addresses::NetAddresses, Context, InprocAddress, NetAddress},
+    connection_manager::connection_manager::ConnectionManager,
+    inbound_message_service::inbound_message_service::InboundMessageService,
+    outbound_message_service::{
+        outbound_message_pool::OutboundMessagePool,
+        outbound_message_service::OutboundMessageService,
+    },
+    peer_manager::{
+        manager::PeerManager,
+        node_id::NodeId,
+        node_identity::NodeIdentity,
+        peer::{Peer, PeerFlags},
+    },
+};
+use tari_core::{
+    transaction::UnblindedOutput,
+    types::{CommitmentFactory, HashDigest, PublicKey, SecretKey},
+};
+use tari_crypto::{
+    commitment::{HomomorphicCommitment, HomomorphicCommitmentFactory},
+    keys::PublicKey as PK,
+};
+use tari_storage::{
+    hashmap_store::HashmapStore,
+    lmdb::{LMDBBuilder, LMDBStore},
+};
+use tari_utilities::{
+    hex::{to_hex, Hex},
+    ByteArray,
+};
+use wallet::{
+    output_manager::OutputStatus,
+    transaction_manager::TransactionManager,
+    transaction_service::{construct_transaction_message_dispatcher, TransactionService},
+    wallet_db::{TransactionQuery, WalletDatabase},
+};
+
+/// The file the wallet keys are stored in, encrypted with the wallet passphrase, inside the wallet directory
+const KEYS_FILE: &str = "wallet_keys.backup";
+/// The directory of the wallet database, inside the wallet directory
+const DATABASE_DIR: &str = "db";
+/// The maximum size of the wallet database, in MB
+const DATABASE_MAPSIZE: usize = 64;
+
+/// The location of a wallet, the passphrase its keys and backups are encrypted with, the comms addresses it sends
+/// and receives messages on, and the peers it knows the net addresses of
+#[derive(Clone)]
+pub struct WalletConfig {
+    pub wallet_dir: PathBuf,
+    pub passphrase: String,
+    pub context: Context,
+    /// The address the inbound message service receives messages for the wallet on
+    pub inbound_address: InprocAddress,
+    /// The address of the outbound message pool the wallet writes messages to
+    pub outbound_address: InprocAddress,
+    /// The net address the wallet listens for peers on. Without one the wallet can send, but not receive, messages.
+    pub listen_address: Option<NetAddress>,
+    /// The public keys and net addresses of peers that are added to the peer database when the wallet starts
+    pub peers: Vec<(PublicKey, NetAddress)>,
+}
+
+impl WalletConfig {
+    /// A configuration for the wallet in `wallet_dir` with a new comms context and random comms addresses
+    pub fn new<P: AsRef<Path>>(wallet_dir: P, passphrase: &str) -> WalletConfig {
+        WalletConfig {
+            wallet_dir: wallet_dir.as_ref().to_path_buf(),
+            passphrase: passphrase.to_string(),
+            context: Context::new(),
+            inbound_address: InprocAddress::random(),
+            outbound_address: InprocAddress::random(),
+            listen_address: None,
+            peers: Vec::new(),
+        }
+    }
+
+    /// Listen for peers on the net address
+    pub fn with_listen_address(mut self, listen_address: NetAddress) -> WalletConfig {
+        self.listen_address = Some(listen_address);
+        self
+    }
+
+    /// Add the peer with the public key, which is reachable on the net address, when the wallet starts
+    pub fn with_peer(mut self, public_key: PublicKey, net_address: NetAddress) -> WalletConfig {
+        self.peers.push((public_key, net_address));
+        self
+    }
+}
+
+/// Everything needed to restore a wallet: its keys and the outputs it owns
+#[derive(Serialize, Deserialize)]
+pub struct WalletBackup {
+    pub key_manager: KeyManager<SecretKey, HashDigest>,
+    pub outputs: Vec<UnblindedOutput>,
+}
+
+/// A wallet stored in a directory, built on a `KeyManager`, a `TransactionManager` with an LMDB wallet database, and
+/// a `TransactionService` that sends and receives transactions over comms.
+pub struct CliWallet {
+    passphrase: String,
+    node_identity: Arc<NodeIdentity<PublicKey, SecretKey>>,
+    peer_manager: Arc<PeerManager<PublicKey, HashmapStore>>,
+    transaction_service: Arc<Mutex<TransactionService<LMDBStore>>>,
+}
+
+impl CliWallet {
+    /// Determine if there is a wallet in the directory
+    pub fn exists<P: AsRef<Path>>(wallet_dir: P) -> bool {
+        wallet_dir.as_ref().join(KEYS_FILE).exists()
+    }
+
+    /// Create a new wallet with a random master key
+    /// # Returns
+    /// The wallet and the mnemonic of its master key, from which it can be restored
+    pub fn create(config: WalletConfig) -> Result<(CliWallet, Vec<String>), CliWalletError> {
+        let key_manager = KeyManager::new(&mut OsRng::new()?);
+        let mnemonic = key_manager.master_key.to_mnemonic(&MnemonicLanguage::English)?;
+        let wallet = CliWallet::init(config, key_manager, Vec::new())?;
+        Ok((wallet, mnemonic))
+    }
+
+    /// Restore a wallet from the mnemonic of its master key
+    pub fn restore(config: WalletConfig, mnemonic_seq: &Vec<String>) -> Result<CliWallet, CliWalletError> {
+        let unknown_words = mnemonic::find_unknown_words(mnemonic_seq, &MnemonicLanguage::English);
+        if !unknown_words.is_empty() {
+            let corrections = unknown_words
+                .iter()
+                .map(|(position, suggestions)| {
+                    format!(
+                        "word {} \"{}\" is not a mnemonic word, did you mean: {}",
+                        position + 1,
+                        mnemonic_seq[*position],
+                        suggestions.join(", ")
+                    )
+                })
+                .collect::<Vec<_>>();
+            return Err(CliWalletError::InvalidCommand(corrections.join("\n")));
+        }
+        let key_manager = KeyManager::from_mnemonic(mnemonic_seq, String::new(), 0)?;
+        CliWallet::init(config, key_manager, Vec::new())
+    }
+
+    /// Restore a wallet from a backup file written by `export` with the same passphrase
+    pub fn import<P: AsRef<Path>>(config: WalletConfig, backup_file: P) -> Result<CliWallet, CliWalletError> {
+        let backup = WalletBackup::from_file(&path_string(backup_file.as_ref()), &config.passphrase)?;
+        CliWallet::init(config, backup.key_manager, backup.outputs)
+    }
+
+    /// Open the existing wallet in the wallet directory
+    pub fn open(config: WalletConfig) -> Result<CliWallet, CliWalletError> {
+        if !CliWallet::exists(&config.wallet_dir) {
+            return Err(CliWalletError::WalletNotFound);
+        }
+        let key_manager = KeyManager::from_file(&path_string(&config.wallet_dir.join(KEYS_FILE)), &config.passphrase)?;
+        CliWallet::start(config, key_manager)
+    }
+
+    /// Store the keys of a new wallet and the outputs it starts with, then start it
+    fn init(
+        config: WalletConfig,
+        key_manager: KeyManager<SecretKey, HashDigest>,
+        outputs: Vec<UnblindedOutput>,
+    ) -> Result<CliWallet, CliWalletError>
+    {
+        if CliWallet::exists(&config.wallet_dir) {
+            return Err(CliWalletError::WalletExists);
+        }
+        fs::create_dir_all(&config.wallet_dir)?;
+        key_manager.to_file(&path_string(&config.wallet_dir.join(KEYS_FILE)), &config.passphrase)?;
+        let wallet = CliWallet::start(config, key_manager)?;
+        {
+            let mut service = wallet.service()?;
+            for output in outputs {
+                service.transaction_manager_mut().add_output(output)?;
+            }
+        }
+        Ok(wallet)
+    }
+
+    /// Open the wallet database, start the outbound message pool and the inbound message service, and listen for peers
+    /// on the listen address
+    fn start(
+        config: WalletConfig,
+        key_manager: KeyManager<SecretKey, HashDigest>,
+    ) -> Result<CliWallet, CliWalletError>
+    {
+        let database_dir = config.wallet_dir.join(DATABASE_DIR);
+        fs::create_dir_all(&database_dir)?;
+        let store = LMDBBuilder::new()
+            .set_mapsize(DATABASE_MAPSIZE)
+            .set_path(&path_string(&database_dir))
+            .build()?;
+        let database = WalletDatabase::new(store, derive_secret(&key_manager.master_key, b"wallet database"))?;
+        let transaction_manager = TransactionManager::with_database(database)?;
+
+        let comms_secret_key = key_manager
+            .derive_path_key(&KeyPath::new(0, KeyBranch::CommsIdentity, 0))?
+            .k;
+        let comms_public_key = PublicKey::from_secret_key(&comms_secret_key);
+        let node_identity = Arc::new(NodeIdentity::new(
+            NodeId::from_key(&comms_public_key)?,
+            comms_public_key.clone(),
+            Some(comms_secret_key),
+        ));
+        // The wallet learns about the peers it transacts with, so its peer database is kept in memory
+        let peer_manager = Arc::new(PeerManager::new(node_identity.node_id.clone(), HashmapStore::new())?);
+        let connection_manager = Arc::new(ConnectionManager::new(config.context.clone(), peer_manager.clone()));
+        OutboundMessagePool::new(
+            config.context.clone(),
+            config.outbound_address.clone(),
+            connection_manager.clone(),
+        )
+        .start()?;
+        let outbound_message_service = OutboundMessageService::new(
+            config.context.clone(),
+            config.outbound_address.clone(),
+            node_identity.clone(),
+            peer_manager.clone(),
+        );
+        let transaction_service = Arc::new(Mutex::new(TransactionService::new(
+            transaction_manager,
+            key_manager,
+            node_identity.clone(),
+            outbound_message_service,
+        )?));
+        InboundMessageService::new(
+            config.context.clone(),
+            config.inbound_address.clone(),
+            comms_public_key,
+            construct_transaction_message_dispatcher(node_identity.clone(), transaction_service.clone()),
+        )?
+        .start();
+        if let Some(listen_address) = &config.listen_address {
+            connection_manager.listen(listen_address, config.inbound_address.clone())?;
+        }
+
+        let wallet = CliWallet {
+            passphrase: config.passphrase,
+            node_identity,
+            peer_manager,
+            transaction_service,
+        };
+        for (public_key, net_address) in config.peers {
+            wallet.add_peer(public_key, net_address)?;
+        }
+        Ok(wallet)
+    }
+
+    /// Add the peer with the public key, which is reachable on the net address, or add the net address to the peer if
+    /// it is already known
+    pub fn add_peer(&self, public_key: PublicKey, net_address: NetAddress) -> Result<(), CliWalletError> {
+        let node_id = NodeId::from_key(&public_key)?;
+        if self.peer_manager.exists(&node_id)? {
+            self.peer_manager.modify_peer(&node_id, |peer| {
+                // A net address that is already known keeps its usage stats
+                let _ = peer.addresses.add_net_address(&net_address);
+            })?;
+        } else {
+            self.peer_manager.add_peer(Peer::new(
+                public_key,
+                node_id,
+                NetAddresses::from(net_address),
+                PeerFlags::default(),
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// The comms identity that the wallet receives transactions on
+    pub fn node_identity(&self) -> Arc<NodeIdentity<PublicKey, SecretKey>> {
+        self.node_identity.clone()
+    }
+
+    /// The transaction service of the wallet, which is shared with the inbound message handlers
+    pub fn transaction_service(&self) -> Arc<Mutex<TransactionService<LMDBStore>>> {
+        self.transaction_service.clone()
+    }
+
+    fn service(&self) -> Result<MutexGuard<TransactionService<LMDBStore>>, CliWalletError> {
+        self.transaction_service.lock().map_err(|_| CliWalletError::LockError)
+    }
+
+    /// Execute a command on the wallet
+    /// # Returns
+    /// The text to show the user
+    pub fn execute(&self, command: &Command) -> Result<String, CliWalletError> {
+        match command {
+            Command::Create | Command::Restore(_) | Command::Import(_) => Err(CliWalletError::WalletExists),
+            Command::Identity => Ok(format!(
+                "Public key: {}\nNode id: {}",
+                self.node_identity.public_key.to_hex(),
+                to_hex(self.node_identity.node_id.as_bytes())
+            )),
+            Command::Send {
+                public_key,
+                amount,
+                fee_per_gram,
+            } => {
+                let public_key = PublicKey::from_hex(public_key)?;
+                let tx_id = self
+                    .service()?
+                    .send_transaction(&public_key, *amount, *fee_per_gram, 0)?;
+                Ok(format!("Sent transaction {}", tx_id))
+            },
+            Command::Transactions => {
+                let service = self.service()?;
+                let records = service
+                    .transaction_manager()
+                    .transaction_history(&TransactionQuery::new())?;
+                if records.is_empty() {
+                    return Ok("No transactions".to_string());
+                }
+                Ok(records
+                    .iter()
+                    .map(|r| {
+                        format!(
+                            "{} {:?} {:?} amount: {} fee: {} counterparty: {} created: {}",
+                            r.tx_id,
+                            r.direction,
+                            r.status,
+                            r.amount,
+                            r.fee,
+                            r.counterparty.as_ref().map_or("unknown".to_string(), |c| c.to_hex()),
+                            r.timestamp.to_rfc3339()
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            },
+            Command::Utxos => {
+                let service = self.service()?;
+                let output_manager = service.transaction_manager().output_manager();
+                let mut lines = Vec::new();
+                for status in [
+                    OutputStatus::Unspent,
+                    OutputStatus::PendingSpend,
+                    OutputStatus::PendingIncoming,
+                ]
+                .iter()
+                {
+                    for output in output_manager.outputs_with_status(*status) {
+                        let commitment =
+                            CommitmentFactory::create(&output.spending_key, &SecretKey::from(output.value));
+                        lines.push(format!(
+                            "{} {:?} value: {}",
+                            to_hex(commitment.as_bytes()),
+                            status,
+                            output.value
+                        ));
+                    }
+                }
+                if lines.is_empty() {
+                    return Ok("No outputs".to_string());
+                }
+                Ok(lines.join("\n"))
+            },
+            Command::Balance => {
+                let balance = self.service()?.transaction_manager().output_manager().balance();
+                Ok(format!(
+                    "Available: {}\nPending incoming: {}\nPending outgoing: {}",
+                    balance.available, balance.pending_incoming, balance.pending_outgoing
+                ))
+            },
+            Command::Export(file) => {
+                let service = self.service()?;
+                let backup = WalletBackup {
+                    key_manager: service.key_manager().clone(),
+                    outputs: service
+                        .transaction_manager()
+                        .output_manager()
+                        .outputs_with_status(OutputStatus::Unspent),
+                };
+                backup.to_file(&path_string(file), &self.passphrase)?;
+                Ok(format!("Wrote backup to {}", file.display()))
+            },
+            Command::Help => Ok(crate::commands::HELP.to_string()),
+            Command::Exit => Ok(String::new()),
+        }
+    }
+}
+
+/// Derive a 32 byte secret for a specific use from the master key
+fn derive_secret(master_key: &SecretKey, domain: &[u8]) -> [u8; 32] {
+    let mut secret = [0u8; 32];
+    secret.copy_from_slice(&HashDigest::new().chain(domain).chain(master_key.as_bytes()).result());
+    secret
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
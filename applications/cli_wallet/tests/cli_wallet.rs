@@ -0,0 +1,213 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use cli_wallet::{CliWallet, Command, Session, WalletConfig};
+use rand::OsRng;
+use std::{fs, net::TcpListener, process, thread, time::Duration};
+use tari_comms::connection::NetAddress;
+use tari_core::{transaction::UnblindedOutput, types::SecretKey};
+use tari_crypto::keys::SecretKey as SK;
+use tari_utilities::hex::Hex;
+
+/// A local net address with a port that is not in use
+fn available_net_address() -> NetAddress {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    format!("127.0.0.1:{}", port).parse().unwrap()
+}
+
+fn clean_dir(dir: &str) {
+    let _ = fs::remove_dir_all(dir);
+}
+
+fn execute(session: &mut Session, line: &str) -> String {
+    session.execute(&Command::parse(line).unwrap().unwrap()).unwrap()
+}
+
+#[test]
+fn create_restore_and_reopen() {
+    let (dir, restored_dir) = ("./tests/test_cli_wallet_create", "./tests/test_cli_wallet_restore");
+    clean_dir(dir);
+    clean_dir(restored_dir);
+
+    let mut session = Session::new(WalletConfig::new(dir, "passphrase")).unwrap();
+    assert!(session.execute(&Command::Balance).is_err());
+    let created = execute(&mut session, "create");
+    let mnemonic = created.lines().last().unwrap().to_string();
+    assert_eq!(mnemonic.split_whitespace().count(), 24);
+    assert!(session.execute(&Command::Create).is_err());
+    let identity = execute(&mut session, "identity");
+    assert!(execute(&mut session, "balance").starts_with("Available: 0"));
+    drop(session);
+
+    // The wallet is opened again from its directory
+    let mut session = Session::new(WalletConfig::new(dir, "passphrase")).unwrap();
+    assert_eq!(execute(&mut session, "identity"), identity);
+    drop(session);
+
+    // The mnemonic restores the same comms identity
+    let mut session = Session::new(WalletConfig::new(restored_dir, "passphrase")).unwrap();
+    execute(&mut session, &format!("restore {}", mnemonic));
+    assert_eq!(execute(&mut session, "identity"), identity);
+
+    clean_dir(dir);
+    clean_dir(restored_dir);
+}
+
+#[test]
+fn send_transaction_between_cli_wallets() {
+    let (alice_dir, bob_dir) = ("./tests/test_cli_wallet_alice", "./tests/test_cli_wallet_bob");
+    clean_dir(alice_dir);
+    clean_dir(bob_dir);
+    let mut rng = OsRng::new().unwrap();
+    let (alice_address, bob_address) = (available_net_address(), available_net_address());
+
+    // The wallets listen for each other on local net addresses
+    let (alice, _) =
+        CliWallet::create(WalletConfig::new(alice_dir, "passphrase").with_listen_address(alice_address.clone()))
+            .unwrap();
+    let (bob, _) = CliWallet::create(
+        WalletConfig::new(bob_dir, "passphrase")
+            .with_listen_address(bob_address.clone())
+            .with_peer(alice.node_identity().public_key.clone(), alice_address),
+    )
+    .unwrap();
+    alice
+        .add_peer(bob.node_identity().public_key.clone(), bob_address)
+        .unwrap();
+
+    alice
+        .transaction_service()
+        .lock()
+        .unwrap()
+        .transaction_manager_mut()
+        .add_output(UnblindedOutput::new(5000, SecretKey::random(&mut rng), None))
+        .unwrap();
+    let bob_public_key = bob.node_identity().public_key.to_hex();
+    let sent = alice
+        .execute(
+            &Command::parse(&format!("send {} 1000 20", bob_public_key))
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+    assert!(sent.starts_with("Sent transaction"));
+
+    let mut received = false;
+    for _ in 0..50 {
+        thread::sleep(Duration::from_millis(100));
+        if bob
+            .execute(&Command::Balance)
+            .unwrap()
+            .contains("Pending incoming: 1000")
+        {
+            received = true;
+            break;
+        }
+    }
+    assert!(received);
+    let transactions = alice.execute(&Command::Transactions).unwrap();
+    assert!(transactions.contains("Outbound"));
+    assert!(transactions.contains(&bob_public_key));
+    assert!(bob
+        .execute(&Command::Utxos)
+        .unwrap()
+        .contains("PendingIncoming value: 1000"));
+
+    clean_dir(alice_dir);
+    clean_dir(bob_dir);
+}
+
+#[test]
+fn scripted_export_and_import() {
+    let (dir, imported_dir) = ("./tests/test_cli_wallet_export", "./tests/test_cli_wallet_import");
+    let backup_file = "./tests/test_cli_wallet_backup";
+    clean_dir(dir);
+    clean_dir(imported_dir);
+    let _ = fs::remove_file(backup_file);
+
+    let output = process::Command::new(env!("CARGO_BIN_EXE_cli_wallet"))
+        .args(&["--wallet-dir", dir, "--passphrase", "passphrase"])
+        .args(&["-c", "create", "-c", "identity", "-c", "balance"])
+        .args(&["-c", &format!("export {}", backup_file)])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let identity = stdout
+        .lines()
+        .skip_while(|l| !l.starts_with("Public key:"))
+        .take(2)
+        .collect::<Vec<_>>()
+        .join("\n");
+    assert!(identity.starts_with("Public key:"));
+
+    let script = "./tests/test_cli_wallet_script.txt";
+    fs::write(
+        script,
+        format!("# Import the backup\nimport {}\n\nidentity\n", backup_file),
+    )
+    .unwrap();
+    let output = process::Command::new(env!("CARGO_BIN_EXE_cli_wallet"))
+        .args(&[
+            "--wallet-dir",
+            imported_dir,
+            "--passphrase",
+            "passphrase",
+            "--script",
+            script,
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains(&identity));
+
+    // Importing into a directory that already holds a wallet fails
+    let output = process::Command::new(env!("CARGO_BIN_EXE_cli_wallet"))
+        .args(&[
+            "--wallet-dir",
+            imported_dir,
+            "--passphrase",
+            "passphrase",
+            "--script",
+            script,
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    // The backup can't be imported without the passphrase it was encrypted with
+    let wrong_dir = "./tests/test_cli_wallet_wrong_passphrase";
+    clean_dir(wrong_dir);
+    let output = process::Command::new(env!("CARGO_BIN_EXE_cli_wallet"))
+        .args(&["--wallet-dir", wrong_dir, "--passphrase", "wrong", "--script", script])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("WrongPassphrase"));
+    assert!(!CliWallet::exists(wrong_dir));
+
+    clean_dir(dir);
+    clean_dir(imported_dir);
+    clean_dir(wrong_dir);
+    let _ = fs::remove_file(backup_file);
+    let _ = fs::remove_file(script);
+}
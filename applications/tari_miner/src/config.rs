@@ -0,0 +1,98 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::error::MinerError;
+use std::{net::SocketAddr, time::Duration};
+use tari_core::types::SecretKey;
+use tari_utilities::hex::Hex;
+
+/// The address that a base node serves its mining API on by default
+pub const DEFAULT_NODE_ADDRESS: &str = "127.0.0.1:18142";
+/// The number of seconds a template is mined before a fresh one is requested from the base node
+pub const DEFAULT_TEMPLATE_REFRESH_SECS: u64 = 30;
+
+/// Configuration for the Tari miner
+#[derive(Clone, Debug)]
+pub struct MinerConfig {
+    /// The number of threads used to search for a nonce
+    pub num_threads: usize,
+    /// The address of the base node mining API
+    pub node_address: SocketAddr,
+    /// The wallet key from which the spending keys of the coinbase outputs are derived
+    pub wallet_key: SecretKey,
+    /// How long to mine a template before requesting a fresh one, so that new transactions and chain tips are
+    /// picked up
+    pub template_refresh: Duration,
+}
+
+impl MinerConfig {
+    /// Create a new miner configuration with the default template refresh interval
+    pub fn new(num_threads: usize, node_address: SocketAddr, wallet_key: SecretKey) -> MinerConfig {
+        MinerConfig {
+            num_threads,
+            node_address,
+            wallet_key,
+            template_refresh: Duration::from_secs(DEFAULT_TEMPLATE_REFRESH_SECS),
+        }
+    }
+
+    /// Create a miner configuration from string values, as provided on the command line
+    pub fn from_strings(num_threads: &str, node_address: &str, wallet_key: &str) -> Result<MinerConfig, MinerError> {
+        let num_threads = num_threads
+            .parse::<usize>()
+            .map_err(|_| MinerError::ConfigError(format!("Invalid thread count: {}", num_threads)))?;
+        if num_threads == 0 {
+            return Err(MinerError::ConfigError("At least one mining thread is required".into()));
+        }
+        let node_address = node_address
+            .parse::<SocketAddr>()
+            .map_err(|_| MinerError::ConfigError(format!("Invalid base node address: {}", node_address)))?;
+        let wallet_key = SecretKey::from_hex(wallet_key)
+            .map_err(|_| MinerError::ConfigError("The wallet key must be a 32-byte hex string".into()))?;
+        Ok(MinerConfig::new(num_threads, node_address, wallet_key))
+    }
+
+    /// Set how long a template is mined before a fresh one is requested
+    pub fn with_template_refresh(mut self, template_refresh: Duration) -> MinerConfig {
+        self.template_refresh = template_refresh;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_from_strings() {
+        let key = "0c0d0e0f0c0d0e0f0c0d0e0f0c0d0e0f0c0d0e0f0c0d0e0f0c0d0e0f0c0d0e00";
+        let config = MinerConfig::from_strings("4", "127.0.0.1:18142", key).unwrap();
+        assert_eq!(config.num_threads, 4);
+        assert_eq!(config.node_address, DEFAULT_NODE_ADDRESS.parse().unwrap());
+        assert_eq!(config.wallet_key.to_hex(), key);
+
+        assert!(MinerConfig::from_strings("0", "127.0.0.1:18142", key).is_err());
+        assert!(MinerConfig::from_strings("four", "127.0.0.1:18142", key).is_err());
+        assert!(MinerConfig::from_strings("4", "localhost", key).is_err());
+        assert!(MinerConfig::from_strings("4", "127.0.0.1:18142", "zz").is_err());
+    }
+}
@@ -0,0 +1,48 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use derive_error::Error;
+use mining::MiningError;
+use std::io;
+use tari_utilities::byte_array::ByteArrayError;
+
+#[derive(Debug, Error)]
+pub enum MinerError {
+    // Could not communicate with the base node
+    IoError(io::Error),
+    // A mining API message could not be serialized or deserialized
+    SerializationError(serde_json::Error),
+    // The coinbase could not be constructed
+    MiningError(MiningError),
+    // A coinbase spending key could not be derived from the wallet key
+    KeyDerivationError(ByteArrayError),
+    // The operating system random number generator could not be created
+    RandomError(rand::Error),
+    // The base node rejected the request
+    #[error(msg_embedded, no_from, non_std)]
+    Rejected(String),
+    // The base node sent a response that does not match the request
+    UnexpectedResponse,
+    // The miner configuration is invalid
+    #[error(msg_embedded, no_from, non_std)]
+    ConfigError(String),
+}
@@ -0,0 +1,159 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use clap::{App, Arg};
+use rand::OsRng;
+use std::process;
+use tari_core::types::SecretKey;
+use tari_crypto::keys::SecretKey as SK;
+use tari_miner::{
+    config::{DEFAULT_NODE_ADDRESS, DEFAULT_TEMPLATE_REFRESH_SECS},
+    MinerConfig,
+    MinerError,
+    StandInNode,
+    TariMiner,
+};
+use tari_utilities::hex::Hex;
+
+/// The target difficulty of the stand-in node, low enough to mine blocks in well under a second
+const DEFAULT_STAND_IN_DIFFICULTY: &str = "1000";
+/// The block reward paid by the stand-in node
+const STAND_IN_REWARD: u64 = 5_000;
+
+fn main() {
+    let matches = App::new("Tari miner")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Mines blocks for a Tari base node")
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .short("t")
+                .takes_value(true)
+                .default_value("1")
+                .help("The number of mining threads"),
+        )
+        .arg(
+            Arg::with_name("node-address")
+                .long("node-address")
+                .short("a")
+                .takes_value(true)
+                .default_value(DEFAULT_NODE_ADDRESS)
+                .help("The address of the base node mining API"),
+        )
+        .arg(
+            Arg::with_name("wallet-key")
+                .long("wallet-key")
+                .short("k")
+                .takes_value(true)
+                .required_unless("stand-in")
+                .help("The hex encoded wallet key from which coinbase spending keys are derived"),
+        )
+        .arg(
+            Arg::with_name("refresh")
+                .long("refresh")
+                .takes_value(true)
+                .help("The number of seconds to mine a template before requesting a new one"),
+        )
+        .arg(
+            Arg::with_name("blocks")
+                .long("blocks")
+                .short("n")
+                .takes_value(true)
+                .help("Stop after mining this many blocks"),
+        )
+        .arg(Arg::with_name("stand-in").long("stand-in").help(
+            "Serve the mining API from a local stand-in node on the node address instead of connecting to a base node",
+        ))
+        .arg(
+            Arg::with_name("difficulty")
+                .long("difficulty")
+                .takes_value(true)
+                .default_value(DEFAULT_STAND_IN_DIFFICULTY)
+                .help("The target difficulty of the stand-in node, if one is used"),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("Mining failed: {:?}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &clap::ArgMatches) -> Result<(), MinerError> {
+    let wallet_key = match matches.value_of("wallet-key") {
+        Some(key) => key.to_string(),
+        None => {
+            let key = SecretKey::random(&mut OsRng::new()?).to_hex();
+            println!("No wallet key provided, mining to new key {}", key);
+            key
+        },
+    };
+    let mut config = MinerConfig::from_strings(
+        matches.value_of("threads").unwrap(),
+        matches.value_of("node-address").unwrap(),
+        &wallet_key,
+    )?;
+    let refresh =
+        parse_number(matches.value_of("refresh"), "refresh interval")?.unwrap_or(DEFAULT_TEMPLATE_REFRESH_SECS);
+    config = config.with_template_refresh(std::time::Duration::from_secs(refresh));
+    let max_blocks = parse_number(matches.value_of("blocks"), "block count")?;
+
+    // The stand-in node must outlive the miner, so it is kept in scope until mining is done
+    let _stand_in = if matches.is_present("stand-in") {
+        let difficulty = parse_number(matches.value_of("difficulty"), "difficulty")?.unwrap();
+        let node = StandInNode::start(config.node_address, difficulty, STAND_IN_REWARD)?;
+        config.node_address = node.address();
+        println!("Stand-in node serving the mining API on {}", node.address());
+        Some(node)
+    } else {
+        None
+    };
+
+    println!(
+        "Mining for {} with {} thread(s)",
+        config.node_address, config.num_threads
+    );
+    let mut miner = TariMiner::new(config)?;
+    let mut mined = 0;
+    while max_blocks.map_or(true, |max| mined < max) {
+        match miner.mine_next_block() {
+            Ok(Some(block)) => {
+                mined += 1;
+                println!("Mined block {} with nonce {}", block.header.height, block.header.nonce);
+            },
+            Ok(None) => println!("No solution found before the template expired, requesting a new template"),
+            // Another miner may have extended the chain in the meantime, so simply start on a fresh template
+            Err(MinerError::Rejected(reason)) => println!("Block rejected: {}", reason),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn parse_number(value: Option<&str>, name: &str) -> Result<Option<u64>, MinerError> {
+    value
+        .map(|v| {
+            v.parse::<u64>()
+                .map_err(|_| MinerError::ConfigError(format!("Invalid {}: {}", name, v)))
+        })
+        .transpose()
+}
@@ -0,0 +1,107 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::error::MinerError;
+use mining::{MiningRequest, MiningResponse, NewBlockTemplate};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+use tari_core::block::Block;
+
+/// How long to wait for the base node to connect or respond before giving up
+const NODE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Write a single newline-terminated JSON message to `writer`
+pub(crate) fn write_message<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<(), MinerError> {
+    serde_json::to_writer(&mut *writer, message)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read a single newline-terminated JSON message from `reader`
+pub(crate) fn read_message<R: BufRead, T: DeserializeOwned>(reader: &mut R) -> Result<T, MinerError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// A client for the base node mining API. Each request is sent as a line of JSON on a new TCP connection, and the
+/// base node answers with a single line of JSON before closing the connection.
+pub struct NodeClient {
+    address: SocketAddr,
+}
+
+impl NodeClient {
+    /// Create a client for the mining API served at `address`
+    pub fn new(address: SocketAddr) -> NodeClient {
+        NodeClient { address }
+    }
+
+    /// The address of the base node mining API
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Request a template for the next block on top of the base node's chain tip
+    pub fn get_new_block_template(&self) -> Result<NewBlockTemplate, MinerError> {
+        match self.request(&MiningRequest::GetNewBlockTemplate)? {
+            MiningResponse::NewBlockTemplate(template) => Ok(template),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Ask the base node to complete a template, including the miner's coinbase, into a block that is ready to mine
+    pub fn get_new_block(&self, template: NewBlockTemplate) -> Result<Block, MinerError> {
+        match self.request(&MiningRequest::GetNewBlock(template))? {
+            MiningResponse::NewBlock(block) => Ok(block),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Submit a block with a solved proof of work to the base node
+    pub fn submit_block(&self, block: Block) -> Result<(), MinerError> {
+        match self.request(&MiningRequest::SubmitBlock(block))? {
+            MiningResponse::BlockAccepted => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Send a request to the base node and wait for its response
+    pub fn request(&self, request: &MiningRequest) -> Result<MiningResponse, MinerError> {
+        let mut stream = TcpStream::connect_timeout(&self.address, NODE_TIMEOUT)?;
+        stream.set_read_timeout(Some(NODE_TIMEOUT))?;
+        write_message(&mut stream, request)?;
+        read_message(&mut BufReader::new(stream))
+    }
+}
+
+/// Rejections are reported as such, anything else does not match the request that was sent
+fn unexpected(response: MiningResponse) -> MinerError {
+    match response {
+        MiningResponse::Rejected(reason) => MinerError::Rejected(reason),
+        _ => MinerError::UnexpectedResponse,
+    }
+}
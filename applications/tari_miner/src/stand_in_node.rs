@@ -0,0 +1,260 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{
+    error::MinerError,
+    node_client::{read_message, write_message},
+};
+use chrono::Utc;
+use digest::Digest;
+use mining::{MiningRequest, MiningResponse, NewBlockTemplate};
+use std::{
+    io::BufReader,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+        Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use tari_core::{
+    block::{AggregateBody, Block},
+    blockheader::{BlockHash, BlockHeader},
+    pow::{Difficulty, ProofOfWork},
+    transaction::KernelFeatures,
+    types::{Commitment, HashDigest, PublicKey, SecretKey, TariCommitment},
+};
+use tari_utilities::Hashable;
+
+/// How long the stand-in node waits for a miner to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The chain held by the stand-in node. There is no mempool, so templates only ever contain the miner's coinbase.
+struct StandInChain {
+    blocks: Vec<Block>,
+    target_difficulty: Difficulty,
+    reward: u64,
+}
+
+impl StandInChain {
+    fn tip_hash(&self) -> BlockHash {
+        let mut hash = [0u8; 32];
+        if let Some(block) = self.blocks.last() {
+            hash.copy_from_slice(&block.header.hash());
+        }
+        hash
+    }
+
+    fn height(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    fn handle(&mut self, request: MiningRequest) -> MiningResponse {
+        let result = match request {
+            MiningRequest::GetNewBlockTemplate => Ok(MiningResponse::NewBlockTemplate(self.new_block_template())),
+            MiningRequest::GetNewBlock(template) => self.new_block(template).map(MiningResponse::NewBlock),
+            MiningRequest::SubmitBlock(block) => self.submit_block(block).map(|_| MiningResponse::BlockAccepted),
+        };
+        result.unwrap_or_else(MiningResponse::Rejected)
+    }
+
+    fn new_block_template(&self) -> NewBlockTemplate {
+        let header = BlockHeader {
+            version: 0,
+            height: self.height(),
+            prev_hash: self.tip_hash(),
+            timestamp: Utc::now(),
+            output_mmr: [0u8; 32],
+            kernel_mmr: [0u8; 32],
+            total_kernel_offset: PublicKey::default(),
+            nonce: 0,
+            pow: ProofOfWork::new(self.target_difficulty),
+        };
+        NewBlockTemplate {
+            header,
+            body: AggregateBody::empty(),
+            reward: self.reward,
+        }
+    }
+
+    fn new_block(&self, template: NewBlockTemplate) -> Result<Block, String> {
+        self.check_tip(&template.header)?;
+        let mut body = template.body;
+        check_body(&mut body, self.reward)?;
+        let mut header = template.header;
+        header.output_mmr = stand_in_root(body.outputs.iter().map(Hashable::hash));
+        header.kernel_mmr = stand_in_root(body.kernels.iter().map(Hashable::hash));
+        Ok(Block { header, body })
+    }
+
+    fn submit_block(&mut self, block: Block) -> Result<(), String> {
+        self.check_tip(&block.header)?;
+        if block.header.pow.target_difficulty < self.target_difficulty {
+            return Err("The block target difficulty is too low".into());
+        }
+        if !block.header.validate_pow() {
+            return Err("The block does not meet its target difficulty".into());
+        }
+        let mut body = block.body.clone();
+        check_body(&mut body, self.reward)?;
+        if block.header.output_mmr != stand_in_root(body.outputs.iter().map(Hashable::hash)) ||
+            block.header.kernel_mmr != stand_in_root(body.kernels.iter().map(Hashable::hash))
+        {
+            return Err("The block header roots do not match the block body".into());
+        }
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    fn check_tip(&self, header: &BlockHeader) -> Result<(), String> {
+        if header.height != self.height() || header.prev_hash != self.tip_hash() {
+            return Err("The block does not build on the current chain tip".into());
+        }
+        Ok(())
+    }
+}
+
+/// Sort the body and check that its kernel signatures are valid, and that it contains exactly one coinbase that
+/// claims no more than the reward plus fees. Since the stand-in node applies no kernel offsets, the output
+/// commitments must exceed the input commitments by exactly the kernel excesses and the reward.
+fn check_body(body: &mut AggregateBody, reward: u64) -> Result<(), String> {
+    body.verify_kernel_signatures()
+        .map_err(|e| format!("Invalid kernel signature: {:?}", e))?;
+    let coinbase_kernels = body
+        .kernels
+        .iter()
+        .filter(|k| k.features.contains(KernelFeatures::COINBASE_KERNEL))
+        .count();
+    if coinbase_kernels != 1 {
+        return Err("The block must contain exactly one coinbase".into());
+    }
+    let outputs: Commitment = body.outputs.iter().map(|o| &o.commitment).sum();
+    let inputs: Commitment = body.inputs.iter().map(|i| &i.commitment).sum();
+    let excess: Commitment = body.kernels.iter().map(|k| &k.excess).sum();
+    let emission = Commitment::commit(reward, &SecretKey::default());
+    if &outputs - &inputs != &excess + &emission {
+        return Err("The coinbase does not match the block reward".into());
+    }
+    Ok(())
+}
+
+/// The stand-in node does not maintain MMRs, so the header roots are simply a hash over the sorted leaf hashes
+fn stand_in_root<I: Iterator<Item = Vec<u8>>>(hashes: I) -> BlockHash {
+    let mut hasher = HashDigest::new();
+    for hash in hashes {
+        hasher.input(hash);
+    }
+    let mut root = [0u8; 32];
+    root.copy_from_slice(&hasher.result());
+    root
+}
+
+/// A minimal base node that serves the mining API from its own in-memory chain. It hands out templates on top of its
+/// tip, completes them into blocks and validates submitted blocks, so that the miner can be run and tested without a
+/// full base node. Transactions, kernel offsets and real MMRs are not supported.
+pub struct StandInNode {
+    address: SocketAddr,
+    chain: Arc<Mutex<StandInChain>>,
+    shutdown_flag: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl StandInNode {
+    /// Start serving the mining API on `address`. Use port 0 to let the operating system pick a free port; the bound
+    /// address is available from `address()`.
+    pub fn start(address: SocketAddr, target_difficulty: Difficulty, reward: u64) -> Result<StandInNode, MinerError> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let chain = Arc::new(Mutex::new(StandInChain {
+            blocks: Vec::new(),
+            target_difficulty,
+            reward,
+        }));
+        let shutdown_flag = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let chain = chain.clone();
+            let shutdown_flag = shutdown_flag.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown_flag.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        // A misbehaving miner only affects its own request
+                        let _ = serve(stream, &chain);
+                    }
+                }
+            })
+        };
+        Ok(StandInNode {
+            address,
+            chain,
+            shutdown_flag,
+            handle: Some(handle),
+        })
+    }
+
+    /// The address the mining API is served on
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The number of blocks that have been accepted
+    pub fn height(&self) -> u64 {
+        self.chain.lock().unwrap().height()
+    }
+
+    /// A copy of the accepted blocks, starting with the block at height 0
+    pub fn blocks(&self) -> Vec<Block> {
+        self.chain.lock().unwrap().blocks.clone()
+    }
+
+    /// Stop serving the mining API
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.shutdown_flag.store(true, Ordering::SeqCst);
+            // Wake up the listener so that it notices the shutdown flag
+            let _ = TcpStream::connect(self.address);
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for StandInNode {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn serve(stream: TcpStream, chain: &Mutex<StandInChain>) -> Result<(), MinerError> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let request: MiningRequest = read_message(&mut BufReader::new(stream))?;
+    let response = chain.lock().unwrap().handle(request);
+    write_message(&mut writer, &response)
+}
@@ -0,0 +1,94 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{config::MinerConfig, error::MinerError, node_client::NodeClient};
+use keymanager::keymanager::KeyManager;
+use mining::Miner;
+use rand::OsRng;
+use std::{
+    sync::{atomic::AtomicBool, Arc},
+    time::Instant,
+};
+use tari_core::{
+    block::Block,
+    types::{HashDigest, SecretKey},
+};
+use tari_crypto::keys::SecretKey as SK;
+
+/// The key manager branch from which coinbase spending keys are derived
+pub const COINBASE_BRANCH_SEED: &str = "coinbase";
+
+/// Mines blocks for a base node and pays the block rewards to the configured wallet. The spending key of the coinbase
+/// in the block at height _h_ is derived from the wallet key at index _h_, so the wallet can recover its coinbases.
+pub struct TariMiner {
+    config: MinerConfig,
+    client: NodeClient,
+    miner: Miner,
+    key_manager: KeyManager<SecretKey, HashDigest>,
+    rng: OsRng,
+}
+
+impl TariMiner {
+    pub fn new(config: MinerConfig) -> Result<TariMiner, MinerError> {
+        let key_manager = KeyManager::from(config.wallet_key.clone(), COINBASE_BRANCH_SEED.to_string(), 0);
+        Ok(TariMiner {
+            client: NodeClient::new(config.node_address),
+            miner: Miner::new(config.num_threads),
+            key_manager,
+            rng: OsRng::new()?,
+            config,
+        })
+    }
+
+    /// Returns a flag that aborts mining when it is set
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.miner.stop_flag()
+    }
+
+    /// The spending key of the coinbase output in the block at `height`
+    pub fn coinbase_key(&self, height: u64) -> Result<SecretKey, MinerError> {
+        Ok(self.key_manager.derive_key(height as usize)?.k)
+    }
+
+    /// Request a template from the base node, add a coinbase, and mine the resulting block. The block is returned
+    /// once the base node accepted it. If no solution is found before the template refresh interval passes, or the
+    /// miner is stopped, `None` is returned and a fresh template should be requested.
+    pub fn mine_next_block(&mut self) -> Result<Option<Block>, MinerError> {
+        let template = self.client.get_new_block_template()?;
+        let spending_key = self.coinbase_key(template.header.height)?;
+        let nonce = SecretKey::random(&mut self.rng);
+        let template = template.with_coinbase(&spending_key, nonce)?;
+        let block = self.client.get_new_block(template)?;
+        let deadline = Instant::now() + self.config.template_refresh;
+        match self.miner.mine(block.header, Some(deadline)) {
+            Some(header) => {
+                let block = Block {
+                    header,
+                    body: block.body,
+                };
+                self.client.submit_block(block.clone())?;
+                Ok(Some(block))
+            },
+            None => Ok(None),
+        }
+    }
+}
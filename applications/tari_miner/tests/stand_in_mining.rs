@@ -0,0 +1,102 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use rand::OsRng;
+use std::process::Command;
+use tari_core::types::{SecretKey, TariCommitmentValidate};
+use tari_crypto::keys::SecretKey as SK;
+use tari_miner::{node_client::NodeClient, MinerConfig, MinerError, StandInNode, TariMiner};
+use tari_utilities::Hashable;
+
+const REWARD: u64 = 5_000;
+
+fn start_node() -> StandInNode {
+    StandInNode::start("127.0.0.1:0".parse().unwrap(), 100, REWARD).unwrap()
+}
+
+#[test]
+fn mine_blocks_on_stand_in_node() {
+    let node = start_node();
+    let mut rng = OsRng::new().unwrap();
+    let config = MinerConfig::new(2, node.address(), SecretKey::random(&mut rng));
+    let mut miner = TariMiner::new(config).unwrap();
+    let mut mined = 0;
+    while mined < 3 {
+        if miner.mine_next_block().unwrap().is_some() {
+            mined += 1;
+        }
+    }
+
+    let blocks = node.blocks();
+    assert_eq!(node.height(), 3);
+    for (height, block) in blocks.iter().enumerate() {
+        assert_eq!(block.header.height, height as u64);
+        assert!(block.header.validate_pow());
+        if height > 0 {
+            assert_eq!(block.header.prev_hash.to_vec(), blocks[height - 1].header.hash());
+        }
+        // The coinbase pays the reward to the key derived from the wallet key for this height
+        let key = miner.coinbase_key(height as u64).unwrap();
+        assert_eq!(block.body.outputs.len(), 1);
+        assert!(block.body.outputs[0].commitment.validate(REWARD, &key));
+    }
+    node.shutdown();
+}
+
+#[test]
+fn stale_block_is_rejected() {
+    let node = start_node();
+    let mut rng = OsRng::new().unwrap();
+    let config = MinerConfig::new(1, node.address(), SecretKey::random(&mut rng));
+    let mut miner = TariMiner::new(config).unwrap();
+    while miner.mine_next_block().unwrap().is_none() {}
+    let block = node.blocks()[0].clone();
+
+    // Resubmitting the block at height 0 no longer builds on the tip
+    let client = NodeClient::new(node.address());
+    match client.submit_block(block) {
+        Err(MinerError::Rejected(_)) => {},
+        r => panic!("Expected a rejection, got {:?}", r),
+    }
+    assert_eq!(node.height(), 1);
+}
+
+#[test]
+fn binary_mines_with_stand_in_node() {
+    let output = Command::new(env!("CARGO_BIN_EXE_tari_miner"))
+        .args(&[
+            "--stand-in",
+            "--node-address",
+            "127.0.0.1:0",
+            "--threads",
+            "2",
+            "--blocks",
+            "2",
+        ])
+        .args(&["--difficulty", "100"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Mined block 0"));
+    assert!(stdout.contains("Mined block 1"));
+}
@@ -0,0 +1,205 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use std::collections::{HashMap, VecDeque};
+
+/// How the pool divides block rewards among its workers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PayoutScheme {
+    /// Each block reward is divided in proportion to the share difficulty each worker submitted during the round,
+    /// i.e. since the previous block found by the pool
+    Proportional,
+    /// Pay per last N shares: each block reward is divided in proportion to the difficulty of the last `window`
+    /// shares, regardless of the round in which they were submitted
+    Pplns { window: usize },
+}
+
+/// A share accepted by the pool
+#[derive(Clone, Debug, PartialEq)]
+pub struct Share {
+    pub worker: String,
+    pub difficulty: u64,
+}
+
+/// The amount credited to a worker for a block found by the pool
+#[derive(Clone, Debug, PartialEq)]
+pub struct Payout {
+    pub height: u64,
+    pub worker: String,
+    pub amount: u64,
+}
+
+/// Keeps track of the shares submitted to the pool and credits block rewards to workers according to the payout
+/// scheme. Any remainder left after integer division of a reward is kept by the pool.
+pub struct ShareLedger {
+    scheme: PayoutScheme,
+    shares: VecDeque<Share>,
+    payouts: Vec<Payout>,
+    balances: HashMap<String, u64>,
+}
+
+impl ShareLedger {
+    pub fn new(scheme: PayoutScheme) -> ShareLedger {
+        ShareLedger {
+            scheme,
+            shares: VecDeque::new(),
+            payouts: Vec::new(),
+            balances: HashMap::new(),
+        }
+    }
+
+    pub fn scheme(&self) -> PayoutScheme {
+        self.scheme
+    }
+
+    /// Record an accepted share
+    pub fn add_share(&mut self, worker: &str, difficulty: u64) {
+        self.shares.push_back(Share {
+            worker: worker.to_string(),
+            difficulty,
+        });
+        if let PayoutScheme::Pplns { window } = self.scheme {
+            while self.shares.len() > window {
+                self.shares.pop_front();
+            }
+        }
+    }
+
+    /// The shares that the next block reward will be divided over
+    pub fn pending_shares(&self) -> impl Iterator<Item = &Share> {
+        self.shares.iter()
+    }
+
+    /// Divide the reward of the block at `height` among the workers and return the resulting payouts. Proportional
+    /// payouts close the round, so the shares are cleared, whereas PPLNS keeps the window of shares for the next
+    /// block. If there are no shares the pool keeps the full reward.
+    pub fn block_found(&mut self, height: u64, reward: u64) -> Vec<Payout> {
+        let mut difficulty_by_worker: Vec<(String, u64)> = Vec::new();
+        for share in self.shares.iter() {
+            match difficulty_by_worker.iter_mut().find(|(w, _)| w == &share.worker) {
+                Some((_, d)) => *d += share.difficulty,
+                None => difficulty_by_worker.push((share.worker.clone(), share.difficulty)),
+            }
+        }
+        let total: u128 = difficulty_by_worker.iter().map(|(_, d)| u128::from(*d)).sum();
+        let payouts = if total == 0 {
+            Vec::new()
+        } else {
+            difficulty_by_worker
+                .into_iter()
+                .map(|(worker, difficulty)| Payout {
+                    height,
+                    worker,
+                    amount: (u128::from(reward) * u128::from(difficulty) / total) as u64,
+                })
+                .filter(|p| p.amount > 0)
+                .collect()
+        };
+        for payout in payouts.iter() {
+            *self.balances.entry(payout.worker.clone()).or_insert(0) += payout.amount;
+        }
+        self.payouts.extend(payouts.iter().cloned());
+        if self.scheme == PayoutScheme::Proportional {
+            self.shares.clear();
+        }
+        payouts
+    }
+
+    /// All payouts made so far, in order
+    pub fn payouts(&self) -> &[Payout] {
+        &self.payouts
+    }
+
+    /// The total amount credited to `worker`
+    pub fn balance(&self, worker: &str) -> u64 {
+        self.balances.get(worker).cloned().unwrap_or(0)
+    }
+
+    /// The total amount credited to each worker
+    pub fn balances(&self) -> &HashMap<String, u64> {
+        &self.balances
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::accounting::{PayoutScheme, ShareLedger};
+
+    #[test]
+    fn proportional() {
+        let mut ledger = ShareLedger::new(PayoutScheme::Proportional);
+        ledger.add_share("alice", 10);
+        ledger.add_share("bob", 10);
+        ledger.add_share("alice", 20);
+        let payouts = ledger.block_found(1, 900);
+        assert_eq!(payouts.len(), 2);
+        assert_eq!(ledger.balance("alice"), 675);
+        assert_eq!(ledger.balance("bob"), 225);
+        assert_eq!(ledger.pending_shares().count(), 0);
+
+        // The next round starts from scratch
+        ledger.add_share("bob", 5);
+        ledger.block_found(2, 100);
+        assert_eq!(ledger.balance("alice"), 675);
+        assert_eq!(ledger.balance("bob"), 325);
+        assert_eq!(ledger.payouts().len(), 3);
+    }
+
+    #[test]
+    fn pplns() {
+        let mut ledger = ShareLedger::new(PayoutScheme::Pplns { window: 3 });
+        ledger.add_share("alice", 10);
+        ledger.add_share("alice", 10);
+        ledger.add_share("bob", 10);
+        ledger.add_share("carol", 10);
+        // Only the last 3 shares count
+        ledger.block_found(1, 300);
+        assert_eq!(ledger.balance("alice"), 100);
+        assert_eq!(ledger.balance("bob"), 100);
+        assert_eq!(ledger.balance("carol"), 100);
+
+        // The window carries over to the next block
+        ledger.add_share("carol", 10);
+        ledger.block_found(2, 300);
+        assert_eq!(ledger.balance("alice"), 100);
+        assert_eq!(ledger.balance("bob"), 200);
+        assert_eq!(ledger.balance("carol"), 300);
+    }
+
+    #[test]
+    fn no_shares() {
+        let mut ledger = ShareLedger::new(PayoutScheme::Proportional);
+        assert!(ledger.block_found(1, 300).is_empty());
+        assert!(ledger.balances().is_empty());
+    }
+
+    #[test]
+    fn remainder_is_kept_by_pool() {
+        let mut ledger = ShareLedger::new(PayoutScheme::Proportional);
+        ledger.add_share("alice", 1);
+        ledger.add_share("bob", 1);
+        ledger.add_share("carol", 1);
+        ledger.block_found(1, 100);
+        let total: u64 = ledger.balances().values().sum();
+        assert_eq!(total, 99);
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{
+    error::PoolError,
+    protocol::{read_message, write_message, Job, PoolMessage, WorkerMessage},
+};
+use mining::Miner;
+use std::{
+    io::BufReader,
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, TryRecvError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long the worker searches for a share before checking for a new job
+const SHARE_SEARCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Counters for the shares a pool miner submitted
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolMinerStats {
+    pub submitted: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub blocks_found: u64,
+}
+
+/// A worker that mines shares for a pool
+pub struct PoolMiner {
+    worker: String,
+    stream: TcpStream,
+    messages: Receiver<PoolMessage>,
+    miner: Miner,
+    job: Option<Job>,
+    next_nonce: u64,
+    stats: PoolMinerStats,
+}
+
+impl PoolMiner {
+    /// Connect to the pool at `address` and log in as `worker`
+    pub fn connect(address: SocketAddr, worker: &str, num_threads: usize) -> Result<PoolMiner, PoolError> {
+        let mut stream = TcpStream::connect(address)?;
+        write_message(&mut stream, &WorkerMessage::Login {
+            worker: worker.to_string(),
+        })?;
+        let (sender, messages) = mpsc::channel();
+        let mut reader = BufReader::new(stream.try_clone()?);
+        thread::spawn(move || {
+            while let Ok(message) = read_message(&mut reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(PoolMiner {
+            worker: worker.to_string(),
+            stream,
+            messages,
+            miner: Miner::new(num_threads),
+            job: None,
+            next_nonce: 0,
+            stats: PoolMinerStats::default(),
+        })
+    }
+
+    pub fn worker(&self) -> &str {
+        &self.worker
+    }
+
+    /// Returns a flag that stops the worker when it is set
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.miner.stop_flag()
+    }
+
+    pub fn stats(&self) -> &PoolMinerStats {
+        &self.stats
+    }
+
+    /// Mine shares until `max_shares` shares were accepted by the pool, or until the worker is stopped
+    pub fn run(&mut self, max_shares: Option<u64>) -> Result<PoolMinerStats, PoolError> {
+        let stop_flag = self.stop_flag();
+        while !stop_flag.load(Ordering::Relaxed) && max_shares.map_or(true, |max| self.stats.accepted < max) {
+            self.process_messages()?;
+            let job = match &self.job {
+                Some(job) if job.contains_nonce(self.next_nonce) => job.clone(),
+                // Without a job, or once the nonce range of the job is exhausted, wait for the next job
+                _ => {
+                    self.wait_for_message(SHARE_SEARCH_INTERVAL)?;
+                    continue;
+                },
+            };
+            let mut header = job.header;
+            header.nonce = self.next_nonce;
+            let deadline = Instant::now() + SHARE_SEARCH_INTERVAL;
+            if let Some(share) = self.miner.mine_to_target(header, job.share_difficulty, Some(deadline)) {
+                self.next_nonce = share.nonce.saturating_add(1);
+                // The mining threads may overshoot the end of the nonce range
+                if !job.contains_nonce(share.nonce) {
+                    continue;
+                }
+                write_message(&mut self.stream, &WorkerMessage::SubmitShare {
+                    job_id: job.job_id,
+                    nonce: share.nonce,
+                })?;
+                self.stats.submitted += 1;
+                // Wait briefly for the response, so that accepted shares are counted promptly
+                self.wait_for_message(SHARE_SEARCH_INTERVAL)?;
+            }
+        }
+        Ok(self.stats.clone())
+    }
+
+    /// Handle all the messages the pool has sent so far
+    fn process_messages(&mut self) -> Result<(), PoolError> {
+        loop {
+            match self.messages.try_recv() {
+                Ok(message) => self.handle(message),
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => return Err(PoolError::ConnectionClosed),
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for a message from the pool
+    fn wait_for_message(&mut self, timeout: Duration) -> Result<(), PoolError> {
+        match self.messages.recv_timeout(timeout) {
+            Ok(message) => {
+                self.handle(message);
+                Ok(())
+            },
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            Err(RecvTimeoutError::Disconnected) => Err(PoolError::ConnectionClosed),
+        }
+    }
+
+    fn handle(&mut self, message: PoolMessage) {
+        match message {
+            PoolMessage::Job(job) => {
+                self.next_nonce = job.nonce_start;
+                self.job = Some(job);
+            },
+            PoolMessage::ShareAccepted { block_found, .. } => {
+                self.stats.accepted += 1;
+                if block_found {
+                    self.stats.blocks_found += 1;
+                }
+            },
+            PoolMessage::ShareRejected { .. } | PoolMessage::Error(_) => self.stats.rejected += 1,
+            PoolMessage::LoginAccepted { .. } => {},
+        }
+    }
+}
+
+impl Drop for PoolMiner {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use rand::OsRng;
+use std::{net::SocketAddr, process, str::FromStr, thread, time::Duration};
+use tari_core::types::SecretKey;
+use tari_crypto::keys::SecretKey as SK;
+use tari_miner::{config::DEFAULT_NODE_ADDRESS, StandInNode};
+use tari_pool_miner::{PayoutScheme, PoolConfig, PoolError, PoolMiner, PoolServer};
+use tari_utilities::hex::Hex;
+
+/// The address the pool listens on for workers by default
+const DEFAULT_POOL_ADDRESS: &str = "127.0.0.1:18143";
+/// The block reward paid by the stand-in node
+const STAND_IN_REWARD: u64 = 5_000;
+/// How often the pool server prints its statistics
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+fn main() {
+    let matches = App::new("Tari pool miner")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Runs a Tari mining pool, or mines shares for one")
+        .subcommand(
+            SubCommand::with_name("server")
+                .about("Run a pool server")
+                .arg(
+                    Arg::with_name("listen-address")
+                        .long("listen-address")
+                        .takes_value(true)
+                        .default_value(DEFAULT_POOL_ADDRESS)
+                        .help("The address workers connect to"),
+                )
+                .arg(
+                    Arg::with_name("node-address")
+                        .long("node-address")
+                        .takes_value(true)
+                        .default_value(DEFAULT_NODE_ADDRESS)
+                        .help("The address of the base node mining API"),
+                )
+                .arg(
+                    Arg::with_name("wallet-key")
+                        .long("wallet-key")
+                        .takes_value(true)
+                        .required_unless("stand-in")
+                        .help("The hex encoded pool wallet key from which coinbase spending keys are derived"),
+                )
+                .arg(
+                    Arg::with_name("share-difficulty")
+                        .long("share-difficulty")
+                        .takes_value(true)
+                        .default_value("100")
+                        .help("The difficulty every share must achieve"),
+                )
+                .arg(
+                    Arg::with_name("pplns")
+                        .long("pplns")
+                        .takes_value(true)
+                        .help("Divide rewards over the last N shares instead of proportionally per round"),
+                )
+                .arg(
+                    Arg::with_name("job-refresh")
+                        .long("job-refresh")
+                        .takes_value(true)
+                        .default_value("30")
+                        .help("The number of seconds after which a job is replaced by a fresh template"),
+                )
+                .arg(
+                    Arg::with_name("stand-in")
+                        .long("stand-in")
+                        .help("Mine against a local stand-in node on the node address instead of a base node"),
+                )
+                .arg(
+                    Arg::with_name("difficulty")
+                        .long("difficulty")
+                        .takes_value(true)
+                        .default_value("10000")
+                        .help("The target difficulty of the stand-in node, if one is used"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mine")
+                .about("Mine shares for a pool")
+                .arg(
+                    Arg::with_name("pool-address")
+                        .long("pool-address")
+                        .takes_value(true)
+                        .default_value(DEFAULT_POOL_ADDRESS)
+                        .help("The address of the pool"),
+                )
+                .arg(
+                    Arg::with_name("worker")
+                        .long("worker")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The worker name that shares are credited to"),
+                )
+                .arg(
+                    Arg::with_name("threads")
+                        .long("threads")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("The number of mining threads"),
+                )
+                .arg(
+                    Arg::with_name("shares")
+                        .long("shares")
+                        .takes_value(true)
+                        .help("Stop after this many shares were accepted"),
+                ),
+        )
+        .get_matches();
+
+    let result = match matches.subcommand() {
+        ("server", Some(matches)) => run_server(matches),
+        ("mine", Some(matches)) => run_miner(matches),
+        _ => {
+            eprintln!("{}", matches.usage());
+            process::exit(2);
+        },
+    };
+    if let Err(e) = result {
+        eprintln!("Pool miner failed: {:?}", e);
+        process::exit(1);
+    }
+}
+
+fn run_server(matches: &ArgMatches) -> Result<(), PoolError> {
+    let mut node_address: SocketAddr = parse(matches, "node-address")?;
+    let difficulty: u64 = parse(matches, "difficulty")?;
+    let _stand_in = if matches.is_present("stand-in") {
+        let node = StandInNode::start(node_address, difficulty, STAND_IN_REWARD)?;
+        node_address = node.address();
+        println!("Stand-in node serving the mining API on {}", node_address);
+        Some(node)
+    } else {
+        None
+    };
+    let wallet_key = match matches.value_of("wallet-key") {
+        Some(key) => SecretKey::from_hex(key)
+            .map_err(|_| PoolError::ConfigError("The wallet key must be a 32-byte hex string".into()))?,
+        None => {
+            let key = SecretKey::random(&mut OsRng::new()?);
+            println!("No wallet key provided, mining to new key {}", key.to_hex());
+            key
+        },
+    };
+    let payout_scheme = match matches.value_of("pplns") {
+        Some(_) => PayoutScheme::Pplns {
+            window: parse(matches, "pplns")?,
+        },
+        None => PayoutScheme::Proportional,
+    };
+    let config = PoolConfig {
+        listen_address: parse(matches, "listen-address")?,
+        node_address,
+        wallet_key,
+        share_difficulty: parse(matches, "share-difficulty")?,
+        payout_scheme,
+        job_refresh: Duration::from_secs(parse(matches, "job-refresh")?),
+    };
+    let server = PoolServer::start(config)?;
+    println!("Pool listening for workers on {}", server.address());
+    loop {
+        thread::sleep(REPORT_INTERVAL);
+        println!("Blocks found: {}", server.blocks_found().len());
+        for (worker, balance) in server.balances() {
+            println!("  {}: {}", worker, balance);
+        }
+    }
+}
+
+fn run_miner(matches: &ArgMatches) -> Result<(), PoolError> {
+    let worker = matches.value_of("worker").unwrap();
+    let max_shares = match matches.value_of("shares") {
+        Some(_) => Some(parse(matches, "shares")?),
+        None => None,
+    };
+    let mut miner = PoolMiner::connect(parse(matches, "pool-address")?, worker, parse(matches, "threads")?)?;
+    println!("Mining for the pool as {}", worker);
+    let stats = miner.run(max_shares)?;
+    println!(
+        "Submitted {} shares: {} accepted, {} rejected, {} blocks found",
+        stats.submitted, stats.accepted, stats.rejected, stats.blocks_found
+    );
+    Ok(())
+}
+
+fn parse<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<T, PoolError> {
+    let value = matches.value_of(name).unwrap_or_default();
+    value
+        .parse()
+        .map_err(|_| PoolError::ConfigError(format!("Invalid {}: {}", name, value)))
+}
@@ -0,0 +1,133 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+//! The pool protocol is modelled on Stratum. Workers open a single TCP connection to the pool and exchange
+//! newline-delimited JSON messages over it:
+//!
+//! 1. The worker sends `Login` and the pool replies with `LoginAccepted`, followed by a `Job` notification.
+//! 2. The pool sends a new `Job` notification whenever the block it is mining changes. Jobs with a new `job_id` replace
+//!    all previous jobs.
+//! 3. The worker searches the nonce range of its job for nonces for which the job header hash achieves the job's share
+//!    difficulty and sends each one in a `SubmitShare` message. The pool answers with `ShareAccepted` or
+//!    `ShareRejected`.
+
+use crate::error::PoolError;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use tari_core::blockheader::BlockHeader;
+
+/// A unit of work handed out by the pool. The header has all fields except the nonce filled in. Shares must achieve
+/// `share_difficulty`, which is lower than the target difficulty of the header so that workers submit shares often
+/// enough for the pool to estimate their hash rate. Every worker is assigned its own nonce range, so that workers don't
+/// repeat each other's search.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub job_id: u64,
+    pub header: BlockHeader,
+    pub share_difficulty: u64,
+    /// The first nonce of the range assigned to the worker
+    pub nonce_start: u64,
+    /// The end of the nonce range assigned to the worker, exclusive
+    pub nonce_end: u64,
+}
+
+impl Job {
+    /// Returns true if the nonce lies in the nonce range of the job
+    pub fn contains_nonce(&self, nonce: u64) -> bool {
+        nonce >= self.nonce_start && nonce < self.nonce_end
+    }
+}
+
+/// Messages sent by a worker to the pool
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WorkerMessage {
+    Login { worker: String },
+    SubmitShare { job_id: u64, nonce: u64 },
+}
+
+/// Messages sent by the pool to a worker
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum PoolMessage {
+    LoginAccepted {
+        worker: String,
+    },
+    /// A job notification, which replaces any previous job
+    Job(Job),
+    /// The share was credited to the worker. `block_found` is set if the share also met the block target and the
+    /// block was accepted by the base node.
+    ShareAccepted {
+        job_id: u64,
+        nonce: u64,
+        block_found: bool,
+    },
+    ShareRejected {
+        job_id: u64,
+        nonce: u64,
+        reason: String,
+    },
+    /// The message could not be processed
+    Error(String),
+}
+
+/// Write a single newline-terminated JSON message to `writer`
+pub fn write_message<W: Write, T: serde::Serialize>(writer: &mut W, message: &T) -> Result<(), PoolError> {
+    serde_json::to_writer(&mut *writer, message)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read a single newline-terminated JSON message from `reader`. `ConnectionClosed` is returned at the end of the
+/// stream.
+pub fn read_message<R: BufRead, T: DeserializeOwned>(reader: &mut R) -> Result<T, PoolError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(PoolError::ConnectionClosed);
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::PoolError,
+        protocol::{read_message, write_message, PoolMessage, WorkerMessage},
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = Vec::new();
+        let login = WorkerMessage::Login { worker: "alice".into() };
+        let share = WorkerMessage::SubmitShare { job_id: 3, nonce: 42 };
+        write_message(&mut buffer, &login).unwrap();
+        write_message(&mut buffer, &share).unwrap();
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_message::<_, WorkerMessage>(&mut reader).unwrap(), login);
+        assert_eq!(read_message::<_, WorkerMessage>(&mut reader).unwrap(), share);
+        match read_message::<_, PoolMessage>(&mut reader) {
+            Err(PoolError::ConnectionClosed) => {},
+            r => panic!("Expected the connection to be closed, got {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,515 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{
+    accounting::{Payout, PayoutScheme, ShareLedger},
+    error::PoolError,
+    protocol::{read_message, write_message, Job, PoolMessage, WorkerMessage},
+};
+use keymanager::keymanager::KeyManager;
+use mining::NewBlockTemplate;
+use rand::OsRng;
+use std::{
+    collections::{HashMap, HashSet},
+    io::BufReader,
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+        Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use tari_core::{
+    block::{AggregateBody, Block},
+    blockheader::BlockHeader,
+    pow::ProofOfWork,
+    types::{HashDigest, SecretKey},
+};
+use tari_crypto::keys::SecretKey as SK;
+use tari_miner::{node_client::NodeClient, tari_miner::COINBASE_BRANCH_SEED};
+use tari_utilities::Hashable;
+
+/// How often the pool checks the base node for a new chain tip
+const TIP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Every worker connection is assigned a range of 2^40 nonces, enough for a day of mining at ten terahashes per second
+const NONCE_RANGE_BITS: u32 = 40;
+
+/// Configuration for the pool server
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// The address workers connect to
+    pub listen_address: SocketAddr,
+    /// The address of the base node mining API
+    pub node_address: SocketAddr,
+    /// The pool wallet key, from which the spending keys of the coinbases are derived
+    pub wallet_key: SecretKey,
+    /// The difficulty every share must achieve
+    pub share_difficulty: u64,
+    /// How block rewards are divided among the workers
+    pub payout_scheme: PayoutScheme,
+    /// How long a job is handed out before it is replaced by a fresh template, even if the chain tip did not change
+    pub job_refresh: Duration,
+}
+
+/// The block the pool is currently mining
+struct CurrentJob {
+    /// The job covering the whole nonce space, which is divided among the workers
+    job: Job,
+    body: AggregateBody,
+    reward: u64,
+    created: Instant,
+    /// The nonces that were already submitted for this job, by worker connection
+    shares: HashSet<(u64, u64)>,
+}
+
+impl CurrentJob {
+    /// The job handed out to the worker with the given connection id, restricted to the worker's nonce range
+    fn job_for(&self, connection_id: u64) -> Job {
+        let (nonce_start, nonce_end) = nonce_range(connection_id);
+        Job {
+            nonce_start,
+            nonce_end,
+            ..self.job.clone()
+        }
+    }
+}
+
+/// The nonce range of a worker connection. The ranges are reused once 2^24 connections have been made.
+fn nonce_range(connection_id: u64) -> (u64, u64) {
+    let start = (connection_id % (1 << (64 - NONCE_RANGE_BITS))) << NONCE_RANGE_BITS;
+    (
+        start,
+        start.checked_add(1 << NONCE_RANGE_BITS).unwrap_or(u64::max_value()),
+    )
+}
+
+/// A connected worker. Messages for the worker are queued on its channel and written to its socket by a writer thread,
+/// so that a worker that stops reading can't hold up the pool.
+struct Worker {
+    name: Option<String>,
+    sender: Sender<PoolMessage>,
+    stream: TcpStream,
+}
+
+/// A block that met the block target, which still has to be submitted to the base node
+struct FoundBlock {
+    block: Block,
+    reward: u64,
+}
+
+/// Builds jobs from the base node's block templates. It is owned by the job thread, so that the base node is never
+/// called while the pool state is locked.
+struct JobBuilder {
+    node: NodeClient,
+    key_manager: KeyManager<SecretKey, HashDigest>,
+    rng: OsRng,
+    share_difficulty: u64,
+}
+
+impl JobBuilder {
+    /// Request a block for the template with a coinbase that pays the block reward to the pool. The share difficulty
+    /// must be below the target difficulty of the template, otherwise every share would be a block and shares could
+    /// not measure the work of the workers.
+    fn build_block(&mut self, template: NewBlockTemplate) -> Result<FoundBlock, PoolError> {
+        let target_difficulty = template.header.pow.target_difficulty;
+        if self.share_difficulty >= target_difficulty {
+            return Err(PoolError::ConfigError(format!(
+                "The share difficulty {} must be below the target difficulty {} of the block template",
+                self.share_difficulty, target_difficulty
+            )));
+        }
+        let spending_key = self.key_manager.derive_key(template.header.height as usize)?.k;
+        let reward = template.coinbase_value();
+        let template = template.with_coinbase(&spending_key, SecretKey::random(&mut self.rng))?;
+        let block = self.node.get_new_block(template)?;
+        Ok(FoundBlock { block, reward })
+    }
+}
+
+/// The state shared by the pool threads. Only in-memory bookkeeping is done while it is locked; requests to the base
+/// node and writes to worker sockets happen outside the lock.
+struct PoolState {
+    config: PoolConfig,
+    job: Option<CurrentJob>,
+    next_job_id: u64,
+    workers: HashMap<u64, Worker>,
+    ledger: ShareLedger,
+    blocks_found: Vec<Block>,
+}
+
+impl PoolState {
+    /// Returns true if the current job must be replaced, because the chain tip moved or the job is older than the
+    /// refresh interval
+    fn is_stale(&self, template_header: &BlockHeader) -> bool {
+        match &self.job {
+            Some(current) => {
+                current.job.header.height != template_header.height ||
+                    current.job.header.prev_hash != template_header.prev_hash ||
+                    current.created.elapsed() >= self.config.job_refresh
+            },
+            None => true,
+        }
+    }
+
+    /// Replace the current job with a job for the block, and notify all workers
+    fn set_job(&mut self, block: FoundBlock) {
+        let job = Job {
+            job_id: self.next_job_id,
+            header: block.block.header,
+            share_difficulty: self.config.share_difficulty,
+            nonce_start: 0,
+            nonce_end: u64::max_value(),
+        };
+        self.next_job_id += 1;
+        self.job = Some(CurrentJob {
+            job,
+            body: block.block.body,
+            reward: block.reward,
+            created: Instant::now(),
+            shares: HashSet::new(),
+        });
+        self.broadcast_job();
+    }
+
+    /// Queue the current job for every logged in worker. Workers whose writer thread has stopped are dropped.
+    fn broadcast_job(&mut self) {
+        let current = match &self.job {
+            Some(current) => current,
+            None => return,
+        };
+        self.workers.retain(|connection_id, worker| {
+            worker.name.is_none() ||
+                worker
+                    .sender
+                    .send(PoolMessage::Job(current.job_for(*connection_id)))
+                    .is_ok()
+        });
+    }
+
+    /// Log the worker in and queue the reply, followed by the current job
+    fn login(&mut self, connection_id: u64, name: String) {
+        let job = self.job.as_ref().map(|current| current.job_for(connection_id));
+        if let Some(worker) = self.workers.get_mut(&connection_id) {
+            worker.name = Some(name.clone());
+            let _ = worker.sender.send(PoolMessage::LoginAccepted { worker: name });
+            if let Some(job) = job {
+                let _ = worker.sender.send(PoolMessage::Job(job));
+            }
+        }
+    }
+
+    /// The name of the worker with the given connection id, if it has logged in
+    fn worker_name(&self, connection_id: u64) -> Option<String> {
+        self.workers.get(&connection_id).and_then(|w| w.name.clone())
+    }
+
+    /// Validate a share and credit it to the worker. If the share also meets the block target, the block is returned
+    /// so that it can be submitted to the base node.
+    fn submit_share(
+        &mut self,
+        connection_id: u64,
+        worker: &str,
+        job_id: u64,
+        nonce: u64,
+    ) -> Result<Option<FoundBlock>, String>
+    {
+        let current = match &mut self.job {
+            Some(current) if current.job.job_id == job_id => current,
+            _ => return Err("Stale job".into()),
+        };
+        if !current.job_for(connection_id).contains_nonce(nonce) {
+            return Err("Nonce outside the assigned range".into());
+        }
+        if current.shares.contains(&(connection_id, nonce)) {
+            return Err("Duplicate share".into());
+        }
+        let mut header = current.job.header.clone();
+        header.nonce = nonce;
+        if ProofOfWork::achieved_difficulty(&header.hash()) < self.config.share_difficulty {
+            return Err("Share difficulty not met".into());
+        }
+        current.shares.insert((connection_id, nonce));
+        self.ledger.add_share(worker, self.config.share_difficulty);
+        if !header.validate_pow() {
+            return Ok(None);
+        }
+        Ok(Some(FoundBlock {
+            block: Block {
+                header,
+                body: current.body.clone(),
+            },
+            reward: current.reward,
+        }))
+    }
+
+    /// Divide the reward of a block that was accepted by the base node among the workers
+    fn block_accepted(&mut self, found: FoundBlock) {
+        self.ledger.block_found(found.block.header.height, found.reward);
+        self.blocks_found.push(found.block);
+    }
+
+    /// Queue a message for a worker
+    fn send(&self, connection_id: u64, message: PoolMessage) {
+        if let Some(worker) = self.workers.get(&connection_id) {
+            let _ = worker.sender.send(message);
+        }
+    }
+}
+
+/// A mining pool server. Workers connect over TCP and mine jobs built from the base node's block templates, with
+/// the coinbase paying the pool. Shares are accounted in a `ShareLedger`, which credits block rewards to workers.
+pub struct PoolServer {
+    address: SocketAddr,
+    state: Arc<Mutex<PoolState>>,
+    shutdown_flag: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl PoolServer {
+    /// Start the pool. The first job is requested from the base node before workers are accepted, so this fails if
+    /// the base node cannot be reached, or if the share difficulty is not below the target difficulty of its block
+    /// template.
+    pub fn start(config: PoolConfig) -> Result<PoolServer, PoolError> {
+        if config.share_difficulty == 0 {
+            return Err(PoolError::ConfigError("The share difficulty must be positive".into()));
+        }
+        let listener = TcpListener::bind(config.listen_address)?;
+        let address = listener.local_addr()?;
+        let node_address = config.node_address;
+        let mut job_builder = JobBuilder {
+            node: NodeClient::new(node_address),
+            key_manager: KeyManager::from(config.wallet_key.clone(), COINBASE_BRANCH_SEED.to_string(), 0),
+            rng: OsRng::new()?,
+            share_difficulty: config.share_difficulty,
+        };
+        let template = job_builder.node.get_new_block_template()?;
+        let first_block = job_builder.build_block(template)?;
+        let mut state = PoolState {
+            job: None,
+            next_job_id: 0,
+            workers: HashMap::new(),
+            ledger: ShareLedger::new(config.payout_scheme),
+            blocks_found: Vec::new(),
+            config,
+        };
+        state.set_job(first_block);
+        let state = Arc::new(Mutex::new(state));
+        let shutdown_flag = Arc::new(AtomicBool::new(false));
+        // Workers that found a block ask the job thread for a new job through this channel
+        let (refresh_sender, refresh_receiver) = mpsc::channel();
+
+        let accept_handle = {
+            let state = state.clone();
+            let shutdown_flag = shutdown_flag.clone();
+            thread::spawn(move || {
+                for (connection_id, stream) in listener.incoming().enumerate() {
+                    if shutdown_flag.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let state = state.clone();
+                        let refresh_sender = refresh_sender.clone();
+                        thread::spawn(move || {
+                            let node = NodeClient::new(node_address);
+                            let _ = serve_worker(connection_id as u64, stream, &state, &node, &refresh_sender);
+                            state.lock().unwrap().workers.remove(&(connection_id as u64));
+                        });
+                    }
+                }
+            })
+        };
+        let job_handle = {
+            let state = state.clone();
+            let shutdown_flag = shutdown_flag.clone();
+            thread::spawn(move || {
+                run_job_updates(job_builder, &state, &refresh_receiver, &shutdown_flag);
+            })
+        };
+        Ok(PoolServer {
+            address,
+            state,
+            shutdown_flag,
+            handles: vec![accept_handle, job_handle],
+        })
+    }
+
+    /// The address workers connect to
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The job that is currently being mined, covering the whole nonce space
+    pub fn current_job(&self) -> Option<Job> {
+        self.state
+            .lock()
+            .unwrap()
+            .job
+            .as_ref()
+            .map(|current| current.job.clone())
+    }
+
+    /// The blocks found by the pool and accepted by the base node
+    pub fn blocks_found(&self) -> Vec<Block> {
+        self.state.lock().unwrap().blocks_found.clone()
+    }
+
+    /// The payouts made to workers so far
+    pub fn payouts(&self) -> Vec<Payout> {
+        self.state.lock().unwrap().ledger.payouts().to_vec()
+    }
+
+    /// The total amount credited to each worker
+    pub fn balances(&self) -> HashMap<String, u64> {
+        self.state.lock().unwrap().ledger.balances().clone()
+    }
+
+    /// Disconnect all workers and stop the pool
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if self.handles.is_empty() {
+            return;
+        }
+        self.shutdown_flag.store(true, Ordering::SeqCst);
+        // Wake up the listener so that it notices the shutdown flag
+        let _ = TcpStream::connect(self.address);
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+        for (_, worker) in self.state.lock().unwrap().workers.drain() {
+            let _ = worker.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for PoolServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Replace the current job whenever the chain tip moves, the job is older than the refresh interval, or a worker found
+/// a block. The base node is polled without holding the state lock, which is only taken to compare and swap the job.
+/// The current job is kept if no job can be built for a template, including a template whose target difficulty is not
+/// above the share difficulty.
+fn run_job_updates(
+    mut job_builder: JobBuilder,
+    state: &Mutex<PoolState>,
+    refresh_receiver: &Receiver<()>,
+    shutdown_flag: &AtomicBool,
+) {
+    while !shutdown_flag.load(Ordering::SeqCst) {
+        let block_found = match refresh_receiver.recv_timeout(TIP_POLL_INTERVAL) {
+            Ok(()) => true,
+            Err(RecvTimeoutError::Timeout) => false,
+            // The listener has stopped, so the pool is shutting down
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if shutdown_flag.load(Ordering::SeqCst) {
+            break;
+        }
+        // The base node may be temporarily unavailable, in which case the current job is kept
+        let template = match job_builder.node.get_new_block_template() {
+            Ok(template) => template,
+            Err(_) => continue,
+        };
+        if !block_found && !state.lock().unwrap().is_stale(&template.header) {
+            continue;
+        }
+        if let Ok(block) = job_builder.build_block(template) {
+            state.lock().unwrap().set_job(block);
+        }
+    }
+}
+
+/// Write the messages queued for a worker to its socket. The socket is shut down if the worker can't be written to,
+/// which also ends the worker's reader.
+fn write_worker_messages(mut stream: TcpStream, messages: Receiver<PoolMessage>) {
+    for message in messages {
+        if write_message(&mut stream, &message).is_err() {
+            let _ = stream.shutdown(Shutdown::Both);
+            break;
+        }
+    }
+}
+
+fn serve_worker(
+    connection_id: u64,
+    stream: TcpStream,
+    state: &Mutex<PoolState>,
+    node: &NodeClient,
+    refresh_sender: &Sender<()>,
+) -> Result<(), PoolError>
+{
+    let (sender, messages) = mpsc::channel();
+    let writer = stream.try_clone()?;
+    thread::spawn(move || write_worker_messages(writer, messages));
+    state.lock().unwrap().workers.insert(connection_id, Worker {
+        name: None,
+        sender,
+        stream: stream.try_clone()?,
+    });
+    let mut reader = BufReader::new(stream);
+    loop {
+        match read_message(&mut reader)? {
+            WorkerMessage::Login { worker } => state.lock().unwrap().login(connection_id, worker),
+            WorkerMessage::SubmitShare { job_id, nonce } => {
+                let result = {
+                    let mut state = state.lock().unwrap();
+                    match state.worker_name(connection_id) {
+                        Some(worker) => state
+                            .submit_share(connection_id, &worker, job_id, nonce)
+                            .map_err(|reason| PoolMessage::ShareRejected { job_id, nonce, reason }),
+                        None => Err(PoolMessage::Error("Log in before submitting shares".into())),
+                    }
+                };
+                // A found block is submitted to the base node without holding the state lock
+                let reply = match result {
+                    Ok(found) => PoolMessage::ShareAccepted {
+                        job_id,
+                        nonce,
+                        block_found: found.map_or(false, |found| submit_block(found, state, node, refresh_sender)),
+                    },
+                    Err(reply) => reply,
+                };
+                state.lock().unwrap().send(connection_id, reply);
+            },
+        }
+    }
+}
+
+/// Submit a found block to the base node. If it is accepted, the reward is divided among the workers and the job
+/// thread is asked for a new job. Returns whether the block was accepted.
+fn submit_block(found: FoundBlock, state: &Mutex<PoolState>, node: &NodeClient, refresh_sender: &Sender<()>) -> bool {
+    // The base node may have moved on to a new tip; the share still counts
+    if node.submit_block(found.block.clone()).is_err() {
+        return false;
+    }
+    state.lock().unwrap().block_accepted(found);
+    let _ = refresh_sender.send(());
+    true
+}
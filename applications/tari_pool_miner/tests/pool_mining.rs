@@ -0,0 +1,190 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use rand::OsRng;
+use std::{
+    io::BufReader,
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+use tari_core::{pow::ProofOfWork, types::SecretKey};
+use tari_crypto::keys::SecretKey as SK;
+use tari_miner::StandInNode;
+use tari_pool_miner::{
+    protocol::{read_message, write_message, Job, PoolMessage, WorkerMessage},
+    PayoutScheme,
+    PoolConfig,
+    PoolError,
+    PoolMiner,
+    PoolServer,
+};
+use tari_utilities::Hashable;
+
+const REWARD: u64 = 5_000;
+const SHARE_DIFFICULTY: u64 = 20;
+
+fn pool_config(node: &StandInNode, payout_scheme: PayoutScheme) -> PoolConfig {
+    PoolConfig {
+        listen_address: "127.0.0.1:0".parse().unwrap(),
+        node_address: node.address(),
+        wallet_key: SecretKey::random(&mut OsRng::new().unwrap()),
+        share_difficulty: SHARE_DIFFICULTY,
+        payout_scheme,
+        job_refresh: Duration::from_secs(30),
+    }
+}
+
+fn start_pool(block_difficulty: u64, payout_scheme: PayoutScheme) -> (StandInNode, PoolServer) {
+    let node = StandInNode::start("127.0.0.1:0".parse().unwrap(), block_difficulty, REWARD).unwrap();
+    let pool = PoolServer::start(pool_config(&node, payout_scheme)).unwrap();
+    (node, pool)
+}
+
+#[test]
+fn share_difficulty_must_be_below_block_difficulty() {
+    for block_difficulty in &[SHARE_DIFFICULTY - 1, SHARE_DIFFICULTY] {
+        let node = StandInNode::start("127.0.0.1:0".parse().unwrap(), *block_difficulty, REWARD).unwrap();
+        match PoolServer::start(pool_config(&node, PayoutScheme::Proportional)) {
+            Err(PoolError::ConfigError(_)) => {},
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => panic!("The pool started with a share difficulty that is not below the block difficulty"),
+        }
+    }
+}
+
+fn mine_blocks(payout_scheme: PayoutScheme) {
+    let (node, pool) = start_pool(200, payout_scheme);
+    let handles = ["alice", "bob"]
+        .iter()
+        .map(|worker| {
+            let mut miner = PoolMiner::connect(pool.address(), worker, 1).unwrap();
+            let stop_flag = miner.stop_flag();
+            (stop_flag, thread::spawn(move || miner.run(None).unwrap()))
+        })
+        .collect::<Vec<_>>();
+
+    let timeout = Instant::now() + Duration::from_secs(60);
+    while pool.blocks_found().len() < 2 {
+        assert!(Instant::now() < timeout, "The pool did not find two blocks in time");
+        thread::sleep(Duration::from_millis(50));
+    }
+    let mut stats = Vec::new();
+    for (stop_flag, handle) in handles {
+        stop_flag.store(true, std::sync::atomic::Ordering::Relaxed);
+        stats.push(handle.join().unwrap());
+    }
+
+    // Every block found by the pool is on the node's chain, and its reward was divided among the workers
+    let blocks = pool.blocks_found();
+    assert_eq!(node.height() as usize, blocks.len());
+    assert!(stats.iter().map(|s| s.accepted).sum::<u64>() > 0);
+    let payouts = pool.payouts();
+    for block in blocks.iter() {
+        let paid: u64 = payouts
+            .iter()
+            .filter(|p| p.height == block.header.height)
+            .map(|p| p.amount)
+            .sum();
+        assert!(paid <= REWARD && paid + 2 >= REWARD);
+    }
+    pool.shutdown();
+}
+
+#[test]
+fn pool_mining_proportional() {
+    mine_blocks(PayoutScheme::Proportional);
+}
+
+#[test]
+fn pool_mining_pplns() {
+    mine_blocks(PayoutScheme::Pplns { window: 50 });
+}
+
+/// Connect a raw worker that speaks the pool protocol directly and return its first job
+fn login(pool: &PoolServer) -> (TcpStream, BufReader<TcpStream>, Job) {
+    let mut stream = TcpStream::connect(pool.address()).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    write_message(&mut stream, &WorkerMessage::Login { worker: "carol".into() }).unwrap();
+    match read_message(&mut reader).unwrap() {
+        PoolMessage::LoginAccepted { worker } => assert_eq!(worker, "carol"),
+        m => panic!("Expected the login to be accepted, got {:?}", m),
+    }
+    match read_message(&mut reader).unwrap() {
+        PoolMessage::Job(job) => (stream, reader, job),
+        m => panic!("Expected a job, got {:?}", m),
+    }
+}
+
+fn submit(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, job_id: u64, nonce: u64) -> PoolMessage {
+    write_message(stream, &WorkerMessage::SubmitShare { job_id, nonce }).unwrap();
+    read_message(reader).unwrap()
+}
+
+#[test]
+fn invalid_shares_are_rejected() {
+    // The block difficulty is too high to find a block by accident, so the job stays the same
+    let (_node, pool) = start_pool(u64::max_value(), PayoutScheme::Proportional);
+    let (mut stream, mut reader, job) = login(&pool);
+
+    let achieved = |nonce: u64| {
+        let mut header = job.header.clone();
+        header.nonce = nonce;
+        ProofOfWork::achieved_difficulty(&header.hash())
+    };
+    let good = (job.nonce_start..).find(|n| achieved(*n) >= SHARE_DIFFICULTY).unwrap();
+    let bad = (job.nonce_start..).find(|n| achieved(*n) < SHARE_DIFFICULTY).unwrap();
+    let outside = (job.nonce_end..).find(|n| achieved(*n) >= SHARE_DIFFICULTY).unwrap();
+
+    match submit(&mut stream, &mut reader, job.job_id, good) {
+        PoolMessage::ShareAccepted { block_found, .. } => assert!(!block_found),
+        m => panic!("Expected the share to be accepted, got {:?}", m),
+    }
+    match submit(&mut stream, &mut reader, job.job_id, good) {
+        PoolMessage::ShareRejected { reason, .. } => assert_eq!(reason, "Duplicate share"),
+        m => panic!("Expected a duplicate share, got {:?}", m),
+    }
+    match submit(&mut stream, &mut reader, job.job_id, outside) {
+        PoolMessage::ShareRejected { reason, .. } => assert_eq!(reason, "Nonce outside the assigned range"),
+        m => panic!("Expected a share outside the nonce range, got {:?}", m),
+    }
+    match submit(&mut stream, &mut reader, job.job_id, bad) {
+        PoolMessage::ShareRejected { reason, .. } => assert_eq!(reason, "Share difficulty not met"),
+        m => panic!("Expected a low difficulty share, got {:?}", m),
+    }
+    match submit(&mut stream, &mut reader, job.job_id + 1, good) {
+        PoolMessage::ShareRejected { reason, .. } => assert_eq!(reason, "Stale job"),
+        m => panic!("Expected a stale share, got {:?}", m),
+    }
+    assert!(pool.blocks_found().is_empty());
+}
+
+#[test]
+fn workers_get_disjoint_nonce_ranges() {
+    let (_node, pool) = start_pool(u64::max_value(), PayoutScheme::Proportional);
+    let (_stream1, _reader1, job1) = login(&pool);
+    let (_stream2, _reader2, job2) = login(&pool);
+
+    assert_eq!(job1.job_id, job2.job_id);
+    assert!(job1.nonce_start < job1.nonce_end && job2.nonce_start < job2.nonce_end);
+    assert!(job1.nonce_end <= job2.nonce_start || job2.nonce_end <= job1.nonce_start);
+}
@@ -27,10 +27,12 @@ use crate::{
     blockheader::BlockHeader,
     transaction::{TransactionError, TransactionInput, TransactionKernel, TransactionOutput},
 };
+use serde::{Deserialize, Serialize};
 
 //----------------------------------------         Blocks         ----------------------------------------------------//
 
 /// A Tari block. Blocks are linked together into a blockchain.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Block {
     pub header: BlockHeader,
     pub body: AggregateBody,
@@ -40,7 +42,7 @@ pub struct Block {
 
 /// The components of the block or transaction. The same struct can be used for either, since in Mimblewimble,
 /// cut-through means that blocks and transactions have the same structure.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AggregateBody {
     sorted: bool,
     /// List of inputs spent by the transaction.
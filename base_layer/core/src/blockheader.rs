@@ -26,14 +26,15 @@
 use crate::{pow::ProofOfWork, types::*};
 use chrono::{DateTime, Utc};
 use digest::Input;
+use serde::{Deserialize, Serialize};
 use tari_infra_derive::Hashable;
 use tari_utilities::{ExtendBytes, Hashable};
 
-type BlockHash = [u8; 32];
+pub type BlockHash = [u8; 32];
 
 /// The BlockHeader contains all the metadata for the block, including proof of work, a link to the previous block
 /// and the transaction kernels.
-#[derive(Hashable)]
+#[derive(Clone, Debug, Hashable, Serialize, Deserialize)]
 #[digest = "SignatureHash"]
 pub struct BlockHeader {
     /// Version of the block
@@ -52,6 +53,7 @@ pub struct BlockHeader {
     /// block from the total kernel offset of the previous block header.
     pub total_kernel_offset: PublicKey,
     /// Nonce used
+    pub nonce: u64,
     /// Proof of work summary
     pub pow: ProofOfWork,
 }
@@ -59,6 +61,6 @@ pub struct BlockHeader {
 impl BlockHeader {
     /// This function will validate the proof of work in the header
     pub fn validate_pow(&self) -> bool {
-        unimplemented!();
+        self.pow.is_met_by(&self.hash())
     }
 }
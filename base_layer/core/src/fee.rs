@@ -31,7 +31,12 @@ pub const BASE_COST: u64 = 1;
 impl Fee {
     /// Computes the absolute transaction fee given the fee-per-gram, and the size of the transaction
     pub fn calculate(fee_per_gram: u64, num_inputs: usize, num_outputs: usize) -> u64 {
-        BASE_COST + (COST_PER_INPUT * num_inputs as u64 + COST_PER_OUTPUT * num_outputs as u64) * fee_per_gram
+        BASE_COST + Fee::weight(num_inputs, num_outputs) * fee_per_gram
+    }
+
+    /// Computes the weight, in grams, of a transaction with the given number of inputs and outputs
+    pub fn weight(num_inputs: usize, num_outputs: usize) -> u64 {
+        COST_PER_INPUT * num_inputs as u64 + COST_PER_OUTPUT * num_outputs as u64
     }
 
     /// Computes the absolute transaction fee using `calculate`, but the resulting fee will always be at least the
@@ -0,0 +1,262 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{
+    block::{AggregateBody, Block},
+    fee::{Fee, BASE_COST},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// The number of recent blocks the fee estimate is based on
+pub const DEFAULT_BLOCK_WINDOW: usize = 30;
+/// The weight, in grams, of the transactions that fit in a block
+pub const DEFAULT_MAX_BLOCK_WEIGHT: u64 = 10_000;
+/// The fee-per-gram recommended when no recent blocks have been seen
+pub const DEFAULT_FEE_PER_GRAM: u64 = 25;
+/// The fee-per-gram is never raised by more than this factor when the mempool is congested
+const MAX_CONGESTION_FACTOR: u64 = 4;
+
+/// How quickly a transaction should be mined
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfirmationTarget {
+    /// Within about a day
+    Slow,
+    /// Within a few blocks
+    Normal,
+    /// In the next block
+    Fast,
+}
+
+impl ConfirmationTarget {
+    /// The number of blocks within which the transaction should be mined
+    pub fn blocks(&self) -> u64 {
+        match self {
+            ConfirmationTarget::Slow => 720,
+            ConfirmationTarget::Normal => 6,
+            ConfirmationTarget::Fast => 1,
+        }
+    }
+
+    /// The percentile of recent block fee rates that a transaction should pay to meet the target
+    fn percentile(&self) -> usize {
+        match self {
+            ConfirmationTarget::Slow => 25,
+            ConfirmationTarget::Normal => 50,
+            ConfirmationTarget::Fast => 90,
+        }
+    }
+}
+
+/// The recommended fee-per-gram for each confirmation target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeEstimate {
+    pub slow: u64,
+    pub normal: u64,
+    pub fast: u64,
+}
+
+impl FeeEstimate {
+    /// The recommended fee-per-gram for the confirmation target
+    pub fn fee_per_gram(&self, target: ConfirmationTarget) -> u64 {
+        match target {
+            ConfirmationTarget::Slow => self.slow,
+            ConfirmationTarget::Normal => self.normal,
+            ConfirmationTarget::Fast => self.fast,
+        }
+    }
+}
+
+/// The transactions waiting in the mempool to be mined
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolStats {
+    pub transaction_count: usize,
+    /// The total weight, in grams, of the waiting transactions
+    pub total_weight: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct BlockFeeRate {
+    height: u64,
+    fee_per_gram: u64,
+}
+
+/// The FeeEstimator recommends a fee-per-gram for a confirmation target from the fees paid in recent blocks and the
+/// depth of the mempool. The kernel fees of a block, less the base cost of each kernel, are divided by the weight of
+/// the block's inputs and outputs to give the fee rate of the block. Each target pays a percentile of the recent rates,
+/// and the rate is raised when the mempool holds more transactions than can be mined within the target.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeeEstimator {
+    block_window: usize,
+    max_block_weight: u64,
+    fee_rates: VecDeque<BlockFeeRate>,
+    mempool: MempoolStats,
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        FeeEstimator::new(DEFAULT_BLOCK_WINDOW, DEFAULT_MAX_BLOCK_WEIGHT)
+    }
+}
+
+impl FeeEstimator {
+    /// Create a FeeEstimator that is based on the last `block_window` blocks, each fitting `max_block_weight` grams
+    pub fn new(block_window: usize, max_block_weight: u64) -> FeeEstimator {
+        FeeEstimator {
+            block_window: block_window.max(1),
+            max_block_weight: max_block_weight.max(1),
+            fee_rates: VecDeque::new(),
+            mempool: MempoolStats::default(),
+        }
+    }
+
+    /// Add the fees paid in a block to the estimate. A block at or below the height of blocks that were already added
+    /// replaces them, as happens when the chain is reorganised. Blocks without transactions are ignored.
+    pub fn add_block(&mut self, block: &Block) {
+        let (fee, weight) = block_fee_and_weight(&block.body);
+        self.add_block_fees(block.header.height, fee, weight);
+    }
+
+    fn add_block_fees(&mut self, height: u64, fee: u64, weight: u64) {
+        while self.fee_rates.back().map_or(false, |r| r.height >= height) {
+            self.fee_rates.pop_back();
+        }
+        if weight == 0 {
+            return;
+        }
+        self.fee_rates.push_back(BlockFeeRate {
+            height,
+            fee_per_gram: fee / weight,
+        });
+        while self.fee_rates.len() > self.block_window {
+            self.fee_rates.pop_front();
+        }
+    }
+
+    /// Update the state of the mempool
+    pub fn update_mempool(&mut self, mempool: MempoolStats) {
+        self.mempool = mempool;
+    }
+
+    /// The number of blocks it would take to mine every transaction in the mempool
+    pub fn mempool_depth(&self) -> u64 {
+        (self.mempool.total_weight + self.max_block_weight - 1) / self.max_block_weight
+    }
+
+    /// The recommended fee-per-gram for every confirmation target
+    pub fn estimate(&self) -> FeeEstimate {
+        let slow = self.fee_per_gram(ConfirmationTarget::Slow);
+        let normal = self.fee_per_gram(ConfirmationTarget::Normal).max(slow);
+        let fast = self.fee_per_gram(ConfirmationTarget::Fast).max(normal);
+        FeeEstimate { slow, normal, fast }
+    }
+
+    /// The recommended fee-per-gram for the confirmation target. The result is always at least 1.
+    pub fn fee_per_gram(&self, target: ConfirmationTarget) -> u64 {
+        let base_rate = if self.fee_rates.is_empty() {
+            DEFAULT_FEE_PER_GRAM
+        } else {
+            let mut rates: Vec<u64> = self.fee_rates.iter().map(|r| r.fee_per_gram).collect();
+            rates.sort();
+            rates[(rates.len() - 1) * target.percentile() / 100]
+        };
+        let target_blocks = target.blocks();
+        let depth = self.mempool_depth();
+        let rate = if depth > target_blocks {
+            base_rate * depth.min(MAX_CONGESTION_FACTOR * target_blocks) / target_blocks
+        } else {
+            base_rate
+        };
+        rate.max(1)
+    }
+}
+
+/// The fees paid for the weight of a block body, less the base cost of each kernel
+fn block_fee_and_weight(body: &AggregateBody) -> (u64, u64) {
+    let fee: u64 = body.kernels.iter().map(|k| k.fee).sum();
+    let base_cost = BASE_COST * body.kernels.len() as u64;
+    let weight = if body.kernels.is_empty() {
+        0
+    } else {
+        Fee::weight(body.inputs.len(), body.outputs.len())
+    };
+    (fee.saturating_sub(base_cost), weight)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn estimate_without_blocks() {
+        let estimator = FeeEstimator::default();
+        let estimate = estimator.estimate();
+        assert_eq!(estimate.slow, DEFAULT_FEE_PER_GRAM);
+        assert_eq!(estimate.normal, DEFAULT_FEE_PER_GRAM);
+        assert_eq!(estimate.fast, DEFAULT_FEE_PER_GRAM);
+    }
+
+    #[test]
+    fn estimate_from_recent_blocks() {
+        let mut estimator = FeeEstimator::new(10, 1000);
+        // Rates 1..=20, of which only the last 10 are kept
+        for height in 0..20 {
+            estimator.add_block_fees(height, (height + 1) * 100, 100);
+        }
+        // An empty block doesn't change the estimate
+        estimator.add_block_fees(20, 0, 0);
+        let estimate = estimator.estimate();
+        assert_eq!(estimate, FeeEstimate {
+            slow: 13,
+            normal: 15,
+            fast: 19
+        });
+        assert_eq!(estimate.fee_per_gram(ConfirmationTarget::Normal), 15);
+
+        // A reorg replaces the blocks above the fork point
+        estimator.add_block_fees(15, 100, 100);
+        assert_eq!(estimator.fee_rates.len(), 6);
+        assert_eq!(estimator.fee_rates.back().unwrap().fee_per_gram, 1);
+    }
+
+    #[test]
+    fn congestion_raises_the_estimate() {
+        let mut estimator = FeeEstimator::new(10, 1000);
+        estimator.add_block_fees(0, 1000, 100);
+        estimator.update_mempool(MempoolStats {
+            transaction_count: 500,
+            total_weight: 2500,
+        });
+        assert_eq!(estimator.mempool_depth(), 3);
+        let estimate = estimator.estimate();
+        assert_eq!(estimate.slow, 10);
+        assert_eq!(estimate.normal, 10);
+        assert_eq!(estimate.fast, 30);
+
+        estimator.update_mempool(MempoolStats {
+            transaction_count: 5000,
+            total_weight: 100_000,
+        });
+        assert_eq!(estimator.estimate().normal, 40);
+        assert_eq!(estimator.estimate().fast, 40);
+    }
+}
@@ -26,6 +26,7 @@ extern crate bitflags;
 pub mod block;
 pub mod blockheader;
 pub mod fee;
+pub mod fee_estimator;
 pub mod pow;
 #[allow(clippy::op_ref)]
 pub mod transaction;
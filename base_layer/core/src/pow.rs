@@ -22,9 +22,68 @@
 
 use crate::types::*;
 use digest::Input;
+use serde::{Deserialize, Serialize};
 use tari_infra_derive::Hashable;
 use tari_utilities::{ExtendBytes, Hashable};
 
-#[derive(Clone, Debug, PartialEq, Hashable)]
+/// The difficulty of a block, expressed as the expected number of header hashes required to find a valid nonce.
+pub type Difficulty = u64;
+
+/// The proof of work summary for a block. The nonce that satisfies the proof of work is kept in the block header, so
+/// that it is included in the header hash.
+#[derive(Clone, Debug, PartialEq, Hashable, Serialize, Deserialize)]
 #[digest = "SignatureHash"]
-pub struct ProofOfWork {}
+pub struct ProofOfWork {
+    /// The difficulty that the block header hash must meet
+    pub target_difficulty: Difficulty,
+}
+
+impl ProofOfWork {
+    /// Create a new proof of work summary for the given target difficulty
+    pub fn new(target_difficulty: Difficulty) -> ProofOfWork {
+        ProofOfWork { target_difficulty }
+    }
+
+    /// Calculates the difficulty achieved by a block header hash. The first 8 bytes of the hash are interpreted as a
+    /// big-endian integer, _h_, and the difficulty is given by $$ \frac{2^{64} - 1}{h} $$.
+    pub fn achieved_difficulty(hash: &[u8]) -> Difficulty {
+        let mut bytes = [0u8; 8];
+        let n = hash.len().min(8);
+        bytes[..n].copy_from_slice(&hash[..n]);
+        match u64::from_be_bytes(bytes) {
+            0 => Difficulty::max_value(),
+            h => Difficulty::max_value() / h,
+        }
+    }
+
+    /// Returns true if the given block header hash meets the target difficulty
+    pub fn is_met_by(&self, hash: &[u8]) -> bool {
+        ProofOfWork::achieved_difficulty(hash) >= self.target_difficulty
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pow::ProofOfWork;
+
+    #[test]
+    fn achieved_difficulty() {
+        assert_eq!(ProofOfWork::achieved_difficulty(&[0u8; 32]), u64::max_value());
+        assert_eq!(ProofOfWork::achieved_difficulty(&[0xffu8; 32]), 1);
+        let mut hash = [0u8; 32];
+        hash[0] = 0x01;
+        assert_eq!(ProofOfWork::achieved_difficulty(&hash), 0xff);
+    }
+
+    #[test]
+    fn target_difficulty() {
+        let pow = ProofOfWork::new(256);
+        let mut hash = [0u8; 32];
+        hash[0] = 0x01;
+        assert!(!pow.is_met_by(&hash));
+        hash[0] = 0x00;
+        hash[1] = 0xff;
+        assert!(pow.is_met_by(&hash));
+        assert!(ProofOfWork::new(1).is_met_by(&[0xffu8; 32]));
+    }
+}
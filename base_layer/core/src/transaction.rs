@@ -29,7 +29,7 @@ use crate::{
 };
 
 use crate::{
-    transaction_protocol::{build_challenge, TransactionMetadata},
+    transaction_protocol::{build_challenge, build_multisig_challenge, TransactionMetadata},
     types::{HashDigest, PublicKey, RangeProof, RangeProofService, SecretKey},
 };
 use derive_error::Error;
@@ -48,6 +48,8 @@ pub const MAX_TRANSACTION_INPUTS: usize = 500;
 pub const MAX_TRANSACTION_OUTPUTS: usize = 100;
 pub const MAX_TRANSACTION_RECIPIENTS: usize = 15;
 pub const MINIMUM_TRANSACTION_FEE: u64 = 100;
+/// The domain of the hash of an output's spending key that the output's value is encrypted with
+const VALUE_MASK_DOMAIN: &[u8] = b"transaction_output.value_mask";
 
 #[cfg(test)]
 pub const MAX_RANGE_PROOF_RANGE: usize = 1 << 5; // 2^32 This is the only way to produce failing range proofs for the tests
@@ -59,9 +61,12 @@ pub const MAX_RANGE_PROOF_RANGE: usize = 1 << 6; // 2^64
 bitflags! {
     /// Options for a kernel's structure or use.
     /// TODO:  expand to accommodate Tari DAN transaction types, such as namespace and validator node registrations
+    #[derive(Deserialize, Serialize)]
     pub struct KernelFeatures: u8 {
         /// Coinbase transaction
         const COINBASE_KERNEL = 1u8;
+        /// The excess signature is an aggregated MuSig signature, whose challenge also commits to the excess
+        const MULTISIG_KERNEL = 2u8;
     }
 }
 
@@ -92,7 +97,7 @@ pub enum TransactionError {
 
 /// An unblinded output is one where the value and spending key (blinding factor) are known. This can be used to
 /// build both inputs and outputs (every input comes from an output)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UnblindedOutput {
     pub value: u64,
     pub spending_key: BlindingFactor,
@@ -108,6 +113,24 @@ impl UnblindedOutput {
             features: features.unwrap_or_else(OutputFeatures::empty),
         }
     }
+
+    /// The value encrypted with a key derived from the spending key, as it is stored in the output. Only the owner of
+    /// the spending key can recover it, see `TransactionOutput::recover_value`.
+    pub fn encrypted_value(&self) -> u64 {
+        self.value ^ value_mask(&self.spending_key)
+    }
+}
+
+/// The mask that the value of an output is encrypted with. Every output has its own spending key, so no two outputs
+/// share a mask.
+fn value_mask(spending_key: &BlindingFactor) -> u64 {
+    let hash = HashDigest::new()
+        .chain(VALUE_MASK_DOMAIN)
+        .chain(spending_key.as_bytes())
+        .result();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    u64::from_le_bytes(bytes)
 }
 
 /// Converts an UnblindedOutput into a Transaction input with default output features.
@@ -133,6 +156,7 @@ impl<'a> TryFrom<&'a UnblindedOutput> for TransactionOutput {
             features: v.features,
             commitment: c,
             proof: prover.construct_proof(&v.spending_key, v.value)?,
+            encrypted_value: v.encrypted_value(),
         };
 
         // A range proof can be constructed for an invalid value so we should confirm that the proof can be verified.
@@ -151,7 +175,7 @@ impl<'a> TryFrom<&'a UnblindedOutput> for TransactionOutput {
 /// A transaction input.
 ///
 /// Primarily a reference to an output being spent by the transaction.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct TransactionInput {
     /// The features of the output being spent. We will check maturity for coinbase output.
     pub features: OutputFeatures,
@@ -193,7 +217,7 @@ impl Hashable for TransactionInput {
 /// Output for a transaction, defining the new ownership of coins that are being transferred. The commitment is a
 /// blinded value for the output while the range proof guarantees the commitment includes a positive value without
 /// overflow and the ownership of the private key.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct TransactionOutput {
     /// Options for an output's structure or use
     pub features: OutputFeatures,
@@ -201,16 +225,25 @@ pub struct TransactionOutput {
     pub commitment: Commitment,
     /// A proof that the commitment is in the right range
     pub proof: RangeProof,
+    /// The value of the output, encrypted by its owner so that it can be recovered with the spending key
+    pub encrypted_value: u64,
 }
 
 /// An output for a transaction, includes a range proof
 impl TransactionOutput {
     /// Create new Transaction Output
-    pub fn new(features: OutputFeatures, commitment: Commitment, proof: RangeProof) -> TransactionOutput {
+    pub fn new(
+        features: OutputFeatures,
+        commitment: Commitment,
+        proof: RangeProof,
+        encrypted_value: u64,
+    ) -> TransactionOutput
+    {
         TransactionOutput {
             features,
             commitment,
             proof,
+            encrypted_value,
         }
     }
 
@@ -240,6 +273,17 @@ impl TransactionOutput {
         };
         Ok(prover.verify(&self.proof, &self.commitment))
     }
+
+    /// Decrypt the value of the output with `spending_key`. The value is only returned if the commitment opens to it
+    /// with the key, i.e. if the output can be spent with `spending_key`.
+    pub fn recover_value(&self, spending_key: &BlindingFactor) -> Option<u64> {
+        let value = self.encrypted_value ^ value_mask(spending_key);
+        if self.commitment.open(spending_key, &value.into()) {
+            Some(value)
+        } else {
+            None
+        }
+    }
 }
 
 /// Implement the canonical hashing function for TransactionOutput for use in ordering
@@ -249,6 +293,7 @@ impl Hashable for TransactionOutput {
             .chain(vec![self.features.bits])
             .chain(self.commitment.as_bytes())
             .chain(self.proof.as_bytes())
+            .chain(self.encrypted_value.to_le_bytes())
             .result()
             .to_vec()
     }
@@ -260,6 +305,7 @@ impl Default for TransactionOutput {
             OutputFeatures::empty(),
             CommitmentFactory::zero(),
             RangeProof::default(),
+            0,
         )
     }
 }
@@ -271,7 +317,7 @@ impl Default for TransactionOutput {
 /// [Mimblewimble TLU post](https://tlu.tarilabs.com/protocols/mimblewimble-1/sources/PITCHME.link.html?highlight=mimblewimble#mimblewimble).
 /// The kernel also tracks other transaction metadata, such as the lock height for the transaction (i.e. the earliest
 /// this transaction can be mined) and the transaction fee, in cleartext.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct TransactionKernel {
     /// Options for a kernel's structure or use
     pub features: KernelFeatures,
@@ -369,7 +415,11 @@ impl TransactionKernel {
             lock_height: self.lock_height,
             fee: self.fee,
         };
-        let c = build_challenge(r, &m);
+        let c = if self.features.contains(KernelFeatures::MULTISIG_KERNEL) {
+            build_multisig_challenge(r, excess, &m)
+        } else {
+            build_challenge(r, &m)
+        };
         if self.excess_sig.verify_challenge(excess, &c) {
             Ok(())
         } else {
@@ -400,7 +450,7 @@ impl Hashable for TransactionKernel {
 /// This struct is used to describe single transactions only. The common part between transactions and Tari blocks is
 /// accessible via the `body` field, but single transactions also need to carry the public offset around with them so
 /// that these can be aggregated into block offsets.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Transaction {
     /// This kernel offset will be accumulated when transactions are aggregated to prevent the "subset" problem where
     /// kernels can be linked to inputs and outputs by testing a series of subsets and see which produce valid
@@ -593,6 +643,17 @@ mod test {
         assert!(input.opened_by(&i));
     }
 
+    #[test]
+    fn recover_output_value() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let k = BlindingFactor::random(&mut rng);
+        let unblinded = UnblindedOutput::new(2u64.pow(32) - 1, k.clone(), None);
+        let output = TransactionOutput::try_from(&unblinded).unwrap();
+        assert_ne!(output.encrypted_value, unblinded.value);
+        assert_eq!(output.recover_value(&k), Some(unblinded.value));
+        assert_eq!(output.recover_value(&BlindingFactor::random(&mut rng)), None);
+    }
+
     #[test]
     fn range_proof_verification() {
         let mut rng = rand::OsRng::new().unwrap();
@@ -621,7 +682,7 @@ mod test {
         let prover = RangeProofService::new(MAX_RANGE_PROOF_RANGE, CommitmentFactory::default()).unwrap();
         let proof = prover.construct_proof(&k2, 2u64.pow(32) + 1).unwrap();
 
-        let tx_output3 = TransactionOutput::new(OutputFeatures::empty(), c, proof);
+        let tx_output3 = TransactionOutput::new(OutputFeatures::empty(), c, proof, 0);
 
         assert_eq!(tx_output3.verify_range_proof(Some(&prover)).unwrap(), false);
     }
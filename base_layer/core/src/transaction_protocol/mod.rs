@@ -49,9 +49,10 @@
 //!   end
 //! </div>
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-helpers"))]
 pub mod test_common;
 
+pub mod multisig;
 pub mod recipient;
 pub mod sender;
 pub mod single_receiver;
@@ -64,7 +65,7 @@ use crate::{
 use derive_error::Error;
 use digest::Digest;
 use serde::{Deserialize, Serialize};
-use tari_crypto::{range_proof::RangeProofError, signatures::SchnorrSignatureError};
+use tari_crypto::{musig::MuSigError, range_proof::RangeProofError, signatures::SchnorrSignatureError};
 use tari_utilities::byte_array::ByteArray;
 
 #[derive(Clone, Debug, PartialEq, Error)]
@@ -91,6 +92,8 @@ pub enum TransactionProtocolError {
     // This set of parameters is currently not supported
     #[error(msg_embedded, no_from, non_std)]
     UnsupportedError(String),
+    // The MuSig signing session failed
+    MuSigError(MuSigError),
 }
 
 /// Transaction metadata, including the fee and lock height
@@ -111,3 +114,27 @@ pub fn build_challenge(sum_public_nonces: &PublicKey, metadata: &TransactionMeta
         .result()
         .to_vec()
 }
+
+/// The message that the parties of a multi-signature kernel sign: the fee and lock height of the transaction
+pub fn multisig_message(metadata: &TransactionMetadata) -> Vec<u8> {
+    let mut message = metadata.fee.to_le_bytes().to_vec();
+    message.extend_from_slice(&metadata.lock_height.to_le_bytes());
+    message
+}
+
+/// Calculates the challenge for kernels with the `MULTISIG_KERNEL` feature. It matches the challenge that MuSig uses,
+/// $$ H(R_{agg} || P_{agg} || H(m)) $$, where the excess is the aggregated public key and the message is given by
+/// `multisig_message`.
+pub fn build_multisig_challenge(
+    sum_public_nonces: &PublicKey,
+    excess: &PublicKey,
+    metadata: &TransactionMetadata,
+) -> MessageHash
+{
+    Challenge::new()
+        .chain(sum_public_nonces.as_bytes())
+        .chain(excess.as_bytes())
+        .chain(Challenge::digest(&multisig_message(metadata)))
+        .result()
+        .to_vec()
+}
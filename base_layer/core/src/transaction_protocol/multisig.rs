@@ -0,0 +1,607 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+//! Transactions that are signed by _n_ parties together, with an aggregated
+//! [MuSig](https://blockstream.com/2018/01/23/musig-key-aggregation-schnorr-signatures/) kernel signature.
+//!
+//! Each party contributes its own inputs and outputs, and no party learns the blinding factors of the others. An
+//! output whose commitment is the sum of the parties' keys, \\( C = \sum k_i.G + v.H \\), can't be created this
+//! way: its range proof needs the full blinding factor, and there is no multi-party range proof yet, so whoever built
+//! the proof could spend the output alone. Joint outputs are left until such a proof is available.
+//!
+//! To sign a transaction every party runs a `MultiSigSession`, which drives the three MuSig rounds. Every party
+//! chooses a fresh session key \\( x_i \\), and the kernel excess is the MuSig joint key \\( X = \sum a_i.x_i.G \\).
+//! Party _i_ contributes the blinding factor \\( b_i \\) to the transaction (the blinding factors of the outputs it
+//! creates minus those of the inputs it spends) and reveals the offset share \\( o_i = b_i - a_i.x_i \\) with its
+//! partial signature, so that \\( X + (\sum o_i).G = \sum b_i.G \\) and the transaction balances.
+//!
+//! Every `MultiSigMessage` is serializable, so the rounds can be carried over any transport. Each party broadcasts
+//! its own messages and passes every message it receives (including its own) to `handle_message`. Messages that
+//! arrive early are kept until the session reaches the round they belong to.
+
+use crate::{
+    transaction::{
+        KernelBuilder,
+        KernelFeatures,
+        Transaction,
+        TransactionBuilder,
+        TransactionInput,
+        TransactionKernel,
+        TransactionOutput,
+    },
+    transaction_protocol::{multisig_message, TransactionMetadata, TransactionProtocolError as TPE},
+    types::{BlindingFactor, Challenge, Commitment, CommitmentFactory, MessageHash, PublicKey, SecretKey, Signature},
+};
+use digest::Digest;
+use rand::{CryptoRng, Rng};
+use serde::{Deserialize, Serialize};
+use std::mem;
+use tari_crypto::{commitment::HomomorphicCommitmentFactory, keys::PublicKey as PK, ristretto::musig::RistrettoMuSig};
+use tari_utilities::ByteArray;
+
+//----------------------------------------      Signing session      -------------------------------------------------//
+
+/// The messages that the parties of a `MultiSigSession` broadcast to each other, one per round
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MultiSigMessage {
+    /// Round one: the party's session public key, the hash of its public nonce and the public part of the blinding
+    /// factor it contributes to the transaction, \\( b_i.G \\)
+    NonceCommitment {
+        public_key: PublicKey,
+        nonce_commitment: MessageHash,
+        public_excess: PublicKey,
+    },
+    /// Round two: the party's public nonce, which is only revealed once all the nonce commitments are known
+    PublicNonce {
+        public_key: PublicKey,
+        public_nonce: PublicKey,
+    },
+    /// Round three: the party's partial signature and its share of the transaction offset
+    PartialSignature {
+        public_key: PublicKey,
+        signature: Signature,
+        offset: BlindingFactor,
+    },
+}
+
+impl MultiSigMessage {
+    /// The session public key of the party that sent the message
+    pub fn public_key(&self) -> &PublicKey {
+        match self {
+            MultiSigMessage::NonceCommitment { public_key, .. } => public_key,
+            MultiSigMessage::PublicNonce { public_key, .. } => public_key,
+            MultiSigMessage::PartialSignature { public_key, .. } => public_key,
+        }
+    }
+}
+
+/// What a session knows about one of its parties
+struct Participant {
+    public_key: PublicKey,
+    nonce_commitment: MessageHash,
+    public_excess: PublicKey,
+    public_nonce: Option<PublicKey>,
+    offset: Option<BlindingFactor>,
+}
+
+/// One party's view of the MuSig ceremony that signs the kernel of a jointly built transaction. The session
+/// takes care of its own messages: they are added as soon as the session reaches the round they belong to, and only
+/// need to be broadcast to the other parties.
+pub struct MultiSigSession {
+    participant_count: usize,
+    metadata: TransactionMetadata,
+    musig: RistrettoMuSig<Challenge>,
+    session_key: SecretKey,
+    public_key: PublicKey,
+    nonce: SecretKey,
+    public_nonce: PublicKey,
+    excess_share: BlindingFactor,
+    participants: Vec<Participant>,
+    pending: Vec<MultiSigMessage>,
+    partial_signature: Option<MultiSigMessage>,
+}
+
+impl MultiSigSession {
+    /// Start a signing session for `participant_count` parties. `excess_share` is the blinding factor this party
+    /// contributes to the transaction, i.e. the spending keys of the outputs it creates minus those of the inputs it
+    /// spends. Every party must use the same metadata.
+    pub fn new<R: Rng + CryptoRng>(
+        rng: &mut R,
+        participant_count: usize,
+        excess_share: BlindingFactor,
+        metadata: TransactionMetadata,
+    ) -> Result<MultiSigSession, TPE>
+    {
+        let (session_key, public_key) = PublicKey::random_keypair(rng);
+        let (nonce, public_nonce) = PublicKey::random_keypair(rng);
+        let musig = RistrettoMuSig::<Challenge>::new(participant_count).set_message(&multisig_message(&metadata));
+        if let Some(e) = musig.failure_reason() {
+            return Err(TPE::MuSigError(e));
+        }
+        let mut session = MultiSigSession {
+            participant_count,
+            metadata,
+            musig,
+            session_key,
+            public_key,
+            nonce,
+            public_nonce,
+            excess_share,
+            participants: Vec::with_capacity(participant_count),
+            pending: Vec::new(),
+            partial_signature: None,
+        };
+        session.handle_message(session.nonce_commitment_message())?;
+        Ok(session)
+    }
+
+    /// The number of parties that sign the kernel
+    pub fn participant_count(&self) -> usize {
+        self.participant_count
+    }
+
+    /// The fee and lock height of the transaction
+    pub fn metadata(&self) -> &TransactionMetadata {
+        &self.metadata
+    }
+
+    /// The session public key that identifies this party in the ceremony
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// The round one message of this party
+    pub fn nonce_commitment_message(&self) -> MultiSigMessage {
+        MultiSigMessage::NonceCommitment {
+            public_key: self.public_key.clone(),
+            nonce_commitment: Challenge::digest(self.public_nonce.as_bytes()).to_vec(),
+            public_excess: PublicKey::from_secret_key(&self.excess_share),
+        }
+    }
+
+    /// The round two message of this party. It is only available once the nonce commitments of all the parties have
+    /// been received.
+    pub fn public_nonce_message(&self) -> Result<MultiSigMessage, TPE> {
+        if self.participants.len() < self.participant_count {
+            return Err(TPE::IncompleteStateError(
+                "The nonce commitments of all the parties have not been received".into(),
+            ));
+        }
+        Ok(MultiSigMessage::PublicNonce {
+            public_key: self.public_key.clone(),
+            public_nonce: self.public_nonce.clone(),
+        })
+    }
+
+    /// The round three message of this party. It is only available once the public nonces of all the parties have
+    /// been received.
+    pub fn partial_signature_message(&self) -> Result<MultiSigMessage, TPE> {
+        self.partial_signature.clone().ok_or_else(|| {
+            TPE::IncompleteStateError("The public nonces of all the parties have not been received".into())
+        })
+    }
+
+    /// Apply a message from one of the parties. Messages for a later round are kept until the session reaches that
+    /// round, and repeated messages are ignored. A message that contradicts an earlier one, or that fails
+    /// validation, fails the session.
+    pub fn handle_message(&mut self, message: MultiSigMessage) -> Result<(), TPE> {
+        self.pending.push(message);
+        while let Some(i) = self.pending.iter().position(|m| self.is_ready_for(m)) {
+            let message = self.pending.remove(i);
+            self.apply_message(message)?;
+        }
+        Ok(())
+    }
+
+    /// True once all the partial signatures have been received and validated
+    pub fn is_finalized(&self) -> bool {
+        self.musig.is_finalized()
+    }
+
+    /// The kernel excess, i.e. the MuSig joint key of the session keys. It is known once all the nonce commitments
+    /// have been received.
+    pub fn excess(&self) -> Option<Commitment> {
+        self.musig
+            .get_aggregated_public_key()
+            .map(CommitmentFactory::from_public_key)
+    }
+
+    /// The aggregated kernel signature, once the session is finalized
+    pub fn signature(&self) -> Option<&Signature> {
+        self.musig.get_aggregated_signature()
+    }
+
+    /// The transaction offset, i.e. the sum of the offset shares of all the parties
+    pub fn offset(&self) -> Result<BlindingFactor, TPE> {
+        if !self.is_finalized() {
+            return Err(TPE::IncompleteStateError("The signing session is not finalized".into()));
+        }
+        Ok(self
+            .participants
+            .iter()
+            .filter_map(|p| p.offset.as_ref())
+            .fold(SecretKey::default(), |acc, offset| &acc + offset))
+    }
+
+    /// Build the kernel that carries the aggregated signature
+    pub fn build_kernel(&self) -> Result<TransactionKernel, TPE> {
+        let (excess, signature) = match (self.excess(), self.signature()) {
+            (Some(excess), Some(signature)) => (excess, signature),
+            _ => return Err(TPE::IncompleteStateError("The signing session is not finalized".into())),
+        };
+        Ok(KernelBuilder::new()
+            .with_features(KernelFeatures::MULTISIG_KERNEL)
+            .with_fee(self.metadata.fee)
+            .with_lock_height(self.metadata.lock_height)
+            .with_excess(&excess)
+            .with_signature(signature)
+            .build()?)
+    }
+
+    /// Build and validate the transaction that spends `inputs` into `outputs` with the aggregated signature
+    pub fn build_transaction(
+        &self,
+        mut inputs: Vec<TransactionInput>,
+        mut outputs: Vec<TransactionOutput>,
+    ) -> Result<Transaction, TPE>
+    {
+        let mut builder = TransactionBuilder::new();
+        builder
+            .add_inputs(&mut inputs)
+            .add_outputs(&mut outputs)
+            .add_offset(self.offset()?)
+            .with_kernel(self.build_kernel()?);
+        let mut tx = builder.build()?;
+        tx.validate_internal_consistency(None)?;
+        Ok(tx)
+    }
+
+    fn is_ready_for(&self, message: &MultiSigMessage) -> bool {
+        match message {
+            MultiSigMessage::NonceCommitment { .. } => true,
+            MultiSigMessage::PublicNonce { .. } => self.musig.is_collecting_nonces(),
+            MultiSigMessage::PartialSignature { .. } => self.musig.is_collecting_signatures(),
+        }
+    }
+
+    fn apply_message(&mut self, message: MultiSigMessage) -> Result<(), TPE> {
+        match message {
+            MultiSigMessage::NonceCommitment {
+                public_key,
+                nonce_commitment,
+                public_excess,
+            } => self.add_nonce_commitment(public_key, nonce_commitment, public_excess),
+            MultiSigMessage::PublicNonce {
+                public_key,
+                public_nonce,
+            } => self.add_public_nonce(public_key, public_nonce),
+            MultiSigMessage::PartialSignature {
+                public_key,
+                signature,
+                offset,
+            } => self.add_partial_signature(public_key, signature, offset),
+        }
+    }
+
+    fn add_nonce_commitment(
+        &mut self,
+        public_key: PublicKey,
+        nonce_commitment: MessageHash,
+        public_excess: PublicKey,
+    ) -> Result<(), TPE>
+    {
+        if let Some(p) = self.participant(&public_key) {
+            if p.nonce_commitment == nonce_commitment && p.public_excess == public_excess {
+                return Ok(());
+            }
+            return Err(TPE::ValidationError(
+                "Conflicting nonce commitments from a party".into(),
+            ));
+        }
+        if self.participants.len() == self.participant_count {
+            return Err(TPE::ValidationError(
+                "Received a nonce commitment from too many parties".into(),
+            ));
+        }
+        self.participants.push(Participant {
+            public_key,
+            nonce_commitment,
+            public_excess,
+            public_nonce: None,
+            offset: None,
+        });
+        if self.participants.len() < self.participant_count {
+            return Ok(());
+        }
+        // All the keys are known, so the joint key can be built and round one completed
+        let keys: Vec<PublicKey> = self.participants.iter().map(|p| p.public_key.clone()).collect();
+        for key in &keys {
+            self.update_musig(|musig| musig.add_public_key(key))?;
+        }
+        let commitments: Vec<MessageHash> = self.participants.iter().map(|p| p.nonce_commitment.clone()).collect();
+        for (key, commitment) in keys.iter().zip(commitments) {
+            self.update_musig(|musig| musig.add_nonce_commitment(key, commitment))?;
+        }
+        let own_nonce = self.public_nonce_message()?;
+        self.pending.push(own_nonce);
+        Ok(())
+    }
+
+    fn add_public_nonce(&mut self, public_key: PublicKey, public_nonce: PublicKey) -> Result<(), TPE> {
+        let participant = self.participant_mut(&public_key)?;
+        if let Some(nonce) = &participant.public_nonce {
+            if nonce == &public_nonce {
+                return Ok(());
+            }
+            return Err(TPE::ValidationError("Conflicting public nonces from a party".into()));
+        }
+        participant.public_nonce = Some(public_nonce.clone());
+        self.update_musig(|musig| musig.add_nonce(&public_key, public_nonce))?;
+        if self.musig.is_collecting_signatures() {
+            self.sign()?;
+        }
+        Ok(())
+    }
+
+    fn add_partial_signature(
+        &mut self,
+        public_key: PublicKey,
+        signature: Signature,
+        offset: BlindingFactor,
+    ) -> Result<(), TPE>
+    {
+        let a = self
+            .musig
+            .get_musig_scalar(&public_key)
+            .ok_or(TPE::InvalidStateError)?
+            .clone();
+        let participant = self.participant_mut(&public_key)?;
+        if let Some(existing) = &participant.offset {
+            if existing == &offset {
+                return Ok(());
+            }
+            return Err(TPE::ValidationError("Conflicting offsets from a party".into()));
+        }
+        if participant.public_nonce.as_ref() != Some(signature.get_public_nonce()) {
+            return Err(TPE::ValidationError(
+                "A partial signature does not use the public nonce of its party".into(),
+            ));
+        }
+        // o_i.G + a_i.X_i must equal the blinding factor the party committed to in round one
+        if &PublicKey::from_secret_key(&offset) + &(&a * &public_key) != participant.public_excess {
+            return Err(TPE::ValidationError(
+                "An offset share does not match the excess its party committed to".into(),
+            ));
+        }
+        self.update_musig(|musig| musig.add_signature(&signature, true))?;
+        self.participant_mut(&public_key)?.offset = Some(offset);
+        Ok(())
+    }
+
+    /// Calculate this party's partial signature and offset share, and queue them to be added to the session
+    fn sign(&mut self) -> Result<(), TPE> {
+        let signature = self
+            .musig
+            .calculate_partial_signature(&self.public_key, &self.session_key, &self.nonce)
+            .ok_or(TPE::InvalidStateError)?;
+        let a = self
+            .musig
+            .get_musig_scalar(&self.public_key)
+            .ok_or(TPE::InvalidStateError)?;
+        let offset = &self.excess_share - &(a * &self.session_key);
+        let message = MultiSigMessage::PartialSignature {
+            public_key: self.public_key.clone(),
+            signature,
+            offset,
+        };
+        self.partial_signature = Some(message.clone());
+        self.pending.push(message);
+        Ok(())
+    }
+
+    /// Pass the MuSig state machine through `f`, failing if MuSig has failed
+    fn update_musig<F>(&mut self, f: F) -> Result<(), TPE>
+    where F: FnOnce(RistrettoMuSig<Challenge>) -> RistrettoMuSig<Challenge> {
+        let musig = mem::replace(&mut self.musig, RistrettoMuSig::new(0));
+        self.musig = f(musig);
+        match self.musig.failure_reason() {
+            Some(e) => Err(TPE::MuSigError(e)),
+            None => Ok(()),
+        }
+    }
+
+    fn participant(&self, public_key: &PublicKey) -> Option<&Participant> {
+        self.participants.iter().find(|p| &p.public_key == public_key)
+    }
+
+    fn participant_mut(&mut self, public_key: &PublicKey) -> Result<&mut Participant, TPE> {
+        self.participants
+            .iter_mut()
+            .find(|p| &p.public_key == public_key)
+            .ok_or_else(|| TPE::ValidationError("Received a message from an unknown party".into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        transaction::{TransactionInput, TransactionOutput, UnblindedOutput},
+        transaction_protocol::{
+            multisig::{MultiSigMessage, MultiSigSession},
+            TransactionMetadata,
+            TransactionProtocolError,
+        },
+        types::{SecretKey, Signature},
+    };
+    use rand::OsRng;
+    use std::convert::TryFrom;
+    use tari_crypto::{keys::SecretKey as SK, musig::MuSigError};
+
+    /// Deliver every message to every session, in the order given
+    fn broadcast(sessions: &mut [MultiSigSession], messages: Vec<MultiSigMessage>) {
+        for message in messages {
+            for session in sessions.iter_mut() {
+                session.handle_message(message.clone()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn joint_transaction() {
+        let mut rng = OsRng::new().unwrap();
+        let metadata = TransactionMetadata {
+            fee: 200,
+            lock_height: 0,
+        };
+        // Every party spends one of its own outputs, and the first party creates the new output
+        let inputs: Vec<UnblindedOutput> = (0..3)
+            .map(|_| UnblindedOutput::new(5_000, SecretKey::random(&mut rng), None))
+            .collect();
+        let spend_key = SecretKey::random(&mut rng);
+        let output = TransactionOutput::try_from(&UnblindedOutput::new(14_800, spend_key.clone(), None)).unwrap();
+        let mut sessions: Vec<MultiSigSession> = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                let mut excess_share = &SecretKey::default() - &input.spending_key;
+                if i == 0 {
+                    excess_share = &excess_share + &spend_key;
+                }
+                MultiSigSession::new(&mut rng, 3, excess_share, metadata.clone()).unwrap()
+            })
+            .collect();
+        // Public nonces can't be revealed before all the commitments are known
+        assert!(sessions[0].public_nonce_message().is_err());
+        // Round one
+        let messages = sessions.iter().map(|s| s.nonce_commitment_message()).collect();
+        broadcast(&mut sessions, messages);
+        assert!(sessions[0].excess().is_some());
+        assert!(sessions.iter().all(|s| s.excess() == sessions[0].excess()));
+        // Round two. The messages are serialized to check that they survive the trip
+        let messages = sessions
+            .iter()
+            .map(|s| {
+                let data = serde_json::to_string(&s.public_nonce_message().unwrap()).unwrap();
+                serde_json::from_str(&data).unwrap()
+            })
+            .collect();
+        broadcast(&mut sessions, messages);
+        // Round three
+        let messages = sessions
+            .iter()
+            .map(|s| s.partial_signature_message().unwrap())
+            .collect();
+        broadcast(&mut sessions, messages);
+        assert!(sessions.iter().all(|s| s.is_finalized()));
+
+        let inputs: Vec<TransactionInput> = inputs.iter().map(TransactionInput::from).collect();
+        let tx = sessions[1].build_transaction(inputs, vec![output]).unwrap();
+        assert_eq!(tx.body.kernels[0].fee, 200);
+        assert!(tx.body.kernels[0].verify_signature().is_ok());
+        assert_eq!(tx.offset, sessions[2].offset().unwrap());
+    }
+
+    #[test]
+    fn messages_can_arrive_early() {
+        let mut rng = OsRng::new().unwrap();
+        let metadata = TransactionMetadata {
+            fee: 100,
+            lock_height: 5,
+        };
+        let mut alice = MultiSigSession::new(&mut rng, 2, SecretKey::random(&mut rng), metadata.clone()).unwrap();
+        let mut bob = MultiSigSession::new(&mut rng, 2, SecretKey::random(&mut rng), metadata).unwrap();
+        bob.handle_message(alice.nonce_commitment_message()).unwrap();
+        // Repeated messages are ignored
+        bob.handle_message(alice.nonce_commitment_message()).unwrap();
+        assert!(alice.public_nonce_message().is_err());
+        alice.handle_message(bob.nonce_commitment_message()).unwrap();
+        // Bob signs as soon as Alice's nonce arrives
+        bob.handle_message(alice.public_nonce_message().unwrap()).unwrap();
+        // Bob's partial signature reaches Alice before his nonce
+        alice.handle_message(bob.partial_signature_message().unwrap()).unwrap();
+        assert!(!alice.is_finalized());
+        alice.handle_message(bob.public_nonce_message().unwrap()).unwrap();
+        assert!(alice.is_finalized());
+        bob.handle_message(alice.partial_signature_message().unwrap()).unwrap();
+        assert!(bob.is_finalized());
+        assert_eq!(alice.signature(), bob.signature());
+        assert_eq!(alice.offset(), bob.offset());
+        assert!(alice.build_kernel().unwrap().verify_signature().is_ok());
+    }
+
+    #[test]
+    fn invalid_partial_signature() {
+        let mut rng = OsRng::new().unwrap();
+        let metadata = TransactionMetadata {
+            fee: 100,
+            lock_height: 0,
+        };
+        let mut sessions: Vec<MultiSigSession> = (0..2)
+            .map(|_| MultiSigSession::new(&mut rng, 2, SecretKey::random(&mut rng), metadata.clone()).unwrap())
+            .collect();
+        let messages = sessions.iter().map(|s| s.nonce_commitment_message()).collect();
+        broadcast(&mut sessions, messages);
+        let messages = sessions.iter().map(|s| s.public_nonce_message().unwrap()).collect();
+        broadcast(&mut sessions, messages);
+        let tampered = match sessions[1].partial_signature_message().unwrap() {
+            MultiSigMessage::PartialSignature {
+                public_key,
+                signature,
+                offset,
+            } => {
+                let s = signature.get_signature() + &SecretKey::from(1);
+                MultiSigMessage::PartialSignature {
+                    public_key,
+                    signature: Signature::new(signature.get_public_nonce().clone(), s),
+                    offset,
+                }
+            },
+            _ => panic!("Expected a partial signature"),
+        };
+        match sessions[0].handle_message(tampered) {
+            Err(TransactionProtocolError::MuSigError(MuSigError::InvalidPartialSignature(_))) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert!(sessions[0].build_kernel().is_err());
+    }
+
+    #[test]
+    fn mismatched_metadata() {
+        let mut rng = OsRng::new().unwrap();
+        let mut alice = MultiSigSession::new(&mut rng, 2, SecretKey::random(&mut rng), TransactionMetadata {
+            fee: 100,
+            lock_height: 0,
+        })
+        .unwrap();
+        let mut bob = MultiSigSession::new(&mut rng, 2, SecretKey::random(&mut rng), TransactionMetadata {
+            fee: 200,
+            lock_height: 0,
+        })
+        .unwrap();
+        alice.handle_message(bob.nonce_commitment_message()).unwrap();
+        bob.handle_message(alice.nonce_commitment_message()).unwrap();
+        alice.handle_message(bob.public_nonce_message().unwrap()).unwrap();
+        bob.handle_message(alice.public_nonce_message().unwrap()).unwrap();
+        // Bob signed a different fee, so his partial signature does not validate against Alice's challenge
+        assert!(alice.handle_message(bob.partial_signature_message().unwrap()).is_err());
+    }
+}
@@ -29,28 +29,33 @@ use crate::{
     },
     types::{MessageHash, PublicKey, SecretKey, Signature},
 };
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize)]
 pub enum RecipientState {
     Finalized(RecipientSignedTransactionData),
+    /// Failed protocols are never stored, so this state is not serialized
+    #[serde(skip)]
     Failed(TransactionProtocolError),
 }
 
 /// An enum describing the types of information that a recipient can send back to the receiver
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(super) enum RecipientInfo {
     None,
     Single(Option<Box<RecipientSignedTransactionData>>),
     Multiple(HashMap<u64, MultiRecipientInfo>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(super) struct MultiRecipientInfo {
     pub commitment: MessageHash,
     pub data: RecipientSignedTransactionData,
 }
 
 /// This is the message containing the public data that the Receiver will send back to the Sender
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct RecipientSignedTransactionData {
     pub tx_id: u64,
     pub output: TransactionOutput,
@@ -60,6 +65,7 @@ pub struct RecipientSignedTransactionData {
 
 /// The generalised transaction recipient protocol. A different state transition network is followed depending on
 /// whether this is a single recipient or one of many.
+#[derive(Serialize, Deserialize)]
 pub struct ReceiverTransactionProtocol {
     state: RecipientState,
 }
@@ -49,7 +49,7 @@ use tari_utilities::ByteArray;
 
 /// This struct contains all the information that a transaction initiator (the sender) will manage throughout the
 /// Transaction construction process.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub(super) struct RawTransactionInfo {
     pub num_recipients: usize,
     // The sum of self-created outputs plus change
@@ -103,7 +103,7 @@ pub enum SenderMessage {
 }
 
 //----------------------------------------  Sender State Protocol ----------------------------------------------------//
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SenderTransactionProtocol {
     pub(super) state: SenderState,
 }
@@ -200,6 +200,31 @@ impl SenderTransactionProtocol {
         }
     }
 
+    /// The amount the sender pays back to itself, i.e. the value of the change output plus any other outputs the
+    /// sender added
+    pub fn get_amount_to_self(&self) -> Result<u64, TPE> {
+        match &self.state {
+            SenderState::Initializing(info) |
+            SenderState::Finalizing(info) |
+            SenderState::SingleRoundMessageReady(info) |
+            SenderState::CollectingSingleSignature(info) => Ok(info.amount_to_self),
+            SenderState::FinalizedTransaction(_) => Err(TPE::InvalidStateError),
+            SenderState::Failed(_) => Err(TPE::InvalidStateError),
+        }
+    }
+
+    /// The total fee of the transaction
+    pub fn get_fee(&self) -> Result<u64, TPE> {
+        match &self.state {
+            SenderState::Initializing(info) |
+            SenderState::Finalizing(info) |
+            SenderState::SingleRoundMessageReady(info) |
+            SenderState::CollectingSingleSignature(info) => Ok(info.metadata.fee),
+            SenderState::FinalizedTransaction(tx) => Ok(tx.body.kernels.iter().map(|k| k.fee).sum()),
+            SenderState::Failed(_) => Err(TPE::InvalidStateError),
+        }
+    }
+
     /// Build the sender's message for the single-round protocol (one recipient) and move to next State
     pub fn build_single_round_message(&mut self) -> Result<SingleRoundSenderData, TPE> {
         match &self.state {
@@ -371,7 +396,7 @@ pub fn calculate_tx_id<D: Digest>(pub_nonce: &PublicKey, index: usize) -> u64 {
 //----------------------------------------      Sender State      ----------------------------------------------------//
 
 /// This enum contains all the states of the Sender state machine
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub(super) enum SenderState {
     /// Transitional state that kicks of the relevant transaction protocol
     Initializing(RawTransactionInfo),
@@ -384,7 +409,9 @@ pub(super) enum SenderState {
     Finalizing(RawTransactionInfo),
     /// The final transaction is ready to be broadcast
     FinalizedTransaction(Transaction),
-    /// An unrecoverable failure has occurred and the transaction must be abandoned
+    /// An unrecoverable failure has occurred and the transaction must be abandoned. Failed protocols are never
+    /// stored, so this state is not serialized.
+    #[serde(skip)]
     Failed(TPE),
 }
 
@@ -21,7 +21,7 @@
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use crate::{
-    transaction::{OutputFeatures, TransactionOutput, MAX_RANGE_PROOF_RANGE},
+    transaction::{OutputFeatures, TransactionOutput, UnblindedOutput, MAX_RANGE_PROOF_RANGE},
     transaction_protocol::{
         build_challenge,
         recipient::RecipientSignedTransactionData as RD,
@@ -68,11 +68,13 @@ impl SingleReceiverTransactionProtocol {
     fn build_output(sender_info: &SD, spending_key: &SK, features: OutputFeatures) -> Result<TransactionOutput, TPE> {
         let commitment = CommitmentFactory::commit(sender_info.amount, &spending_key);
         let prover = RangeProofService::new(MAX_RANGE_PROOF_RANGE, CommitmentFactory::default())?;
+        let unblinded = UnblindedOutput::new(sender_info.amount, spending_key.clone(), Some(features));
 
         Ok(TransactionOutput::new(
             features,
             commitment,
             prover.construct_proof(&spending_key, sender_info.amount)?,
+            unblinded.encrypted_value(),
         ))
     }
 }
@@ -145,5 +147,10 @@ mod test {
         assert!(out.commitment.validate(info.amount, &k), "Output commitment is invalid");
         assert!(out.verify_range_proof(None).unwrap(), "Range proof is invalid");
         assert!(out.features.is_empty(), "Output features have changed");
+        assert_eq!(
+            out.recover_value(&k),
+            Some(info.amount),
+            "Output value can't be recovered"
+        );
     }
 }
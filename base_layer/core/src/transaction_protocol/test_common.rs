@@ -20,12 +20,16 @@
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-// Used in tests only
+// Used in tests only. Other crates use these helpers in their tests by enabling the `test-helpers` feature.
 
 use crate::{
-    transaction::{OutputFeatures, TransactionInput, UnblindedOutput},
+    block::{AggregateBody, Block},
+    blockheader::{BlockHash, BlockHeader},
+    pow::ProofOfWork,
+    transaction::{OutputFeatures, Transaction, TransactionInput, UnblindedOutput},
     types::{CommitmentFactory, PublicKey, SecretKey},
 };
+use chrono::Utc;
 use rand::{CryptoRng, Rng};
 use tari_crypto::{
     commitment::HomomorphicCommitmentFactory,
@@ -60,3 +64,30 @@ pub fn make_input<R: Rng + CryptoRng>(rng: &mut R, val: u64) -> (TransactionInpu
     let input = TransactionInput::new(OutputFeatures::empty(), commitment);
     (input, UnblindedOutput::new(val, key, None))
 }
+
+/// Create a block at `height` on top of `prev_hash` that contains `transactions`. The header is not mined and the
+/// MMR roots are left empty, so the block is only suitable for code that looks at the block contents. The header hash
+/// doesn't depend on the block body, so competing blocks at the same height must be given different `nonce`s.
+pub fn create_test_block(height: u64, prev_hash: BlockHash, nonce: u64, transactions: Vec<Transaction>) -> Block {
+    let mut body = AggregateBody::empty();
+    for tx in transactions {
+        let mut tx_body = tx.body;
+        body.add_inputs(&mut tx_body.inputs);
+        body.add_outputs(&mut tx_body.outputs);
+        for kernel in tx_body.kernels {
+            body.add_kernel(kernel);
+        }
+    }
+    let header = BlockHeader {
+        version: 0,
+        height,
+        prev_hash,
+        timestamp: Utc::now(),
+        output_mmr: [0u8; 32],
+        kernel_mmr: [0u8; 32],
+        total_kernel_offset: PublicKey::default(),
+        nonce,
+        pow: ProofOfWork::new(1),
+    };
+    Block { header, body }
+}
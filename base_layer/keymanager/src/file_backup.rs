@@ -21,10 +21,29 @@
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use derive_error::Error;
+use hmac::{Hmac, Mac};
+use rand::{OsRng, RngCore};
+use scrypt::{scrypt, ScryptParams};
 use serde::de::DeserializeOwned;
-use std::{fs::File, io::prelude::*};
+use sha2::Sha256;
+use std::{
+    fs::{self, File},
+    io::prelude::*,
+};
+use tari_utilities::chacha20;
 
-// TODO: file should be decrypted using Salsa20 or ChaCha20
+/// The version of the backup file format written by `to_file`
+pub const BACKUP_VERSION: u8 = 1;
+/// Identifies a file as a Tari backup
+const MAGIC: &[u8; 4] = b"TBAK";
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 32;
+/// magic | version | log_n | r | p | salt | nonce
+const HEADER_SIZE: usize = 4 + 1 + 1 + 4 + 4 + SALT_SIZE + NONCE_SIZE;
+/// Backups asking for more scrypt work than this are rejected rather than tying up the machine
+const MAX_LOG_N: u8 = 22;
+const MAX_R_TIMES_P: u64 = 64;
 
 #[derive(Debug, Error)]
 pub enum FileError {
@@ -36,77 +55,236 @@ pub enum FileError {
     FileRead,
     // Could not write to backup file
     FileWrite,
+    // The temporary backup file could not be moved into place
+    FileRename,
     // Problem serializing struct into JSON
     Serialize,
     // Problem deserializing JSON into a new struct
     Deserialize,
+    // The file is not a backup file, or it has been truncated
+    InvalidFormat,
+    // The backup file was written by a newer, unsupported version
+    UnsupportedVersion,
+    // The key derivation parameters are invalid
+    InvalidParameters,
+    // The passphrase is wrong, or the backup file has been tampered with
+    WrongPassphrase,
+    // The salt and nonce could not be generated
+    RandomError,
 }
 
+/// The scrypt parameters used to derive the backup encryption key from the passphrase. They are stored in the header
+/// of the backup file, so they can be raised later without breaking existing backups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KdfParams {
+    /// The base-2 logarithm of the scrypt cost parameter N
+    pub log_n: u8,
+    /// The scrypt block size
+    pub r: u32,
+    /// The scrypt parallelization parameter
+    pub p: u32,
+}
+
+impl KdfParams {
+    pub fn new(log_n: u8, r: u32, p: u32) -> KdfParams {
+        KdfParams { log_n, r, p }
+    }
+
+    fn to_scrypt_params(&self) -> Result<ScryptParams, FileError> {
+        if self.log_n > MAX_LOG_N || u64::from(self.r) * u64::from(self.p) > MAX_R_TIMES_P {
+            return Err(FileError::InvalidParameters);
+        }
+        ScryptParams::new(self.log_n, self.r, self.p).map_err(|_| FileError::InvalidParameters)
+    }
+}
+
+impl Default for KdfParams {
+    /// The parameters recommended for interactive use: N = 2^15, r = 8, p = 1
+    fn default() -> Self {
+        KdfParams::new(15, 8, 1)
+    }
+}
+
+/// Save and load structs as encrypted backup files. The struct is serialized to JSON and encrypted with ChaCha20
+/// under a key derived from the passphrase with scrypt. The file is laid out as
+/// `magic | version | log_n | r | p | salt | nonce | ciphertext | tag`, where the tag is an HMAC-SHA256 over
+/// everything before it, so a wrong passphrase or a modified file is detected before anything is deserialized.
 pub trait FileBackup<T> {
-    fn from_file(filename: &String) -> Result<T, FileError>;
-    fn to_file(&self, filename: &String) -> Result<(), FileError>;
+    fn from_file(filename: &String, passphrase: &str) -> Result<T, FileError>;
+    fn to_file(&self, filename: &String, passphrase: &str) -> Result<(), FileError>;
+    fn to_file_with_params(&self, filename: &String, passphrase: &str, params: &KdfParams) -> Result<(), FileError>;
 }
 
 impl<T> FileBackup<T> for T
 where T: serde::Serialize + DeserializeOwned
 {
     /// Load struct state from backup file
-    fn from_file(filename: &String) -> Result<T, FileError> {
+    fn from_file(filename: &String, passphrase: &str) -> Result<T, FileError> {
         let mut file_handle = match File::open(&filename) {
             Ok(file) => file,
             Err(_e) => return Err(FileError::FileOpen),
         };
-        let mut file_content = String::new();
-        match file_handle.read_to_string(&mut file_content) {
-            Ok(_) => match serde_json::from_str(&file_content) {
-                Ok(km) => Ok(km),
-                Err(_) => Err(FileError::Deserialize),
-            },
-            Err(_) => Err(FileError::FileRead),
+        let mut file_content = Vec::new();
+        if file_handle.read_to_end(&mut file_content).is_err() {
+            return Err(FileError::FileRead);
+        }
+        let plaintext = decrypt(&file_content, passphrase)?;
+        match serde_json::from_slice(&plaintext) {
+            Ok(km) => Ok(km),
+            Err(_) => Err(FileError::Deserialize),
         }
     }
 
-    /// Backup struct state in file specified by filename
-    fn to_file(&self, filename: &String) -> Result<(), FileError> {
-        match File::create(filename) {
-            Ok(mut file_handle) => match serde_json::to_string(&self) {
-                Ok(json_data) => match file_handle.write_all(json_data.as_bytes()) {
-                    Ok(_) => Ok(()),
-                    Err(_) => Err(FileError::FileWrite),
-                },
-                Err(_) => Err(FileError::Serialize),
-            },
+    /// Backup struct state in file specified by filename, using the default key derivation parameters
+    fn to_file(&self, filename: &String, passphrase: &str) -> Result<(), FileError> {
+        self.to_file_with_params(filename, passphrase, &KdfParams::default())
+    }
 
-            Err(_) => Err(FileError::FileCreate),
+    /// Backup struct state in file specified by filename. The backup is written to a temporary file first and then
+    /// renamed, so an existing backup is never left half written.
+    fn to_file_with_params(&self, filename: &String, passphrase: &str, params: &KdfParams) -> Result<(), FileError> {
+        let json_data = match serde_json::to_vec(&self) {
+            Ok(json_data) => json_data,
+            Err(_) => return Err(FileError::Serialize),
+        };
+        let file_content = encrypt(&json_data, passphrase, params)?;
+        let temp_filename = format!("{}.tmp", filename);
+        let mut file_handle = match File::create(&temp_filename) {
+            Ok(file_handle) => file_handle,
+            Err(_) => return Err(FileError::FileCreate),
+        };
+        if file_handle.write_all(&file_content).is_err() || file_handle.sync_all().is_err() {
+            let _ = fs::remove_file(&temp_filename);
+            return Err(FileError::FileWrite);
+        }
+        if fs::rename(&temp_filename, filename).is_err() {
+            let _ = fs::remove_file(&temp_filename);
+            return Err(FileError::FileRename);
         }
+        Ok(())
+    }
+}
+
+/// Encrypt `plaintext` under a key derived from `passphrase`, returning the complete contents of a backup file
+fn encrypt(plaintext: &[u8], passphrase: &str, params: &KdfParams) -> Result<Vec<u8>, FileError> {
+    let mut rng = OsRng::new().map_err(|_| FileError::RandomError)?;
+    let mut salt = [0u8; SALT_SIZE];
+    rng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; NONCE_SIZE];
+    rng.fill_bytes(&mut nonce);
+    let (encryption_key, mac_key) = derive_keys(passphrase, &salt, params)?;
+
+    let mut data = Vec::with_capacity(HEADER_SIZE + plaintext.len() + TAG_SIZE);
+    data.extend_from_slice(MAGIC);
+    data.push(BACKUP_VERSION);
+    data.push(params.log_n);
+    data.extend_from_slice(&params.r.to_le_bytes());
+    data.extend_from_slice(&params.p.to_le_bytes());
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&chacha20::encode_with_nonce(
+        plaintext,
+        &encryption_key,
+        &nonce_from_bytes(&nonce),
+    ));
+    let tag = tag(&mac_key, &data)?;
+    data.extend_from_slice(&tag);
+    Ok(data)
+}
+
+/// Check the header and tag of a backup file and decrypt its contents
+fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, FileError> {
+    if data.len() < HEADER_SIZE + TAG_SIZE || &data[..4] != MAGIC {
+        return Err(FileError::InvalidFormat);
+    }
+    if data[4] != BACKUP_VERSION {
+        return Err(FileError::UnsupportedVersion);
+    }
+    let params = KdfParams::new(data[5], read_u32(&data[6..10]), read_u32(&data[10..14]));
+    let salt = &data[14..14 + SALT_SIZE];
+    let nonce = &data[14 + SALT_SIZE..HEADER_SIZE];
+    let (authenticated, expected_tag) = data.split_at(data.len() - TAG_SIZE);
+    let (encryption_key, mac_key) = derive_keys(passphrase, salt, &params)?;
+
+    let mut mac = Hmac::<Sha256>::new_varkey(&mac_key).map_err(|_| FileError::InvalidParameters)?;
+    mac.input(authenticated);
+    if mac.verify(expected_tag).is_err() {
+        return Err(FileError::WrongPassphrase);
+    }
+    Ok(chacha20::decode_with_nonce(
+        &authenticated[HEADER_SIZE..],
+        &encryption_key,
+        &nonce_from_bytes(nonce),
+    ))
+}
+
+/// Derive independent encryption and authentication keys from the passphrase
+fn derive_keys(passphrase: &str, salt: &[u8], params: &KdfParams) -> Result<([u8; 32], [u8; 32]), FileError> {
+    let mut output = [0u8; 64];
+    scrypt(passphrase.as_bytes(), salt, &params.to_scrypt_params()?, &mut output)
+        .map_err(|_| FileError::InvalidParameters)?;
+    let mut encryption_key = [0u8; 32];
+    let mut mac_key = [0u8; 32];
+    encryption_key.copy_from_slice(&output[..32]);
+    mac_key.copy_from_slice(&output[32..]);
+    Ok((encryption_key, mac_key))
+}
+
+fn tag(mac_key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, FileError> {
+    let mut mac = Hmac::<Sha256>::new_varkey(mac_key).map_err(|_| FileError::InvalidParameters)?;
+    mac.input(data);
+    Ok(mac.result().code().to_vec())
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut word = [0u8; 4];
+    word.copy_from_slice(bytes);
+    u32::from_le_bytes(word)
+}
+
+fn nonce_from_bytes(bytes: &[u8]) -> [u32; 3] {
+    let mut nonce = [0u32; 3];
+    for (i, n) in nonce.iter_mut().enumerate() {
+        *n = read_u32(&bytes[i * 4..i * 4 + 4]);
     }
+    nonce
 }
 
 #[cfg(test)]
 mod test {
     use crate::file_backup::*;
     use serde_derive::{Deserialize, Serialize};
-    use std::fs::remove_file;
+    use std::fs::{read, remove_file, write};
 
-    #[test]
-    fn test_struct_to_file_and_from_file() {
-        #[derive(Debug, PartialEq, Serialize, Deserialize)]
-        pub struct R {
-            pub var1: String,
-            pub var2: Vec<u8>,
-            pub var3: usize,
-        }
-        let desired_struct = R {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    pub struct R {
+        pub var1: String,
+        pub var2: Vec<u8>,
+        pub var3: usize,
+    }
+
+    fn desired_struct() -> R {
+        R {
             var1: "Test".to_string(),
             var2: vec![0, 1, 2],
             var3: 3,
-        };
+        }
+    }
+
+    // Cheap parameters, so the tests run quickly
+    fn test_params() -> KdfParams {
+        KdfParams::new(4, 8, 1)
+    }
+
+    #[test]
+    fn test_struct_to_file_and_from_file() {
+        let desired_struct = desired_struct();
         // Backup struct to file
         let backup_filename = "test_backup.json".to_string();
-        match desired_struct.to_file(&backup_filename) {
+        match desired_struct.to_file_with_params(&backup_filename, "passphrase", &test_params()) {
             Ok(_v) => {
                 // Restore struct from file
-                let backup_result: Result<R, FileError> = R::from_file(&backup_filename);
+                let backup_result: Result<R, FileError> = R::from_file(&backup_filename, "passphrase");
                 match backup_result {
                     Ok(backup_struct) => {
                         // Remove temp backup file
@@ -119,4 +297,55 @@ mod test {
             Err(_e) => assert!(false),
         };
     }
+
+    #[test]
+    fn backup_is_encrypted_and_authenticated() {
+        let backup_filename = "test_backup_encrypted.json".to_string();
+        desired_struct()
+            .to_file_with_params(&backup_filename, "passphrase", &test_params())
+            .unwrap();
+        let mut data = read(&backup_filename).unwrap();
+        assert_eq!(&data[..4], MAGIC);
+        assert_eq!(data[4], BACKUP_VERSION);
+        assert!(!String::from_utf8_lossy(&data).contains("Test"));
+        // No temporary file is left behind
+        assert!(File::open(format!("{}.tmp", backup_filename)).is_err());
+
+        match R::from_file(&backup_filename, "wrong passphrase") {
+            Err(FileError::WrongPassphrase) => {},
+            _ => panic!("A wrong passphrase must be reported"),
+        }
+
+        // Flipping a bit of the ciphertext is detected
+        data[HEADER_SIZE] ^= 1;
+        write(&backup_filename, &data).unwrap();
+        match R::from_file(&backup_filename, "passphrase") {
+            Err(FileError::WrongPassphrase) => {},
+            _ => panic!("A modified backup must be rejected"),
+        }
+
+        data[4] = BACKUP_VERSION + 1;
+        write(&backup_filename, &data).unwrap();
+        match R::from_file(&backup_filename, "passphrase") {
+            Err(FileError::UnsupportedVersion) => {},
+            _ => panic!("An unknown version must be rejected"),
+        }
+
+        write(&backup_filename, b"{\"var1\":\"Test\"}").unwrap();
+        match R::from_file(&backup_filename, "passphrase") {
+            Err(FileError::InvalidFormat) => {},
+            _ => panic!("A plaintext file must be rejected"),
+        }
+        remove_file(backup_filename).unwrap();
+    }
+
+    #[test]
+    fn excessive_parameters_are_rejected() {
+        let backup_filename = "test_backup_params.json".to_string();
+        match desired_struct().to_file_with_params(&backup_filename, "passphrase", &KdfParams::new(40, 8, 1)) {
+            Err(FileError::InvalidParameters) => {},
+            _ => panic!("Excessive parameters must be rejected"),
+        }
+        assert!(File::open(&backup_filename).is_err());
+    }
 }
@@ -28,7 +28,7 @@ use serde::de::DeserializeOwned;
 use serde_derive::{Deserialize, Serialize};
 use std::marker::PhantomData;
 use tari_crypto::keys::SecretKey;
-use tari_utilities::{byte_array::ByteArrayError, hex::Hex};
+use tari_utilities::byte_array::{ByteArray, ByteArrayError};
 
 #[derive(Debug, Error)]
 pub enum KeyManagerError {
@@ -38,6 +38,9 @@ pub enum KeyManagerError {
     MnemonicError(mnemonic::MnemonicError),
 }
 
+/// Separates key manager derivations from every other use of the master key
+const DERIVATION_DOMAIN: &[u8] = b"com.tari.keymanager.derive_key.v1";
+
 #[derive(Clone, Debug)]
 pub struct DerivedKey<K>
 where K: SecretKey
@@ -46,11 +49,82 @@ where K: SecretKey
     pub key_index: usize,
 }
 
+/// The purpose a key is derived for. Each branch is an independent chain of keys, so keys of one purpose can never be
+/// mistaken for, or linked to, keys of another.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KeyBranch {
+    /// Spending keys of received outputs
+    Spend,
+    /// Spending keys of change outputs
+    Change,
+    /// Keys of the node identity used on the comms network
+    CommsIdentity,
+    /// Nonces of signatures
+    Nonce,
+    /// An application defined branch
+    Custom(String),
+}
+
+impl KeyBranch {
+    /// The label of the branch that is hashed into its keys
+    pub fn label(&self) -> String {
+        match self {
+            KeyBranch::Spend => "spend".to_string(),
+            KeyBranch::Change => "change".to_string(),
+            KeyBranch::CommsIdentity => "comms".to_string(),
+            KeyBranch::Nonce => "nonce".to_string(),
+            KeyBranch::Custom(name) => format!("custom/{}", name),
+        }
+    }
+}
+
+/// The account, branch and index a key is derived at
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct KeyPath {
+    pub account: u32,
+    pub branch: KeyBranch,
+    pub index: usize,
+}
+
+impl KeyPath {
+    pub fn new(account: u32, branch: KeyBranch, index: usize) -> KeyPath {
+        KeyPath { account, branch, index }
+    }
+}
+
+/// The next unused index of a branch of an account
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct BranchIndex {
+    account: u32,
+    branch: KeyBranch,
+    next_index: usize,
+}
+
+/// Deterministically derives keys from a master key. Every key is identified by a `KeyPath` and derived as
+///
+/// `k = D(len(domain) || domain || master_key || account || len(label) || label || index)`
+///
+/// where `domain` is `com.tari.keymanager.derive_key.v1`, `label` is the branch label, lengths and the index are
+/// little-endian u64s and the account is a little-endian u32. The digest is reduced to a secret key by
+/// `SecretKey::from_bytes`. `derive_key` and `next_key` derive keys on the `Custom(branch_seed)` branch of account 0.
+///
+/// Test vectors for `KeyManager<RistrettoSecretKey, Sha256>` with the master key `0707..07` (32 bytes):
+///
+/// | account | branch             | index | key                                                                |
+/// |---------|--------------------|-------|--------------------------------------------------------------------|
+/// | 0       | `Spend`            | 0     | `b3fe0d5736297ed5a05706df9857b485b8466f81b8a47a1df13aac1b802cde0c` |
+/// | 0       | `Change`           | 1     | `82d5d004dba52c3dd6cc1631a7e28b493482529c23dbaa8189df75271cab720d` |
+/// | 1       | `CommsIdentity`    | 0     | `26582b6a46f0db6d8bd4c4b314e3b4f8d68232d5dfba4b8195276be2fd7af401` |
+/// | 0       | `Nonce`            | 5     | `c3c8a8595c0737bc949be4232cb97d504c9fb1fa2adda2277ff616323c5d6f0d` |
+/// | 0       | `Custom("")`       | 3     | `34047fa254701988c294357990a23cec085eb924233bee66713d6baa8cc03603` |
+/// | 2       | `Custom("savings")`| 7     | `0b37b30c02c1efcd32c07ec196d2838fbf1309db6a30550ea337dd8339ab9800` |
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct KeyManager<K: SecretKey, D: Digest> {
     pub master_key: K,
     pub branch_seed: String,
     pub primary_key_index: usize,
+    #[serde(default)]
+    branch_indices: Vec<BranchIndex>,
     digest_type: PhantomData<D>,
 }
 
@@ -65,6 +139,7 @@ where
             master_key: SecretKey::random(rng),
             branch_seed: "".to_string(),
             primary_key_index: 0,
+            branch_indices: Vec::new(),
             digest_type: PhantomData,
         }
     }
@@ -75,6 +150,7 @@ where
             master_key,
             branch_seed,
             primary_key_index,
+            branch_indices: Vec::new(),
             digest_type: PhantomData,
         }
     }
@@ -91,6 +167,7 @@ where
                 master_key,
                 branch_seed,
                 primary_key_index,
+                branch_indices: Vec::new(),
                 digest_type: PhantomData,
             }),
             Err(e) => Err(KeyManagerError::from(e)),
@@ -110,17 +187,35 @@ where
                 master_key,
                 branch_seed,
                 primary_key_index,
+                branch_indices: Vec::new(),
                 digest_type: PhantomData,
             }),
             Err(e) => Err(KeyManagerError::from(e)),
         }
     }
 
-    /// Derive a new private key from master key: derived_key=SHA256(master_key||branch_seed||index)
+    /// Derive a new private key from master key on the branch_seed branch of account 0
     pub fn derive_key(&self, key_index: usize) -> Result<DerivedKey<K>, ByteArrayError> {
-        let concatenated = format!("{}{}", self.master_key.to_hex(), key_index.to_string());
-        match K::from_bytes(D::digest(&concatenated.into_bytes()).as_slice()) {
-            Ok(k) => Ok(DerivedKey { k, key_index }),
+        self.derive_path_key(&self.primary_path(key_index))
+    }
+
+    /// Derive the private key at `path`
+    pub fn derive_path_key(&self, path: &KeyPath) -> Result<DerivedKey<K>, ByteArrayError> {
+        let label = path.branch.label().into_bytes();
+        let digest = D::new()
+            .chain((DERIVATION_DOMAIN.len() as u64).to_le_bytes())
+            .chain(DERIVATION_DOMAIN)
+            .chain(self.master_key.as_bytes())
+            .chain(path.account.to_le_bytes())
+            .chain((label.len() as u64).to_le_bytes())
+            .chain(label)
+            .chain((path.index as u64).to_le_bytes())
+            .result();
+        match K::from_bytes(digest.as_slice()) {
+            Ok(k) => Ok(DerivedKey {
+                k,
+                key_index: path.index,
+            }),
             Err(e) => Err(e),
         }
     }
@@ -130,6 +225,79 @@ where
         self.primary_key_index += 1;
         (self.derive_key(self.primary_key_index))
     }
+
+    /// The next unused index of `branch` in `account`
+    pub fn branch_index(&self, account: u32, branch: &KeyBranch) -> usize {
+        self.branch_indices
+            .iter()
+            .find(|b| b.account == account && &b.branch == branch)
+            .map_or(0, |b| b.next_index)
+    }
+
+    /// Generate the next unused key of `branch` in `account`. The first key of every branch has index 0.
+    pub fn next_branch_key(&mut self, account: u32, branch: KeyBranch) -> Result<DerivedKey<K>, ByteArrayError> {
+        let index = self.branch_index(account, &branch);
+        let key = self.derive_path_key(&KeyPath::new(account, branch.clone(), index))?;
+        match self
+            .branch_indices
+            .iter_mut()
+            .find(|b| b.account == account && b.branch == branch)
+        {
+            Some(b) => b.next_index = index + 1,
+            None => self.branch_indices.push(BranchIndex {
+                account,
+                branch,
+                next_index: index + 1,
+            }),
+        }
+        Ok(key)
+    }
+
+    /// Find the path of the key for which `is_match` returns true. The primary branch, the named branches of
+    /// account 0 and every branch a key has been generated on are searched, up to `lookahead` indices past the last
+    /// generated key of each branch.
+    pub fn find_key_path<F>(&self, lookahead: usize, is_match: F) -> Result<Option<KeyPath>, ByteArrayError>
+    where F: Fn(&K) -> bool {
+        let mut branches = vec![(
+            0,
+            KeyBranch::Custom(self.branch_seed.clone()),
+            self.primary_key_index + 1,
+        )];
+        for branch in &[
+            KeyBranch::Spend,
+            KeyBranch::Change,
+            KeyBranch::CommsIdentity,
+            KeyBranch::Nonce,
+        ] {
+            branches.push((0, branch.clone(), self.branch_index(0, branch)));
+        }
+        for b in &self.branch_indices {
+            if !branches
+                .iter()
+                .any(|(account, branch, _)| *account == b.account && *branch == b.branch)
+            {
+                branches.push((b.account, b.branch.clone(), b.next_index));
+            }
+        }
+        for (account, branch, next_index) in branches {
+            for index in 0..next_index + lookahead {
+                let path = KeyPath::new(account, branch.clone(), index);
+                if is_match(&self.derive_path_key(&path)?.k) {
+                    return Ok(Some(path));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Recover the path that `key` was derived at, see `find_key_path`
+    pub fn key_path(&self, key: &K, lookahead: usize) -> Result<Option<KeyPath>, ByteArrayError> {
+        self.find_key_path(lookahead, |k| k == key)
+    }
+
+    fn primary_path(&self, key_index: usize) -> KeyPath {
+        KeyPath::new(0, KeyBranch::Custom(self.branch_seed.clone()), key_index)
+    }
 }
 
 #[cfg(test)]
@@ -138,6 +306,7 @@ mod test {
     use sha2::Sha256;
     use std::fs::remove_file;
     use tari_crypto::ristretto::RistrettoSecretKey;
+    use tari_utilities::hex::Hex;
 
     #[test]
     fn test_new_keymanager() {
@@ -166,7 +335,7 @@ mod test {
         let mnemonic_seq1 = vec![
             "clever", "jaguar", "bus", "engage", "oil", "august", "media", "high", "trick", "remove", "tiny", "join",
             "item", "tobacco", "orange", "pony", "tomorrow", "also", "dignity", "giraffe", "little", "board", "army",
-            "scale",
+            "submit",
         ]
         .iter()
         .map(|x| x.to_string())
@@ -174,7 +343,7 @@ mod test {
         let mnemonic_seq2 = vec![
             "spatial", "travel", "remove", "few", "cinnamon", "three", "drift", "grit", "amazing", "isolate", "merge",
             "tonight", "apple", "garden", "damage", "job", "equal", "ahead", "wolf", "initial", "woman", "regret",
-            "neither", "divorce",
+            "neither", "kitchen",
         ]
         .iter()
         .map(|x| x.to_string())
@@ -223,11 +392,11 @@ mod test {
         let desired_km = KeyManager::<RistrettoSecretKey, Sha256>::new(&mut rng);
         let backup_filename = "test_km_backup.json".to_string();
         // Backup KeyManager to file
-        match desired_km.to_file(&backup_filename) {
+        match desired_km.to_file(&backup_filename, "passphrase") {
             Ok(_v) => {
                 // Restore KeyManager from file
                 let backup_km_result: Result<KeyManager<RistrettoSecretKey, Sha256>, FileError> =
-                    KeyManager::from_file(&backup_filename);
+                    KeyManager::from_file(&backup_filename, "passphrase");
                 match backup_km_result {
                     Ok(backup_km) => {
                         // Remove temp keymanager backup file
@@ -243,4 +412,111 @@ mod test {
             Err(_e) => assert!(false),
         };
     }
+
+    #[test]
+    fn test_vectors() {
+        let master_key = RistrettoSecretKey::from_bytes(&[7u8; 32]).unwrap();
+        let km = KeyManager::<RistrettoSecretKey, Sha256>::from(master_key, "".to_string(), 0);
+        let vectors = [
+            (
+                0,
+                KeyBranch::Spend,
+                0,
+                "b3fe0d5736297ed5a05706df9857b485b8466f81b8a47a1df13aac1b802cde0c",
+            ),
+            (
+                0,
+                KeyBranch::Change,
+                1,
+                "82d5d004dba52c3dd6cc1631a7e28b493482529c23dbaa8189df75271cab720d",
+            ),
+            (
+                1,
+                KeyBranch::CommsIdentity,
+                0,
+                "26582b6a46f0db6d8bd4c4b314e3b4f8d68232d5dfba4b8195276be2fd7af401",
+            ),
+            (
+                0,
+                KeyBranch::Nonce,
+                5,
+                "c3c8a8595c0737bc949be4232cb97d504c9fb1fa2adda2277ff616323c5d6f0d",
+            ),
+            (
+                0,
+                KeyBranch::Custom("".to_string()),
+                3,
+                "34047fa254701988c294357990a23cec085eb924233bee66713d6baa8cc03603",
+            ),
+            (
+                2,
+                KeyBranch::Custom("savings".to_string()),
+                7,
+                "0b37b30c02c1efcd32c07ec196d2838fbf1309db6a30550ea337dd8339ab9800",
+            ),
+        ];
+        for (account, branch, index, key) in vectors.iter() {
+            let path = KeyPath::new(*account, branch.clone(), *index);
+            assert_eq!(km.derive_path_key(&path).unwrap().k.to_hex(), *key);
+        }
+        // derive_key uses the branch_seed branch of account 0
+        assert_eq!(km.derive_key(3).unwrap().k.to_hex(), vectors[4].3);
+    }
+
+    #[test]
+    fn test_branches_and_accounts_are_separated() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let km = KeyManager::<RistrettoSecretKey, Sha256>::new(&mut rng);
+        let key = |account, branch| km.derive_path_key(&KeyPath::new(account, branch, 0)).unwrap().k;
+        assert_ne!(key(0, KeyBranch::Spend), key(0, KeyBranch::Change));
+        assert_ne!(key(0, KeyBranch::Spend), key(1, KeyBranch::Spend));
+        assert_ne!(key(0, KeyBranch::CommsIdentity), key(0, KeyBranch::Nonce));
+        // The branch seed changes the primary branch
+        let other = KeyManager::<RistrettoSecretKey, Sha256>::from(km.master_key.clone(), "other".to_string(), 0);
+        assert_ne!(km.derive_key(1).unwrap().k, other.derive_key(1).unwrap().k);
+    }
+
+    #[test]
+    fn test_next_branch_key_and_key_path() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let mut km = KeyManager::<RistrettoSecretKey, Sha256>::new(&mut rng);
+        let spend0 = km.next_branch_key(0, KeyBranch::Spend).unwrap();
+        let spend1 = km.next_branch_key(0, KeyBranch::Spend).unwrap();
+        let savings = km.next_branch_key(3, KeyBranch::Change).unwrap();
+        assert_eq!((spend0.key_index, spend1.key_index, savings.key_index), (0, 1, 0));
+        assert_eq!(km.branch_index(0, &KeyBranch::Spend), 2);
+        assert_eq!(km.branch_index(3, &KeyBranch::Change), 1);
+        assert_eq!(km.branch_index(3, &KeyBranch::Spend), 0);
+        let primary = km.next_key().unwrap();
+
+        assert_eq!(
+            km.key_path(&spend1.k, 0).unwrap(),
+            Some(KeyPath::new(0, KeyBranch::Spend, 1))
+        );
+        assert_eq!(
+            km.key_path(&savings.k, 0).unwrap(),
+            Some(KeyPath::new(3, KeyBranch::Change, 0))
+        );
+        assert_eq!(
+            km.key_path(&primary.k, 0).unwrap(),
+            Some(KeyPath::new(0, KeyBranch::Custom("".to_string()), 1))
+        );
+        // Keys past the last generated key are only found within the lookahead
+        let future = km.derive_path_key(&KeyPath::new(0, KeyBranch::Nonce, 4)).unwrap().k;
+        assert_eq!(km.key_path(&future, 4).unwrap(), None);
+        assert_eq!(
+            km.key_path(&future, 5).unwrap(),
+            Some(KeyPath::new(0, KeyBranch::Nonce, 4))
+        );
+        assert_eq!(km.key_path(&RistrettoSecretKey::random(&mut rng), 10).unwrap(), None);
+
+        // The branch indices survive a backup
+        let backup_filename = "test_km_branch_backup.json".to_string();
+        km.to_file_with_params(&backup_filename, "passphrase", &KdfParams::new(4, 8, 1))
+            .unwrap();
+        let restored: KeyManager<RistrettoSecretKey, Sha256> =
+            KeyManager::from_file(&backup_filename, "passphrase").unwrap();
+        remove_file(backup_filename).unwrap();
+        assert_eq!(restored, km);
+    }
 }
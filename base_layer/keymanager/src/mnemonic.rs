@@ -22,12 +22,15 @@
 
 use crate::{diacritics::*, mnemonic_wordlists::*};
 use derive_error::Error;
-use std::slice::Iter;
+use sha2::{Digest, Sha256};
+use std::{cmp::min, slice::Iter};
 use tari_crypto::keys::SecretKey;
 use tari_utilities::{bit::*, byte_array::ByteArrayError};
 
 /// The Mnemonic system simplifies the encoding and decoding of a secret key into and from a Mnemonic word sequence
 /// It can autodetect the language of the Mnemonic word sequence
+/// The first byte of the SHA256 hash of the encoded bytes is appended as a checksum, as in BIP39, so that a 32 byte
+/// secret key fills exactly 24 words and a mistyped word is detected when decoding
 // TODO: Develop a language autodetection mechanism to distinguish between ChineseTraditional and ChineseSimplified
 
 #[derive(Debug, Error)]
@@ -43,8 +46,19 @@ pub enum MnemonicError {
     ByteArrayError(ByteArrayError),
     // Encoding and decoding a mnemonic sequence from bytes require exactly 32 bytes or 24 mnemonic words
     ConversionProblem,
+    // The checksum of the mnemonic sequence does not match, one of the words is probably mistyped
+    ChecksumMismatch,
 }
 
+/// The number of checksum bits appended to the encoded bytes
+const CHECKSUM_BIT_COUNT: usize = 8;
+/// The number of bits encoded by each mnemonic word
+const WORD_BIT_COUNT: usize = 11;
+/// Words further than this edit distance from a mistyped word are not suggested
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+/// The maximum number of suggestions for a mistyped word
+const MAX_SUGGESTIONS: usize = 5;
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum MnemonicLanguage {
     ChineseSimplified,
@@ -84,30 +98,7 @@ impl MnemonicLanguage {
 
 /// Finds and returns the index of a specific word in a mnemonic word list defined by the specified language
 fn find_mnemonic_index_from_word(word: &String, language: &MnemonicLanguage) -> Result<usize, MnemonicError> {
-    let search_result: Result<usize, usize>;
-    let lowercase_word = word.to_lowercase();
-    match language {
-        // Search through languages are ordered according to the predominance (number of speakers in the world) of that
-        // language
-        MnemonicLanguage::ChineseSimplified => {
-            search_result = MNEMONIC_CHINESE_SIMPLIFIED_WORDS.binary_search(&lowercase_word.as_str())
-        },
-        MnemonicLanguage::English => {
-            search_result = MNEMONIC_ENGLISH_WORDS.binary_search(&remove_diacritics(&lowercase_word).as_str())
-        },
-        MnemonicLanguage::French => {
-            search_result = MNEMONIC_FRENCH_WORDS.binary_search(&remove_diacritics(&lowercase_word).as_str())
-        },
-        MnemonicLanguage::Italian => {
-            search_result = MNEMONIC_ITALIAN_WORDS.binary_search(&remove_diacritics(&lowercase_word).as_str())
-        },
-        MnemonicLanguage::Japanese => search_result = MNEMONIC_JAPANESE_WORDS.binary_search(&lowercase_word.as_str()),
-        MnemonicLanguage::Korean => search_result = MNEMONIC_KOREAN_WORDS.binary_search(&lowercase_word.as_str()),
-        MnemonicLanguage::Spanish => {
-            search_result = MNEMONIC_SPANISH_WORDS.binary_search(&remove_diacritics(&lowercase_word).as_str())
-        },
-    }
-    match search_result {
+    match mnemonic_word_list(language).binary_search(&normalise_word(word, language).as_str()) {
         Ok(v) => Ok(v),
         Err(_err) => Err(MnemonicError::WordNotFound),
     }
@@ -132,12 +123,13 @@ fn find_mnemonic_word_from_index(index: usize, language: &MnemonicLanguage) -> R
     }
 }
 
-/// Converts a vector of bytes to a sequence of mnemonic words using the specified language
+/// Converts a vector of bytes, followed by a checksum, to a sequence of mnemonic words using the specified language
 pub fn from_bytes(bytes: Vec<u8>, language: &MnemonicLanguage) -> Result<Vec<String>, MnemonicError> {
     let mut bits = bytes_to_bits(&bytes);
+    bits.extend(uint_to_bits(checksum(&bytes) as usize, CHECKSUM_BIT_COUNT));
 
     // Pad with zeros if length not devisable by 11
-    let group_bit_count = 11;
+    let group_bit_count = WORD_BIT_COUNT;
     let padded_size = ((bits.len() as f32 / group_bit_count as f32).ceil() * group_bit_count as f32) as usize;
     bits.resize(padded_size, false);
 
@@ -179,25 +171,98 @@ pub fn to_bytes_with_language(
     for curr_word in mnemonic_seq {
         match find_mnemonic_index_from_word(curr_word, &language) {
             Ok(index) => {
-                let curr_bits = uint_to_bits(index, 11);
+                let curr_bits = uint_to_bits(index, WORD_BIT_COUNT);
                 bits.extend(curr_bits.iter().map(|&i| i));
             },
             Err(err) => return Err(err),
         }
     }
-    // Discard unused bytes
-    let mut bytes = bits_to_bytes(&bits);
-    for _i in 32..bytes.len() {
-        bytes.pop();
+    if bits.len() != 32 * 8 + CHECKSUM_BIT_COUNT {
+        return Err(MnemonicError::ConversionProblem);
     }
 
-    if bytes.len() == 32 {
-        Ok(bytes)
-    } else {
-        Err(MnemonicError::ConversionProblem)
+    // Split off and verify the checksum
+    let bytes = bits_to_bytes(&bits[..32 * 8]);
+    if bits_to_uint(&bits[32 * 8..]) as u8 != checksum(&bytes) {
+        return Err(MnemonicError::ChecksumMismatch);
+    }
+    Ok(bytes)
+}
+
+/// The checksum of the encoded bytes: the first byte of their SHA256 hash
+fn checksum(bytes: &[u8]) -> u8 {
+    Sha256::digest(bytes)[0]
+}
+
+/// Suggests the words of the mnemonic word list of the specified language that are closest to a mistyped word, the
+/// closest first. Words are compared after lowercasing and, for languages written in the Latin alphabet, removing
+/// diacritics.
+pub fn suggest_words(word: &String, language: &MnemonicLanguage) -> Vec<String> {
+    let normalised_word = normalise_word(word, language);
+    let mut suggestions: Vec<(usize, &str)> = mnemonic_word_list(language)
+        .iter()
+        .map(|candidate| (edit_distance(&normalised_word, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .collect();
+    suggestions.sort();
+    suggestions
+        .iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate.to_string())
+        .collect()
+}
+
+/// Finds the words of a mnemonic sequence that are not in the mnemonic word list of the specified language
+/// # Returns
+/// The position of every unknown word in the sequence, with suggestions to replace it
+pub fn find_unknown_words(mnemonic_seq: &Vec<String>, language: &MnemonicLanguage) -> Vec<(usize, Vec<String>)> {
+    mnemonic_seq
+        .iter()
+        .enumerate()
+        .filter(|(_, word)| find_mnemonic_index_from_word(word, language).is_err())
+        .map(|(position, word)| (position, suggest_words(word, language)))
+        .collect()
+}
+
+fn mnemonic_word_list(language: &MnemonicLanguage) -> &'static [&'static str] {
+    match language {
+        MnemonicLanguage::ChineseSimplified => &MNEMONIC_CHINESE_SIMPLIFIED_WORDS,
+        MnemonicLanguage::English => &MNEMONIC_ENGLISH_WORDS,
+        MnemonicLanguage::French => &MNEMONIC_FRENCH_WORDS,
+        MnemonicLanguage::Italian => &MNEMONIC_ITALIAN_WORDS,
+        MnemonicLanguage::Japanese => &MNEMONIC_JAPANESE_WORDS,
+        MnemonicLanguage::Korean => &MNEMONIC_KOREAN_WORDS,
+        MnemonicLanguage::Spanish => &MNEMONIC_SPANISH_WORDS,
     }
 }
 
+/// Normalises a word the same way it is normalised before it is searched for in the mnemonic word list
+fn normalise_word(word: &String, language: &MnemonicLanguage) -> String {
+    let lowercase_word = word.to_lowercase();
+    match language {
+        MnemonicLanguage::English |
+        MnemonicLanguage::French |
+        MnemonicLanguage::Italian |
+        MnemonicLanguage::Spanish => remove_diacritics(&lowercase_word),
+        _ => lowercase_word,
+    }
+}
+
+/// The Levenshtein distance between two words, counted in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current[j + 1] = min(substitution, min(previous[j + 1], current[j]) + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
 /// Generates a SecretKey that represent the provided mnemonic sequence of words, the language of the mnemonic sequence
 /// is autodetected
 pub fn to_secretkey<K: SecretKey>(mnemonic_seq: &Vec<String>) -> Result<K, MnemonicError> {
@@ -463,4 +528,53 @@ mod test {
             Err(_e) => assert!(true),
         }
     }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let desired_k = RistrettoSecretKey::random(&mut rng);
+        let mut mnemonic_seq = desired_k.to_mnemonic(&MnemonicLanguage::English).unwrap();
+        assert_eq!(mnemonic_seq.len(), 24);
+        // Replace the last word with the valid word that differs only in the first checksum bit
+        let index = find_mnemonic_index_from_word(&mnemonic_seq[23], &MnemonicLanguage::English).unwrap();
+        mnemonic_seq[23] = MNEMONIC_ENGLISH_WORDS[index ^ (1 << 3)].to_string();
+        match RistrettoSecretKey::from_mnemonic(&mnemonic_seq) {
+            Err(MnemonicError::ChecksumMismatch) => {},
+            _ => panic!("A mistyped word must be detected"),
+        }
+        // A missing word is still a conversion problem
+        mnemonic_seq.pop();
+        match RistrettoSecretKey::from_mnemonic(&mnemonic_seq) {
+            Err(MnemonicError::ConversionProblem) => {},
+            _ => panic!("A missing word must be detected"),
+        }
+    }
+
+    #[test]
+    fn test_word_suggestions() {
+        assert_eq!(edit_distance("pony", "pony"), 0);
+        assert_eq!(edit_distance("pny", "pony"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+
+        let suggestions = suggest_words(&"pny".to_string(), &MnemonicLanguage::English);
+        assert!(suggestions.contains(&"pony".to_string()));
+        assert!(suggestions.len() <= MAX_SUGGESTIONS);
+        // An exact match after removing diacritics is suggested first
+        assert_eq!(
+            suggest_words(&"Trìck".to_string(), &MnemonicLanguage::English)[0],
+            "trick".to_string()
+        );
+        assert!(suggest_words(&"xylophonist".to_string(), &MnemonicLanguage::English).is_empty());
+
+        let mnemonic_seq = vec!["clever", "jagwar", "bus", "engag"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        let unknown_words = find_unknown_words(&mnemonic_seq, &MnemonicLanguage::English);
+        assert_eq!(unknown_words.len(), 2);
+        assert_eq!(unknown_words[0].0, 1);
+        assert!(unknown_words[0].1.contains(&"jaguar".to_string()));
+        assert_eq!(unknown_words[1].0, 3);
+        assert!(unknown_words[1].1.contains(&"engage".to_string()));
+    }
 }
@@ -0,0 +1,96 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::error::MiningError;
+use std::convert::TryFrom;
+use tari_core::{
+    transaction::{
+        KernelBuilder,
+        KernelFeatures,
+        OutputFeatures,
+        TransactionKernel,
+        TransactionOutput,
+        UnblindedOutput,
+    },
+    transaction_protocol::{build_challenge, TransactionMetadata},
+    types::{CommitmentFactory, PublicKey, SecretKey, Signature},
+};
+use tari_crypto::{commitment::HomomorphicCommitmentFactory, keys::PublicKey as PK};
+
+/// Construct the coinbase output and kernel that pay the block reward to the miner.
+///
+/// The output commits to `reward` using `spending_key` as the blinding factor and is flagged as a coinbase output.
+/// Since no inputs are spent, the kernel excess is simply the public spending key, $$ k.G $$, and the kernel is
+/// signed with `spending_key` and the provided `nonce`. Coinbase kernels carry no fee.
+pub fn create_coinbase(
+    reward: u64,
+    spending_key: &SecretKey,
+    nonce: SecretKey,
+) -> Result<(TransactionOutput, TransactionKernel), MiningError>
+{
+    let unblinded = UnblindedOutput::new(reward, spending_key.clone(), Some(OutputFeatures::COINBASE_OUTPUT));
+    let output = TransactionOutput::try_from(&unblinded)?;
+
+    let metadata = TransactionMetadata { fee: 0, lock_height: 0 };
+    let public_nonce = PublicKey::from_secret_key(&nonce);
+    let challenge = build_challenge(&public_nonce, &metadata);
+    let signature = Signature::sign(spending_key.clone(), nonce, &challenge)?;
+    let excess = CommitmentFactory::from_public_key(&PublicKey::from_secret_key(spending_key));
+    let kernel = KernelBuilder::new()
+        .with_features(KernelFeatures::COINBASE_KERNEL)
+        .with_fee(metadata.fee)
+        .with_lock_height(metadata.lock_height)
+        .with_excess(&excess)
+        .with_signature(&signature)
+        .build()?;
+    Ok((output, kernel))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::coinbase::create_coinbase;
+    use rand::OsRng;
+    use tari_core::{
+        transaction::{KernelFeatures, OutputFeatures},
+        types::{CommitmentFactory, SecretKey, TariCommitmentValidate},
+    };
+    use tari_crypto::{commitment::HomomorphicCommitmentFactory, keys::SecretKey as SK};
+
+    #[test]
+    fn coinbase_is_valid() {
+        let mut rng = OsRng::new().unwrap();
+        let key = SecretKey::random(&mut rng);
+        let nonce = SecretKey::random(&mut rng);
+        let (output, kernel) = create_coinbase(5000, &key, nonce).unwrap();
+
+        assert_eq!(output.features, OutputFeatures::COINBASE_OUTPUT);
+        assert!(output.commitment.validate(5000, &key));
+        assert!(output.verify_range_proof(None).unwrap());
+
+        assert_eq!(kernel.features, KernelFeatures::COINBASE_KERNEL);
+        assert_eq!(kernel.fee, 0);
+        assert!(kernel.verify_signature().is_ok());
+        // The output minus the reward must equal the kernel excess
+        let reward = CommitmentFactory::create(&SecretKey::default(), &SecretKey::from(5000));
+        assert_eq!(&output.commitment - &reward, kernel.excess);
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+        Arc,
+    },
+    thread,
+    time::Instant,
+};
+use tari_core::{
+    blockheader::BlockHeader,
+    pow::{Difficulty, ProofOfWork},
+};
+use tari_utilities::Hashable;
+
+/// The number of nonces each mining thread tries between checks of the mining deadline
+const DEADLINE_CHECK_INTERVAL: u64 = 1_000;
+
+/// A multi-threaded proof of work miner. Each thread searches a disjoint set of nonces for a block header hash that
+/// meets the target difficulty: starting from the header nonce _s_, thread _i_ of _n_ tries nonces _s + i_,
+/// _s + i + n_, _s + i + 2n_, and so on.
+pub struct Miner {
+    num_threads: usize,
+    stop_flag: Arc<AtomicBool>,
+}
+
+impl Miner {
+    /// Create a new miner that will search for nonces on `num_threads` threads. At least one thread is always used.
+    pub fn new(num_threads: usize) -> Miner {
+        Miner {
+            num_threads: num_threads.max(1),
+            stop_flag: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// The number of threads used to search for nonces
+    pub fn num_threads(&self) -> usize {
+        self.num_threads
+    }
+
+    /// Returns a flag that will abort any mining in progress, and all future calls to `mine`, when it is set
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop_flag.clone()
+    }
+
+    /// Search for a nonce that satisfies the proof of work of `header`. The solved header is returned, or `None` if
+    /// the nonce space was exhausted, the deadline passed or the miner was stopped before a solution was found.
+    pub fn mine(&self, header: BlockHeader, deadline: Option<Instant>) -> Option<BlockHeader> {
+        let target = header.pow.target_difficulty;
+        self.mine_to_target(header, target, deadline)
+    }
+
+    /// Search for a nonce for which the hash of `header` achieves `target` difficulty, rather than the target in the
+    /// header itself. Pool miners use this to find shares, which have a lower difficulty than the block target.
+    pub fn mine_to_target(
+        &self,
+        header: BlockHeader,
+        target: Difficulty,
+        deadline: Option<Instant>,
+    ) -> Option<BlockHeader>
+    {
+        let found_flag = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+        let step = self.num_threads as u64;
+        let handles = (0..self.num_threads)
+            .map(|i| {
+                let mut header = header.clone();
+                let sender = sender.clone();
+                let found_flag = found_flag.clone();
+                let stop_flag = self.stop_flag.clone();
+                thread::spawn(move || {
+                    let mut nonce = match header.nonce.checked_add(i as u64) {
+                        Some(n) => n,
+                        None => return,
+                    };
+                    let mut attempts = 0u64;
+                    loop {
+                        if found_flag.load(Ordering::Relaxed) || stop_flag.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        if attempts % DEADLINE_CHECK_INTERVAL == 0 && deadline.map_or(false, |d| Instant::now() > d) {
+                            return;
+                        }
+                        header.nonce = nonce;
+                        if ProofOfWork::achieved_difficulty(&header.hash()) >= target {
+                            found_flag.store(true, Ordering::Relaxed);
+                            let _ = sender.send(header);
+                            return;
+                        }
+                        nonce = match nonce.checked_add(step) {
+                            Some(n) => n,
+                            None => return,
+                        };
+                        attempts += 1;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        // Only the mining threads hold senders now, so `recv` fails once they have all given up
+        drop(sender);
+        let result = receiver.recv().ok();
+        found_flag.store(true, Ordering::Relaxed);
+        for handle in handles {
+            let _ = handle.join();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::miner::Miner;
+    use chrono::Utc;
+    use std::{
+        sync::atomic::Ordering,
+        time::{Duration, Instant},
+    };
+    use tari_core::{blockheader::BlockHeader, pow::ProofOfWork, types::PublicKey};
+    use tari_utilities::Hashable;
+
+    fn make_header(difficulty: u64) -> BlockHeader {
+        BlockHeader {
+            version: 0,
+            height: 1,
+            prev_hash: [1u8; 32],
+            timestamp: Utc::now(),
+            output_mmr: [2u8; 32],
+            kernel_mmr: [3u8; 32],
+            total_kernel_offset: PublicKey::default(),
+            nonce: 0,
+            pow: ProofOfWork::new(difficulty),
+        }
+    }
+
+    #[test]
+    fn mine_header() {
+        let miner = Miner::new(4);
+        let header = make_header(500);
+        let solved = miner.mine(header, None).unwrap();
+        assert!(solved.validate_pow());
+        assert_eq!(solved.height, 1);
+    }
+
+    #[test]
+    fn mine_to_lower_target() {
+        let miner = Miner::new(2);
+        let mut header = make_header(u64::max_value());
+        header.nonce = 1_000;
+        let share = miner.mine_to_target(header, 100, None).unwrap();
+        assert!(share.nonce >= 1_000);
+        assert!(ProofOfWork::achieved_difficulty(&share.hash()) >= 100);
+        assert!(!share.validate_pow());
+    }
+
+    #[test]
+    fn deadline_expires() {
+        let miner = Miner::new(2);
+        let header = make_header(u64::max_value());
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert!(miner.mine(header, Some(deadline)).is_none());
+    }
+
+    #[test]
+    fn stopped_miner() {
+        let miner = Miner::new(2);
+        miner.stop_flag().store(true, Ordering::Relaxed);
+        assert!(miner.mine(make_header(1), None).is_none());
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{coinbase::create_coinbase, error::MiningError};
+use serde_derive::{Deserialize, Serialize};
+use tari_core::{
+    block::{AggregateBody, Block},
+    blockheader::BlockHeader,
+    transaction::{TransactionKernel, TransactionOutput},
+    types::SecretKey,
+};
+
+/// A template for the next block on top of a base node's chain tip. The body contains the transactions the base node
+/// selected for the block, but not the coinbase, which the miner adds with `add_coinbase`. The header MMR roots
+/// are only filled in once the base node completes the template into a new block.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NewBlockTemplate {
+    pub header: BlockHeader,
+    pub body: AggregateBody,
+    /// The block reward, excluding fees, that the coinbase output may claim
+    pub reward: u64,
+}
+
+impl NewBlockTemplate {
+    /// The total value the coinbase output may claim, i.e. the block reward plus all the fees in the template
+    pub fn coinbase_value(&self) -> u64 {
+        self.reward + self.body.kernels.iter().map(|k| k.fee).sum::<u64>()
+    }
+
+    /// Add the coinbase output and kernel to the template body
+    pub fn add_coinbase(&mut self, output: TransactionOutput, kernel: TransactionKernel) {
+        self.body.add_output(output);
+        self.body.add_kernel(kernel);
+    }
+
+    /// Convenience function that builds a coinbase for the full coinbase value and adds it to the template
+    pub fn with_coinbase(mut self, spending_key: &SecretKey, nonce: SecretKey) -> Result<Self, MiningError> {
+        let (output, kernel) = create_coinbase(self.coinbase_value(), spending_key, nonce)?;
+        self.add_coinbase(output, kernel);
+        Ok(self)
+    }
+}
+
+/// The requests a miner sends to a base node
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MiningRequest {
+    /// Request a template for the next block on top of the current chain tip
+    GetNewBlockTemplate,
+    /// Ask the base node to complete a template, which must include the miner's coinbase, into a block that is ready
+    /// to be mined
+    GetNewBlock(NewBlockTemplate),
+    /// Submit a block with a solved proof of work
+    SubmitBlock(Block),
+}
+
+/// The responses a base node sends back to a miner
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MiningResponse {
+    NewBlockTemplate(NewBlockTemplate),
+    NewBlock(Block),
+    BlockAccepted,
+    /// The request could not be fulfilled, or the submitted block was rejected, for the given reason
+    Rejected(String),
+}
@@ -0,0 +1,663 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    domain_dispatcher::{DomainMessageDispatcher, PeerContext},
+    peer::PeerType,
+    tari_message::{NetMessage, TariMessage, TariMessageType},
+};
+use chrono::{DateTime, Duration, Utc};
+use derive_error::Error;
+use rand::{OsRng, RngCore};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Mul,
+    sync::{Arc, Mutex, RwLock},
+};
+use tari_comms::{
+    connection::{
+        message::IdentityFlags,
+        net_address::{net_address_with_stats::NetAddressWithStats, net_addresses::NetAddresses},
+        NetAddress,
+    },
+    inbound_message_service::message_dispatcher::DispatchError,
+    outbound_message_service::{
+        broadcast_strategy::BroadcastStrategy,
+        outbound_message_service::{OutboundError, OutboundMessageService},
+    },
+    peer_manager::{
+        ban_list::{BanList, BanListError, Misbehaviour},
+        manager::PeerManagerError,
+        node_id::{NodeId, NodeIdError},
+        node_identity::NodeIdentity,
+        peer::{Peer, PeerFlags},
+    },
+};
+use tari_crypto::keys::{DiffieHellmanSharedSecret, PublicKey, SecretKey};
+use tari_storage::{hashmap_store::HashmapStore, keyvalue_store::DataStore};
+use tari_utilities::Hashable;
+
+/// The default maximum number of nodes returned in response to a discovery request
+const DEFAULT_RESPONSE_PEER_COUNT: usize = 8;
+/// The default number of seconds that a discovery request is pending before a response to it is no longer accepted
+const DEFAULT_REQUEST_TIMEOUT_SECS: i64 = 60;
+/// The default maximum number of pending discovery requests
+const DEFAULT_MAX_PENDING_REQUESTS: usize = 1000;
+
+#[derive(Debug, Error)]
+pub enum DiscoveryError {
+    /// A discovery message could not be sent
+    OutboundError(OutboundError),
+    /// A discovered node could not be added to or found in the peer manager
+    PeerManagerError(PeerManagerError),
+    /// The node id of a node could not be derived from its public key
+    NodeIdError(NodeIdError),
+    /// The random number generator could not be created
+    RandomError(rand::Error),
+    /// A protocol violation of a node could not be reported to the ban list
+    BanListError(BanListError),
+    /// A discovery message could not be serialized
+    #[error(msg_embedded, no_from, non_std)]
+    SerializationError(String),
+    /// The node id of an announced node does not belong to its public key
+    InvalidNodeId,
+    /// The announcement of a node was not sent by the node itself
+    AnnouncementSourceMismatch,
+    /// A discovery response was received that does not answer a pending discovery request of this node
+    UnexpectedResponse,
+    /// The lock on the peer types of discovered nodes or on the pending discovery requests has been poisoned
+    PoisonedAccess,
+}
+
+impl From<bincode::Error> for DiscoveryError {
+    fn from(e: bincode::Error) -> Self {
+        DiscoveryError::SerializationError(e.to_string())
+    }
+}
+
+impl DiscoveryError {
+    /// Returns true if the error was caused by a discovery message that the protocol does not allow
+    pub fn is_protocol_violation(&self) -> bool {
+        match self {
+            DiscoveryError::InvalidNodeId |
+            DiscoveryError::AnnouncementSourceMismatch |
+            DiscoveryError::UnexpectedResponse => true,
+            _ => false,
+        }
+    }
+}
+
+/// A node that is contacted to join the network
+#[derive(Clone, Debug)]
+pub struct SeedPeer<PubKey> {
+    pub public_key: PubKey,
+    pub net_addresses: Vec<NetAddress>,
+}
+
+/// Configuration for the discovery of nodes
+#[derive(Clone, Debug)]
+pub struct DiscoveryConfig<PubKey> {
+    /// The nodes that are contacted to join the network and discover the nodes closest to this node
+    pub seed_peers: Vec<SeedPeer<PubKey>>,
+    /// The maximum number of nodes returned in response to a discovery request
+    pub response_peer_count: usize,
+    /// The time that a discovery request is pending, responses that arrive later are not accepted
+    pub request_timeout: Duration,
+    /// The maximum number of pending discovery requests, the oldest request is dropped to make room for a new one
+    pub max_pending_requests: usize,
+}
+
+impl<PubKey> Default for DiscoveryConfig<PubKey> {
+    fn default() -> Self {
+        DiscoveryConfig {
+            seed_peers: Vec::new(),
+            response_peer_count: DEFAULT_RESPONSE_PEER_COUNT,
+            request_timeout: Duration::seconds(DEFAULT_REQUEST_TIMEOUT_SECS),
+            max_pending_requests: DEFAULT_MAX_PENDING_REQUESTS,
+        }
+    }
+}
+
+/// The description of a node that it announces when it joins the network, and that is used to describe the nodes in
+/// a discovery response
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NodeAnnouncement<PubKey> {
+    pub node_id: NodeId,
+    pub public_key: PubKey,
+    pub net_addresses: Vec<NetAddress>,
+    pub peer_type: PeerType,
+}
+
+/// A request for the nodes closest to the target. The request carries the announcement of the requesting node, so
+/// that the node it is sent to can respond to it, and a random nonce that the response must repeat.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DiscoverRequest<PubKey> {
+    pub announcement: NodeAnnouncement<PubKey>,
+    pub target: NodeId,
+    pub nonce: u64,
+}
+
+/// The response to a DiscoverRequest, with the announcement of the responding node and the nodes it knows that are
+/// closest to the target
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DiscoverResponse<PubKey> {
+    pub announcement: NodeAnnouncement<PubKey>,
+    pub target: NodeId,
+    pub nonce: u64,
+    pub nodes: Vec<NodeAnnouncement<PubKey>>,
+}
+
+/// The DiscoveryService populates the peer manager with the nodes of the network. A node joins the network by sending
+/// a `Join` announcement, and a `Discover` request for the nodes closest to itself, to its seed peers. The nodes in a
+/// discovery response are added to the peer manager, but are not contacted until this node chooses to join them.
+///
+/// Announced node ids must belong to the announced public key, and a node can only announce itself, so that nodes
+/// can't be impersonated. Only responses to the pending discovery requests of this node are accepted, and at most
+/// `response_peer_count` nodes are taken from a response. Nodes learnt about from discovery responses are only checked
+/// for a valid node id, so they are added if they are not known yet, but never change a known peer. A node that sends
+/// a discovery message that the protocol does not allow is reported to the ban list.
+pub struct DiscoveryService<PubKey, SecKey, DS = HashmapStore>
+where PubKey: PublicKey
+{
+    announcement: NodeAnnouncement<PubKey>,
+    outbound_message_service: OutboundMessageService<PubKey, SecKey, DS>,
+    ban_list: Arc<BanList<PubKey, DS>>,
+    config: DiscoveryConfig<PubKey>,
+    peer_types: RwLock<HashMap<NodeId, PeerType>>,
+    pending_requests: Mutex<PendingRequests>,
+}
+
+impl<PubKey, SecKey, DS> DiscoveryService<PubKey, SecKey, DS>
+where
+    PubKey: PublicKey<K = SecKey> + Hashable + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey> + Serialize,
+    SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize,
+    DS: DataStore,
+{
+    /// Construct a DiscoveryService that announces the node with the identity as a node of the peer type that is
+    /// reachable on the net addresses. Discovered nodes are added to the peer manager of the OutboundMessageService,
+    /// and protocol violations are reported to the ban list.
+    pub fn new(
+        node_identity: &NodeIdentity<PubKey, SecKey>,
+        net_addresses: Vec<NetAddress>,
+        peer_type: PeerType,
+        outbound_message_service: OutboundMessageService<PubKey, SecKey, DS>,
+        ban_list: Arc<BanList<PubKey, DS>>,
+        config: DiscoveryConfig<PubKey>,
+    ) -> DiscoveryService<PubKey, SecKey, DS>
+    {
+        let pending_requests = PendingRequests::new(config.max_pending_requests, config.request_timeout);
+        DiscoveryService {
+            announcement: NodeAnnouncement {
+                node_id: node_identity.node_id.clone(),
+                public_key: node_identity.public_key.clone(),
+                net_addresses,
+                peer_type,
+            },
+            outbound_message_service,
+            ban_list,
+            config,
+            peer_types: RwLock::new(HashMap::new()),
+            pending_requests: Mutex::new(pending_requests),
+        }
+    }
+
+    /// The announcement this node makes of itself
+    pub fn announcement(&self) -> &NodeAnnouncement<PubKey> {
+        &self.announcement
+    }
+
+    /// The peer type that the node with the node id announced, if it has been discovered
+    pub fn peer_type(&self, node_id: &NodeId) -> Result<Option<PeerType>, DiscoveryError> {
+        Ok(self
+            .peer_types
+            .read()
+            .map_err(|_| DiscoveryError::PoisonedAccess)?
+            .get(node_id)
+            .cloned())
+    }
+
+    /// Add the seed peers to the peer manager, and join the network through them
+    pub fn bootstrap(&self) -> Result<(), DiscoveryError> {
+        let peer_manager = self.outbound_message_service.peer_manager();
+        for seed_peer in &self.config.seed_peers {
+            let node_id = NodeId::from_key(&seed_peer.public_key)?;
+            if !peer_manager.exists(&node_id)? {
+                peer_manager.add_peer(Peer::new(
+                    seed_peer.public_key.clone(),
+                    node_id.clone(),
+                    net_addresses_with_stats(&seed_peer.net_addresses),
+                    PeerFlags::default(),
+                ))?;
+            }
+            self.join(&node_id)?;
+            self.discover(&node_id, self.announcement.node_id.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Announce this node to the known peer with the node id
+    pub fn join(&self, node_id: &NodeId) -> Result<(), DiscoveryError> {
+        self.send(node_id, NetMessage::Join, &self.announcement)
+    }
+
+    /// Request the nodes closest to the target from the known peer with the node id. The request is pending until the
+    /// peer responds to it or the request timeout has passed.
+    pub fn discover(&self, node_id: &NodeId, target: NodeId) -> Result<(), DiscoveryError> {
+        let request = DiscoverRequest {
+            announcement: self.announcement.clone(),
+            target,
+            nonce: OsRng::new()?.next_u64(),
+        };
+        let pending_request = (node_id.clone(), request.target.clone(), request.nonce);
+        self.pending_requests
+            .lock()
+            .map_err(|_| DiscoveryError::PoisonedAccess)?
+            .insert_at(pending_request.clone(), Utc::now());
+        // The lock is not held while sending, so that responses can be handled in the meantime
+        self.send(node_id, NetMessage::Discover, &request).or_else(|e| {
+            self.pending_requests
+                .lock()
+                .map_err(|_| DiscoveryError::PoisonedAccess)?
+                .remove(&pending_request);
+            Err(e)
+        })
+    }
+
+    /// Add or update the node that announced itself in the peer manager
+    pub fn handle_join(
+        &self,
+        announcement: NodeAnnouncement<PubKey>,
+        peer_context: &PeerContext<PubKey>,
+    ) -> Result<(), DiscoveryError>
+    {
+        let result = check_source(&announcement, peer_context).and_then(|_| self.add_node(&announcement));
+        self.report_protocol_violation(result, peer_context)
+    }
+
+    /// Add or update the requesting node in the peer manager, and respond with the known nodes closest to the target
+    pub fn handle_discover(
+        &self,
+        request: DiscoverRequest<PubKey>,
+        peer_context: &PeerContext<PubKey>,
+    ) -> Result<(), DiscoveryError>
+    {
+        let result = self.respond_to_discover(request, peer_context);
+        self.report_protocol_violation(result, peer_context)
+    }
+
+    fn respond_to_discover(
+        &self,
+        request: DiscoverRequest<PubKey>,
+        peer_context: &PeerContext<PubKey>,
+    ) -> Result<(), DiscoveryError>
+    {
+        check_source(&request.announcement, peer_context)?;
+        self.add_node(&request.announcement)?;
+        let requester = &request.announcement.node_id;
+        // One extra peer is selected in case the requesting node is among the closest peers
+        let closest_peers = self
+            .outbound_message_service
+            .peer_manager()
+            .closest_peers(&request.target, self.config.response_peer_count + 1)?;
+        let peer_types = self.peer_types.read().map_err(|_| DiscoveryError::PoisonedAccess)?;
+        let nodes = closest_peers
+            .into_iter()
+            .filter(|peer| peer.node_id != *requester)
+            .filter_map(|peer| {
+                peer_types.get(&peer.node_id).map(|peer_type| NodeAnnouncement {
+                    node_id: peer.node_id,
+                    public_key: peer.public_key,
+                    net_addresses: peer.addresses.net_addresses(),
+                    peer_type: *peer_type,
+                })
+            })
+            .take(self.config.response_peer_count)
+            .collect();
+        drop(peer_types);
+        let response = DiscoverResponse {
+            announcement: self.announcement.clone(),
+            target: request.target,
+            nonce: request.nonce,
+            nodes,
+        };
+        self.send(requester, NetMessage::DiscoverResponse, &response)
+    }
+
+    /// Add the responding node to the peer manager, and the nodes in the response that are not known yet. The response
+    /// must answer a pending discovery request that this node sent to the responding node, and arrive before the
+    /// request timeout has passed.
+    pub fn handle_discover_response(
+        &self,
+        response: DiscoverResponse<PubKey>,
+        peer_context: &PeerContext<PubKey>,
+    ) -> Result<(), DiscoveryError>
+    {
+        let result = self.accept_discover_response(response, peer_context);
+        self.report_protocol_violation(result, peer_context)
+    }
+
+    fn accept_discover_response(
+        &self,
+        mut response: DiscoverResponse<PubKey>,
+        peer_context: &PeerContext<PubKey>,
+    ) -> Result<(), DiscoveryError>
+    {
+        check_source(&response.announcement, peer_context)?;
+        let pending_request = (
+            response.announcement.node_id.clone(),
+            response.target.clone(),
+            response.nonce,
+        );
+        if !self
+            .pending_requests
+            .lock()
+            .map_err(|_| DiscoveryError::PoisonedAccess)?
+            .take_at(&pending_request, Utc::now())
+        {
+            return Err(DiscoveryError::UnexpectedResponse);
+        }
+        self.add_node(&response.announcement)?;
+        response.nodes.truncate(self.config.response_peer_count);
+        for announcement in &response.nodes {
+            self.add_discovered_node(announcement)?;
+        }
+        Ok(())
+    }
+
+    /// Add the node that announced itself to the peer manager, or add the announced net addresses to it if it is
+    /// already known. Announcements of this node are ignored.
+    fn add_node(&self, announcement: &NodeAnnouncement<PubKey>) -> Result<(), DiscoveryError> {
+        if !self.is_other_node(announcement)? {
+            return Ok(());
+        }
+        self.peer_types
+            .write()
+            .map_err(|_| DiscoveryError::PoisonedAccess)?
+            .insert(announcement.node_id.clone(), announcement.peer_type);
+        let peer_manager = self.outbound_message_service.peer_manager();
+        match peer_manager.add_peer(announcement_peer(announcement)) {
+            Ok(()) => Ok(()),
+            Err(PeerManagerError::DuplicatePeerError) => {
+                peer_manager.modify_peer(&announcement.node_id, |peer| {
+                    for net_address in &announcement.net_addresses {
+                        // Net addresses that are already known keep their usage stats
+                        let _ = peer.addresses.add_net_address(net_address);
+                    }
+                })?;
+                Ok(())
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Add a node that was described by another node to the peer manager, if it is not known yet. The description
+    /// can't be verified, so the peer and peer type of a known node are left unchanged.
+    fn add_discovered_node(&self, announcement: &NodeAnnouncement<PubKey>) -> Result<(), DiscoveryError> {
+        if !self.is_other_node(announcement)? {
+            return Ok(());
+        }
+        let peer_manager = self.outbound_message_service.peer_manager();
+        match peer_manager.add_peer(announcement_peer(announcement)) {
+            Ok(()) => {
+                self.peer_types
+                    .write()
+                    .map_err(|_| DiscoveryError::PoisonedAccess)?
+                    .entry(announcement.node_id.clone())
+                    .or_insert(announcement.peer_type);
+                Ok(())
+            },
+            Err(PeerManagerError::DuplicatePeerError) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Report the node that sent a discovery message to the ban list if handling the message failed because the
+    /// message violated the protocol. The result of handling the message is returned.
+    fn report_protocol_violation(
+        &self,
+        result: Result<(), DiscoveryError>,
+        peer_context: &PeerContext<PubKey>,
+    ) -> Result<(), DiscoveryError>
+    {
+        match result {
+            Err(e) if e.is_protocol_violation() => {
+                self.ban_list
+                    .report_misbehaviour(&peer_context.node_id, Misbehaviour::ProtocolViolation)?;
+                Err(e)
+            },
+            result => result,
+        }
+    }
+
+    /// Check that the announced node id belongs to the announced public key
+    /// # Returns
+    /// False if the announcement is of this node
+    fn is_other_node(&self, announcement: &NodeAnnouncement<PubKey>) -> Result<bool, DiscoveryError> {
+        if NodeId::from_key(&announcement.public_key)? != announcement.node_id {
+            return Err(DiscoveryError::InvalidNodeId);
+        }
+        Ok(announcement.node_id != self.announcement.node_id)
+    }
+
+    /// Serialize a discovery message and send it to the known peer with the node id. Discovery messages describe public
+    /// information, so they are not encrypted.
+    fn send<T: Serialize>(&self, node_id: &NodeId, message_type: u8, message: &T) -> Result<(), DiscoveryError> {
+        let body = TariMessage::new(TariMessageType::new(message_type), bincode::serialize(message)?).to_frame();
+        Ok(self.outbound_message_service.send(
+            BroadcastStrategy::Direct(node_id.clone()),
+            IdentityFlags::empty(),
+            &body,
+            &mut OsRng::new()?,
+        )?)
+    }
+}
+
+/// A node can only announce itself
+/// A pending discovery request, identified by the node id of the node it was sent to, its target and its nonce
+type PendingRequest = (NodeId, NodeId, u64);
+
+/// The discovery requests that have been sent and are waiting for a response, together with the time they were sent.
+/// Requests are dropped when they time out, and the oldest request is dropped when the maximum number of pending
+/// requests is reached, so that requests that are never answered don't accumulate.
+struct PendingRequests {
+    capacity: usize,
+    timeout: Duration,
+    sent_at: HashMap<PendingRequest, DateTime<Utc>>,
+    order: VecDeque<(DateTime<Utc>, PendingRequest)>,
+}
+
+impl PendingRequests {
+    fn new(capacity: usize, timeout: Duration) -> PendingRequests {
+        PendingRequests {
+            capacity,
+            timeout,
+            sent_at: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Add the request that was sent at the given time
+    fn insert_at(&mut self, request: PendingRequest, now: DateTime<Utc>) {
+        self.remove_expired(now);
+        while !self.order.is_empty() && self.sent_at.len() >= self.capacity {
+            self.remove_oldest();
+        }
+        self.sent_at.insert(request.clone(), now);
+        self.order.push_back((now, request));
+    }
+
+    /// Remove the request, returning true if it was pending and has not timed out
+    fn take_at(&mut self, request: &PendingRequest, now: DateTime<Utc>) -> bool {
+        self.remove_expired(now);
+        self.remove(request)
+    }
+
+    /// Remove the request, returning true if it was pending
+    fn remove(&mut self, request: &PendingRequest) -> bool {
+        self.sent_at.remove(request).is_some()
+    }
+
+    fn remove_expired(&mut self, now: DateTime<Utc>) {
+        let timeout = self.timeout;
+        while self
+            .order
+            .front()
+            .map_or(false, |(sent_at, _)| *sent_at + timeout <= now)
+        {
+            self.remove_oldest();
+        }
+    }
+
+    // Entries of requests that have been removed are skipped, the request is only removed if it was not sent again
+    // since the entry was added
+    fn remove_oldest(&mut self) {
+        if let Some((sent_at, request)) = self.order.pop_front() {
+            if self.sent_at.get(&request) == Some(&sent_at) {
+                self.sent_at.remove(&request);
+            }
+        }
+    }
+}
+
+fn check_source<PubKey: PublicKey>(
+    announcement: &NodeAnnouncement<PubKey>,
+    peer_context: &PeerContext<PubKey>,
+) -> Result<(), DiscoveryError>
+{
+    if announcement.public_key == peer_context.public_key {
+        Ok(())
+    } else {
+        Err(DiscoveryError::AnnouncementSourceMismatch)
+    }
+}
+
+/// A peer with the announced identity and net addresses
+fn announcement_peer<PubKey: PublicKey>(announcement: &NodeAnnouncement<PubKey>) -> Peer<PubKey> {
+    Peer::new(
+        announcement.public_key.clone(),
+        announcement.node_id.clone(),
+        net_addresses_with_stats(&announcement.net_addresses),
+        PeerFlags::default(),
+    )
+}
+
+fn net_addresses_with_stats(net_addresses: &[NetAddress]) -> NetAddresses {
+    NetAddresses::new(net_addresses.iter().cloned().map(NetAddressWithStats::from).collect())
+}
+
+/// Register the handlers of the discovery messages with the domain message dispatcher, which pass the messages to the
+/// discovery service
+pub fn register_discovery_message_handlers<PubKey, SecKey, DS>(
+    domain_dispatcher: DomainMessageDispatcher<PubKey, SecKey>,
+    discovery_service: Arc<DiscoveryService<PubKey, SecKey, DS>>,
+) -> DomainMessageDispatcher<PubKey, SecKey>
+where
+    PubKey: PublicKey<K = SecKey>
+        + Hashable
+        + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>
+        + Serialize
+        + DeserializeOwned
+        + Send
+        + Sync
+        + 'static,
+    SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize + Send + Sync + 'static,
+    DS: DataStore + Send + Sync + 'static,
+{
+    let join_discovery_service = discovery_service.clone();
+    let discover_discovery_service = discovery_service.clone();
+    domain_dispatcher
+        .route(
+            TariMessageType::new(NetMessage::Join),
+            move |announcement: NodeAnnouncement<PubKey>, peer_context: PeerContext<PubKey>| {
+                join_discovery_service
+                    .handle_join(announcement, &peer_context)
+                    .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))
+            },
+        )
+        .route(
+            TariMessageType::new(NetMessage::Discover),
+            move |request: DiscoverRequest<PubKey>, peer_context: PeerContext<PubKey>| {
+                discover_discovery_service
+                    .handle_discover(request, &peer_context)
+                    .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))
+            },
+        )
+        .route(
+            TariMessageType::new(NetMessage::DiscoverResponse),
+            move |response: DiscoverResponse<PubKey>, peer_context: PeerContext<PubKey>| {
+                discovery_service
+                    .handle_discover_response(response, &peer_context)
+                    .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))
+            },
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pending_request(nonce: u64) -> PendingRequest {
+        (NodeId::new(), NodeId::new(), nonce)
+    }
+
+    #[test]
+    fn pending_requests_time_out() {
+        let mut pending_requests = PendingRequests::new(10, Duration::seconds(60));
+        let now = Utc::now();
+        pending_requests.insert_at(pending_request(1), now);
+        pending_requests.insert_at(pending_request(2), now);
+
+        // A request can only be answered once
+        assert!(pending_requests.take_at(&pending_request(1), now + Duration::seconds(30)));
+        assert!(!pending_requests.take_at(&pending_request(1), now + Duration::seconds(30)));
+        assert!(!pending_requests.take_at(&pending_request(3), now + Duration::seconds(30)));
+
+        // Responses that arrive after the request timeout are not accepted
+        assert!(!pending_requests.take_at(&pending_request(2), now + Duration::seconds(60)));
+        assert!(pending_requests.sent_at.is_empty());
+        assert!(pending_requests.order.is_empty());
+    }
+
+    #[test]
+    fn pending_requests_are_capped() {
+        let mut pending_requests = PendingRequests::new(3, Duration::seconds(60));
+        let now = Utc::now();
+        for nonce in 0..5 {
+            pending_requests.insert_at(pending_request(nonce), now + Duration::seconds(nonce as i64));
+        }
+        assert_eq!(pending_requests.sent_at.len(), 3);
+
+        // The oldest requests were dropped to make room for the newer ones
+        let now = now + Duration::seconds(5);
+        assert!(!pending_requests.take_at(&pending_request(0), now));
+        assert!(!pending_requests.take_at(&pending_request(1), now));
+        for nonce in 2..5 {
+            assert!(pending_requests.take_at(&pending_request(nonce), now));
+        }
+
+        // A request that failed to send is no longer pending
+        pending_requests.insert_at(pending_request(5), now);
+        assert!(pending_requests.remove(&pending_request(5)));
+        assert!(!pending_requests.take_at(&pending_request(5), now));
+    }
+}
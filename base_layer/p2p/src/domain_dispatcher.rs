@@ -0,0 +1,257 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::tari_message::{TariMessage, TariMessageType};
+use derive_error::Error;
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+use tari_comms::{
+    connection::message::{decrypt_envelope_body, IdentityFlags, MessageEnvelopeHeader, MessageError},
+    inbound_message_service::{
+        message_context::MessageContext,
+        message_dispatcher::{DispatchError, Dispatchable, MessageDispatcher},
+    },
+    peer_manager::{
+        node_id::{NodeId, NodeIdError},
+        node_identity::NodeIdentity,
+    },
+};
+use tari_crypto::keys::{DiffieHellmanSharedSecret, PublicKey, SecretKey};
+use tari_utilities::Hashable;
+
+#[derive(Debug, Error)]
+pub enum DomainDispatchError {
+    /// The message envelope body could not be decrypted
+    MessageError(MessageError),
+    /// The node id of the message source could not be derived
+    NodeIdError(NodeIdError),
+    /// The node identity has no secret key to decrypt messages with
+    UndefinedSecretKey,
+    /// The message envelope body does not contain a Tari message
+    EmptyMessage,
+}
+
+/// The peer context of a domain message, which describes the node that the message was received from
+#[derive(Clone, Debug)]
+pub struct PeerContext<PubKey> {
+    /// The public key of the node that sent the message
+    pub public_key: PubKey,
+    /// The node id of the node that sent the message
+    pub node_id: NodeId,
+    /// The header of the message envelope that the message arrived in
+    pub message_envelope_header: MessageEnvelopeHeader<PubKey>,
+}
+
+/// A decrypted Tari message with the peer context it was received with
+#[derive(Clone, Debug)]
+pub struct DomainMessageContext<PubKey> {
+    pub message: TariMessage,
+    pub peer_context: PeerContext<PubKey>,
+}
+
+impl<PubKey> Dispatchable for DomainMessageContext<PubKey> {
+    /// Domain messages are dispatched by their TariMessageType
+    fn dispatch_type(&self) -> u32 {
+        u32::from(self.message.message_type.value())
+    }
+}
+
+/// The DomainMessageDispatcher is the second dispatch stage for messages that the comms layer has determined must be
+/// handled by this node. The message envelope body is decrypted, read as a TariMessage and dispatched on its
+/// TariMessageType to the typed handler that a service, such as the wallet, mempool, chain sync or DHT, registered for
+/// it. Handlers receive the deserialized message with the peer context of its sender.
+///
+/// A DomainMessageDispatcher is connected to the comms layer by dispatching the messages of the comms Handle route
+/// with it.
+pub struct DomainMessageDispatcher<PubKey, SecKey> {
+    node_identity: Arc<NodeIdentity<PubKey, SecKey>>,
+    message_dispatcher: MessageDispatcher<DomainMessageContext<PubKey>>,
+}
+
+impl<PubKey, SecKey> Clone for DomainMessageDispatcher<PubKey, SecKey> {
+    fn clone(&self) -> Self {
+        DomainMessageDispatcher {
+            node_identity: self.node_identity.clone(),
+            message_dispatcher: self.message_dispatcher.clone(),
+        }
+    }
+}
+
+impl<PubKey, SecKey> DomainMessageDispatcher<PubKey, SecKey>
+where
+    PubKey: PublicKey<K = SecKey> + Hashable + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey> + 'static,
+    SecKey: SecretKey,
+{
+    /// Construct a DomainMessageDispatcher with no registered handlers, that decrypts messages for the node with the
+    /// given identity
+    pub fn new(node_identity: Arc<NodeIdentity<PubKey, SecKey>>) -> DomainMessageDispatcher<PubKey, SecKey> {
+        DomainMessageDispatcher {
+            node_identity,
+            message_dispatcher: MessageDispatcher::new(),
+        }
+    }
+
+    /// Register the handler for messages of the message type. The body of these messages is deserialized into a `T`
+    /// before it is passed to the handler, and a message that cannot be deserialized is rejected without calling the
+    /// handler.
+    pub fn route<T, F>(mut self, message_type: TariMessageType, handler_function: F) -> Self
+    where
+        T: DeserializeOwned,
+        F: Fn(T, PeerContext<PubKey>) -> Result<(), DispatchError> + Send + Sync + 'static,
+    {
+        self.message_dispatcher = self.message_dispatcher.route(
+            u32::from(message_type.value()),
+            move |domain_message_context: DomainMessageContext<PubKey>| {
+                let message = bincode::deserialize(&domain_message_context.message.body)
+                    .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))?;
+                handler_function(message, domain_message_context.peer_context)
+            },
+        );
+        self
+    }
+
+    /// Decrypt and read the Tari message in the message context, and dispatch it to the handler registered for its
+    /// message type
+    pub fn dispatch(&self, message_context: MessageContext<PubKey>) -> Result<(), DispatchError> {
+        let domain_message_context = self
+            .domain_message_context(message_context)
+            .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))?;
+        self.message_dispatcher.dispatch(domain_message_context)
+    }
+
+    fn domain_message_context(
+        &self,
+        message_context: MessageContext<PubKey>,
+    ) -> Result<DomainMessageContext<PubKey>, DomainDispatchError>
+    {
+        let header = message_context.message_envelope_header;
+        let body = if header.flags.contains(IdentityFlags::ENCRYPTED) {
+            let secret_key = self
+                .node_identity
+                .secret_key
+                .as_ref()
+                .ok_or(DomainDispatchError::UndefinedSecretKey)?;
+            decrypt_envelope_body(secret_key, &header.source, &message_context.message_envelope_body)?
+        } else {
+            message_context.message_envelope_body
+        };
+        let message = TariMessage::from_frame(&body).ok_or(DomainDispatchError::EmptyMessage)?;
+        let peer_context = PeerContext {
+            public_key: header.source.clone(),
+            node_id: NodeId::from_key(&header.source)?,
+            message_envelope_header: header,
+        };
+        Ok(DomainMessageContext { message, peer_context })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tari_message::{BlockchainMessage, WalletMessage};
+    use std::sync::Mutex;
+    use tari_comms::connection::message::{encrypt_envelope_body, NodeDestination};
+    use tari_crypto::ristretto::{RistrettoPublicKey, RistrettoSecretKey};
+
+    type TestNodeIdentity = NodeIdentity<RistrettoPublicKey, RistrettoSecretKey>;
+
+    fn create_node_identity(rng: &mut rand::OsRng) -> Arc<TestNodeIdentity> {
+        let (sk, pk) = RistrettoPublicKey::random_keypair(rng);
+        Arc::new(NodeIdentity::new(NodeId::from_key(&pk).unwrap(), pk, Some(sk)))
+    }
+
+    // Construct the message context of a Tari message sent by the source, encrypted for the destination if one is
+    // given. The domain dispatcher does not verify signatures, as the comms layer has already done so.
+    fn create_message_context(
+        source: &TestNodeIdentity,
+        encrypt_for: Option<&TestNodeIdentity>,
+        message: &TariMessage,
+    ) -> MessageContext<RistrettoPublicKey>
+    {
+        let (body, flags) = match encrypt_for {
+            Some(dest) => (
+                encrypt_envelope_body(
+                    source.secret_key.as_ref().unwrap(),
+                    &dest.public_key,
+                    &message.to_frame(),
+                    &mut rand::OsRng::new().unwrap(),
+                )
+                .unwrap(),
+                IdentityFlags::ENCRYPTED,
+            ),
+            None => (message.to_frame(), IdentityFlags::empty()),
+        };
+        let header = MessageEnvelopeHeader::new(
+            0,
+            source.public_key.clone(),
+            NodeDestination::Unknown,
+            Vec::new(),
+            flags,
+        );
+        MessageContext::new(vec![0], vec![1], vec![0], None, header, body)
+    }
+
+    #[test]
+    fn test_route_and_dispatch() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let node_identity = create_node_identity(&mut rng);
+        let source_identity = create_node_identity(&mut rng);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let handler_received = received.clone();
+        let domain_dispatcher = DomainMessageDispatcher::new(node_identity.clone()).route(
+            TariMessageType::new(WalletMessage::SendTransaction),
+            move |message: String, peer_context: PeerContext<RistrettoPublicKey>| {
+                handler_received.lock().unwrap().push((message, peer_context.node_id));
+                Ok(())
+            },
+        );
+        let message = TariMessage::new(
+            TariMessageType::new(WalletMessage::SendTransaction),
+            bincode::serialize(&"Hello".to_string()).unwrap(),
+        );
+
+        // Unencrypted and encrypted messages are deserialized and passed to the handler of their message type
+        let message_context = create_message_context(&source_identity, None, &message);
+        assert!(domain_dispatcher.dispatch(message_context).is_ok());
+        let message_context = create_message_context(&source_identity, Some(&node_identity), &message);
+        assert!(domain_dispatcher.dispatch(message_context).is_ok());
+        let expected = ("Hello".to_string(), source_identity.node_id.clone());
+        assert_eq!(*received.lock().unwrap(), vec![expected.clone(), expected]);
+
+        // Messages that cannot be decrypted or deserialized are not passed to the handler
+        let other_identity = create_node_identity(&mut rng);
+        let message_context = create_message_context(&source_identity, Some(&other_identity), &message);
+        assert!(domain_dispatcher.dispatch(message_context).is_err());
+        let invalid_message = TariMessage::new(TariMessageType::new(WalletMessage::SendTransaction), vec![1]);
+        let message_context = create_message_context(&source_identity, None, &invalid_message);
+        assert!(domain_dispatcher.dispatch(message_context).is_err());
+        assert_eq!(received.lock().unwrap().len(), 2);
+
+        // Message types without a handler are not dispatched
+        let message = TariMessage::new(TariMessageType::new(BlockchainMessage::NewBlock), Vec::new());
+        let message_context = create_message_context(&source_identity, None, &message);
+        match domain_dispatcher.dispatch(message_context) {
+            Err(DispatchError::MessageHandlerUndefined) => {},
+            result => panic!("Unexpected dispatch result: {:?}", result),
+        }
+    }
+}
@@ -20,5 +20,9 @@
 //  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+pub mod discovery;
+pub mod domain_dispatcher;
+pub mod liveness;
 pub mod peer;
+pub mod store_and_forward;
 pub mod tari_message;
@@ -0,0 +1,315 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    domain_dispatcher::{DomainMessageDispatcher, PeerContext},
+    tari_message::{PeerMessage, TariMessage, TariMessageType},
+};
+use chrono::Duration;
+use derive_error::Error;
+use log::warn;
+use rand::{OsRng, Rng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    ops::Mul,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+use tari_comms::{
+    connection::message::IdentityFlags,
+    inbound_message_service::message_dispatcher::DispatchError,
+    outbound_message_service::{
+        broadcast_strategy::BroadcastStrategy,
+        outbound_message_service::{OutboundError, OutboundMessageService},
+    },
+    peer_manager::{
+        ban_list::{BanList, BanListError, Misbehaviour},
+        liveness::{LivenessError, PeerLiveness},
+        manager::PeerManagerError,
+        node_id::NodeId,
+    },
+};
+use tari_crypto::keys::{DiffieHellmanSharedSecret, PublicKey, SecretKey};
+use tari_storage::{hashmap_store::HashmapStore, keyvalue_store::DataStore};
+use tari_utilities::Hashable;
+
+/// The default interval between the rounds of pings sent to peers
+const DEFAULT_PING_INTERVAL_SECS: i64 = 60;
+/// The default period after which an unanswered ping has failed
+const DEFAULT_PING_TIMEOUT_SECS: i64 = 30;
+
+const LOG_TARGET: &str = "base_layer::p2p::liveness";
+
+#[derive(Debug, Error)]
+pub enum LivenessServiceError {
+    /// The liveness of a peer could not be recorded
+    LivenessError(LivenessError),
+    /// A ping or pong could not be sent
+    OutboundError(OutboundError),
+    /// The random number generator could not be created
+    RandomError(rand::Error),
+    /// An unexpected pong could not be reported to the ban list
+    BanListError(BanListError),
+    /// A pong was received that does not answer a pending ping that was sent to the peer
+    UnexpectedPong,
+    /// A ping or pong could not be serialized
+    #[error(msg_embedded, no_from, non_std)]
+    SerializationError(String),
+    /// The liveness configuration is not valid
+    #[error(msg_embedded, no_from, non_std)]
+    InvalidConfig(String),
+}
+
+impl From<bincode::Error> for LivenessServiceError {
+    fn from(e: bincode::Error) -> Self {
+        LivenessServiceError::SerializationError(e.to_string())
+    }
+}
+
+/// Configuration for probing the liveness of peers. The ping interval and ping timeout are always positive.
+#[derive(Clone, Debug)]
+pub struct LivenessConfig {
+    ping_interval: Duration,
+    ping_timeout: Duration,
+}
+
+impl LivenessConfig {
+    /// Construct a LivenessConfig from the interval between the rounds of pings sent to peers, and the period after
+    /// which an unanswered ping has failed. Both must be positive.
+    pub fn new(ping_interval: Duration, ping_timeout: Duration) -> Result<LivenessConfig, LivenessServiceError> {
+        if ping_interval <= Duration::zero() {
+            return Err(LivenessServiceError::InvalidConfig(
+                "The ping interval must be positive".to_string(),
+            ));
+        }
+        if ping_timeout <= Duration::zero() {
+            return Err(LivenessServiceError::InvalidConfig(
+                "The ping timeout must be positive".to_string(),
+            ));
+        }
+        Ok(LivenessConfig {
+            ping_interval,
+            ping_timeout,
+        })
+    }
+
+    /// The interval between the rounds of pings sent to peers
+    pub fn ping_interval(&self) -> Duration {
+        self.ping_interval
+    }
+
+    /// The period after which an unanswered ping has failed
+    pub fn ping_timeout(&self) -> Duration {
+        self.ping_timeout
+    }
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        LivenessConfig {
+            ping_interval: Duration::seconds(DEFAULT_PING_INTERVAL_SECS),
+            ping_timeout: Duration::seconds(DEFAULT_PING_TIMEOUT_SECS),
+        }
+    }
+}
+
+/// A request for a Pong with the same nonce
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Ping {
+    pub nonce: u64,
+}
+
+/// The answer to the Ping with the nonce
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Pong {
+    pub nonce: u64,
+}
+
+/// The LivenessService probes peers with pings, and answers the pings of other nodes with pongs. The outcome of each
+/// ping is recorded with `PeerLiveness`, which updates the latency, last seen time and failed connection attempts of
+/// the net address of the peer that the ping was sent to, and marks peers that fail repeatedly as offline. A peer that
+/// sends a pong that does not answer one of its pending pings is reported to the ban list.
+pub struct LivenessService<PubKey, SecKey, DS = HashmapStore>
+where PubKey: PublicKey
+{
+    peer_liveness: PeerLiveness<PubKey, DS>,
+    outbound_message_service: OutboundMessageService<PubKey, SecKey, DS>,
+    ban_list: Arc<BanList<PubKey, DS>>,
+    config: LivenessConfig,
+}
+
+impl<PubKey, SecKey, DS> LivenessService<PubKey, SecKey, DS>
+where
+    PubKey: PublicKey<K = SecKey> + Hashable + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>,
+    SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize,
+    DS: DataStore,
+{
+    /// Construct a LivenessService that probes the peers in the peer manager of the OutboundMessageService, and reports
+    /// protocol violations to the ban list
+    pub fn new(
+        outbound_message_service: OutboundMessageService<PubKey, SecKey, DS>,
+        ban_list: Arc<BanList<PubKey, DS>>,
+        config: LivenessConfig,
+    ) -> LivenessService<PubKey, SecKey, DS>
+    {
+        LivenessService {
+            peer_liveness: PeerLiveness::new(outbound_message_service.peer_manager(), config.ping_timeout()),
+            outbound_message_service,
+            ban_list,
+            config,
+        }
+    }
+
+    /// The record of the pings that have been sent to peers
+    pub fn peer_liveness(&self) -> &PeerLiveness<PubKey, DS> {
+        &self.peer_liveness
+    }
+
+    /// Fail the pings that have not been answered in time, and send a ping to every peer that can be probed. A ping
+    /// that can't be sent is logged and the round continues with the next peer; the ping stays pending, so it fails
+    /// once the ping timeout has passed.
+    /// # Returns
+    /// The number of pings that were sent
+    pub fn probe_peers(&self) -> Result<usize, LivenessServiceError> {
+        self.peer_liveness.expire_pings()?;
+        let mut rng = OsRng::new()?;
+        let mut ping_count = 0;
+        for node_id in self.peer_liveness.probe_targets()? {
+            let nonce = rng.gen::<u64>();
+            if !self.peer_liveness.ping_sent(&node_id, nonce)? {
+                continue;
+            }
+            match self.send(&node_id, PeerMessage::Ping, &Ping { nonce }, &mut rng) {
+                Ok(()) => ping_count += 1,
+                Err(e) => warn!(target: LOG_TARGET, "Failed to send a ping to peer {:?}: {:?}", node_id, e),
+            }
+        }
+        Ok(ping_count)
+    }
+
+    /// Answer the ping with a pong. Pings from unknown nodes are ignored, as the pong can't be sent to them.
+    pub fn handle_ping(&self, ping: Ping, peer_context: &PeerContext<PubKey>) -> Result<(), LivenessServiceError> {
+        match self.peer_liveness.peer_seen(&peer_context.node_id) {
+            Ok(()) => {},
+            Err(LivenessError::PeerManagerError(PeerManagerError::PeerNotFoundError)) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let pong = Pong { nonce: ping.nonce };
+        self.send(&peer_context.node_id, PeerMessage::Pong, &pong, &mut OsRng::new()?)
+    }
+
+    /// Record the pong as the answer to the ping that was sent to the peer. A pong that does not answer a pending ping
+    /// of the peer, including one that arrives after its ping has failed, is a protocol violation of the peer.
+    pub fn handle_pong(&self, pong: Pong, peer_context: &PeerContext<PubKey>) -> Result<(), LivenessServiceError> {
+        if self.peer_liveness.pong_received(&peer_context.node_id, pong.nonce)? {
+            return Ok(());
+        }
+        self.ban_list
+            .report_misbehaviour(&peer_context.node_id, Misbehaviour::ProtocolViolation)?;
+        Err(LivenessServiceError::UnexpectedPong)
+    }
+
+    /// Serialize a ping or pong and send it to the known peer with the node id. Pings and pongs carry no private
+    /// information, so they are not encrypted.
+    fn send<T: Serialize>(
+        &self,
+        node_id: &NodeId,
+        message_type: u8,
+        message: &T,
+        rng: &mut OsRng,
+    ) -> Result<(), LivenessServiceError>
+    {
+        let body = TariMessage::new(TariMessageType::new(message_type), bincode::serialize(message)?).to_frame();
+        Ok(self.outbound_message_service.send(
+            BroadcastStrategy::Direct(node_id.clone()),
+            IdentityFlags::empty(),
+            &body,
+            rng,
+        )?)
+    }
+}
+
+impl<PubKey, SecKey, DS> LivenessService<PubKey, SecKey, DS>
+where
+    PubKey:
+        PublicKey<K = SecKey> + Hashable + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey> + Send + Sync + 'static,
+    SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize + Send + Sync + 'static,
+    DS: DataStore + Send + Sync + 'static,
+{
+    /// Probe the peers from a new thread, once every ping interval. The thread stops once the liveness service has
+    /// been dropped everywhere else.
+    pub fn start(liveness_service: &Arc<Self>) -> JoinHandle<()> {
+        let interval = liveness_service
+            .config
+            .ping_interval()
+            .to_std()
+            .unwrap_or_else(|_| std::time::Duration::from_secs(DEFAULT_PING_INTERVAL_SECS as u64));
+        let liveness_service = Arc::downgrade(liveness_service);
+        thread::spawn(move || loop {
+            match liveness_service.upgrade() {
+                // A round of pings that could not be completed is retried in the next interval
+                Some(liveness_service) => {
+                    let _ = liveness_service.probe_peers();
+                },
+                None => break,
+            }
+            thread::sleep(interval);
+        })
+    }
+}
+
+/// Register the handlers of pings and pongs with the domain message dispatcher, which pass the messages to the
+/// liveness service
+pub fn register_liveness_message_handlers<PubKey, SecKey, DS>(
+    domain_dispatcher: DomainMessageDispatcher<PubKey, SecKey>,
+    liveness_service: Arc<LivenessService<PubKey, SecKey, DS>>,
+) -> DomainMessageDispatcher<PubKey, SecKey>
+where
+    PubKey: PublicKey<K = SecKey>
+        + Hashable
+        + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>
+        + DeserializeOwned
+        + Send
+        + Sync
+        + 'static,
+    SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize + Send + Sync + 'static,
+    DS: DataStore + Send + Sync + 'static,
+{
+    let ping_liveness_service = liveness_service.clone();
+    domain_dispatcher
+        .route(
+            TariMessageType::new(PeerMessage::Ping),
+            move |ping: Ping, peer_context: PeerContext<PubKey>| {
+                ping_liveness_service
+                    .handle_ping(ping, &peer_context)
+                    .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))
+            },
+        )
+        .route(
+            TariMessageType::new(PeerMessage::Pong),
+            move |pong: Pong, peer_context: PeerContext<PubKey>| {
+                liveness_service
+                    .handle_pong(pong, &peer_context)
+                    .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))
+            },
+        )
+}
@@ -1,7 +1,8 @@
+use serde::{Deserialize, Serialize};
 use tari_comms::peer_manager::peer::Peer;
 use tari_crypto::keys::PublicKey;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
 pub enum PeerType {
     BaseNode,
     ValidatorNode,
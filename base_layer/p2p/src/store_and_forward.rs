@@ -0,0 +1,134 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    domain_dispatcher::{DomainMessageDispatcher, PeerContext},
+    tari_message::{NetMessage, TariMessage, TariMessageType},
+};
+use derive_error::Error;
+use rand::OsRng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    ops::Mul,
+    sync::{Arc, Mutex},
+};
+use tari_comms::{
+    connection::message::IdentityFlags,
+    inbound_message_service::message_dispatcher::DispatchError,
+    outbound_message_service::{
+        broadcast_strategy::BroadcastStrategy,
+        outbound_message_service::{OutboundError, OutboundMessageService},
+    },
+    store_and_forward::store_and_forward_service::{StoreAndForwardError, StoreAndForwardService},
+};
+use tari_crypto::keys::{DiffieHellmanSharedSecret, PublicKey, SecretKey};
+use tari_storage::keyvalue_store::DataStore;
+use tari_utilities::Hashable;
+
+#[derive(Debug, Error)]
+pub enum StoredMessagesError {
+    /// The request for stored messages could not be sent
+    OutboundError(OutboundError),
+    /// The stored messages could not be forwarded to the requesting node
+    StoreAndForwardError(StoreAndForwardError),
+    /// The random number generator could not be created
+    RandomError(rand::Error),
+    /// The request for stored messages could not be serialized
+    #[error(msg_embedded, no_from, non_std)]
+    SerializationError(String),
+    /// The lock on the store and forward service has been poisoned
+    PoisonedAccess,
+}
+
+impl From<bincode::Error> for StoredMessagesError {
+    fn from(e: bincode::Error) -> Self {
+        StoredMessagesError::SerializationError(e.to_string())
+    }
+}
+
+/// A request for the messages that the receiving node holds for the requesting node, which is identified by the source
+/// of the message
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct StoredMessagesRequest {}
+
+/// Request the messages held for this node from the known peers closest to it, which are the nodes that hold messages
+/// for this node while it is offline. This is done when the node starts, once it has joined the network.
+pub fn request_stored_messages<PubKey, SecKey, DS>(
+    outbound_message_service: &OutboundMessageService<PubKey, SecKey, DS>,
+    peer_count: usize,
+) -> Result<(), StoredMessagesError>
+where
+    PubKey: PublicKey<K = SecKey> + Hashable + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>,
+    SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize,
+    DS: DataStore,
+{
+    let message = bincode::serialize(&StoredMessagesRequest::default())?;
+    let body = TariMessage::new(TariMessageType::new(NetMessage::StoredMessagesRequest), message).to_frame();
+    Ok(outbound_message_service.send(
+        BroadcastStrategy::Closest(peer_count as u32),
+        IdentityFlags::empty(),
+        &body,
+        &mut OsRng::new()?,
+    )?)
+}
+
+/// Register the handler of stored message requests with the domain message dispatcher, which forwards the messages
+/// held for the requesting node by the store and forward service
+pub fn register_store_and_forward_message_handlers<PubKey, SecKey, DS>(
+    domain_dispatcher: DomainMessageDispatcher<PubKey, SecKey>,
+    store_and_forward_service: Arc<Mutex<StoreAndForwardService<PubKey, SecKey, DS>>>,
+) -> DomainMessageDispatcher<PubKey, SecKey>
+where
+    PubKey: PublicKey<K = SecKey>
+        + Hashable
+        + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>
+        + Serialize
+        + DeserializeOwned
+        + Send
+        + Sync
+        + 'static,
+    SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize + Send + Sync + 'static,
+    DS: DataStore + Send + Sync + 'static,
+{
+    domain_dispatcher.route(
+        TariMessageType::new(NetMessage::StoredMessagesRequest),
+        move |_: StoredMessagesRequest, peer_context: PeerContext<PubKey>| {
+            handle_stored_messages_request(&store_and_forward_service, &peer_context)
+                .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))
+        },
+    )
+}
+
+fn handle_stored_messages_request<PubKey, SecKey, DS>(
+    store_and_forward_service: &Mutex<StoreAndForwardService<PubKey, SecKey, DS>>,
+    peer_context: &PeerContext<PubKey>,
+) -> Result<usize, StoredMessagesError>
+where
+    PubKey: PublicKey<K = SecKey> + Hashable + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>,
+    SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize,
+    DS: DataStore,
+{
+    Ok(store_and_forward_service
+        .lock()
+        .map_err(|_| StoredMessagesError::PoisonedAccess)?
+        .handle_stored_messages_request(&peer_context.public_key)?)
+}
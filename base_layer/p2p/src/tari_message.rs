@@ -32,21 +32,26 @@ macro_rules! is_type {
 /// A tari message type is an immutable 8-bit unsigned integer indicating the type of message being received or sent
 /// over the network. Details are in
 /// [RFC-0172](https://rfc.tari.com/RFC-0172_PeerToPeerMessagingProtocol.html#messagetype).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TariMessageType(u8);
 
 #[allow(non_snake_case, non_upper_case_globals)]
 pub mod NetMessage {
     pub(super) const START_RANGE: u8 = 1;
-    pub(super) const END_RANGE: u8 = 2; // Can be extended to 32
+    pub(super) const END_RANGE: u8 = 4; // Can be extended to 32
     pub const Join: u8 = 1;
     pub const Discover: u8 = 2;
+    pub const StoredMessagesRequest: u8 = 3;
+    pub const DiscoverResponse: u8 = 4;
 }
 
 #[allow(non_snake_case, non_upper_case_globals)]
 pub mod PeerMessage {
     pub(super) const START_RANGE: u8 = 33;
-    pub(super) const END_RANGE: u8 = 33; // Can be extended to 64
+    pub(super) const END_RANGE: u8 = 35; // Can be extended to 64
     pub const Connect: u8 = 33;
+    pub const Ping: u8 = 34;
+    pub const Pong: u8 = 35;
 }
 
 #[allow(non_snake_case, non_upper_case_globals)]
@@ -63,6 +68,14 @@ pub mod ValidatorNodeMessage {
     pub const Instruction: u8 = 97;
 }
 
+#[allow(non_snake_case, non_upper_case_globals)]
+pub mod WalletMessage {
+    pub(super) const START_RANGE: u8 = 225;
+    pub(super) const END_RANGE: u8 = 226; // Can be extended to 255
+    pub const SendTransaction: u8 = 225;
+    pub const ReceiveTransactionReply: u8 = 226;
+}
+
 impl TariMessageType {
     is_type!(NetMessage, is_net_message);
 
@@ -72,6 +85,8 @@ impl TariMessageType {
 
     is_type!(ValidatorNodeMessage, is_vn_message);
 
+    is_type!(WalletMessage, is_wallet_message);
+
     pub fn new(value: u8) -> TariMessageType {
         TariMessageType(value)
     }
@@ -81,7 +96,11 @@ impl TariMessageType {
     }
 
     pub fn is_known_message(&self) -> bool {
-        self.is_net_message() || self.is_peer_message() || self.is_blockchain_message() || self.is_vn_message()
+        self.is_net_message() ||
+            self.is_peer_message() ||
+            self.is_blockchain_message() ||
+            self.is_vn_message() ||
+            self.is_wallet_message()
     }
 }
 
@@ -91,6 +110,34 @@ impl From<u8> for TariMessageType {
     }
 }
 
+/// A message as it is carried in the body of a `MessageEnvelope`: a single message type byte followed by the
+/// serialized message
+#[derive(Clone, Debug, PartialEq)]
+pub struct TariMessage {
+    pub message_type: TariMessageType,
+    pub body: Vec<u8>,
+}
+
+impl TariMessage {
+    pub fn new(message_type: TariMessageType, body: Vec<u8>) -> TariMessage {
+        TariMessage { message_type, body }
+    }
+
+    /// Serialize the message into a message envelope body
+    pub fn to_frame(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(self.body.len() + 1);
+        frame.push(self.message_type.value());
+        frame.extend_from_slice(&self.body);
+        frame
+    }
+
+    /// Read a message from a message envelope body. Returns `None` if the frame is empty.
+    pub fn from_frame(frame: &[u8]) -> Option<TariMessage> {
+        let (message_type, body) = frame.split_first()?;
+        Some(TariMessage::new(TariMessageType::from(*message_type), body.to_vec()))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -115,6 +162,23 @@ mod test {
         assert!(t.is_known_message());
     }
 
+    #[test]
+    fn wallet_message_type() {
+        let t = TariMessageType::new(WalletMessage::ReceiveTransactionReply);
+        assert!(t.is_wallet_message());
+        assert!(t.is_known_message());
+        assert!(!t.is_vn_message());
+    }
+
+    #[test]
+    fn message_frames() {
+        let msg = TariMessage::new(TariMessageType::new(WalletMessage::SendTransaction), vec![1, 2, 3]);
+        let frame = msg.to_frame();
+        assert_eq!(frame, vec![225, 1, 2, 3]);
+        assert_eq!(TariMessage::from_frame(&frame), Some(msg));
+        assert_eq!(TariMessage::from_frame(&[]), None);
+    }
+
     #[test]
     fn unknown_message_type() {
         let t = TariMessageType::from(30);
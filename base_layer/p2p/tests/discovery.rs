@@ -0,0 +1,317 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+mod support;
+
+use p2p::{
+    discovery::{register_discovery_message_handlers, DiscoveryConfig, DiscoveryService, SeedPeer},
+    domain_dispatcher::{DomainMessageDispatcher, PeerContext},
+    peer::PeerType,
+    store_and_forward::{register_store_and_forward_message_handlers, request_stored_messages},
+    tari_message::{BlockchainMessage, TariMessage, TariMessageType},
+};
+use rand::OsRng;
+use std::{
+    convert::TryFrom,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use support::start_relay;
+use tari_comms::{
+    connection::{
+        connection::EstablishedConnection,
+        message::{IdentityFlags, MessageEnvelope, MessageEnvelopeHeader},
+        zmq::{Context, InprocAddress, ZmqEndpoint},
+        NetAddress,
+        SocketType,
+    },
+    inbound_message_service::{
+        comms_msg_handlers::construct_comms_msg_dispatcher,
+        inbound_message_service::InboundMessageService,
+        message_context::MessageContext,
+        message_forwarder::{ForwardingConfig, MessageForwarder},
+    },
+    outbound_message_service::{
+        broadcast_strategy::BroadcastStrategy,
+        outbound_message::OutboundMessage,
+        outbound_message_service::OutboundMessageService,
+    },
+    peer_manager::{
+        ban_list::{BanList, BanListConfig},
+        manager::PeerManager,
+        node_id::NodeId,
+        node_identity::NodeIdentity,
+    },
+    store_and_forward::{message_store::MessageStoreConfig, store_and_forward_service::StoreAndForwardService},
+};
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+};
+use tari_storage::hashmap_store::HashmapStore;
+
+const NODE_COUNT: usize = 12;
+
+struct TestNode {
+    node_identity: Arc<NodeIdentity<RistrettoPublicKey, RistrettoSecretKey>>,
+    net_address: NetAddress,
+    inbound_address: InprocAddress,
+    peer_manager: Arc<PeerManager<RistrettoPublicKey, HashmapStore>>,
+    discovery_service: Arc<DiscoveryService<RistrettoPublicKey, RistrettoSecretKey>>,
+    outbound_message_service: OutboundMessageService<RistrettoPublicKey, RistrettoSecretKey>,
+    store_and_forward_service: Arc<Mutex<StoreAndForwardService<RistrettoPublicKey, RistrettoSecretKey>>>,
+    // The node id of the sender and the text of the test messages that the node handled
+    received_messages: Arc<Mutex<Vec<(NodeId, String)>>>,
+}
+
+/// Start the comms services of a node whose discovery service bootstraps from the seed peers
+fn start_node(
+    context: &Context,
+    rng: &mut OsRng,
+    index: usize,
+    pool_address: &InprocAddress,
+    seed_peers: Vec<SeedPeer<RistrettoPublicKey>>,
+) -> TestNode
+{
+    let (sk, pk) = RistrettoPublicKey::random_keypair(rng);
+    let node_identity = Arc::new(NodeIdentity::new(NodeId::from_key(&pk).unwrap(), pk, Some(sk)));
+    let net_address = format!("127.0.0.1:{}", 9000 + index).parse::<NetAddress>().unwrap();
+    let inbound_address = InprocAddress::random();
+    let peer_manager = Arc::new(PeerManager::new(node_identity.node_id.clone(), HashmapStore::new()).unwrap());
+    let outbound_message_service = || {
+        OutboundMessageService::new(
+            context.clone(),
+            pool_address.clone(),
+            node_identity.clone(),
+            peer_manager.clone(),
+        )
+    };
+    let config = DiscoveryConfig {
+        seed_peers,
+        response_peer_count: NODE_COUNT,
+        ..Default::default()
+    };
+    let ban_list = Arc::new(BanList::new(peer_manager.clone(), HashmapStore::new(), BanListConfig::default()).unwrap());
+    let discovery_service = Arc::new(DiscoveryService::new(
+        &node_identity,
+        vec![net_address.clone()],
+        PeerType::BaseNode,
+        outbound_message_service(),
+        ban_list.clone(),
+        config,
+    ));
+    let message_forwarder = Arc::new(MessageForwarder::new(
+        node_identity.clone(),
+        outbound_message_service(),
+        ForwardingConfig::default(),
+    ));
+    let store_and_forward_service = Arc::new(Mutex::new(StoreAndForwardService::new(
+        node_identity.clone(),
+        MessageStoreConfig::default(),
+        outbound_message_service(),
+    )));
+    let received_messages = Arc::new(Mutex::new(Vec::new()));
+    let test_message_handler = {
+        let received_messages = received_messages.clone();
+        move |text: String, peer_context: PeerContext<RistrettoPublicKey>| {
+            received_messages.lock().unwrap().push((peer_context.node_id, text));
+            Ok(())
+        }
+    };
+    let domain_dispatcher = register_store_and_forward_message_handlers(
+        register_discovery_message_handlers(
+            DomainMessageDispatcher::new(node_identity.clone())
+                .route(TariMessageType::new(BlockchainMessage::NewBlock), test_message_handler),
+            discovery_service.clone(),
+        ),
+        store_and_forward_service.clone(),
+    );
+    let message_dispatcher = construct_comms_msg_dispatcher(
+        node_identity.clone(),
+        ban_list,
+        message_forwarder,
+        store_and_forward_service.clone(),
+        move |mc| domain_dispatcher.dispatch(mc),
+    );
+    InboundMessageService::new(
+        context.clone(),
+        inbound_address.clone(),
+        node_identity.public_key.clone(),
+        message_dispatcher,
+    )
+    .unwrap()
+    .start();
+    TestNode {
+        outbound_message_service: outbound_message_service(),
+        node_identity,
+        net_address,
+        inbound_address,
+        peer_manager,
+        discovery_service,
+        store_and_forward_service,
+        received_messages,
+    }
+}
+
+/// Construct the test message envelope that the sender would send to the destination, signed by the sender and
+/// encrypted for the destination, by capturing it from an outbound message service of the sender instead of relaying
+/// it
+fn create_message_envelope(context: &Context, sender: &TestNode, dest: &TestNode, text: &str) -> MessageEnvelope {
+    let capture_address = InprocAddress::random();
+    let capture_socket = context.socket(SocketType::Reply).unwrap();
+    capture_socket.bind(&capture_address.to_zmq_endpoint()).unwrap();
+    let capture_connection = EstablishedConnection { socket: capture_socket };
+    let outbound_message_service = OutboundMessageService::new(
+        context.clone(),
+        capture_address,
+        sender.node_identity.clone(),
+        sender.peer_manager.clone(),
+    );
+    let body = TariMessage::new(
+        TariMessageType::new(BlockchainMessage::NewBlock),
+        bincode::serialize(text).unwrap(),
+    )
+    .to_frame();
+    outbound_message_service
+        .send(
+            BroadcastStrategy::Direct(dest.node_identity.node_id.clone()),
+            IdentityFlags::ENCRYPTED,
+            &body,
+            &mut OsRng::new().unwrap(),
+        )
+        .unwrap();
+    let mut frames = capture_connection.receive(2000).unwrap();
+    OutboundMessage::<MessageEnvelope>::try_from(frames.remove(0))
+        .unwrap()
+        .message_envelope
+}
+
+/// Wait up to ten seconds for the condition to hold
+fn wait_for<F: Fn() -> bool>(condition: F) -> bool {
+    for _ in 0..100 {
+        thread::sleep(Duration::from_millis(100));
+        if condition() {
+            return true;
+        }
+    }
+    false
+}
+
+#[test]
+fn nodes_discover_each_other() {
+    let mut rng = OsRng::new().unwrap();
+    let context = Context::new();
+    let pool_address = InprocAddress::random();
+
+    // The nodes form a chain, every node bootstraps from the node started before it and knows no other node
+    let mut nodes = vec![start_node(&context, &mut rng, 0, &pool_address, Vec::new())];
+    for index in 1..NODE_COUNT {
+        let previous_node = &nodes[index - 1];
+        let seed_peer = SeedPeer {
+            public_key: previous_node.node_identity.public_key.clone(),
+            net_addresses: vec![previous_node.net_address.clone()],
+        };
+        nodes.push(start_node(&context, &mut rng, index, &pool_address, vec![seed_peer]));
+    }
+    let inbound_addresses = nodes
+        .iter()
+        .map(|node| (node.node_identity.node_id.clone(), node.inbound_address.clone()))
+        .collect();
+    start_relay(context.clone(), pool_address, inbound_addresses);
+
+    for node in &nodes {
+        node.discovery_service.bootstrap().unwrap();
+    }
+    let knows =
+        |node: &TestNode, other_node: &TestNode| node.peer_manager.exists(&other_node.node_identity.node_id).unwrap();
+    assert!(wait_for(|| nodes.windows(2).all(|pair| knows(&pair[0], &pair[1]))));
+
+    // After bootstrapping, the first node only knows the node that joined it
+    assert_eq!(nodes[0].peer_manager.peer_count().unwrap(), 1);
+    assert!(!knows(&nodes[0], &nodes[NODE_COUNT - 1]));
+
+    // Nodes find the rest of the network by asking the peers they know for the nodes closest to themselves. Every
+    // round spreads the nodes known by each peer to the nodes that ask it, and the peer learns about the nodes that
+    // ask.
+    let knows_all_nodes = |node: &TestNode| node.peer_manager.peer_count().unwrap() == NODE_COUNT - 1;
+    for _ in 0..NODE_COUNT {
+        if nodes.iter().all(knows_all_nodes) {
+            break;
+        }
+        for node in &nodes {
+            for peer in node.peer_manager.peers().unwrap() {
+                node.discovery_service
+                    .discover(&peer.node_id, node.node_identity.node_id.clone())
+                    .unwrap();
+            }
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    assert!(wait_for(|| nodes.iter().all(knows_all_nodes)));
+
+    // A message that a peer held for a node while it was offline is delivered once the node has joined the network and
+    // requests the messages held for it from its closest peers
+    let (sender, holder, dest) = (&nodes[1], &nodes[0], &nodes[NODE_COUNT - 1]);
+    let message_envelope = create_message_envelope(&context, sender, dest, "held message");
+    let header = MessageEnvelopeHeader::<RistrettoPublicKey>::try_from(message_envelope.header().clone()).unwrap();
+    let message_context = MessageContext::new(
+        vec![0],
+        vec![1],
+        message_envelope.version().clone(),
+        None,
+        header,
+        message_envelope.body().clone(),
+    );
+    assert!(holder
+        .store_and_forward_service
+        .lock()
+        .unwrap()
+        .store_message(&message_context, &[])
+        .unwrap());
+    request_stored_messages(&dest.outbound_message_service, NODE_COUNT).unwrap();
+    let expected = vec![(sender.node_identity.node_id.clone(), "held message".to_string())];
+    assert!(wait_for(|| *dest.received_messages.lock().unwrap() == expected));
+    assert!(holder
+        .store_and_forward_service
+        .lock()
+        .unwrap()
+        .message_store()
+        .is_empty());
+
+    // Every node knows the net address and peer type that each other node announced
+    for node in &nodes {
+        for other_node in nodes
+            .iter()
+            .filter(|other_node| other_node.net_address != node.net_address)
+        {
+            let node_id = &other_node.node_identity.node_id;
+            let mut peer = node.peer_manager.find_with_node_id(node_id).unwrap();
+            assert_eq!(peer.public_key, other_node.node_identity.public_key);
+            assert!(peer.addresses.find_address_mut(&other_node.net_address).is_ok());
+            assert_eq!(
+                node.discovery_service.peer_type(node_id).unwrap(),
+                Some(PeerType::BaseNode)
+            );
+        }
+    }
+}
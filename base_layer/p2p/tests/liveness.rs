@@ -0,0 +1,205 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+mod support;
+
+use p2p::{
+    domain_dispatcher::DomainMessageDispatcher,
+    liveness::{register_liveness_message_handlers, LivenessConfig, LivenessService},
+};
+use rand::OsRng;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use support::start_relay;
+use tari_comms::{
+    connection::{
+        net_address::net_addresses::NetAddresses,
+        zmq::{Context, InprocAddress},
+        NetAddress,
+    },
+    inbound_message_service::{
+        comms_msg_handlers::construct_comms_msg_dispatcher,
+        inbound_message_service::InboundMessageService,
+        message_forwarder::{ForwardingConfig, MessageForwarder},
+    },
+    outbound_message_service::outbound_message_service::OutboundMessageService,
+    peer_manager::{
+        ban_list::{BanList, BanListConfig},
+        manager::PeerManager,
+        node_id::NodeId,
+        node_identity::NodeIdentity,
+        peer::{Peer, PeerFlags},
+    },
+    store_and_forward::{message_store::MessageStoreConfig, store_and_forward_service::StoreAndForwardService},
+};
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+};
+use tari_storage::hashmap_store::HashmapStore;
+
+struct TestNode {
+    node_identity: Arc<NodeIdentity<RistrettoPublicKey, RistrettoSecretKey>>,
+    net_address: NetAddress,
+    inbound_address: InprocAddress,
+    peer_manager: Arc<PeerManager<RistrettoPublicKey, HashmapStore>>,
+    liveness_service: Arc<LivenessService<RistrettoPublicKey, RistrettoSecretKey>>,
+}
+
+/// Start the comms services of a node that answers pings and can probe its peers
+fn start_node(context: &Context, rng: &mut OsRng, net_address: &str, pool_address: &InprocAddress) -> TestNode {
+    let (sk, pk) = RistrettoPublicKey::random_keypair(rng);
+    let node_identity = Arc::new(NodeIdentity::new(NodeId::from_key(&pk).unwrap(), pk, Some(sk)));
+    let inbound_address = InprocAddress::random();
+    let peer_manager = Arc::new(PeerManager::new(node_identity.node_id.clone(), HashmapStore::new()).unwrap());
+    let outbound_message_service = || {
+        OutboundMessageService::new(
+            context.clone(),
+            pool_address.clone(),
+            node_identity.clone(),
+            peer_manager.clone(),
+        )
+    };
+    let ban_list = Arc::new(BanList::new(peer_manager.clone(), HashmapStore::new(), BanListConfig::default()).unwrap());
+    let liveness_service = Arc::new(LivenessService::new(
+        outbound_message_service(),
+        ban_list.clone(),
+        LivenessConfig::default(),
+    ));
+    let message_forwarder = Arc::new(MessageForwarder::new(
+        node_identity.clone(),
+        outbound_message_service(),
+        ForwardingConfig::default(),
+    ));
+    let store_and_forward_service = Arc::new(Mutex::new(StoreAndForwardService::new(
+        node_identity.clone(),
+        MessageStoreConfig::default(),
+        outbound_message_service(),
+    )));
+    let domain_dispatcher = register_liveness_message_handlers(
+        DomainMessageDispatcher::new(node_identity.clone()),
+        liveness_service.clone(),
+    );
+    let message_dispatcher = construct_comms_msg_dispatcher(
+        node_identity.clone(),
+        ban_list,
+        message_forwarder,
+        store_and_forward_service,
+        move |mc| domain_dispatcher.dispatch(mc),
+    );
+    InboundMessageService::new(
+        context.clone(),
+        inbound_address.clone(),
+        node_identity.public_key.clone(),
+        message_dispatcher,
+    )
+    .unwrap()
+    .start();
+    TestNode {
+        node_identity,
+        net_address: net_address.parse::<NetAddress>().unwrap(),
+        inbound_address,
+        peer_manager,
+        liveness_service,
+    }
+}
+
+fn add_peer(node: &TestNode, other_node: &TestNode) {
+    node.peer_manager
+        .add_peer(Peer::new(
+            other_node.node_identity.public_key.clone(),
+            other_node.node_identity.node_id.clone(),
+            NetAddresses::from(other_node.net_address.clone()),
+            PeerFlags::default(),
+        ))
+        .unwrap();
+}
+
+#[test]
+fn ping_pong_updates_net_address_stats() {
+    let mut rng = OsRng::new().unwrap();
+    let context = Context::new();
+    let pool_address = InprocAddress::random();
+    let alice = start_node(&context, &mut rng, "127.0.0.1:9000", &pool_address);
+    let bob = start_node(&context, &mut rng, "127.0.0.1:9001", &pool_address);
+    add_peer(&alice, &bob);
+    add_peer(&bob, &alice);
+    let mut inbound_addresses = HashMap::new();
+    inbound_addresses.insert(alice.node_identity.node_id.clone(), alice.inbound_address.clone());
+    inbound_addresses.insert(bob.node_identity.node_id.clone(), bob.inbound_address.clone());
+    start_relay(context.clone(), pool_address, inbound_addresses);
+
+    assert_eq!(alice.liveness_service.probe_peers().unwrap(), 1);
+
+    // Bob's pong marks the net address that Alice pinged as seen
+    let mut answered = false;
+    for _ in 0..50 {
+        thread::sleep(Duration::from_millis(100));
+        let mut peer = alice
+            .peer_manager
+            .find_with_node_id(&bob.node_identity.node_id)
+            .unwrap();
+        let address = peer.addresses.find_address_mut(&bob.net_address).unwrap();
+        if address.last_seen.is_some() {
+            assert_eq!(address.connection_attempts, 0);
+            assert!(!peer.is_offline());
+            answered = true;
+            break;
+        }
+    }
+    assert!(answered);
+}
+
+#[test]
+fn liveness_config_rejects_non_positive_intervals() {
+    let second = chrono::Duration::seconds(1);
+    assert!(LivenessConfig::new(second, second).is_ok());
+    assert!(LivenessConfig::new(chrono::Duration::zero(), second).is_err());
+    assert!(LivenessConfig::new(-second, second).is_err());
+    assert!(LivenessConfig::new(second, chrono::Duration::zero()).is_err());
+    assert!(LivenessConfig::new(second, -second).is_err());
+}
+
+#[test]
+fn probing_stops_once_the_service_is_dropped() {
+    let mut rng = OsRng::new().unwrap();
+    let context = Context::new();
+    let (sk, pk) = RistrettoPublicKey::random_keypair(&mut rng);
+    let node_identity = Arc::new(NodeIdentity::new(NodeId::from_key(&pk).unwrap(), pk, Some(sk)));
+    let peer_manager = Arc::new(PeerManager::new(node_identity.node_id.clone(), HashmapStore::new()).unwrap());
+    let ban_list = Arc::new(BanList::new(peer_manager.clone(), HashmapStore::new(), BanListConfig::default()).unwrap());
+    let config = LivenessConfig::new(chrono::Duration::milliseconds(10), chrono::Duration::seconds(1)).unwrap();
+    let liveness_service = Arc::new(LivenessService::new(
+        OutboundMessageService::new(context, InprocAddress::random(), node_identity, peer_manager),
+        ban_list,
+        config,
+    ));
+    let probing = LivenessService::start(&liveness_service);
+    thread::sleep(Duration::from_millis(50));
+
+    drop(liveness_service);
+    probing.join().unwrap();
+}
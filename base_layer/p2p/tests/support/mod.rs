@@ -0,0 +1,69 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::{collections::HashMap, convert::TryFrom, thread};
+use tari_comms::{
+    connection::{
+        connection::EstablishedConnection,
+        message::MessageEnvelope,
+        zmq::{Context, InprocAddress, ZmqEndpoint},
+        SocketType,
+    },
+    outbound_message_service::outbound_message::OutboundMessage,
+    peer_manager::node_id::NodeId,
+};
+
+/// Stands in for the outbound message pools of the nodes and the connections between them: messages written to the
+/// shared pool address are unwrapped from their OutboundMessage and handed to the inbound message service of the node
+/// they are destined for
+pub fn start_relay(context: Context, pool_address: InprocAddress, inbound_addresses: HashMap<NodeId, InprocAddress>) {
+    thread::spawn(move || {
+        let pool_socket = context.socket(SocketType::Reply).unwrap();
+        pool_socket.bind(&pool_address.to_zmq_endpoint()).unwrap();
+        let pool_connection = EstablishedConnection { socket: pool_socket };
+        let inbound_connections: HashMap<NodeId, EstablishedConnection> = inbound_addresses
+            .into_iter()
+            .map(|(node_id, inbound_address)| {
+                let inbound_socket = context.socket(SocketType::Request).unwrap();
+                inbound_socket.connect(&inbound_address.to_zmq_endpoint()).unwrap();
+                (node_id, EstablishedConnection { socket: inbound_socket })
+            })
+            .collect();
+        loop {
+            if let Ok(mut frames) = pool_connection.receive(100) {
+                let outbound_message = OutboundMessage::<MessageEnvelope>::try_from(frames.remove(0)).unwrap();
+                let inbound_connection = &inbound_connections[&outbound_message.destination_node_id];
+                let envelope = outbound_message.message_envelope;
+                let message_context_frames = vec![
+                    vec![0u8],
+                    vec![1u8],
+                    envelope.version().clone(),
+                    envelope.header().clone(),
+                    envelope.body().clone(),
+                ];
+                inbound_connection.send(&message_context_frames).unwrap();
+                inbound_connection.receive(2000).unwrap();
+                pool_connection.send(&[b"OK".to_vec()]).unwrap();
+            }
+        }
+    });
+}
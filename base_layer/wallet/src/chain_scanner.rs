@@ -0,0 +1,267 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::output_manager::PendingTransactionOutputs;
+use derive_error::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use tari_core::{blockheader::BlockHeader, transaction::UnblindedOutput};
+use tari_utilities::Hashable;
+
+/// The number of scanned blocks the scanner remembers, and so the deepest reorg it can recover from
+pub const DEFAULT_REORG_DEPTH: usize = 1000;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ChainScannerError {
+    // The block does not build on any of the scanned blocks
+    OrphanBlock,
+    // The block replaces a block that is older than the scanner's history
+    ReorgTooDeep,
+    // The block has already been scanned
+    BlockAlreadyScanned,
+}
+
+/// A wallet transaction that was found in a block
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MinedTransaction {
+    /// A kernel of a completed outbound transaction was found
+    Outbound { tx_id: u64 },
+    /// The output of a pending inbound transaction was found. The outputs the transaction confirmed are kept so that
+    /// they can be made pending again if the block is reorged out.
+    Inbound {
+        tx_id: u64,
+        outputs: PendingTransactionOutputs,
+    },
+}
+
+impl MinedTransaction {
+    pub fn tx_id(&self) -> u64 {
+        match self {
+            MinedTransaction::Outbound { tx_id } => *tx_id,
+            MinedTransaction::Inbound { tx_id, .. } => *tx_id,
+        }
+    }
+}
+
+/// What the wallet found in a block
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScannedBlock {
+    pub height: u64,
+    pub hash: Vec<u8>,
+    pub mined_transactions: Vec<MinedTransaction>,
+    /// Outputs spendable with the wallet's keys that no wallet transaction created, e.g. outputs sent by another
+    /// wallet with the same master key
+    #[serde(default)]
+    pub received_outputs: Vec<UnblindedOutput>,
+    /// Unspent outputs of the wallet that were spent by a transaction the wallet did not create
+    #[serde(default)]
+    pub spent_outputs: Vec<UnblindedOutput>,
+}
+
+/// A change to the wallet's transactions caused by scanning a block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScanEvent {
+    /// The transaction was mined at the given height
+    TransactionMined { tx_id: u64, height: u64 },
+    /// The block the transaction was mined in was reorged out, and the transaction is pending again
+    TransactionReverted { tx_id: u64 },
+    /// An output spendable with one of the wallet's keys was found in the block at `height`
+    OutputReceived { value: u64, height: u64 },
+    /// An unspent output of the wallet was spent in the block at `height` by a transaction the wallet did not create
+    OutputSpent { value: u64, height: u64 },
+}
+
+/// The ChainScanner keeps track of the blocks a wallet has scanned, the height at which each of the wallet's
+/// transactions was mined, and what each block changed, so that the changes can be undone when a block is reorged
+/// out. Matching block contents against the wallet's transactions is done by the `TransactionManager`; the scanner
+/// only decides where a new block attaches to the scanned chain.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChainScanner {
+    blocks: VecDeque<ScannedBlock>,
+    mined_heights: HashMap<u64, u64>,
+    reorg_depth: usize,
+}
+
+impl Default for ChainScanner {
+    fn default() -> Self {
+        ChainScanner::new(DEFAULT_REORG_DEPTH)
+    }
+}
+
+impl ChainScanner {
+    /// Create a scanner that can recover from reorgs up to `reorg_depth` blocks deep
+    pub fn new(reorg_depth: usize) -> ChainScanner {
+        ChainScanner {
+            blocks: VecDeque::new(),
+            mined_heights: HashMap::new(),
+            reorg_depth: reorg_depth.max(1),
+        }
+    }
+
+    /// The height of the last scanned block
+    pub fn tip_height(&self) -> Option<u64> {
+        self.blocks.back().map(|b| b.height)
+    }
+
+    /// The hash of the last scanned block
+    pub fn tip_hash(&self) -> Option<&[u8]> {
+        self.blocks.back().map(|b| b.hash.as_slice())
+    }
+
+    /// The height of the block the transaction was mined in, if it has been mined
+    pub fn mined_height(&self, tx_id: u64) -> Option<u64> {
+        self.mined_heights.get(&tx_id).cloned()
+    }
+
+    /// The number of blocks, including the block it was mined in, that have been built on a mined transaction. The
+    /// result is zero if the transaction has not been mined, or was mined in a block older than the scanner's history;
+    /// such a transaction can no longer be reorged out and its database record stays mined.
+    pub fn confirmations(&self, tx_id: u64) -> u64 {
+        match (self.mined_height(tx_id), self.tip_height()) {
+            (Some(mined), Some(tip)) if tip >= mined => tip - mined + 1,
+            _ => 0,
+        }
+    }
+
+    /// The number of scanned blocks that must be reverted before the block with this header can be scanned
+    pub fn blocks_to_revert(&self, header: &BlockHeader) -> Result<usize, ChainScannerError> {
+        let first_height = match self.blocks.front() {
+            Some(block) => block.height,
+            None => return Ok(0),
+        };
+        let hash = header.hash();
+        if self.blocks.iter().any(|b| b.height == header.height && b.hash == hash) {
+            return Err(ChainScannerError::BlockAlreadyScanned);
+        }
+        if header.height <= first_height {
+            return Err(ChainScannerError::ReorgTooDeep);
+        }
+        let parent = self
+            .blocks
+            .iter()
+            .position(|b| b.height + 1 == header.height && b.hash[..] == header.prev_hash[..])
+            .ok_or(ChainScannerError::OrphanBlock)?;
+        Ok(self.blocks.len() - 1 - parent)
+    }
+
+    /// Add a scanned block to the tip of the scanned chain. The oldest block, and the mined heights of its
+    /// transactions, are forgotten once the history is longer than the reorg depth.
+    pub fn push_block(&mut self, block: ScannedBlock) {
+        for mined in block.mined_transactions.iter() {
+            self.mined_heights.insert(mined.tx_id(), block.height);
+        }
+        self.blocks.push_back(block);
+        while self.blocks.len() > self.reorg_depth {
+            if let Some(forgotten) = self.blocks.pop_front() {
+                for mined in forgotten.mined_transactions.iter() {
+                    self.mined_heights.remove(&mined.tx_id());
+                }
+            }
+        }
+    }
+
+    /// Remove the block at the tip of the scanned chain so that its changes can be undone
+    pub fn pop_block(&mut self) -> Option<ScannedBlock> {
+        let block = self.blocks.pop_back()?;
+        for mined in block.mined_transactions.iter() {
+            self.mined_heights.remove(&mined.tx_id());
+        }
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chain_scanner::{ChainScanner, ChainScannerError, MinedTransaction, ScannedBlock};
+    use tari_core::{blockheader::BlockHeader, transaction_protocol::test_common::create_test_block};
+    use tari_utilities::Hashable;
+
+    fn scanned(header: &BlockHeader) -> ScannedBlock {
+        ScannedBlock {
+            height: header.height,
+            hash: header.hash(),
+            mined_transactions: Vec::new(),
+            received_outputs: Vec::new(),
+            spent_outputs: Vec::new(),
+        }
+    }
+
+    fn block_hash(header: &BlockHeader) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&header.hash());
+        hash
+    }
+
+    #[test]
+    fn blocks_attach_to_the_scanned_chain() {
+        let mut scanner = ChainScanner::new(3);
+        let b0 = create_test_block(0, [0u8; 32], 0, vec![]).header;
+        assert_eq!(scanner.blocks_to_revert(&b0), Ok(0));
+        scanner.push_block(scanned(&b0));
+        assert_eq!(
+            scanner.blocks_to_revert(&b0),
+            Err(ChainScannerError::BlockAlreadyScanned)
+        );
+
+        let b1 = create_test_block(1, block_hash(&b0), 0, vec![]).header;
+        assert_eq!(scanner.blocks_to_revert(&b1), Ok(0));
+        scanner.push_block(scanned(&b1));
+        let b2 = create_test_block(2, block_hash(&b1), 0, vec![]).header;
+        scanner.push_block(scanned(&b2));
+        assert_eq!(scanner.tip_height(), Some(2));
+
+        // A competing block at height 2 replaces one block, and one at height 1 replaces two
+        let fork2 = create_test_block(2, block_hash(&b1), 1, vec![]).header;
+        assert_eq!(scanner.blocks_to_revert(&fork2), Ok(1));
+        let fork1 = create_test_block(1, block_hash(&b0), 1, vec![]).header;
+        assert_eq!(scanner.blocks_to_revert(&fork1), Ok(2));
+        let orphan = create_test_block(3, [9u8; 32], 0, vec![]).header;
+        assert_eq!(scanner.blocks_to_revert(&orphan), Err(ChainScannerError::OrphanBlock));
+
+        // Only three blocks are remembered, so block 0 can no longer be replaced
+        let b3 = create_test_block(3, block_hash(&b2), 0, vec![]).header;
+        scanner.push_block(scanned(&b3));
+        assert_eq!(scanner.blocks_to_revert(&fork1), Err(ChainScannerError::ReorgTooDeep));
+        assert_eq!(scanner.pop_block().unwrap().height, 3);
+        assert_eq!(scanner.tip_height(), Some(2));
+    }
+
+    #[test]
+    fn mined_heights_are_forgotten_with_their_blocks() {
+        let mut scanner = ChainScanner::new(2);
+        let mut prev_hash = [0u8; 32];
+        for height in 0..3 {
+            let header = create_test_block(height, prev_hash, 0, vec![]).header;
+            prev_hash = block_hash(&header);
+            let mut block = scanned(&header);
+            block
+                .mined_transactions
+                .push(MinedTransaction::Outbound { tx_id: height });
+            scanner.push_block(block);
+        }
+        assert_eq!(scanner.mined_height(0), None);
+        assert_eq!(scanner.confirmations(0), 0);
+        assert_eq!(scanner.mined_height(1), Some(1));
+        assert_eq!(scanner.confirmations(1), 2);
+        assert_eq!(scanner.mined_height(2), Some(2));
+    }
+}
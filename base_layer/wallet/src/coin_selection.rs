@@ -0,0 +1,344 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use derive_error::Error;
+use rand::{OsRng, Rng};
+use serde::{Deserialize, Serialize};
+use tari_core::{
+    fee::Fee,
+    transaction::{UnblindedOutput, MAX_TRANSACTION_INPUTS, MINIMUM_TRANSACTION_FEE},
+};
+
+/// The maximum number of branches the branch-and-bound search explores before it gives up
+const BNB_MAX_TRIES: usize = 100_000;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CoinSelectionError {
+    // The available outputs are not enough to cover the amount and the fee
+    NotEnoughFunds,
+    // The outputs cover the amount, but the fee at this fee-per-gram is below the minimum transaction fee
+    FeeBelowMinimum,
+    // The operating system random number generator could not be created
+    #[error(msg_embedded, no_from, non_std)]
+    RandomError(String),
+}
+
+/// The ways in which inputs can be selected for a transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CoinSelectionStrategy {
+    /// Spend the smallest outputs first, which consolidates dust at the cost of higher fees
+    SmallestFirst,
+    /// Spend the largest outputs first, which minimises the number of inputs and so the fee
+    LargestFirst,
+    /// Search for a set of outputs that covers the amount and fee without a change output, wasting no more than the
+    /// cost of a change output. Falls back to `LargestFirst` if there is no such set.
+    BranchAndBound,
+    /// Spend outputs in random order, so that the selection reveals nothing about the wallet's other outputs
+    Random,
+}
+
+impl Default for CoinSelectionStrategy {
+    fn default() -> Self {
+        CoinSelectionStrategy::SmallestFirst
+    }
+}
+
+/// The result of coin selection
+#[derive(Clone, Debug)]
+pub struct CoinSelection {
+    /// The outputs to spend
+    pub inputs: Vec<UnblindedOutput>,
+    /// The total fee of the transaction
+    pub fee: u64,
+    /// The value of the change output, or zero if the transaction has no change output
+    pub change: u64,
+}
+
+/// Select outputs from `outputs` to send `amount` to a single recipient, paying `fee_per_gram`.
+///
+/// The fee and change are determined exactly as `SenderTransactionInitializer` does when the transaction is built: if
+/// what is left over after the amount and the fee is not more than the cost of adding a change output, it is added to
+/// the fee instead.
+pub fn select_coins(
+    outputs: &[UnblindedOutput],
+    amount: u64,
+    fee_per_gram: u64,
+    strategy: CoinSelectionStrategy,
+) -> Result<CoinSelection, CoinSelectionError>
+{
+    let mut candidates = outputs.to_vec();
+    match strategy {
+        CoinSelectionStrategy::SmallestFirst => candidates.sort_by_key(|o| o.value),
+        CoinSelectionStrategy::LargestFirst => candidates.sort_by(|a, b| b.value.cmp(&a.value)),
+        CoinSelectionStrategy::BranchAndBound => {
+            if let Some(selection) = branch_and_bound(&candidates, amount, fee_per_gram) {
+                return Ok(selection);
+            }
+            candidates.sort_by(|a, b| b.value.cmp(&a.value));
+        },
+        CoinSelectionStrategy::Random => {
+            let mut rng = OsRng::new().map_err(|e| CoinSelectionError::RandomError(e.to_string()))?;
+            rng.shuffle(&mut candidates);
+        },
+    }
+    accumulate(candidates, amount, fee_per_gram)
+}
+
+/// Calculate the fee and change for spending `total` in `num_inputs` inputs. Fails with `NotEnoughFunds` if the
+/// inputs don't cover the amount and fee, and with `FeeBelowMinimum` if they do but the fee is below the minimum
+/// transaction fee.
+pub fn fee_and_change(
+    total: u64,
+    num_inputs: usize,
+    amount: u64,
+    fee_per_gram: u64,
+) -> Result<(u64, u64), CoinSelectionError>
+{
+    let fee_without_change = Fee::calculate(fee_per_gram, num_inputs, 1);
+    let fee_with_change = Fee::calculate(fee_per_gram, num_inputs, 2);
+    let left_over = total
+        .checked_sub(amount)
+        .and_then(|v| v.checked_sub(fee_without_change))
+        .ok_or(CoinSelectionError::NotEnoughFunds)?;
+    let (fee, change) = match left_over.checked_sub(fee_with_change - fee_without_change) {
+        None | Some(0) => (fee_without_change + left_over, 0),
+        Some(change) => (fee_with_change, change),
+    };
+    if fee < MINIMUM_TRANSACTION_FEE {
+        return Err(CoinSelectionError::FeeBelowMinimum);
+    }
+    Ok((fee, change))
+}
+
+/// Take outputs in order until they cover the amount and fee. If the outputs cover the amount, but never with a fee
+/// of at least the minimum, the selection fails with `FeeBelowMinimum` rather than `NotEnoughFunds`.
+fn accumulate(
+    candidates: Vec<UnblindedOutput>,
+    amount: u64,
+    fee_per_gram: u64,
+) -> Result<CoinSelection, CoinSelectionError>
+{
+    let mut inputs = Vec::new();
+    let mut total = 0u64;
+    let mut error = CoinSelectionError::NotEnoughFunds;
+    for output in candidates.into_iter().take(MAX_TRANSACTION_INPUTS) {
+        total += output.value;
+        inputs.push(output);
+        match fee_and_change(total, inputs.len(), amount, fee_per_gram) {
+            Ok((fee, change)) => return Ok(CoinSelection { inputs, fee, change }),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Depth-first search, largest outputs first, for a set of outputs that needs no change output. Branches are cut
+/// when the selection already overshoots by more than the cost of a change output, or when the remaining outputs
+/// can't reach the target.
+fn branch_and_bound(candidates: &[UnblindedOutput], amount: u64, fee_per_gram: u64) -> Option<CoinSelection> {
+    let mut sorted = candidates.to_vec();
+    sorted.sort_by(|a, b| b.value.cmp(&a.value));
+    // remaining[i] is the total value of the outputs from index i onwards
+    let mut remaining = vec![0u64; sorted.len() + 1];
+    for i in (0..sorted.len()).rev() {
+        remaining[i] = remaining[i + 1] + sorted[i].value;
+    }
+    let change_cost = Fee::calculate(fee_per_gram, 0, 2) - Fee::calculate(fee_per_gram, 0, 1);
+
+    let mut selected = Vec::new();
+    let mut tries = 0;
+    let found = search(
+        &sorted,
+        &remaining,
+        0,
+        0,
+        &mut selected,
+        amount,
+        fee_per_gram,
+        change_cost,
+        &mut tries,
+    );
+    if !found {
+        return None;
+    }
+    let inputs: Vec<UnblindedOutput> = selected.iter().map(|&i| sorted[i].clone()).collect();
+    let total = inputs.iter().map(|o| o.value).sum();
+    let (fee, change) = fee_and_change(total, inputs.len(), amount, fee_per_gram).ok()?;
+    Some(CoinSelection { inputs, fee, change })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn search(
+    sorted: &[UnblindedOutput],
+    remaining: &[u64],
+    index: usize,
+    total: u64,
+    selected: &mut Vec<usize>,
+    amount: u64,
+    fee_per_gram: u64,
+    change_cost: u64,
+    tries: &mut usize,
+) -> bool
+{
+    *tries += 1;
+    if *tries > BNB_MAX_TRIES || selected.len() > MAX_TRANSACTION_INPUTS {
+        return false;
+    }
+    let target = amount + Fee::calculate(fee_per_gram, selected.len(), 1);
+    if !selected.is_empty() && total >= target {
+        if total - target > change_cost {
+            return false;
+        }
+        if let Ok((_, 0)) = fee_and_change(total, selected.len(), amount, fee_per_gram) {
+            return true;
+        }
+    }
+    if index >= sorted.len() || total + remaining[index] < target {
+        return false;
+    }
+    // Include the output at `index`, then try without it
+    selected.push(index);
+    if search(
+        sorted,
+        remaining,
+        index + 1,
+        total + sorted[index].value,
+        selected,
+        amount,
+        fee_per_gram,
+        change_cost,
+        tries,
+    ) {
+        return true;
+    }
+    selected.pop();
+    search(
+        sorted,
+        remaining,
+        index + 1,
+        total,
+        selected,
+        amount,
+        fee_per_gram,
+        change_cost,
+        tries,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::coin_selection::{fee_and_change, select_coins, CoinSelectionError, CoinSelectionStrategy};
+    use rand::OsRng;
+    use tari_core::{fee::Fee, transaction::UnblindedOutput, types::SecretKey};
+    use tari_crypto::keys::SecretKey as SK;
+
+    fn make_outputs(values: &[u64]) -> Vec<UnblindedOutput> {
+        let mut rng = OsRng::new().unwrap();
+        values
+            .iter()
+            .map(|v| UnblindedOutput::new(*v, SecretKey::random(&mut rng), None))
+            .collect()
+    }
+
+    fn values(outputs: &[UnblindedOutput]) -> Vec<u64> {
+        outputs.iter().map(|o| o.value).collect()
+    }
+
+    #[test]
+    fn change_threshold() {
+        let fee_without_change = Fee::calculate(20, 1, 1);
+        let fee_with_change = Fee::calculate(20, 1, 2);
+        // Exact match
+        assert_eq!(
+            fee_and_change(1000 + fee_without_change, 1, 1000, 20),
+            Ok((fee_without_change, 0))
+        );
+        // Left over is less than the cost of a change output, so it goes to the fee
+        assert_eq!(
+            fee_and_change(1000 + fee_without_change + 50, 1, 1000, 20),
+            Ok((fee_without_change + 50, 0))
+        );
+        // Enough left over for a change output
+        assert_eq!(
+            fee_and_change(1000 + fee_with_change + 50, 1, 1000, 20),
+            Ok((fee_with_change, 50))
+        );
+        assert_eq!(
+            fee_and_change(1000, 1, 1000, 20),
+            Err(CoinSelectionError::NotEnoughFunds)
+        );
+        // The fee may not be below the minimum
+        assert_eq!(
+            fee_and_change(10_000, 1, 1000, 1),
+            Err(CoinSelectionError::FeeBelowMinimum)
+        );
+    }
+
+    #[test]
+    fn smallest_and_largest_first() {
+        let outputs = make_outputs(&[3000, 1000, 2000, 5000]);
+        let selection = select_coins(&outputs, 2500, 20, CoinSelectionStrategy::SmallestFirst).unwrap();
+        assert_eq!(values(&selection.inputs), vec![1000, 2000]);
+        let selection = select_coins(&outputs, 2500, 20, CoinSelectionStrategy::LargestFirst).unwrap();
+        assert_eq!(values(&selection.inputs), vec![5000]);
+        assert_eq!(selection.change, 5000 - 2500 - selection.fee);
+        assert_eq!(
+            select_coins(&outputs, 11_000, 20, CoinSelectionStrategy::LargestFirst).unwrap_err(),
+            CoinSelectionError::NotEnoughFunds
+        );
+        // The outputs cover the amount, but a fee of 1µT per gram is too low
+        assert_eq!(
+            select_coins(&outputs, 2500, 1, CoinSelectionStrategy::LargestFirst).unwrap_err(),
+            CoinSelectionError::FeeBelowMinimum
+        );
+    }
+
+    #[test]
+    fn branch_and_bound_finds_exact_match() {
+        let fee = Fee::calculate(20, 2, 1);
+        let outputs = make_outputs(&[5000, 4000, 1500, 1000 + fee, 700]);
+        let selection = select_coins(&outputs, 2500, 20, CoinSelectionStrategy::BranchAndBound).unwrap();
+        let mut selected = values(&selection.inputs);
+        selected.sort();
+        assert_eq!(selected, vec![1000 + fee, 1500]);
+        assert_eq!(selection.change, 0);
+        assert_eq!(selection.fee, fee);
+    }
+
+    #[test]
+    fn branch_and_bound_falls_back() {
+        let outputs = make_outputs(&[5000, 4000]);
+        let selection = select_coins(&outputs, 1000, 20, CoinSelectionStrategy::BranchAndBound).unwrap();
+        assert_eq!(values(&selection.inputs), vec![5000]);
+        assert!(selection.change > 0);
+    }
+
+    #[test]
+    fn random_covers_amount() {
+        let outputs = make_outputs(&[1000, 2000, 3000, 4000, 5000]);
+        for _ in 0..10 {
+            let selection = select_coins(&outputs, 6000, 20, CoinSelectionStrategy::Random).unwrap();
+            let total: u64 = selection.inputs.iter().map(|o| o.value).sum();
+            assert_eq!(total, 6000 + selection.fee + selection.change);
+        }
+    }
+}
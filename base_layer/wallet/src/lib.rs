@@ -1 +1,8 @@
+pub mod chain_scanner;
+pub mod coin_selection;
+pub mod output_manager;
+pub mod recovery;
 pub mod transaction_manager;
+pub mod transaction_service;
+pub mod wallet_db;
+pub mod wallet_event;
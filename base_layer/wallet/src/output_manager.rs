@@ -0,0 +1,493 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::coin_selection::{select_coins, CoinSelection, CoinSelectionError, CoinSelectionStrategy};
+use chrono::{DateTime, Utc};
+use derive_error::Error;
+use rand::OsRng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tari_core::{
+    transaction::{TransactionInput, UnblindedOutput},
+    transaction_protocol::TransactionProtocolError,
+    types::{Commitment, CommitmentFactory, SecretKey},
+    SenderTransactionProtocol,
+};
+use tari_crypto::{
+    commitment::{HomomorphicCommitment, HomomorphicCommitmentFactory},
+    common::Blake256,
+    keys::SecretKey as SK,
+};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum OutputManagerError {
+    // The unspent outputs are not enough to cover the amount and the fee
+    NotEnoughFunds,
+    // The fee at the requested fee-per-gram is below the minimum transaction fee
+    FeeBelowMinimum,
+    // There is no pending transaction with this tx_id
+    PendingTransactionNotFound,
+    // The output is already tracked by the output manager
+    DuplicateOutput,
+    // A transaction with this tx_id is already pending
+    DuplicateTransaction,
+    // The sender transaction protocol could not be built
+    #[error(msg_embedded, no_from, non_std)]
+    BuildError(String),
+    // Transaction Protocol Error
+    TransactionProtocolError(TransactionProtocolError),
+}
+
+/// The state of an output owned by the wallet
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputStatus {
+    /// The output can be spent
+    Unspent,
+    /// The output is an input of a pending outbound transaction, and can't be selected for another transaction
+    PendingSpend,
+    /// The output was spent by a completed transaction
+    Spent,
+    /// The output will be received when a pending transaction completes
+    PendingIncoming,
+}
+
+/// The outputs that a pending transaction spends and creates for this wallet
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingTransactionOutputs {
+    pub tx_id: u64,
+    pub outputs_to_be_spent: Vec<UnblindedOutput>,
+    pub outputs_to_be_received: Vec<UnblindedOutput>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The balance of the wallet, in µT
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Balance {
+    /// The total value of the unspent outputs
+    pub available: u64,
+    /// The total value of the outputs that pending transactions will pay to the wallet, including change
+    pub pending_incoming: u64,
+    /// The total value of the outputs reserved as inputs of pending transactions
+    pub pending_outgoing: u64,
+}
+
+/// The OutputManager keeps track of the outputs owned by the wallet. Outputs move through the following states:
+///
+/// * Unspent outputs can be selected as inputs of a new transaction, which reserves them as `PendingSpend` until the
+///   transaction completes or is cancelled.
+/// * The outputs a pending transaction will create for the wallet, such as change or the output of an inbound
+///   transaction, are `PendingIncoming`.
+/// * When a transaction completes its inputs become `Spent` and its outputs become `Unspent`. When a transaction is
+///   cancelled its inputs become `Unspent` again and its outputs are discarded.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OutputManager {
+    unspent_outputs: Vec<UnblindedOutput>,
+    spent_outputs: Vec<UnblindedOutput>,
+    pending_transactions: HashMap<u64, PendingTransactionOutputs>,
+    #[serde(default)]
+    coin_selection_strategy: CoinSelectionStrategy,
+}
+
+impl OutputManager {
+    pub fn new() -> OutputManager {
+        OutputManager {
+            unspent_outputs: Vec::new(),
+            spent_outputs: Vec::new(),
+            pending_transactions: HashMap::new(),
+            coin_selection_strategy: CoinSelectionStrategy::default(),
+        }
+    }
+
+    /// The strategy used to select the outputs to spend in new transactions
+    pub fn coin_selection_strategy(&self) -> CoinSelectionStrategy {
+        self.coin_selection_strategy
+    }
+
+    /// Set the strategy used to select the outputs to spend in new transactions
+    pub fn set_coin_selection_strategy(&mut self, strategy: CoinSelectionStrategy) {
+        self.coin_selection_strategy = strategy;
+    }
+
+    /// Add an output that the wallet can spend, e.g. a mined coinbase
+    pub fn add_output(&mut self, output: UnblindedOutput) -> Result<(), OutputManagerError> {
+        if self.status_of(&output).is_some() {
+            return Err(OutputManagerError::DuplicateOutput);
+        }
+        self.unspent_outputs.push(output);
+        Ok(())
+    }
+
+    /// Track an output that will be received when the transaction `tx_id` completes
+    pub fn add_pending_incoming(&mut self, tx_id: u64, output: UnblindedOutput) -> Result<(), OutputManagerError> {
+        if self.status_of(&output).is_some() {
+            return Err(OutputManagerError::DuplicateOutput);
+        }
+        if self.pending_transactions.contains_key(&tx_id) {
+            return Err(OutputManagerError::DuplicateTransaction);
+        }
+        self.pending_transactions.insert(tx_id, PendingTransactionOutputs {
+            tx_id,
+            outputs_to_be_spent: Vec::new(),
+            outputs_to_be_received: vec![output],
+            timestamp: Utc::now(),
+        });
+        Ok(())
+    }
+
+    /// Build a `SenderTransactionProtocol` that sends `amount` to a single recipient. Unspent outputs are selected,
+    /// using the manager's `CoinSelectionStrategy`, to cover the amount and fee, and reserved until the transaction
+    /// completes or is cancelled. The change output, if any, is spent with `change_key` and tracked as pending
+    /// incoming.
+    pub fn prepare_transaction_to_send(
+        &mut self,
+        amount: u64,
+        fee_per_gram: u64,
+        lock_height: u64,
+        change_key: SecretKey,
+    ) -> Result<SenderTransactionProtocol, OutputManagerError>
+    {
+        let CoinSelection { inputs, fee, change } = self.select_outputs(amount, fee_per_gram)?;
+        let mut rng = OsRng::new().map_err(|e| OutputManagerError::BuildError(e.to_string()))?;
+
+        let mut builder = SenderTransactionProtocol::builder(1);
+        builder
+            .with_lock_height(lock_height)
+            .with_fee_per_gram(fee_per_gram)
+            .with_offset(SecretKey::random(&mut rng))
+            .with_private_nonce(SecretKey::random(&mut rng))
+            .with_change_secret(change_key.clone())
+            .with_amount(0, amount);
+        for input in inputs.iter() {
+            let commitment = CommitmentFactory::create(&input.spending_key, &SecretKey::from(input.value));
+            builder.with_input(TransactionInput::new(input.features, commitment), input.clone());
+        }
+        let stp = builder
+            .build::<Blake256>()
+            .map_err(|e| OutputManagerError::BuildError(e.message))?;
+        if let Some(e) = stp.failure_reason() {
+            return Err(OutputManagerError::TransactionProtocolError(e));
+        }
+        let tx_id = stp.get_tx_id()?;
+        if self.pending_transactions.contains_key(&tx_id) {
+            return Err(OutputManagerError::DuplicateTransaction);
+        }
+
+        // The coin selection and the builder follow the same fee and change rules
+        if stp.get_fee()? != fee || stp.get_amount_to_self()? != change {
+            return Err(OutputManagerError::BuildError(
+                "The transaction does not match the coin selection".to_string(),
+            ));
+        }
+        let outputs_to_be_received = if change > 0 {
+            vec![UnblindedOutput::new(change, change_key, None)]
+        } else {
+            Vec::new()
+        };
+        self.unspent_outputs
+            .retain(|o| !inputs.iter().any(|i| same_output(i, o)));
+        self.pending_transactions.insert(tx_id, PendingTransactionOutputs {
+            tx_id,
+            outputs_to_be_spent: inputs,
+            outputs_to_be_received,
+            timestamp: Utc::now(),
+        });
+        Ok(stp)
+    }
+
+    /// The transaction `tx_id` completed: its inputs are now spent and its outputs can be spent
+    pub fn confirm_transaction(&mut self, tx_id: u64) -> Result<(), OutputManagerError> {
+        let pending = self
+            .pending_transactions
+            .remove(&tx_id)
+            .ok_or(OutputManagerError::PendingTransactionNotFound)?;
+        self.spent_outputs.extend(pending.outputs_to_be_spent);
+        self.unspent_outputs.extend(pending.outputs_to_be_received);
+        Ok(())
+    }
+
+    /// The transaction `tx_id` was cancelled: its inputs are released and its outputs are discarded
+    pub fn cancel_transaction(&mut self, tx_id: u64) -> Result<(), OutputManagerError> {
+        let pending = self
+            .pending_transactions
+            .remove(&tx_id)
+            .ok_or(OutputManagerError::PendingTransactionNotFound)?;
+        self.unspent_outputs.extend(pending.outputs_to_be_spent);
+        Ok(())
+    }
+
+    /// Undo `confirm_transaction`: the outputs the transaction created are removed from the unspent outputs, its inputs
+    /// are no longer spent, and the transaction is pending again. This is used when the block a transaction was mined
+    /// in is reorged out.
+    pub fn restore_pending_transaction(
+        &mut self,
+        pending: PendingTransactionOutputs,
+    ) -> Result<(), OutputManagerError>
+    {
+        if self.pending_transactions.contains_key(&pending.tx_id) {
+            return Err(OutputManagerError::DuplicateTransaction);
+        }
+        self.unspent_outputs
+            .retain(|o| !pending.outputs_to_be_received.iter().any(|r| same_output(r, o)));
+        self.spent_outputs
+            .retain(|o| !pending.outputs_to_be_spent.iter().any(|i| same_output(i, o)));
+        self.pending_transactions.insert(pending.tx_id, pending);
+        Ok(())
+    }
+
+    /// An unspent output was spent by a transaction that the wallet did not create, e.g. one sent by another wallet
+    /// with the same master key. The output with the commitment is moved to the spent outputs.
+    /// # Returns
+    /// The spent output, or `None` if no unspent output has the commitment
+    pub fn spend_output(&mut self, commitment: &Commitment) -> Option<UnblindedOutput> {
+        let index = self
+            .unspent_outputs
+            .iter()
+            .position(|o| commitment_of(o).as_bytes() == commitment.as_bytes())?;
+        let output = self.unspent_outputs.remove(index);
+        self.spent_outputs.push(output.clone());
+        Some(output)
+    }
+
+    /// Undo `spend_output`: the spent output can be spent again. This is used when the block the output was spent in
+    /// is reorged out.
+    pub fn unspend_output(&mut self, output: &UnblindedOutput) {
+        if let Some(index) = self.spent_outputs.iter().position(|o| same_output(o, output)) {
+            let output = self.spent_outputs.remove(index);
+            self.unspent_outputs.push(output);
+        }
+    }
+
+    /// Stop tracking an unspent output, e.g. one found in a block that has since been reorged out
+    pub fn remove_output(&mut self, output: &UnblindedOutput) {
+        self.unspent_outputs.retain(|o| !same_output(o, output));
+    }
+
+    /// Whether the output with the commitment is owned by the wallet, in any state
+    pub fn owns_commitment(&self, commitment: &Commitment) -> bool {
+        let matches = |o: &UnblindedOutput| commitment_of(o).as_bytes() == commitment.as_bytes();
+        self.unspent_outputs.iter().any(matches) ||
+            self.spent_outputs.iter().any(matches) ||
+            self.pending_transactions
+                .values()
+                .any(|p| p.outputs_to_be_spent.iter().any(matches) || p.outputs_to_be_received.iter().any(matches))
+    }
+
+    /// The outputs a pending transaction spends and creates
+    pub fn pending_transaction(&self, tx_id: u64) -> Option<&PendingTransactionOutputs> {
+        self.pending_transactions.get(&tx_id)
+    }
+
+    /// The state of an output, or `None` if the output is not owned by the wallet
+    pub fn status_of(&self, output: &UnblindedOutput) -> Option<OutputStatus> {
+        if self.unspent_outputs.iter().any(|o| same_output(o, output)) {
+            return Some(OutputStatus::Unspent);
+        }
+        if self.spent_outputs.iter().any(|o| same_output(o, output)) {
+            return Some(OutputStatus::Spent);
+        }
+        for pending in self.pending_transactions.values() {
+            if pending.outputs_to_be_spent.iter().any(|o| same_output(o, output)) {
+                return Some(OutputStatus::PendingSpend);
+            }
+            if pending.outputs_to_be_received.iter().any(|o| same_output(o, output)) {
+                return Some(OutputStatus::PendingIncoming);
+            }
+        }
+        None
+    }
+
+    /// All the outputs in the given state
+    pub fn outputs_with_status(&self, status: OutputStatus) -> Vec<UnblindedOutput> {
+        match status {
+            OutputStatus::Unspent => self.unspent_outputs.clone(),
+            OutputStatus::Spent => self.spent_outputs.clone(),
+            OutputStatus::PendingSpend => self
+                .pending_transactions
+                .values()
+                .flat_map(|p| p.outputs_to_be_spent.iter().cloned())
+                .collect(),
+            OutputStatus::PendingIncoming => self
+                .pending_transactions
+                .values()
+                .flat_map(|p| p.outputs_to_be_received.iter().cloned())
+                .collect(),
+        }
+    }
+
+    pub fn balance(&self) -> Balance {
+        let sum = |outputs: Vec<UnblindedOutput>| outputs.iter().map(|o| o.value).sum::<u64>();
+        Balance {
+            available: sum(self.outputs_with_status(OutputStatus::Unspent)),
+            pending_incoming: sum(self.outputs_with_status(OutputStatus::PendingIncoming)),
+            pending_outgoing: sum(self.outputs_with_status(OutputStatus::PendingSpend)),
+        }
+    }
+
+    /// Select unspent outputs with the configured strategy to cover the amount and the fee
+    fn select_outputs(&self, amount: u64, fee_per_gram: u64) -> Result<CoinSelection, OutputManagerError> {
+        select_coins(
+            &self.unspent_outputs,
+            amount,
+            fee_per_gram,
+            self.coin_selection_strategy,
+        )
+        .map_err(|e| match e {
+            CoinSelectionError::NotEnoughFunds => OutputManagerError::NotEnoughFunds,
+            CoinSelectionError::FeeBelowMinimum => OutputManagerError::FeeBelowMinimum,
+            CoinSelectionError::RandomError(e) => OutputManagerError::BuildError(e),
+        })
+    }
+}
+
+/// Outputs are identified by their value and spending key, which together determine the commitment
+fn same_output(a: &UnblindedOutput, b: &UnblindedOutput) -> bool {
+    a.value == b.value && a.spending_key == b.spending_key
+}
+
+fn commitment_of(output: &UnblindedOutput) -> Commitment {
+    CommitmentFactory::create(&output.spending_key, &SecretKey::from(output.value))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        coin_selection::CoinSelectionStrategy,
+        output_manager::{Balance, OutputManager, OutputManagerError, OutputStatus},
+    };
+    use rand::OsRng;
+    use tari_core::{fee::Fee, transaction::UnblindedOutput, types::SecretKey};
+    use tari_crypto::keys::SecretKey as SK;
+
+    fn make_output(rng: &mut OsRng, value: u64) -> UnblindedOutput {
+        UnblindedOutput::new(value, SecretKey::random(rng), None)
+    }
+
+    #[test]
+    fn send_and_confirm() {
+        let mut rng = OsRng::new().unwrap();
+        let mut om = OutputManager::new();
+        let small = make_output(&mut rng, 1_000);
+        let large = make_output(&mut rng, 5_000);
+        om.add_output(small.clone()).unwrap();
+        om.add_output(large.clone()).unwrap();
+        assert_eq!(om.add_output(small.clone()), Err(OutputManagerError::DuplicateOutput));
+        assert_eq!(om.balance().available, 6_000);
+
+        let change_key = SecretKey::random(&mut rng);
+        let stp = om
+            .prepare_transaction_to_send(2_000, 20, 0, change_key.clone())
+            .unwrap();
+        let tx_id = stp.get_tx_id().unwrap();
+        let fee = stp.get_fee().unwrap();
+        let change = &om.pending_transaction(tx_id).unwrap().outputs_to_be_received[0];
+        assert_eq!(change.spending_key, change_key);
+        // Both outputs are needed for 2000 plus the fee
+        assert_eq!(om.status_of(&small), Some(OutputStatus::PendingSpend));
+        assert_eq!(om.status_of(&large), Some(OutputStatus::PendingSpend));
+        assert_eq!(om.balance(), Balance {
+            available: 0,
+            pending_incoming: 6_000 - 2_000 - fee,
+            pending_outgoing: 6_000,
+        });
+        assert_eq!(fee, Fee::calculate(20, 2, 2));
+
+        om.confirm_transaction(tx_id).unwrap();
+        assert_eq!(om.status_of(&small), Some(OutputStatus::Spent));
+        assert_eq!(om.balance(), Balance {
+            available: 6_000 - 2_000 - fee,
+            pending_incoming: 0,
+            pending_outgoing: 0,
+        });
+        assert_eq!(
+            om.confirm_transaction(tx_id),
+            Err(OutputManagerError::PendingTransactionNotFound)
+        );
+    }
+
+    #[test]
+    fn coin_selection_strategy_is_used() {
+        let mut rng = OsRng::new().unwrap();
+        let mut om = OutputManager::new();
+        let small = make_output(&mut rng, 1_000);
+        let large = make_output(&mut rng, 5_000);
+        om.add_output(small.clone()).unwrap();
+        om.add_output(large.clone()).unwrap();
+        om.set_coin_selection_strategy(CoinSelectionStrategy::LargestFirst);
+        assert_eq!(om.coin_selection_strategy(), CoinSelectionStrategy::LargestFirst);
+
+        om.prepare_transaction_to_send(2_000, 20, 0, SecretKey::random(&mut rng))
+            .unwrap();
+        assert_eq!(om.status_of(&small), Some(OutputStatus::Unspent));
+        assert_eq!(om.status_of(&large), Some(OutputStatus::PendingSpend));
+    }
+
+    #[test]
+    fn fee_below_minimum() {
+        let mut rng = OsRng::new().unwrap();
+        let mut om = OutputManager::new();
+        let output = make_output(&mut rng, 5_000);
+        om.add_output(output.clone()).unwrap();
+        assert_eq!(
+            om.prepare_transaction_to_send(1_000, 1, 0, SecretKey::random(&mut rng))
+                .unwrap_err(),
+            OutputManagerError::FeeBelowMinimum
+        );
+        assert_eq!(om.status_of(&output), Some(OutputStatus::Unspent));
+    }
+
+    #[test]
+    fn cancel_releases_reservation() {
+        let mut rng = OsRng::new().unwrap();
+        let mut om = OutputManager::new();
+        let output = make_output(&mut rng, 5_000);
+        om.add_output(output.clone()).unwrap();
+        let stp = om
+            .prepare_transaction_to_send(1_000, 20, 0, SecretKey::random(&mut rng))
+            .unwrap();
+        // The reserved output can't be used for another transaction
+        assert_eq!(
+            om.prepare_transaction_to_send(1_000, 20, 0, SecretKey::random(&mut rng))
+                .unwrap_err(),
+            OutputManagerError::NotEnoughFunds
+        );
+        om.cancel_transaction(stp.get_tx_id().unwrap()).unwrap();
+        assert_eq!(om.status_of(&output), Some(OutputStatus::Unspent));
+        assert_eq!(om.balance(), Balance {
+            available: 5_000,
+            pending_incoming: 0,
+            pending_outgoing: 0,
+        });
+    }
+
+    #[test]
+    fn pending_incoming() {
+        let mut rng = OsRng::new().unwrap();
+        let mut om = OutputManager::new();
+        let output = make_output(&mut rng, 700);
+        om.add_pending_incoming(42, output.clone()).unwrap();
+        assert_eq!(om.status_of(&output), Some(OutputStatus::PendingIncoming));
+        assert_eq!(om.balance().pending_incoming, 700);
+        om.confirm_transaction(42).unwrap();
+        assert_eq!(om.status_of(&output), Some(OutputStatus::Unspent));
+        assert_eq!(om.balance().available, 700);
+    }
+}
@@ -0,0 +1,419 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{
+    transaction_manager::{TransactionManager, TransactionManagerError},
+    wallet_db::{WalletDatabase, WalletDbError},
+};
+use derive_error::Error;
+use keymanager::keymanager::{KeyBranch, KeyManager, KeyPath};
+use serde::{Deserialize, Serialize};
+use tari_core::{
+    block::Block,
+    transaction::{TransactionOutput, UnblindedOutput},
+    types::{Commitment, CommitmentFactory, HashDigest, SecretKey},
+};
+use tari_crypto::commitment::{HomomorphicCommitment, HomomorphicCommitmentFactory};
+use tari_storage::keyvalue_store::DataStore;
+use tari_utilities::{byte_array::ByteArrayError, Hashable};
+
+/// The number of unused keys after the last used key that are tried before recovery assumes no more keys were used
+pub const DEFAULT_GAP_LIMIT: usize = 20;
+
+#[derive(Debug, Error)]
+pub enum RecoveryError {
+    // The block is not the next block of the chain that is being scanned
+    UnexpectedBlock,
+    // A wallet key could not be derived
+    KeyDerivationError(ByteArrayError),
+    // The recovered outputs could not be added to the wallet
+    TransactionManagerError(TransactionManagerError),
+    // The recovery state could not be stored or loaded
+    WalletDbError(WalletDbError),
+}
+
+/// How far recovery has got
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoveryProgress {
+    /// The height of the last scanned block
+    pub height: u64,
+    /// The height recovery is scanning up to, if it is known
+    pub tip_height: Option<u64>,
+    /// The number of outputs found that belong to the wallet
+    pub outputs_found: usize,
+    /// The number of those outputs that have since been spent
+    pub outputs_spent: usize,
+    /// The total value of the unspent outputs found so far
+    pub balance: u64,
+    /// The number of outputs that no wallet key within the gap limit opens, i.e. the outputs of other wallets
+    pub outputs_unmatched: u64,
+}
+
+/// Everything recovery has found so far. The state can be stored with `save` and recovery resumed later from the
+/// next block.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RecoveryState {
+    next_height: u64,
+    last_hash: Option<Vec<u8>>,
+    highest_used_index: Option<usize>,
+    #[serde(default)]
+    highest_used_change_index: Option<usize>,
+    unspent_outputs: Vec<UnblindedOutput>,
+    spent_outputs: Vec<UnblindedOutput>,
+    #[serde(default)]
+    outputs_unmatched: u64,
+}
+
+impl RecoveryState {
+    /// Start recovery at `height`, e.g. the height of the block the wallet was created in
+    pub fn starting_at(height: u64) -> RecoveryState {
+        RecoveryState {
+            next_height: height,
+            ..Default::default()
+        }
+    }
+
+    /// The height of the next block to scan
+    pub fn next_height(&self) -> u64 {
+        self.next_height
+    }
+
+    /// The highest receive key index that an output was found for
+    pub fn highest_used_index(&self) -> Option<usize> {
+        self.highest_used_index
+    }
+
+    /// The highest change key index that an output was found for
+    pub fn highest_used_change_index(&self) -> Option<usize> {
+        self.highest_used_change_index
+    }
+
+    /// The unspent outputs that have been recovered
+    pub fn unspent_outputs(&self) -> &[UnblindedOutput] {
+        &self.unspent_outputs
+    }
+
+    /// Store the recovery state in the wallet database
+    pub fn save<S: DataStore>(&self, database: &mut WalletDatabase<S>) -> Result<(), RecoveryError> {
+        Ok(database.save_recovery_state(self)?)
+    }
+
+    /// The recovery state stored in the wallet database, if there is one
+    pub fn load<S: DataStore>(database: &WalletDatabase<S>) -> Result<Option<RecoveryState>, RecoveryError> {
+        Ok(database.fetch_recovery_state()?)
+    }
+}
+
+/// Recovers a wallet's funds from its master key by scanning the blockchain.
+///
+/// The receive keys `derive_key(0)`, `derive_key(1)`, ... and the keys of the `Change` branch of the key manager are
+/// tried against the outputs of each block, as far as `gap_limit` keys beyond the highest key of each branch that has
+/// been found. Every output carries its value encrypted with its spending key, so each key decrypts a candidate value
+/// and the output belongs to the wallet if its commitment opens with the key and that value. Values of any size are
+/// recovered this way. Outputs that are later spent as inputs are moved to the spent outputs.
+pub struct WalletRecovery {
+    key_manager: KeyManager<SecretKey, HashDigest>,
+    gap_limit: usize,
+    state: RecoveryState,
+    keys: Vec<SecretKey>,
+    change_keys: Vec<SecretKey>,
+}
+
+impl WalletRecovery {
+    /// Start recovery with the default gap limit
+    pub fn new(key_manager: KeyManager<SecretKey, HashDigest>, state: RecoveryState) -> WalletRecovery {
+        WalletRecovery::with_gap_limit(key_manager, state, DEFAULT_GAP_LIMIT)
+    }
+
+    /// Start recovery, trying `gap_limit` keys past the highest used key of each branch
+    pub fn with_gap_limit(
+        key_manager: KeyManager<SecretKey, HashDigest>,
+        state: RecoveryState,
+        gap_limit: usize,
+    ) -> WalletRecovery
+    {
+        WalletRecovery {
+            key_manager,
+            gap_limit: gap_limit.max(1),
+            state,
+            keys: Vec::new(),
+            change_keys: Vec::new(),
+        }
+    }
+
+    /// The recovery state, which can be saved to resume recovery later
+    pub fn state(&self) -> &RecoveryState {
+        &self.state
+    }
+
+    /// The current progress
+    pub fn progress(&self, tip_height: Option<u64>) -> RecoveryProgress {
+        RecoveryProgress {
+            height: self.state.next_height.saturating_sub(1),
+            tip_height,
+            outputs_found: self.state.unspent_outputs.len() + self.state.spent_outputs.len(),
+            outputs_spent: self.state.spent_outputs.len(),
+            balance: self.state.unspent_outputs.iter().map(|o| o.value).sum(),
+            outputs_unmatched: self.state.outputs_unmatched,
+        }
+    }
+
+    /// Scan the next block of the chain for the wallet's outputs. The changes the block makes are applied to a copy
+    /// of the recovery state, which replaces the state once the whole block is scanned, so the state is left as it
+    /// was if scanning fails.
+    pub fn scan_block(&mut self, block: &Block) -> Result<(), RecoveryError> {
+        if block.header.height != self.state.next_height {
+            return Err(RecoveryError::UnexpectedBlock);
+        }
+        if let Some(hash) = &self.state.last_hash {
+            if hash[..] != block.header.prev_hash[..] {
+                return Err(RecoveryError::UnexpectedBlock);
+            }
+        }
+        let mut state = self.state.clone();
+
+        for input in block.body.inputs.iter() {
+            let spent = state
+                .unspent_outputs
+                .iter()
+                .position(|o| commitment_of(o).as_bytes() == input.commitment().as_bytes());
+            if let Some(i) = spent {
+                let output = state.unspent_outputs.remove(i);
+                state.spent_outputs.push(output);
+            }
+        }
+
+        for output in block.body.outputs.iter() {
+            self.extend_key_windows(&state)?;
+            if let Some((index, value)) = find_output(&self.keys, output) {
+                let key = self.keys[index].clone();
+                state
+                    .unspent_outputs
+                    .push(UnblindedOutput::new(value, key, Some(output.features)));
+                state.highest_used_index = Some(state.highest_used_index.map_or(index, |i| i.max(index)));
+            } else if let Some((index, value)) = find_output(&self.change_keys, output) {
+                let key = self.change_keys[index].clone();
+                state
+                    .unspent_outputs
+                    .push(UnblindedOutput::new(value, key, Some(output.features)));
+                state.highest_used_change_index = Some(state.highest_used_change_index.map_or(index, |i| i.max(index)));
+            } else {
+                state.outputs_unmatched += 1;
+            }
+        }
+
+        state.next_height += 1;
+        state.last_hash = Some(block.header.hash());
+        self.state = state;
+        Ok(())
+    }
+
+    /// Scan a sequence of blocks, calling `on_progress` after each block. The blocks must continue from the next
+    /// height in the recovery state.
+    pub fn scan_blocks<I, F>(
+        &mut self,
+        blocks: I,
+        tip_height: Option<u64>,
+        mut on_progress: F,
+    ) -> Result<(), RecoveryError>
+    where
+        I: IntoIterator<Item = Block>,
+        F: FnMut(&RecoveryProgress),
+    {
+        for block in blocks {
+            self.scan_block(&block)?;
+            on_progress(&self.progress(tip_height));
+        }
+        Ok(())
+    }
+
+    /// Add the recovered unspent outputs to the wallet, and store the next unused receive and change key indices so
+    /// that new keys don't reuse a recovered key
+    pub fn finish<S: DataStore>(self, transaction_manager: &mut TransactionManager<S>) -> Result<(), RecoveryError> {
+        for output in self.state.unspent_outputs {
+            if transaction_manager.output_manager().status_of(&output).is_none() {
+                transaction_manager.add_output(output)?;
+            }
+        }
+        if let Some(index) = self.state.highest_used_index {
+            transaction_manager
+                .database_mut()
+                .set_key_index(&self.key_manager.branch_seed, index + 1)?;
+        }
+        if let Some(index) = self.state.highest_used_change_index {
+            transaction_manager
+                .database_mut()
+                .set_key_index(&KeyBranch::Change.label(), index + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Make sure all the receive and change keys up to `gap_limit` past the highest used key of each branch in `state`
+    /// are derived
+    fn extend_key_windows(&mut self, state: &RecoveryState) -> Result<(), RecoveryError> {
+        let window = state.highest_used_index.map_or(0, |i| i + 1) + self.gap_limit;
+        while self.keys.len() < window {
+            let key = self.key_manager.derive_key(self.keys.len())?.k;
+            self.keys.push(key);
+        }
+        let window = state.highest_used_change_index.map_or(0, |i| i + 1) + self.gap_limit;
+        while self.change_keys.len() < window {
+            let key = self
+                .key_manager
+                .derive_path_key(&change_path(self.change_keys.len()))?
+                .k;
+            self.change_keys.push(key);
+        }
+        Ok(())
+    }
+}
+
+/// The index of the key in `keys` that opens the output, and the output's value
+fn find_output(keys: &[SecretKey], output: &TransactionOutput) -> Option<(usize, u64)> {
+    keys.iter()
+        .enumerate()
+        .find_map(|(index, key)| output.recover_value(key).map(|value| (index, value)))
+}
+
+/// The path of the change key with the index, as the transaction service derives it
+fn change_path(index: usize) -> KeyPath {
+    KeyPath::new(0, KeyBranch::Change, index)
+}
+
+fn commitment_of(output: &UnblindedOutput) -> Commitment {
+    CommitmentFactory::create(&output.spending_key, &SecretKey::from(output.value))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        recovery::{RecoveryProgress, RecoveryState, WalletRecovery},
+        transaction_manager::TransactionManager,
+    };
+    use keymanager::keymanager::{KeyBranch, KeyManager, KeyPath};
+    use tari_core::{
+        block::Block,
+        transaction::{OutputFeatures, Transaction, TransactionInput, TransactionOutput, UnblindedOutput},
+        transaction_protocol::test_common::create_test_block,
+        types::{BlindingFactor, CommitmentFactory, HashDigest, SecretKey},
+    };
+    use tari_crypto::{commitment::HomomorphicCommitmentFactory, keys::SecretKey as SK};
+    use tari_utilities::Hashable;
+
+    fn block_hash(block: &Block) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&block.header.hash());
+        hash
+    }
+
+    /// A stand-in transaction with the given commitments; recovery doesn't validate what it scans
+    fn make_tx(inputs: Vec<(SecretKey, u64)>, outputs: Vec<(SecretKey, u64)>) -> Transaction {
+        let inputs = inputs
+            .iter()
+            .map(|(k, v)| {
+                TransactionInput::new(
+                    OutputFeatures::empty(),
+                    CommitmentFactory::create(k, &SecretKey::from(*v)),
+                )
+            })
+            .collect();
+        let outputs = outputs
+            .iter()
+            .map(|(k, v)| {
+                TransactionOutput::new(
+                    OutputFeatures::empty(),
+                    CommitmentFactory::create(k, &SecretKey::from(*v)),
+                    Vec::new(),
+                    UnblindedOutput::new(*v, k.clone(), None).encrypted_value(),
+                )
+            })
+            .collect();
+        Transaction::new(inputs, outputs, Vec::new(), BlindingFactor::default())
+    }
+
+    #[test]
+    fn recover_outputs_from_master_key() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let km = KeyManager::<SecretKey, HashDigest>::new(&mut rng);
+        let key = |i: usize| km.derive_key(i).unwrap().k;
+        let change_key = |i: usize| km.derive_path_key(&KeyPath::new(0, KeyBranch::Change, i)).unwrap().k;
+        let stranger = SecretKey::random(&mut rng);
+
+        let b0 = create_test_block(0, [0u8; 32], 0, vec![make_tx(vec![], vec![
+            (key(0), 1500),
+            (stranger.clone(), 700),
+        ])]);
+        let b1 = create_test_block(1, block_hash(&b0), 0, vec![make_tx(vec![(key(0), 1500)], vec![(
+            key(3),
+            1200,
+        )])]);
+        let b2 = create_test_block(2, block_hash(&b1), 0, vec![make_tx(vec![], vec![
+            (key(7), 300),
+            (change_key(2), 400),
+        ])]);
+        let b3 = create_test_block(3, block_hash(&b2), 0, vec![make_tx(vec![], vec![
+            (key(40), 50),
+            (key(1), 1 << 40),
+        ])]);
+
+        // Scan the first two blocks, then stop
+        let mut recovery = WalletRecovery::with_gap_limit(km.clone(), RecoveryState::starting_at(0), 5);
+        let mut reports = Vec::new();
+        recovery
+            .scan_blocks(vec![b0, b1.clone()], Some(3), |p| reports.push(p.clone()))
+            .unwrap();
+        assert_eq!(
+            reports.last(),
+            Some(&RecoveryProgress {
+                height: 1,
+                tip_height: Some(3),
+                outputs_found: 2,
+                outputs_spent: 1,
+                balance: 1200,
+                outputs_unmatched: 1,
+            })
+        );
+        let progress = recovery.progress(Some(3));
+        assert!(recovery.scan_block(&b1).is_err());
+        assert_eq!(recovery.progress(Some(3)), progress);
+
+        // Resume from the saved state
+        let mut tx_manager = TransactionManager::new().unwrap();
+        recovery.state().save(tx_manager.database_mut()).unwrap();
+        let state = RecoveryState::load(tx_manager.database()).unwrap().unwrap();
+        assert_eq!(state.next_height(), 2);
+        let mut recovery = WalletRecovery::with_gap_limit(km.clone(), state, 5);
+        recovery.scan_blocks(vec![b2, b3], Some(3), |_| {}).unwrap();
+        // Key 40 is beyond the gap limit, so its output isn't found but is counted
+        assert_eq!(recovery.state().highest_used_index(), Some(7));
+        assert_eq!(recovery.state().highest_used_change_index(), Some(2));
+        let progress = recovery.progress(None);
+        assert_eq!(progress.balance, 1900 + (1 << 40));
+        assert_eq!(progress.outputs_unmatched, 2);
+
+        recovery.finish(&mut tx_manager).unwrap();
+        assert_eq!(tx_manager.output_manager().balance().available, 1900 + (1 << 40));
+        let database = tx_manager.database();
+        assert_eq!(database.get_key_index(&km.branch_seed).unwrap(), Some(8));
+        assert_eq!(database.get_key_index(&KeyBranch::Change.label()).unwrap(), Some(3));
+    }
+}
@@ -20,19 +20,38 @@
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
 
+use crate::{
+    chain_scanner::{ChainScanner, ChainScannerError, MinedTransaction, ScanEvent, ScannedBlock},
+    output_manager::{OutputManager, OutputManagerError},
+    recovery::DEFAULT_GAP_LIMIT,
+    wallet_db::{TransactionQuery, TransactionRecord, TransactionStatus, WalletDatabase, WalletDbError},
+    wallet_event::{WalletEvent, WalletEventPublisher},
+};
 use derive_error::Error;
-use std::collections::HashMap;
+use keymanager::keymanager::{KeyBranch, KeyManager, KeyPath};
+use rand::{OsRng, RngCore};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::mpsc::Receiver,
+};
 use tari_core::{
-    transaction::{KernelFeatures, OutputFeatures, Transaction},
+    block::Block,
+    fee_estimator::{ConfirmationTarget, FeeEstimator, MempoolStats},
+    transaction::{KernelFeatures, OutputFeatures, Transaction, UnblindedOutput},
     transaction_protocol::{
+        multisig::MultiSigSession,
         recipient::RecipientSignedTransactionData,
         sender::SenderMessage,
+        TransactionMetadata,
         TransactionProtocolError,
     },
-    types::SecretKey,
+    types::{HashDigest, SecretKey},
     ReceiverTransactionProtocol,
     SenderTransactionProtocol,
 };
+use tari_crypto::commitment::HomomorphicCommitment;
+use tari_storage::{hashmap_store::HashmapStore, keyvalue_store::DataStore};
+use tari_utilities::Hashable;
 
 #[derive(Debug, Error, PartialEq)]
 pub enum TransactionManagerError {
@@ -46,6 +65,18 @@ pub enum TransactionManagerError {
     RepeatedMessageError,
     // A recipient reply was received for a non-existent tx_id
     TransactionDoesNotExistError,
+    // The transaction could not be stored in or loaded from the wallet database
+    WalletDbError(WalletDbError),
+    // The outputs of the transaction could not be updated
+    OutputManagerError(OutputManagerError),
+    // The block could not be scanned
+    ChainScannerError(ChainScannerError),
+    // The operating system random number generator could not be created
+    #[error(msg_embedded, no_from, non_std)]
+    RandomError(String),
+    // A wallet key could not be derived
+    #[error(msg_embedded, no_from, non_std)]
+    KeyDerivationError(String),
 }
 
 /// TransactionManager allows for the management of multiple inbound and outbound transaction protocols
@@ -54,27 +85,200 @@ pub enum TransactionManagerError {
 /// The TransactionManager allows for the sending of transactions to single receivers, when the appropriate recipient
 /// response is handled the transaction is completed and moved to the completed_transaction buffer.
 /// The TransactionManager will accept inbound transactions and generate a reply. Received transactions will remain
-/// in the pending_inbound_transactions buffer.
-/// TODO Allow for inbound transactions that are detected on the blockchain to be marked as complete.
+/// in the pending_inbound_transactions buffer until their output is found on the blockchain by `scan_block`.
 ///
 /// # Fields
 /// 'pending_outbound_transactions' - List of transaction protocols sent by this client and waiting response from the
 /// recipient 'pending_inbound_transactions' - List of transaction protocols that have been received and responded to.
 /// 'completed_transaction' - List of sent transactions that have been responded to and are completed.
+/// 'output_manager' - The outputs owned by the wallet, which are reserved and released as transactions progress.
+/// 'chain_scanner' - The blocks that have been scanned and the heights at which the transactions were mined
+/// 'fee_estimator' - The fee rates of the scanned blocks and the state of the mempool, used to recommend fees
+/// 'database' - The wallet database that every change to the above lists is written to
+/// 'event_publisher' - Publishes a WalletEvent to subscribers whenever the state of a transaction changes
+///
+/// A TransactionManager created with `new` keeps its database in memory; use `with_database` to persist the
+/// transactions.
 
-pub struct TransactionManager {
+pub struct TransactionManager<S = HashmapStore>
+where S: DataStore
+{
     pending_outbound_transactions: HashMap<u64, SenderTransactionProtocol>,
     pending_inbound_transactions: HashMap<u64, ReceiverTransactionProtocol>,
     completed_transactions: HashMap<u64, Transaction>,
+    output_manager: OutputManager,
+    chain_scanner: ChainScanner,
+    fee_estimator: FeeEstimator,
+    database: WalletDatabase<S>,
+    event_publisher: WalletEventPublisher,
 }
 
-impl TransactionManager {
-    pub fn new() -> TransactionManager {
-        TransactionManager {
-            pending_outbound_transactions: HashMap::new(),
-            pending_inbound_transactions: HashMap::new(),
-            completed_transactions: HashMap::new(),
+impl TransactionManager<HashmapStore> {
+    /// Create a TransactionManager with an in-memory database, encrypted with a random key
+    pub fn new() -> Result<TransactionManager<HashmapStore>, TransactionManagerError> {
+        let mut encryption_key = [0u8; 32];
+        OsRng::new()
+            .map_err(|e| TransactionManagerError::RandomError(e.to_string()))?
+            .fill_bytes(&mut encryption_key);
+        let database = WalletDatabase::new(HashmapStore::new(), encryption_key)?;
+        TransactionManager::with_database(database)
+    }
+}
+
+impl<S> TransactionManager<S>
+where S: DataStore
+{
+    /// Create a TransactionManager on top of a wallet database, restoring any transactions stored in it
+    pub fn with_database(database: WalletDatabase<S>) -> Result<TransactionManager<S>, TransactionManagerError> {
+        Ok(TransactionManager {
+            pending_outbound_transactions: database.fetch_all_pending_outbound()?,
+            pending_inbound_transactions: database.fetch_all_pending_inbound()?,
+            completed_transactions: database.fetch_all_completed()?,
+            output_manager: database.fetch_output_manager()?.unwrap_or_default(),
+            chain_scanner: database.fetch_chain_scanner()?.unwrap_or_default(),
+            fee_estimator: database.fetch_fee_estimator()?.unwrap_or_default(),
+            database,
+            event_publisher: WalletEventPublisher::new(),
+        })
+    }
+
+    /// The wallet database the transactions are stored in
+    pub fn database(&self) -> &WalletDatabase<S> {
+        &self.database
+    }
+
+    /// Mutable access to the wallet database, e.g. to store key indices
+    pub fn database_mut(&mut self) -> &mut WalletDatabase<S> {
+        &mut self.database
+    }
+
+    /// Release the wallet database
+    pub fn into_database(self) -> WalletDatabase<S> {
+        self.database
+    }
+
+    /// Subscribe to the events that are published when the state of a transaction changes
+    pub fn subscribe(&mut self) -> Receiver<WalletEvent> {
+        self.event_publisher.subscribe()
+    }
+
+    /// The history of the transaction with the given tx_id
+    pub fn transaction(&self, tx_id: u64) -> Result<Option<TransactionRecord>, TransactionManagerError> {
+        Ok(self.database.fetch_transaction(tx_id)?)
+    }
+
+    /// The history of the transactions that match the query, in the order they were created
+    pub fn transaction_history(
+        &self,
+        query: &TransactionQuery,
+    ) -> Result<Vec<TransactionRecord>, TransactionManagerError>
+    {
+        Ok(self.database.fetch_transactions(query)?)
+    }
+
+    /// The outputs owned by the wallet
+    pub fn output_manager(&self) -> &OutputManager {
+        &self.output_manager
+    }
+
+    /// Add an output that the wallet can spend
+    pub fn add_output(&mut self, output: UnblindedOutput) -> Result<(), TransactionManagerError> {
+        self.output_manager.add_output(output)?;
+        Ok(self.database.save_output_manager(&self.output_manager)?)
+    }
+
+    /// Start this wallet's signing session for a transaction that is built together with `participant_count - 1`
+    /// other parties. `inputs` and `outputs` are the parts of the transaction that this wallet contributes; their
+    /// spending keys make up the wallet's share of the excess and are never revealed to the other parties. The
+    /// returned session has to be driven by exchanging its messages with the other parties.
+    pub fn start_multisig_session(
+        &self,
+        participant_count: usize,
+        inputs: &[UnblindedOutput],
+        outputs: &[UnblindedOutput],
+        metadata: TransactionMetadata,
+    ) -> Result<MultiSigSession, TransactionManagerError>
+    {
+        let excess_share = inputs
+            .iter()
+            .fold(SecretKey::default(), |acc, i| &acc - &i.spending_key);
+        let excess_share = outputs.iter().fold(excess_share, |acc, o| &acc + &o.spending_key);
+        let mut rng = OsRng::new().map_err(|e| TransactionManagerError::RandomError(e.to_string()))?;
+        Ok(MultiSigSession::new(
+            &mut rng,
+            participant_count,
+            excess_share,
+            metadata,
+        )?)
+    }
+
+    /// Send `amount` to a single recipient, funded from the wallet's unspent outputs. The selected outputs are
+    /// reserved until the transaction completes or is cancelled, and the change, if any, is spent with `change_key`.
+    /// # Returns
+    /// Public SenderMessage to be transmitted to the recipient.
+    pub fn send_transaction(
+        &mut self,
+        amount: u64,
+        fee_per_gram: u64,
+        lock_height: u64,
+        change_key: SecretKey,
+    ) -> Result<SenderMessage, TransactionManagerError>
+    {
+        let stp = self
+            .output_manager
+            .prepare_transaction_to_send(amount, fee_per_gram, lock_height, change_key)?;
+        let tx_id = stp.get_tx_id()?;
+        self.start_send_transaction(stp).map_err(|e| {
+            // The reservation was just made, so releasing it can't fail. The reason the send failed is returned.
+            let _ = self.output_manager.cancel_transaction(tx_id);
+            e
+        })
+    }
+
+    /// Send `amount` to a single recipient, paying the fee-per-gram that the fee estimator recommends for the
+    /// confirmation target.
+    /// # Returns
+    /// Public SenderMessage to be transmitted to the recipient.
+    pub fn send_transaction_with_target(
+        &mut self,
+        amount: u64,
+        target: ConfirmationTarget,
+        lock_height: u64,
+        change_key: SecretKey,
+    ) -> Result<SenderMessage, TransactionManagerError>
+    {
+        let fee_per_gram = self.fee_estimator.fee_per_gram(target);
+        self.send_transaction(amount, fee_per_gram, lock_height, change_key)
+    }
+
+    /// The fee estimator, which is fed the blocks passed to `scan_block`
+    pub fn fee_estimator(&self) -> &FeeEstimator {
+        &self.fee_estimator
+    }
+
+    /// Update the state of the mempool that the fee estimate is based on
+    pub fn update_mempool(&mut self, mempool: MempoolStats) {
+        self.fee_estimator.update_mempool(mempool);
+    }
+
+    /// Abandon a pending outbound transaction and release the outputs it reserved
+    pub fn cancel_transaction(&mut self, tx_id: u64) -> Result<(), TransactionManagerError> {
+        if self.pending_outbound_transactions.remove(&tx_id).is_none() {
+            return Err(TransactionManagerError::TransactionDoesNotExistError);
         }
+        let output_manager = &mut self.output_manager;
+        self.database
+            .atomically(|database| -> Result<(), TransactionManagerError> {
+                database.cancel_pending_outbound(tx_id)?;
+                if output_manager.pending_transaction(tx_id).is_some() {
+                    output_manager.cancel_transaction(tx_id)?;
+                    database.save_output_manager(output_manager)?;
+                }
+                Ok(())
+            })?;
+        self.event_publisher
+            .publish(WalletEvent::TransactionCancelled { tx_id });
+        Ok(())
     }
 
     /// Start to send a new transaction.
@@ -94,8 +298,17 @@ impl TransactionManager {
 
         let msg = sender_transaction_protocol.build_single_round_message()?;
 
+        let output_manager = &self.output_manager;
+        self.database.atomically(|database| {
+            database.add_pending_outbound(msg.tx_id, &sender_transaction_protocol, msg.amount)?;
+            database.save_output_manager(output_manager)
+        })?;
         self.pending_outbound_transactions
             .insert(msg.tx_id.clone(), sender_transaction_protocol);
+        self.event_publisher.publish(WalletEvent::TransactionSent {
+            tx_id: msg.tx_id,
+            amount: msg.amount,
+        });
 
         Ok(SenderMessage::Single(Box::new(msg)))
     }
@@ -115,8 +328,22 @@ impl TransactionManager {
             if stp.check_tx_id(recp_tx_id) && stp.is_collecting_single_signature() {
                 stp.add_single_recipient_info(recipient_reply)?;
                 stp.finalize(KernelFeatures::empty())?;
+                self.event_publisher
+                    .publish(WalletEvent::ReplyAccepted { tx_id: recp_tx_id });
                 let tx = stp.get_transaction()?;
+                let output_manager = &mut self.output_manager;
+                self.database
+                    .atomically(|database| -> Result<(), TransactionManagerError> {
+                        database.complete_outbound(recp_tx_id, tx)?;
+                        if output_manager.pending_transaction(recp_tx_id).is_some() {
+                            output_manager.confirm_transaction(recp_tx_id)?;
+                            database.save_output_manager(output_manager)?;
+                        }
+                        Ok(())
+                    })?;
                 self.completed_transactions.insert(recp_tx_id, tx.clone());
+                self.event_publisher
+                    .publish(WalletEvent::TransactionFinalized { tx_id: recp_tx_id });
                 marked_for_removal = Some(tx_id.clone());
                 break;
             }
@@ -147,6 +374,11 @@ impl TransactionManager {
         spending_key: SecretKey,
     ) -> Result<RecipientSignedTransactionData, TransactionManagerError>
     {
+        let (amount, fee) = match &sender_message {
+            SenderMessage::Single(data) => (data.amount, data.metadata.fee),
+            _ => (0, 0),
+        };
+        let output = UnblindedOutput::new(amount, spending_key.clone(), None);
         let rtp = ReceiverTransactionProtocol::new(sender_message, nonce, spending_key, OutputFeatures::empty());
         let recipient_reply = rtp.get_signed_data()?.clone();
 
@@ -164,12 +396,245 @@ impl TransactionManager {
         }
 
         // Otherwise add it to our pending transaction list and return reply
+        let tx_id = recipient_reply.tx_id;
+        let output_manager = &mut self.output_manager;
+        self.database
+            .atomically(|database| -> Result<(), TransactionManagerError> {
+                database.add_pending_inbound(tx_id, &rtp, amount, fee)?;
+                output_manager.add_pending_incoming(tx_id, output)?;
+                database.save_output_manager(output_manager)?;
+                Ok(())
+            })?;
         self.pending_inbound_transactions
             .insert(recipient_reply.tx_id.clone(), rtp);
+        self.event_publisher.publish(WalletEvent::TransactionReceived {
+            tx_id: recipient_reply.tx_id,
+            amount,
+        });
 
         Ok(recipient_reply)
     }
 
+    /// The blocks the wallet has scanned and the heights at which its transactions were mined
+    pub fn chain_scanner(&self) -> &ChainScanner {
+        &self.chain_scanner
+    }
+
+    /// Scan a block from a base node for the wallet's transactions. A completed outbound transaction is mined when
+    /// one of its kernel excesses is in the block, and a pending inbound transaction is mined when the output the
+    /// wallet created for it is in the block; its output then becomes spendable.
+    ///
+    /// The other outputs in the block are matched against commitments to the receive and change keys derived from
+    /// `key_manager`, up to `DEFAULT_GAP_LIMIT` keys past the next unused index of each, so that outputs sent to the
+    /// wallet's keys by another wallet with the same master key are found. Unspent outputs that appear as inputs of
+    /// the block were spent elsewhere and become spent.
+    ///
+    /// If the block replaces blocks that were already scanned, the changes made by those blocks are reverted first.
+    /// All the changes the block makes to the wallet database are stored together.
+    pub fn scan_block(
+        &mut self,
+        block: &Block,
+        key_manager: &KeyManager<SecretKey, HashDigest>,
+    ) -> Result<Vec<ScanEvent>, TransactionManagerError>
+    {
+        self.database.begin_batch();
+        let events = match self.apply_block(block, key_manager) {
+            Ok(events) => events,
+            Err(e) => {
+                self.database.discard_batch();
+                return Err(e);
+            },
+        };
+        self.database.commit_batch()?;
+        for event in events.iter() {
+            self.event_publisher.publish(match *event {
+                ScanEvent::TransactionMined { tx_id, height } => WalletEvent::TransactionMined { tx_id, height },
+                ScanEvent::TransactionReverted { tx_id } => WalletEvent::TransactionReverted { tx_id },
+                ScanEvent::OutputReceived { value, height } => WalletEvent::OutputReceived { value, height },
+                ScanEvent::OutputSpent { value, height } => WalletEvent::OutputSpent { value, height },
+            });
+        }
+        Ok(events)
+    }
+
+    /// Apply the changes that a block makes to the wallet, see `scan_block`
+    fn apply_block(
+        &mut self,
+        block: &Block,
+        key_manager: &KeyManager<SecretKey, HashDigest>,
+    ) -> Result<Vec<ScanEvent>, TransactionManagerError>
+    {
+        let mut events = Vec::new();
+        for _ in 0..self.chain_scanner.blocks_to_revert(&block.header)? {
+            if let Some(scanned) = self.chain_scanner.pop_block() {
+                for output in scanned.spent_outputs.iter() {
+                    self.output_manager.unspend_output(output);
+                }
+                for output in scanned.received_outputs.iter() {
+                    self.output_manager.remove_output(output);
+                }
+                for mined in scanned.mined_transactions.into_iter().rev() {
+                    events.push(ScanEvent::TransactionReverted { tx_id: mined.tx_id() });
+                    self.revert_mined_transaction(mined)?;
+                }
+            }
+        }
+
+        let excesses: HashSet<&[u8]> = block.body.kernels.iter().map(|k| k.excess.as_bytes()).collect();
+        let commitments: HashSet<&[u8]> = block.body.outputs.iter().map(|o| o.commitment.as_bytes()).collect();
+        let mut outbound: Vec<u64> = self
+            .completed_transactions
+            .iter()
+            .filter(|(tx_id, _)| self.chain_scanner.mined_height(**tx_id).is_none())
+            .filter(|(_, tx)| tx.body.kernels.iter().any(|k| excesses.contains(k.excess.as_bytes())))
+            .map(|(tx_id, _)| *tx_id)
+            .collect();
+        outbound.sort();
+        let mut inbound = Vec::new();
+        for (tx_id, rtp) in self.pending_inbound_transactions.iter() {
+            if commitments.contains(rtp.get_signed_data()?.output.commitment.as_bytes()) {
+                inbound.push(*tx_id);
+            }
+        }
+        inbound.sort();
+
+        let mut mined_transactions = Vec::new();
+        for tx_id in outbound {
+            self.database.mark_mined(tx_id)?;
+            mined_transactions.push(MinedTransaction::Outbound { tx_id });
+        }
+        for tx_id in inbound {
+            let outputs = self
+                .output_manager
+                .pending_transaction(tx_id)
+                .cloned()
+                .ok_or(OutputManagerError::PendingTransactionNotFound)?;
+            self.output_manager.confirm_transaction(tx_id)?;
+            self.database.mark_mined(tx_id)?;
+            self.pending_inbound_transactions.remove(&tx_id);
+            mined_transactions.push(MinedTransaction::Inbound { tx_id, outputs });
+        }
+        for mined in mined_transactions.iter() {
+            events.push(ScanEvent::TransactionMined {
+                tx_id: mined.tx_id(),
+                height: block.header.height,
+            });
+        }
+
+        let received_outputs = self.find_received_outputs(block, key_manager)?;
+        for output in received_outputs.iter() {
+            self.output_manager.add_output(output.clone())?;
+            events.push(ScanEvent::OutputReceived {
+                value: output.value,
+                height: block.header.height,
+            });
+        }
+        let mut spent_outputs = Vec::new();
+        for input in block.body.inputs.iter() {
+            if let Some(output) = self.output_manager.spend_output(input.commitment()) {
+                events.push(ScanEvent::OutputSpent {
+                    value: output.value,
+                    height: block.header.height,
+                });
+                spent_outputs.push(output);
+            }
+        }
+
+        self.chain_scanner.push_block(ScannedBlock {
+            height: block.header.height,
+            hash: block.header.hash(),
+            mined_transactions,
+            received_outputs,
+            spent_outputs,
+        });
+        self.database.save_output_manager(&self.output_manager)?;
+        self.database.save_chain_scanner(&self.chain_scanner)?;
+        self.fee_estimator.add_block(block);
+        self.database.save_fee_estimator(&self.fee_estimator)?;
+        Ok(events)
+    }
+
+    /// Find the outputs in the block that the wallet doesn't own yet, but that are spendable with one of its derived
+    /// keys. A key index that was used is stored as used, so that the key is not handed out again.
+    fn find_received_outputs(
+        &mut self,
+        block: &Block,
+        key_manager: &KeyManager<SecretKey, HashDigest>,
+    ) -> Result<Vec<UnblindedOutput>, TransactionManagerError>
+    {
+        let unknown: Vec<_> = block
+            .body
+            .outputs
+            .iter()
+            .filter(|o| !self.output_manager.owns_commitment(&o.commitment))
+            .collect();
+        if unknown.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys = self.derived_keys(key_manager)?;
+        let mut received = Vec::new();
+        for output in unknown {
+            let found = keys
+                .iter()
+                .find_map(|(branch, index, key)| output.recover_value(key).map(|value| (branch, *index, key, value)));
+            if let Some((branch, index, key, value)) = found {
+                received.push(UnblindedOutput::new(value, key.clone(), Some(output.features)));
+                if self.database.get_key_index(branch)?.unwrap_or(0) <= index {
+                    self.database.set_key_index(branch, index + 1)?;
+                }
+            }
+        }
+        Ok(received)
+    }
+
+    /// The receive and change keys that outputs sent to the wallet can be spent with: every key handed out so far,
+    /// and `DEFAULT_GAP_LIMIT` more on each branch. Each key is returned with the name its next unused index is
+    /// stored under and its index.
+    fn derived_keys(
+        &self,
+        key_manager: &KeyManager<SecretKey, HashDigest>,
+    ) -> Result<Vec<(String, usize, SecretKey)>, TransactionManagerError>
+    {
+        let branches = vec![
+            (
+                KeyBranch::Custom(key_manager.branch_seed.clone()),
+                key_manager.branch_seed.clone(),
+            ),
+            (KeyBranch::Change, KeyBranch::Change.label()),
+        ];
+        let mut keys = Vec::new();
+        for (branch, name) in branches {
+            let next_index = self.database.get_key_index(&name)?.unwrap_or(0);
+            for index in 0..next_index + DEFAULT_GAP_LIMIT {
+                let key = key_manager
+                    .derive_path_key(&KeyPath::new(0, branch.clone(), index))
+                    .map_err(|e| TransactionManagerError::KeyDerivationError(e.to_string()))?
+                    .k;
+                keys.push((name.clone(), index, key));
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Undo the changes made when a transaction was found in a block that has since been reorged out
+    fn revert_mined_transaction(&mut self, mined: MinedTransaction) -> Result<(), TransactionManagerError> {
+        match mined {
+            MinedTransaction::Outbound { tx_id } => {
+                self.database.revert_mined(tx_id, TransactionStatus::Completed)?;
+            },
+            MinedTransaction::Inbound { tx_id, outputs } => {
+                self.database.revert_mined(tx_id, TransactionStatus::PendingInbound)?;
+                let rtp = self
+                    .database
+                    .fetch_pending_inbound(tx_id)?
+                    .ok_or(WalletDbError::TransactionNotFound)?;
+                self.pending_inbound_transactions.insert(tx_id, rtp);
+                self.output_manager.restore_pending_transaction(outputs)?;
+            },
+        }
+        Ok(())
+    }
+
     /// Returns the list of the completed transactions
     fn get_completed_transactions(&self) -> &HashMap<u64, Transaction> {
         return &self.completed_transactions;
@@ -186,12 +651,35 @@ impl TransactionManager {
 
 #[cfg(test)]
 mod test {
-    use crate::transaction_manager::{TransactionManager, TransactionManagerError};
+    use crate::{
+        chain_scanner::{ChainScannerError, ScanEvent},
+        transaction_manager::{TransactionManager, TransactionManagerError},
+        wallet_db::{TransactionDirection, TransactionQuery, TransactionStatus, WalletDatabase},
+        wallet_event::WalletEvent,
+    };
+    use keymanager::keymanager::{KeyBranch, KeyManager, KeyPath};
     use rand::{CryptoRng, OsRng, Rng};
+    use std::convert::TryFrom;
     use tari_core::{
-        transaction::{OutputFeatures, TransactionInput, UnblindedOutput},
-        transaction_protocol::{sender::SenderMessage, TransactionProtocolError},
-        types::{CommitmentFactory, PublicKey, SecretKey},
+        block::Block,
+        fee::Fee,
+        fee_estimator::{ConfirmationTarget, DEFAULT_FEE_PER_GRAM},
+        transaction::{
+            KernelFeatures,
+            OutputFeatures,
+            Transaction,
+            TransactionInput,
+            TransactionOutput,
+            UnblindedOutput,
+        },
+        transaction_protocol::{
+            multisig::{MultiSigMessage, MultiSigSession},
+            sender::SenderMessage,
+            test_common::create_test_block,
+            TransactionMetadata,
+            TransactionProtocolError,
+        },
+        types::{BlindingFactor, CommitmentFactory, HashDigest, PublicKey, SecretKey},
         SenderTransactionProtocol,
     };
     use tari_crypto::{
@@ -199,6 +687,8 @@ mod test {
         common::Blake256,
         keys::{PublicKey as PK, SecretKey as SK},
     };
+    use tari_storage::hashmap_store::HashmapStore;
+    use tari_utilities::Hashable;
 
     pub struct TestParams {
         pub spend_key: SecretKey,
@@ -248,8 +738,8 @@ mod test {
             .with_amount(0, 500);
         let alice_stp = builder.build::<Blake256>().unwrap();
 
-        let mut alice_tx_manager = TransactionManager::new();
-        let mut bob_tx_manager = TransactionManager::new();
+        let mut alice_tx_manager = TransactionManager::new().unwrap();
+        let mut bob_tx_manager = TransactionManager::new().unwrap();
 
         let send_msg = alice_tx_manager.start_send_transaction(alice_stp).unwrap();
         let mut tx_id = 0;
@@ -338,9 +828,9 @@ mod test {
             .with_amount(0, 500);
         let bob_stp1 = builder_b1.build::<Blake256>().unwrap();
 
-        let mut alice_tx_manager = TransactionManager::new();
-        let mut bob_tx_manager = TransactionManager::new();
-        let mut carol_tx_manager = TransactionManager::new();
+        let mut alice_tx_manager = TransactionManager::new().unwrap();
+        let mut bob_tx_manager = TransactionManager::new().unwrap();
+        let mut carol_tx_manager = TransactionManager::new().unwrap();
 
         // Now a series of interleaved sending and receiving of transactions
         let send_msg_a1 = alice_tx_manager.start_send_transaction(alice_stp1).unwrap();
@@ -433,8 +923,8 @@ mod test {
             .with_amount(0, 500);
         let alice_stp = builder.build::<Blake256>().unwrap();
 
-        let mut alice_tx_manager = TransactionManager::new();
-        let mut bob_tx_manager = TransactionManager::new();
+        let mut alice_tx_manager = TransactionManager::new().unwrap();
+        let mut bob_tx_manager = TransactionManager::new().unwrap();
 
         let send_msg = alice_tx_manager.start_send_transaction(alice_stp).unwrap();
 
@@ -453,7 +943,7 @@ mod test {
         // Bob's parameters
         let b = TestParams::new(&mut rng);
         let send_msg = SenderMessage::None;
-        let mut bob_tx_manager = TransactionManager::new();
+        let mut bob_tx_manager = TransactionManager::new().unwrap();
         let receive_msg = bob_tx_manager.accept_transaction(send_msg, b.nonce, b.spend_key);
 
         assert_eq!(
@@ -483,8 +973,8 @@ mod test {
             .with_amount(0, 500);
         let alice_stp = builder.build::<Blake256>().unwrap();
 
-        let mut alice_tx_manager = TransactionManager::new();
-        let mut bob_tx_manager = TransactionManager::new();
+        let mut alice_tx_manager = TransactionManager::new().unwrap();
+        let mut bob_tx_manager = TransactionManager::new().unwrap();
 
         let send_msg = alice_tx_manager.start_send_transaction(alice_stp).unwrap();
 
@@ -522,8 +1012,8 @@ mod test {
             .with_amount(0, 500);
         let alice_stp = builder.build::<Blake256>().unwrap();
 
-        let mut alice_tx_manager = TransactionManager::new();
-        let mut bob_tx_manager = TransactionManager::new();
+        let mut alice_tx_manager = TransactionManager::new().unwrap();
+        let mut bob_tx_manager = TransactionManager::new().unwrap();
 
         let send_msg = alice_tx_manager.start_send_transaction(alice_stp).unwrap();
 
@@ -539,4 +1029,386 @@ mod test {
         );
     }
 
+    #[test]
+    fn transactions_are_restored_from_database() {
+        let mut rng = OsRng::new().unwrap();
+        let a = TestParams::new(&mut rng);
+        let b = TestParams::new(&mut rng);
+        let (utxo, input) = make_input(&mut rng, 2500);
+        let mut builder = SenderTransactionProtocol::builder(1);
+        builder
+            .with_lock_height(0)
+            .with_fee_per_gram(20)
+            .with_offset(a.offset.clone())
+            .with_private_nonce(a.nonce.clone())
+            .with_change_secret(a.change_key.clone())
+            .with_input(utxo.clone(), input)
+            .with_amount(0, 500);
+        let alice_stp = builder.build::<Blake256>().unwrap();
+
+        let alice_db = WalletDatabase::new(HashmapStore::new(), [1u8; 32]).unwrap();
+        let mut alice_tx_manager = TransactionManager::with_database(alice_db).unwrap();
+        let mut bob_tx_manager = TransactionManager::new().unwrap();
+
+        let send_msg = alice_tx_manager.start_send_transaction(alice_stp).unwrap();
+        let receive_msg = bob_tx_manager
+            .accept_transaction(send_msg, b.nonce, b.spend_key)
+            .unwrap();
+        let tx_id = receive_msg.tx_id;
+        let record = bob_tx_manager.database().fetch_transaction(tx_id).unwrap().unwrap();
+        assert_eq!(record.status, TransactionStatus::PendingInbound);
+        assert_eq!(record.amount, 500);
+
+        // Restart Alice's wallet, and complete the transaction with the restored protocol
+        let alice_db = alice_tx_manager.into_database();
+        let mut alice_tx_manager = TransactionManager::with_database(alice_db).unwrap();
+        assert_eq!(alice_tx_manager.num_pending_outbound_transactions(), 1);
+        alice_tx_manager.accept_recipient_reply(receive_msg).unwrap();
+        assert_eq!(alice_tx_manager.num_pending_outbound_transactions(), 0);
+
+        let alice_db = alice_tx_manager.into_database();
+        let alice_tx_manager = TransactionManager::with_database(alice_db).unwrap();
+        assert_eq!(alice_tx_manager.num_pending_outbound_transactions(), 0);
+        assert!(alice_tx_manager.get_completed_transactions().contains_key(&tx_id));
+        let record = alice_tx_manager.database().fetch_transaction(tx_id).unwrap().unwrap();
+        assert_eq!(record.status, TransactionStatus::Completed);
+    }
+
+    #[test]
+    fn outputs_advance_with_transactions() {
+        let mut rng = OsRng::new().unwrap();
+        let b = TestParams::new(&mut rng);
+        let mut alice_tx_manager = TransactionManager::new().unwrap();
+        let mut bob_tx_manager = TransactionManager::new().unwrap();
+        alice_tx_manager
+            .add_output(UnblindedOutput::new(5000, SecretKey::random(&mut rng), None))
+            .unwrap();
+
+        let send_msg = alice_tx_manager
+            .send_transaction(1000, 20, 0, SecretKey::random(&mut rng))
+            .unwrap();
+        let alice_balance = alice_tx_manager.output_manager().balance();
+        assert_eq!(alice_balance.available, 0);
+        assert_eq!(alice_balance.pending_outgoing, 5000);
+        let change = alice_balance.pending_incoming;
+        assert!(change > 0 && change < 4000);
+
+        let receive_msg = bob_tx_manager
+            .accept_transaction(send_msg, b.nonce, b.spend_key)
+            .unwrap();
+        assert_eq!(bob_tx_manager.output_manager().balance().pending_incoming, 1000);
+
+        alice_tx_manager.accept_recipient_reply(receive_msg).unwrap();
+        let alice_balance = alice_tx_manager.output_manager().balance();
+        assert_eq!(alice_balance.available, change);
+        assert_eq!(alice_balance.pending_outgoing, 0);
+        assert_eq!(alice_balance.pending_incoming, 0);
+    }
+
+    #[test]
+    fn cancel_transaction_releases_outputs() {
+        let mut rng = OsRng::new().unwrap();
+        let alice_db = WalletDatabase::new(HashmapStore::new(), [2u8; 32]).unwrap();
+        let mut alice_tx_manager = TransactionManager::with_database(alice_db).unwrap();
+        alice_tx_manager
+            .add_output(UnblindedOutput::new(5000, SecretKey::random(&mut rng), None))
+            .unwrap();
+        let send_msg = alice_tx_manager
+            .send_transaction(1000, 20, 0, SecretKey::random(&mut rng))
+            .unwrap();
+        let tx_id = match send_msg {
+            SenderMessage::Single(data) => data.tx_id,
+            _ => panic!("Expected a single round message"),
+        };
+        alice_tx_manager.cancel_transaction(tx_id).unwrap();
+        assert_eq!(
+            alice_tx_manager.cancel_transaction(tx_id),
+            Err(TransactionManagerError::TransactionDoesNotExistError)
+        );
+        assert_eq!(alice_tx_manager.num_pending_outbound_transactions(), 0);
+
+        // The released output and the cancelled status survive a restart
+        let alice_tx_manager = TransactionManager::with_database(alice_tx_manager.into_database()).unwrap();
+        assert_eq!(alice_tx_manager.output_manager().balance().available, 5000);
+        let record = alice_tx_manager.database().fetch_transaction(tx_id).unwrap().unwrap();
+        assert_eq!(record.status, TransactionStatus::Cancelled);
+    }
+
+    fn block_hash(block: &Block) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&block.header.hash());
+        hash
+    }
+
+    #[test]
+    fn scanning_blocks_marks_transactions_mined() {
+        let mut rng = OsRng::new().unwrap();
+        let b = TestParams::new(&mut rng);
+        let km = KeyManager::<SecretKey, HashDigest>::new(&mut rng);
+        let mut alice_tx_manager = TransactionManager::new().unwrap();
+        let mut bob_tx_manager = TransactionManager::new().unwrap();
+        alice_tx_manager
+            .add_output(UnblindedOutput::new(5000, SecretKey::random(&mut rng), None))
+            .unwrap();
+        let send_msg = alice_tx_manager
+            .send_transaction(1000, 20, 0, SecretKey::random(&mut rng))
+            .unwrap();
+        let receive_msg = bob_tx_manager
+            .accept_transaction(send_msg, b.nonce, b.spend_key)
+            .unwrap();
+        let tx_id = receive_msg.tx_id;
+        alice_tx_manager.accept_recipient_reply(receive_msg).unwrap();
+        let tx = alice_tx_manager.get_completed_transactions()[&tx_id].clone();
+
+        let genesis = create_test_block(0, [0u8; 32], 0, vec![]);
+        let block1 = create_test_block(1, block_hash(&genesis), 0, vec![tx.clone()]);
+        let block2 = create_test_block(2, block_hash(&block1), 0, vec![]);
+        for tx_manager in [&mut alice_tx_manager, &mut bob_tx_manager].iter_mut() {
+            assert_eq!(tx_manager.scan_block(&genesis, &km).unwrap(), vec![]);
+            assert_eq!(tx_manager.scan_block(&block1, &km).unwrap(), vec![
+                ScanEvent::TransactionMined { tx_id, height: 1 }
+            ]);
+            assert_eq!(tx_manager.chain_scanner().confirmations(tx_id), 1);
+            assert_eq!(tx_manager.scan_block(&block2, &km).unwrap(), vec![]);
+            assert_eq!(tx_manager.chain_scanner().confirmations(tx_id), 2);
+            let record = tx_manager.database().fetch_transaction(tx_id).unwrap().unwrap();
+            assert_eq!(record.status, TransactionStatus::Mined);
+        }
+        assert_eq!(bob_tx_manager.num_pending_inbound_transactions(), 0);
+        assert_eq!(bob_tx_manager.output_manager().balance().available, 1000);
+        assert_eq!(
+            bob_tx_manager.scan_block(&block2, &km),
+            Err(TransactionManagerError::ChainScannerError(
+                ChainScannerError::BlockAlreadyScanned
+            ))
+        );
+
+        // A competing chain that doesn't include the transaction reverts it
+        let fork1 = create_test_block(1, block_hash(&genesis), 1, vec![]);
+        assert_eq!(bob_tx_manager.scan_block(&fork1, &km).unwrap(), vec![
+            ScanEvent::TransactionReverted { tx_id }
+        ]);
+        assert_eq!(bob_tx_manager.chain_scanner().confirmations(tx_id), 0);
+        assert_eq!(bob_tx_manager.num_pending_inbound_transactions(), 1);
+        let bob_balance = bob_tx_manager.output_manager().balance();
+        assert_eq!(bob_balance.available, 0);
+        assert_eq!(bob_balance.pending_incoming, 1000);
+        let record = bob_tx_manager.database().fetch_transaction(tx_id).unwrap().unwrap();
+        assert_eq!(record.status, TransactionStatus::PendingInbound);
+
+        // ... until the transaction is mined on the new chain
+        let fork2 = create_test_block(2, block_hash(&fork1), 1, vec![tx]);
+        assert_eq!(bob_tx_manager.scan_block(&fork2, &km).unwrap(), vec![
+            ScanEvent::TransactionMined { tx_id, height: 2 }
+        ]);
+        assert_eq!(bob_tx_manager.output_manager().balance().available, 1000);
+    }
+
+    #[test]
+    fn scanning_finds_outputs_of_derived_keys() {
+        let mut rng = OsRng::new().unwrap();
+        let km = KeyManager::<SecretKey, HashDigest>::new(&mut rng);
+        let mut tx_manager = TransactionManager::new().unwrap();
+        let receive_key = km.derive_key(2).unwrap().k;
+        let change_key = km.derive_path_key(&KeyPath::new(0, KeyBranch::Change, 0)).unwrap().k;
+        let commitment = |k: &SecretKey, v: u64| CommitmentFactory::create(k, &SecretKey::from(v));
+        let outputs = vec![
+            (receive_key.clone(), 1500),
+            (change_key, 700),
+            (SecretKey::random(&mut rng), 300),
+        ];
+        let outputs = outputs
+            .iter()
+            .map(|(k, v)| {
+                let encrypted_value = UnblindedOutput::new(*v, k.clone(), None).encrypted_value();
+                TransactionOutput::new(OutputFeatures::empty(), commitment(k, *v), Vec::new(), encrypted_value)
+            })
+            .collect();
+        // The receive key is spent by another wallet with the same master key
+        let inputs = vec![TransactionInput::new(
+            OutputFeatures::empty(),
+            commitment(&receive_key, 1500),
+        )];
+
+        let genesis = create_test_block(0, [0u8; 32], 0, vec![]);
+        let block1 = create_test_block(1, block_hash(&genesis), 0, vec![Transaction::new(
+            Vec::new(),
+            outputs,
+            Vec::new(),
+            BlindingFactor::default(),
+        )]);
+        let block2 = create_test_block(2, block_hash(&block1), 0, vec![Transaction::new(
+            inputs,
+            Vec::new(),
+            Vec::new(),
+            BlindingFactor::default(),
+        )]);
+        tx_manager.scan_block(&genesis, &km).unwrap();
+        let mut events = tx_manager.scan_block(&block1, &km).unwrap();
+        events.sort_by_key(|e| match e {
+            ScanEvent::OutputReceived { value, .. } => *value,
+            _ => 0,
+        });
+        assert_eq!(events, vec![
+            ScanEvent::OutputReceived { value: 700, height: 1 },
+            ScanEvent::OutputReceived { value: 1500, height: 1 },
+        ]);
+        assert_eq!(tx_manager.output_manager().balance().available, 2200);
+        // The keys that were found are not handed out again
+        let database = tx_manager.database();
+        assert_eq!(database.get_key_index(&km.branch_seed).unwrap(), Some(3));
+        assert_eq!(database.get_key_index(&KeyBranch::Change.label()).unwrap(), Some(1));
+
+        assert_eq!(tx_manager.scan_block(&block2, &km).unwrap(), vec![
+            ScanEvent::OutputSpent { value: 1500, height: 2 }
+        ]);
+        assert_eq!(tx_manager.output_manager().balance().available, 700);
+
+        // A competing chain without either block undoes both
+        let fork1 = create_test_block(1, block_hash(&genesis), 1, vec![]);
+        assert_eq!(tx_manager.scan_block(&fork1, &km).unwrap(), vec![]);
+        assert_eq!(tx_manager.output_manager().balance().available, 0);
+    }
+
+    #[test]
+    fn events_and_history_follow_transactions() {
+        let mut rng = OsRng::new().unwrap();
+        let b = TestParams::new(&mut rng);
+        let mut alice_tx_manager = TransactionManager::new().unwrap();
+        let mut bob_tx_manager = TransactionManager::new().unwrap();
+        let alice_events = alice_tx_manager.subscribe();
+        let bob_events = bob_tx_manager.subscribe();
+        alice_tx_manager
+            .add_output(UnblindedOutput::new(5000, SecretKey::random(&mut rng), None))
+            .unwrap();
+        let send_msg = alice_tx_manager
+            .send_transaction(1000, 20, 0, SecretKey::random(&mut rng))
+            .unwrap();
+        let receive_msg = bob_tx_manager
+            .accept_transaction(send_msg, b.nonce, b.spend_key)
+            .unwrap();
+        let tx_id = receive_msg.tx_id;
+        alice_tx_manager.accept_recipient_reply(receive_msg).unwrap();
+        let tx = alice_tx_manager.get_completed_transactions()[&tx_id].clone();
+        let block = create_test_block(0, [0u8; 32], 0, vec![tx]);
+        let km = KeyManager::<SecretKey, HashDigest>::new(&mut rng);
+        alice_tx_manager.scan_block(&block, &km).unwrap();
+
+        assert_eq!(alice_events.try_iter().collect::<Vec<_>>(), vec![
+            WalletEvent::TransactionSent { tx_id, amount: 1000 },
+            WalletEvent::ReplyAccepted { tx_id },
+            WalletEvent::TransactionFinalized { tx_id },
+            WalletEvent::TransactionMined { tx_id, height: 0 },
+        ]);
+        assert_eq!(bob_events.try_iter().collect::<Vec<_>>(), vec![
+            WalletEvent::TransactionReceived { tx_id, amount: 1000 }
+        ]);
+
+        let alice_record = alice_tx_manager.transaction(tx_id).unwrap().unwrap();
+        let bob_record = bob_tx_manager.transaction(tx_id).unwrap().unwrap();
+        assert_eq!(alice_record.direction, TransactionDirection::Outbound);
+        assert_eq!(alice_record.status, TransactionStatus::Mined);
+        assert_eq!(bob_record.direction, TransactionDirection::Inbound);
+        assert_eq!(bob_record.status, TransactionStatus::PendingInbound);
+        assert_eq!(alice_record.fee, bob_record.fee);
+        let query = TransactionQuery::new().with_direction(TransactionDirection::Inbound);
+        assert!(alice_tx_manager.transaction_history(&query).unwrap().is_empty());
+        assert_eq!(bob_tx_manager.transaction_history(&query).unwrap(), vec![bob_record]);
+    }
+
+    #[test]
+    fn fees_are_estimated_from_scanned_blocks() {
+        let mut rng = OsRng::new().unwrap();
+        let b = TestParams::new(&mut rng);
+        let mut alice_tx_manager = TransactionManager::new().unwrap();
+        let mut bob_tx_manager = TransactionManager::new().unwrap();
+        alice_tx_manager
+            .add_output(UnblindedOutput::new(5000, SecretKey::random(&mut rng), None))
+            .unwrap();
+        alice_tx_manager
+            .add_output(UnblindedOutput::new(5000, SecretKey::random(&mut rng), None))
+            .unwrap();
+        let estimate = alice_tx_manager.fee_estimator().estimate();
+        assert_eq!(estimate.normal, DEFAULT_FEE_PER_GRAM);
+
+        // A block paying 40µT per gram raises the estimate
+        let send_msg = alice_tx_manager
+            .send_transaction(1000, 40, 0, SecretKey::random(&mut rng))
+            .unwrap();
+        let receive_msg = bob_tx_manager
+            .accept_transaction(send_msg, b.nonce, b.spend_key)
+            .unwrap();
+        let tx_id = receive_msg.tx_id;
+        alice_tx_manager.accept_recipient_reply(receive_msg).unwrap();
+        let tx = alice_tx_manager.get_completed_transactions()[&tx_id].clone();
+        let km = KeyManager::<SecretKey, HashDigest>::new(&mut rng);
+        alice_tx_manager
+            .scan_block(&create_test_block(0, [0u8; 32], 0, vec![tx]), &km)
+            .unwrap();
+        assert_eq!(alice_tx_manager.fee_estimator().estimate().normal, 40);
+
+        let send_msg = alice_tx_manager
+            .send_transaction_with_target(1000, ConfirmationTarget::Normal, 0, SecretKey::random(&mut rng))
+            .unwrap();
+        let tx_id = match send_msg {
+            SenderMessage::Single(data) => data.tx_id,
+            _ => panic!("Expected a single round message"),
+        };
+        let record = alice_tx_manager.transaction(tx_id).unwrap().unwrap();
+        assert_eq!(record.fee, Fee::calculate(40, 1, 2));
+    }
+
+    #[test]
+    fn multisig_session() {
+        let mut rng = OsRng::new().unwrap();
+        let managers: Vec<TransactionManager> = (0..3).map(|_| TransactionManager::new().unwrap()).collect();
+        // Every wallet spends one of its own outputs, and the first wallet receives the value, less the fee
+        let inputs: Vec<UnblindedOutput> = (0..3)
+            .map(|_| UnblindedOutput::new(2_000, SecretKey::random(&mut rng), None))
+            .collect();
+        let metadata = TransactionMetadata {
+            fee: 100,
+            lock_height: 0,
+        };
+        let output = UnblindedOutput::new(5_900, SecretKey::random(&mut rng), None);
+        let mut sessions: Vec<MultiSigSession> = managers
+            .iter()
+            .zip(inputs.iter())
+            .enumerate()
+            .map(|(i, (manager, input))| {
+                let outputs = if i == 0 { vec![output.clone()] } else { Vec::new() };
+                manager
+                    .start_multisig_session(3, &[input.clone()], &outputs, metadata.clone())
+                    .unwrap()
+            })
+            .collect();
+        let round_one: Vec<MultiSigMessage> = sessions.iter().map(|s| s.nonce_commitment_message()).collect();
+        for message in round_one {
+            sessions
+                .iter_mut()
+                .for_each(|s| s.handle_message(message.clone()).unwrap());
+        }
+        let round_two: Vec<MultiSigMessage> = sessions.iter().map(|s| s.public_nonce_message().unwrap()).collect();
+        for message in round_two {
+            sessions
+                .iter_mut()
+                .for_each(|s| s.handle_message(message.clone()).unwrap());
+        }
+        let round_three: Vec<MultiSigMessage> = sessions
+            .iter()
+            .map(|s| s.partial_signature_message().unwrap())
+            .collect();
+        for message in round_three {
+            sessions
+                .iter_mut()
+                .for_each(|s| s.handle_message(message.clone()).unwrap());
+        }
+        let tx = sessions[0]
+            .build_transaction(inputs.iter().map(TransactionInput::from).collect(), vec![
+                TransactionOutput::try_from(&output).unwrap(),
+            ])
+            .unwrap();
+        assert_eq!(tx.body.kernels[0].features, KernelFeatures::MULTISIG_KERNEL);
+        assert!(managers[0].start_multisig_session(0, &[], &[], metadata).is_err());
+    }
 }
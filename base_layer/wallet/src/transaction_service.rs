@@ -0,0 +1,349 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{
+    chain_scanner::ScanEvent,
+    transaction_manager::{TransactionManager, TransactionManagerError},
+    wallet_db::WalletDbError,
+};
+use derive_error::Error;
+use keymanager::keymanager::{KeyBranch, KeyManager, KeyPath};
+use p2p::{
+    domain_dispatcher::{DomainMessageDispatcher, PeerContext},
+    tari_message::{TariMessage, TariMessageType, WalletMessage},
+};
+use rand::OsRng;
+use serde::Serialize;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tari_comms::{
+    connection::{message::IdentityFlags, net_address::net_addresses::NetAddresses},
+    inbound_message_service::{
+        comms_msg_handlers::{determine_comms_msg_dispatch_type, CommsDispatchType},
+        message_context::MessageContext,
+        message_dispatcher::{DispatchError, MessageDispatcher},
+    },
+    outbound_message_service::{
+        broadcast_strategy::BroadcastStrategy,
+        outbound_message_service::{OutboundError, OutboundMessageService},
+    },
+    peer_manager::{
+        manager::PeerManagerError,
+        node_id::{NodeId, NodeIdError},
+        node_identity::NodeIdentity,
+        peer::{Peer, PeerFlags},
+    },
+};
+use tari_core::{
+    block::Block,
+    fee_estimator::ConfirmationTarget,
+    transaction_protocol::{recipient::RecipientSignedTransactionData, sender::SenderMessage},
+    types::{HashDigest, PublicKey, SecretKey},
+};
+use tari_crypto::keys::SecretKey as SK;
+use tari_storage::{hashmap_store::HashmapStore, keyvalue_store::DataStore};
+use tari_utilities::byte_array::ByteArrayError;
+
+#[derive(Debug, Error)]
+pub enum TransactionServiceError {
+    // The transaction could not be created or processed
+    TransactionManagerError(TransactionManagerError),
+    // The next receive key index could not be stored
+    WalletDbError(WalletDbError),
+    // The message could not be sent
+    OutboundError(OutboundError),
+    // A key could not be derived or converted
+    ByteArrayError(ByteArrayError),
+    // The node id of the message source could not be determined
+    NodeIdError(NodeIdError),
+    // The counterparty could not be added to or found in the peer manager
+    PeerManagerError(PeerManagerError),
+    // The random number generator could not be created
+    RandomError(rand::Error),
+    // A message could not be serialized or deserialized
+    #[error(msg_embedded, no_from, non_std)]
+    SerializationError(String),
+    // The message is not a wallet transaction message
+    InvalidMessageType,
+}
+
+impl From<bincode::Error> for TransactionServiceError {
+    fn from(e: bincode::Error) -> Self {
+        TransactionServiceError::SerializationError(e.to_string())
+    }
+}
+
+/// The TransactionService connects a `TransactionManager` to the comms layer. Outbound transactions are sent to the
+/// recipient's node through the `OutboundMessageService`, which learns about counterparties as peers, and inbound
+/// transaction messages, passed to the handlers registered by `register_transaction_message_handlers`, are accepted
+/// and replied to, or used to complete the transaction they reply to.
+///
+/// The spending keys of received outputs and of change outputs are derived from the key manager, so that the outputs
+/// can be recovered from the master key. The next unused key indices are kept in the wallet database.
+pub struct TransactionService<S = HashmapStore>
+where S: DataStore
+{
+    transaction_manager: TransactionManager<S>,
+    key_manager: KeyManager<SecretKey, HashDigest>,
+    node_identity: Arc<NodeIdentity<PublicKey, SecretKey>>,
+    outbound_message_service: OutboundMessageService<PublicKey, SecretKey>,
+    rng: OsRng,
+}
+
+impl<S> TransactionService<S>
+where S: DataStore
+{
+    pub fn new(
+        transaction_manager: TransactionManager<S>,
+        key_manager: KeyManager<SecretKey, HashDigest>,
+        node_identity: Arc<NodeIdentity<PublicKey, SecretKey>>,
+        outbound_message_service: OutboundMessageService<PublicKey, SecretKey>,
+    ) -> Result<TransactionService<S>, TransactionServiceError>
+    {
+        Ok(TransactionService {
+            transaction_manager,
+            key_manager,
+            node_identity,
+            outbound_message_service,
+            rng: OsRng::new()?,
+        })
+    }
+
+    /// The transaction manager that the service drives
+    pub fn transaction_manager(&self) -> &TransactionManager<S> {
+        &self.transaction_manager
+    }
+
+    /// Mutable access to the transaction manager, e.g. to add outputs or scan blocks
+    pub fn transaction_manager_mut(&mut self) -> &mut TransactionManager<S> {
+        &mut self.transaction_manager
+    }
+
+    /// The key manager that the spending keys of received outputs are derived from
+    pub fn key_manager(&self) -> &KeyManager<SecretKey, HashDigest> {
+        &self.key_manager
+    }
+
+    /// Scan a block from a base node for the wallet's transactions and for outputs spendable with the keys derived
+    /// from the service's key manager
+    pub fn scan_block(&mut self, block: &Block) -> Result<Vec<ScanEvent>, TransactionServiceError> {
+        Ok(self.transaction_manager.scan_block(block, &self.key_manager)?)
+    }
+
+    /// Send `amount` to the wallet with the public key `dest_public_key`, funded from the wallet's unspent outputs. The
+    /// transaction completes when the recipient's reply arrives. If no `fee_per_gram` is given, the fee-per-gram
+    /// recommended for a normal confirmation target is used.
+    /// # Returns
+    /// The tx_id of the new transaction
+    pub fn send_transaction(
+        &mut self,
+        dest_public_key: &PublicKey,
+        amount: u64,
+        fee_per_gram: Option<u64>,
+        lock_height: u64,
+    ) -> Result<u64, TransactionServiceError>
+    {
+        let change_key = self.next_change_key()?;
+        let sender_message = match fee_per_gram {
+            Some(fee_per_gram) => {
+                self.transaction_manager
+                    .send_transaction(amount, fee_per_gram, lock_height, change_key)?
+            },
+            None => self.transaction_manager.send_transaction_with_target(
+                amount,
+                ConfirmationTarget::Normal,
+                lock_height,
+                change_key,
+            )?,
+        };
+        let tx_id = match &sender_message {
+            SenderMessage::Single(data) => data.tx_id,
+            _ => return Err(TransactionServiceError::InvalidMessageType),
+        };
+        let result = self.send_message(
+            dest_public_key,
+            TariMessageType::new(WalletMessage::SendTransaction),
+            &sender_message,
+        );
+        if let Err(e) = result {
+            // The reason the message couldn't be sent is returned, even if the transaction can't be cancelled
+            let _ = self.transaction_manager.cancel_transaction(tx_id);
+            return Err(e);
+        }
+        self.transaction_manager
+            .database_mut()
+            .set_counterparty(tx_id, dest_public_key.clone())?;
+        Ok(tx_id)
+    }
+
+    /// Accept a transaction from the sender with the public key, and send the recipient's reply back to the sender
+    pub fn accept_transaction(
+        &mut self,
+        sender_message: SenderMessage,
+        source: &PublicKey,
+    ) -> Result<(), TransactionServiceError>
+    {
+        let nonce = SecretKey::random(&mut self.rng);
+        let spending_key = self.next_receive_key()?;
+        let reply = self
+            .transaction_manager
+            .accept_transaction(sender_message, nonce, spending_key)?;
+        self.transaction_manager
+            .database_mut()
+            .set_counterparty(reply.tx_id, source.clone())?;
+        self.send_message(
+            source,
+            TariMessageType::new(WalletMessage::ReceiveTransactionReply),
+            &reply,
+        )
+    }
+
+    /// Complete the transaction that the recipient's reply is for
+    pub fn accept_recipient_reply(
+        &mut self,
+        reply: RecipientSignedTransactionData,
+    ) -> Result<(), TransactionServiceError>
+    {
+        Ok(self.transaction_manager.accept_recipient_reply(reply)?)
+    }
+
+    /// Serialize a wallet message and send it, encrypted, to the node with the public key
+    fn send_message<T: Serialize>(
+        &mut self,
+        dest_public_key: &PublicKey,
+        message_type: TariMessageType,
+        message: &T,
+    ) -> Result<(), TransactionServiceError>
+    {
+        let dest_node_id = self.add_peer(dest_public_key)?;
+        let body = TariMessage::new(message_type, bincode::serialize(message)?).to_frame();
+        Ok(self.outbound_message_service.send(
+            BroadcastStrategy::Direct(dest_node_id),
+            IdentityFlags::ENCRYPTED,
+            &body,
+            &mut self.rng,
+        )?)
+    }
+
+    /// Make the node with the public key a known peer, so that messages can be sent to it. The node is reached through
+    /// its node id, so no net addresses are recorded for a new peer.
+    /// # Returns
+    /// The node id of the peer
+    fn add_peer(&self, public_key: &PublicKey) -> Result<NodeId, TransactionServiceError> {
+        let peer_manager = self.outbound_message_service.peer_manager();
+        match peer_manager.find_with_public_key(public_key) {
+            Ok(peer) => Ok(peer.node_id),
+            Err(PeerManagerError::PeerNotFoundError) => {
+                let node_id = NodeId::from_key(public_key)?;
+                peer_manager.add_peer(Peer::new(
+                    public_key.clone(),
+                    node_id.clone(),
+                    NetAddresses::new(Vec::new()),
+                    PeerFlags::default(),
+                ))?;
+                Ok(node_id)
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Derive the spending key for the next received output, and store the next unused key index
+    fn next_receive_key(&mut self) -> Result<SecretKey, TransactionServiceError> {
+        let branch = self.key_manager.branch_seed.clone();
+        let database = self.transaction_manager.database_mut();
+        let index = database.get_key_index(&branch)?.unwrap_or(0);
+        let key = self.key_manager.derive_key(index)?.k;
+        database.set_key_index(&branch, index + 1)?;
+        Ok(key)
+    }
+
+    /// Derive the spending key for the next change output from the `Change` branch, and store the next unused key
+    /// index
+    fn next_change_key(&mut self) -> Result<SecretKey, TransactionServiceError> {
+        let branch = KeyBranch::Change.label();
+        let database = self.transaction_manager.database_mut();
+        let index = database.get_key_index(&branch)?.unwrap_or(0);
+        let key = self
+            .key_manager
+            .derive_path_key(&KeyPath::new(0, KeyBranch::Change, index))?
+            .k;
+        database.set_key_index(&branch, index + 1)?;
+        Ok(key)
+    }
+}
+
+/// Register the handlers of the wallet transaction messages with the domain message dispatcher, which pass the
+/// messages to the transaction service
+pub fn register_transaction_message_handlers<S>(
+    domain_dispatcher: DomainMessageDispatcher<PublicKey, SecretKey>,
+    transaction_service: Arc<Mutex<TransactionService<S>>>,
+) -> DomainMessageDispatcher<PublicKey, SecretKey>
+where
+    S: DataStore + Send + 'static,
+{
+    let reply_transaction_service = transaction_service.clone();
+    domain_dispatcher
+        .route(
+            TariMessageType::new(WalletMessage::SendTransaction),
+            move |sender_message: SenderMessage, peer_context: PeerContext<PublicKey>| {
+                lock_service(&transaction_service)?
+                    .accept_transaction(sender_message, &peer_context.public_key)
+                    .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))
+            },
+        )
+        .route(
+            TariMessageType::new(WalletMessage::ReceiveTransactionReply),
+            move |reply: RecipientSignedTransactionData, _peer_context: PeerContext<PublicKey>| {
+                lock_service(&reply_transaction_service)?
+                    .accept_recipient_reply(reply)
+                    .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))
+            },
+        )
+}
+
+fn lock_service<S>(
+    transaction_service: &Mutex<TransactionService<S>>,
+) -> Result<MutexGuard<TransactionService<S>>, DispatchError>
+where S: DataStore {
+    transaction_service
+        .lock()
+        .map_err(|_| DispatchError::HandlerError("The transaction service lock is poisoned".to_string()))
+}
+
+/// Construct a dispatcher that passes the messages the node handles to the transaction service. Messages that aren't
+/// wallet transaction messages are not dispatched, and messages that are not for the node with the given identity, or
+/// whose signature is invalid, are not handled.
+pub fn construct_transaction_message_dispatcher<S>(
+    node_identity: Arc<NodeIdentity<PublicKey, SecretKey>>,
+    transaction_service: Arc<Mutex<TransactionService<S>>>,
+) -> MessageDispatcher<MessageContext<PublicKey>>
+where
+    S: DataStore + Send + 'static,
+{
+    let domain_dispatcher =
+        register_transaction_message_handlers(DomainMessageDispatcher::new(node_identity.clone()), transaction_service);
+    MessageDispatcher::with_dispatch_type(move |message_context| {
+        determine_comms_msg_dispatch_type(message_context, &node_identity)
+    })
+    .route(CommsDispatchType::Handle as u32, move |message_context| {
+        domain_dispatcher.dispatch(message_context)
+    })
+}
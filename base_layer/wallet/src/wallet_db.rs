@@ -0,0 +1,1030 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+use crate::{chain_scanner::ChainScanner, output_manager::OutputManager, recovery::RecoveryState};
+use chrono::{DateTime, Utc};
+use derive_error::Error;
+use digest::Digest;
+use hmac::{Hmac, Mac};
+use rand::{OsRng, RngCore};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use tari_core::{
+    fee_estimator::FeeEstimator,
+    transaction::{Transaction, UnblindedOutput},
+    types::{Commitment, CommitmentFactory, PublicKey, SecretKey},
+    ReceiverTransactionProtocol,
+    SenderTransactionProtocol,
+};
+use tari_crypto::{
+    commitment::{HomomorphicCommitment, HomomorphicCommitmentFactory},
+    common::Blake256,
+};
+use tari_storage::keyvalue_store::{DataStore, DatastoreError, WriteBatch, WriteOperation};
+use tari_utilities::{chacha20, hex::to_hex};
+
+const TX_IDS_KEY: &str = "tx_ids";
+const OUTPUTS_KEY: &str = "outputs";
+const OUTPUT_MANAGER_KEY: &str = "output_manager";
+const CHAIN_SCANNER_KEY: &str = "chain_scanner";
+const RECOVERY_STATE_KEY: &str = "recovery_state";
+const FEE_ESTIMATOR_KEY: &str = "fee_estimator";
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 32;
+const CIPHER_KEY_DOMAIN: &[u8] = b"wallet_db.cipher_key";
+const MAC_KEY_DOMAIN: &[u8] = b"wallet_db.mac_key";
+const INDEX_KEY_DOMAIN: &[u8] = b"wallet_db.index_key";
+const KEY_CHECK_KEY: &[u8] = b"key_check";
+const KEY_CHECK_VALUE: &str = "tari wallet";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum WalletDbError {
+    // An error occurred in the underlying data store
+    DatastoreError(DatastoreError),
+    // A value could not be serialized or deserialized
+    #[error(msg_embedded, no_from, non_std)]
+    SerializationError(String),
+    // An encrypted value could not be decrypted, most likely because the encryption key is wrong
+    DecryptionError,
+    // A transaction with this tx_id is already stored
+    DuplicateTransaction,
+    // There is no transaction with this tx_id, or it is not in the expected state
+    TransactionNotFound,
+    // The operating system random number generator could not be created
+    #[error(msg_embedded, no_from, non_std)]
+    RandomError(String),
+}
+
+impl From<bincode::Error> for WalletDbError {
+    fn from(e: bincode::Error) -> Self {
+        WalletDbError::SerializationError(e.to_string())
+    }
+}
+
+/// The stage a transaction is at, from the point of view of this wallet
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TransactionStatus {
+    /// Sent to a recipient, and waiting for the recipient's reply
+    PendingOutbound,
+    /// Received from a sender and replied to
+    PendingInbound,
+    /// The transaction is complete and ready to be broadcast
+    Completed,
+    /// The transaction was abandoned before it completed
+    Cancelled,
+    /// The transaction has been found in a block
+    Mined,
+}
+
+/// Whether a transaction sends funds from this wallet or to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TransactionDirection {
+    Outbound,
+    Inbound,
+}
+
+/// The metadata that is kept for every transaction, which allows transactions to be queried without loading their
+/// protocols
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    pub tx_id: u64,
+    pub direction: TransactionDirection,
+    pub status: TransactionStatus,
+    /// The amount, in µT, sent to or received from the counterparty
+    pub amount: u64,
+    /// The fee, in µT, paid by the sender of the transaction
+    pub fee: u64,
+    /// The public key of the node the transaction was sent to or received from, if it is known
+    pub counterparty: Option<PublicKey>,
+    /// When the transaction was first stored
+    pub timestamp: DateTime<Utc>,
+    /// When the status of the transaction last changed
+    pub last_updated: DateTime<Utc>,
+}
+
+/// A filter over the transaction history, with pagination. Fields that are not set match every transaction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransactionQuery {
+    pub status: Option<TransactionStatus>,
+    pub direction: Option<TransactionDirection>,
+    pub counterparty: Option<PublicKey>,
+    /// Only transactions stored at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// Only transactions stored before this time
+    pub to: Option<DateTime<Utc>>,
+    /// The number of matching transactions to skip
+    pub offset: usize,
+    /// The maximum number of transactions to return
+    pub limit: Option<usize>,
+}
+
+impl TransactionQuery {
+    /// A query that matches every transaction
+    pub fn new() -> TransactionQuery {
+        Default::default()
+    }
+
+    pub fn with_status(mut self, status: TransactionStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_direction(mut self, direction: TransactionDirection) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    pub fn with_counterparty(mut self, counterparty: PublicKey) -> Self {
+        self.counterparty = Some(counterparty);
+        self
+    }
+
+    /// Only match transactions stored in the time range `[from, to)`
+    pub fn between(mut self, from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+        self.from = Some(from);
+        self.to = Some(to);
+        self
+    }
+
+    /// Skip the first `offset` matching transactions and return at most `limit` of the rest
+    pub fn page(mut self, offset: usize, limit: usize) -> Self {
+        self.offset = offset;
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Determine if the record matches the filters of the query
+    pub fn matches(&self, record: &TransactionRecord) -> bool {
+        self.status.map_or(true, |s| s == record.status) &&
+            self.direction.map_or(true, |d| d == record.direction) &&
+            self.counterparty
+                .as_ref()
+                .map_or(true, |c| record.counterparty.as_ref() == Some(c)) &&
+            self.from.map_or(true, |from| record.timestamp >= from) &&
+            self.to.map_or(true, |to| record.timestamp < to)
+    }
+}
+
+/// Persistent wallet storage on top of any `DataStore`. The wallet keeps its transactions, owned outputs, key
+/// indices and transaction metadata here. Every value, including the indices of transactions and outputs, is
+/// encrypted with ChaCha20 and authenticated with HMAC-SHA256 so that a wrong key or tampered data is detected, and is
+/// stored at an HMAC of its id rather than the id itself. Independent cipher, MAC and index keys are derived from the
+/// wallet encryption key.
+///
+/// Every change that writes more than one key is stored in a single `WriteBatch`, so it is applied completely or not
+/// at all. Changes that span several calls can be staged with `begin_batch` and stored together with `commit_batch`.
+pub struct WalletDatabase<S: DataStore> {
+    store: S,
+    cipher_key: [u8; 32],
+    mac_key: [u8; 32],
+    index_key: [u8; 32],
+    rng: OsRng,
+    staged: Option<WriteBatch>,
+}
+
+impl<S> WalletDatabase<S>
+where S: DataStore
+{
+    /// Open a wallet database in `store`, encrypting it with `encryption_key`. A `DecryptionError` is returned if the
+    /// database was written with a different key.
+    pub fn new(store: S, encryption_key: [u8; 32]) -> Result<WalletDatabase<S>, WalletDbError> {
+        let rng = OsRng::new().map_err(|e| WalletDbError::RandomError(e.to_string()))?;
+        let mut database = WalletDatabase {
+            store,
+            cipher_key: derive_key(CIPHER_KEY_DOMAIN, &encryption_key),
+            mac_key: derive_key(MAC_KEY_DOMAIN, &encryption_key),
+            index_key: derive_key(INDEX_KEY_DOMAIN, &encryption_key),
+            rng,
+            staged: None,
+        };
+        database.check_key()?;
+        Ok(database)
+    }
+
+    /// Close the database and release the underlying store. Staged changes that were not committed are discarded.
+    pub fn close(self) -> Result<(), WalletDbError> {
+        Ok(self.store.close()?)
+    }
+
+    /// Stage the changes that follow in memory, until they are stored together by `commit_batch` or dropped by
+    /// `discard_batch`. Staged changes are visible to the reads of this database. Batches don't nest: if a batch is
+    /// already being staged, the changes are added to it.
+    pub fn begin_batch(&mut self) {
+        if self.staged.is_none() {
+            self.staged = Some(WriteBatch::new());
+        }
+    }
+
+    /// Store all the staged changes in a single transaction
+    pub fn commit_batch(&mut self) -> Result<(), WalletDbError> {
+        match self.staged.take() {
+            Some(batch) => Ok(self.store.write(batch)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Drop the staged changes without storing them
+    pub fn discard_batch(&mut self) {
+        self.staged = None;
+    }
+
+    /// Run `f` and store all the changes it makes to the database in a single transaction, or none of them if it
+    /// fails. If a batch is already being staged, the changes are added to it instead.
+    pub fn atomically<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut WalletDatabase<S>) -> Result<T, E>,
+        E: From<WalletDbError>,
+    {
+        if self.staged.is_some() {
+            return f(self);
+        }
+        self.begin_batch();
+        match f(self) {
+            Ok(value) => {
+                self.commit_batch()?;
+                Ok(value)
+            },
+            Err(e) => {
+                self.discard_batch();
+                Err(e)
+            },
+        }
+    }
+
+    //------------------------------------------   Transactions   ----------------------------------------------------//
+
+    /// Store a transaction protocol that was sent to a recipient and is waiting for the recipient's reply
+    pub fn add_pending_outbound(
+        &mut self,
+        tx_id: u64,
+        protocol: &SenderTransactionProtocol,
+        amount: u64,
+    ) -> Result<(), WalletDbError>
+    {
+        let fee = protocol.get_fee().unwrap_or(0);
+        let mut batch = self.add_record(
+            tx_id,
+            TransactionDirection::Outbound,
+            TransactionStatus::PendingOutbound,
+            amount,
+            fee,
+        )?;
+        self.stage_put(&mut batch, &pending_outbound_key(tx_id), protocol)?;
+        self.write(batch)
+    }
+
+    /// Store a transaction protocol that was received from a sender and replied to
+    pub fn add_pending_inbound(
+        &mut self,
+        tx_id: u64,
+        protocol: &ReceiverTransactionProtocol,
+        amount: u64,
+        fee: u64,
+    ) -> Result<(), WalletDbError>
+    {
+        let mut batch = self.add_record(
+            tx_id,
+            TransactionDirection::Inbound,
+            TransactionStatus::PendingInbound,
+            amount,
+            fee,
+        )?;
+        self.stage_put(&mut batch, &pending_inbound_key(tx_id), protocol)?;
+        self.write(batch)
+    }
+
+    /// Replace a pending outbound transaction with the completed transaction
+    pub fn complete_outbound(&mut self, tx_id: u64, transaction: &Transaction) -> Result<(), WalletDbError> {
+        let mut record = self
+            .fetch_transaction(tx_id)?
+            .ok_or(WalletDbError::TransactionNotFound)?;
+        if record.status != TransactionStatus::PendingOutbound {
+            return Err(WalletDbError::TransactionNotFound);
+        }
+        let mut batch = WriteBatch::new();
+        self.stage_put(&mut batch, &completed_key(tx_id), transaction)?;
+        self.stage_delete(&mut batch, &pending_outbound_key(tx_id));
+        record.status = TransactionStatus::Completed;
+        self.put_record(&mut batch, record)?;
+        self.write(batch)
+    }
+
+    /// Mark a pending outbound transaction as cancelled and discard its protocol
+    pub fn cancel_pending_outbound(&mut self, tx_id: u64) -> Result<(), WalletDbError> {
+        let mut record = self
+            .fetch_transaction(tx_id)?
+            .ok_or(WalletDbError::TransactionNotFound)?;
+        if record.status != TransactionStatus::PendingOutbound {
+            return Err(WalletDbError::TransactionNotFound);
+        }
+        let mut batch = WriteBatch::new();
+        self.stage_delete(&mut batch, &pending_outbound_key(tx_id));
+        record.status = TransactionStatus::Cancelled;
+        self.put_record(&mut batch, record)?;
+        self.write(batch)
+    }
+
+    /// Mark a completed outbound transaction, or a pending inbound transaction, as mined. The protocol of an inbound
+    /// transaction is kept so that it can be restored if the block is reorged out.
+    pub fn mark_mined(&mut self, tx_id: u64) -> Result<(), WalletDbError> {
+        let mut record = self
+            .fetch_transaction(tx_id)?
+            .ok_or(WalletDbError::TransactionNotFound)?;
+        if record.status != TransactionStatus::Completed && record.status != TransactionStatus::PendingInbound {
+            return Err(WalletDbError::TransactionNotFound);
+        }
+        record.status = TransactionStatus::Mined;
+        self.update_record(record)
+    }
+
+    /// Return a mined transaction to the status it had before it was mined
+    pub fn revert_mined(&mut self, tx_id: u64, status: TransactionStatus) -> Result<(), WalletDbError> {
+        let mut record = self
+            .fetch_transaction(tx_id)?
+            .ok_or(WalletDbError::TransactionNotFound)?;
+        if record.status != TransactionStatus::Mined {
+            return Err(WalletDbError::TransactionNotFound);
+        }
+        record.status = status;
+        self.update_record(record)
+    }
+
+    /// Record the public key of the node that the transaction was sent to or received from
+    pub fn set_counterparty(&mut self, tx_id: u64, counterparty: PublicKey) -> Result<(), WalletDbError> {
+        let mut record = self
+            .fetch_transaction(tx_id)?
+            .ok_or(WalletDbError::TransactionNotFound)?;
+        record.counterparty = Some(counterparty);
+        self.put(&record_key(tx_id), &record)
+    }
+
+    pub fn fetch_pending_outbound(&self, tx_id: u64) -> Result<Option<SenderTransactionProtocol>, WalletDbError> {
+        self.get(&pending_outbound_key(tx_id))
+    }
+
+    pub fn fetch_pending_inbound(&self, tx_id: u64) -> Result<Option<ReceiverTransactionProtocol>, WalletDbError> {
+        self.get(&pending_inbound_key(tx_id))
+    }
+
+    pub fn fetch_completed(&self, tx_id: u64) -> Result<Option<Transaction>, WalletDbError> {
+        self.get(&completed_key(tx_id))
+    }
+
+    /// All the pending outbound transaction protocols, keyed by tx_id
+    pub fn fetch_all_pending_outbound(&self) -> Result<HashMap<u64, SenderTransactionProtocol>, WalletDbError> {
+        let mut result = HashMap::new();
+        for record in self.fetch_transactions_by_status(TransactionStatus::PendingOutbound)? {
+            if let Some(protocol) = self.fetch_pending_outbound(record.tx_id)? {
+                result.insert(record.tx_id, protocol);
+            }
+        }
+        Ok(result)
+    }
+
+    /// All the pending inbound transaction protocols, keyed by tx_id
+    pub fn fetch_all_pending_inbound(&self) -> Result<HashMap<u64, ReceiverTransactionProtocol>, WalletDbError> {
+        let mut result = HashMap::new();
+        for record in self.fetch_transactions_by_status(TransactionStatus::PendingInbound)? {
+            if let Some(protocol) = self.fetch_pending_inbound(record.tx_id)? {
+                result.insert(record.tx_id, protocol);
+            }
+        }
+        Ok(result)
+    }
+
+    /// All the completed transactions, including the outbound transactions that have been mined, keyed by tx_id
+    pub fn fetch_all_completed(&self) -> Result<HashMap<u64, Transaction>, WalletDbError> {
+        let mut result = HashMap::new();
+        for record in self
+            .fetch_all_transactions()?
+            .into_iter()
+            .filter(|r| r.status == TransactionStatus::Completed || r.status == TransactionStatus::Mined)
+        {
+            if let Some(transaction) = self.fetch_completed(record.tx_id)? {
+                result.insert(record.tx_id, transaction);
+            }
+        }
+        Ok(result)
+    }
+
+    /// The metadata of the transaction with the given tx_id
+    pub fn fetch_transaction(&self, tx_id: u64) -> Result<Option<TransactionRecord>, WalletDbError> {
+        self.get(&record_key(tx_id))
+    }
+
+    /// The metadata of all transactions, in the order they were stored
+    pub fn fetch_all_transactions(&self) -> Result<Vec<TransactionRecord>, WalletDbError> {
+        let mut records = Vec::new();
+        for tx_id in self.tx_ids()? {
+            if let Some(record) = self.fetch_transaction(tx_id)? {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// The metadata of all transactions with the given status
+    pub fn fetch_transactions_by_status(
+        &self,
+        status: TransactionStatus,
+    ) -> Result<Vec<TransactionRecord>, WalletDbError>
+    {
+        Ok(self
+            .fetch_all_transactions()?
+            .into_iter()
+            .filter(|r| r.status == status)
+            .collect())
+    }
+
+    /// The metadata of the transactions that match the query, in the order they were stored
+    pub fn fetch_transactions(&self, query: &TransactionQuery) -> Result<Vec<TransactionRecord>, WalletDbError> {
+        Ok(self
+            .fetch_all_transactions()?
+            .into_iter()
+            .filter(|r| query.matches(r))
+            .skip(query.offset)
+            .take(query.limit.unwrap_or(usize::max_value()))
+            .collect())
+    }
+
+    /// The metadata of all transactions that were stored in the time range `[from, to)`
+    pub fn fetch_transactions_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<TransactionRecord>, WalletDbError>
+    {
+        Ok(self
+            .fetch_all_transactions()?
+            .into_iter()
+            .filter(|r| r.timestamp >= from && r.timestamp < to)
+            .collect())
+    }
+
+    fn tx_ids(&self) -> Result<Vec<u64>, WalletDbError> {
+        Ok(self.get(TX_IDS_KEY)?.unwrap_or_default())
+    }
+
+    /// A batch that stores the record of a new transaction and adds its tx_id to the list of transactions
+    fn add_record(
+        &mut self,
+        tx_id: u64,
+        direction: TransactionDirection,
+        status: TransactionStatus,
+        amount: u64,
+        fee: u64,
+    ) -> Result<WriteBatch, WalletDbError>
+    {
+        let mut tx_ids = self.tx_ids()?;
+        if tx_ids.contains(&tx_id) {
+            return Err(WalletDbError::DuplicateTransaction);
+        }
+        let now = Utc::now();
+        let record = TransactionRecord {
+            tx_id,
+            direction,
+            status,
+            amount,
+            fee,
+            counterparty: None,
+            timestamp: now,
+            last_updated: now,
+        };
+        tx_ids.push(tx_id);
+        let mut batch = WriteBatch::new();
+        self.stage_put(&mut batch, &record_key(tx_id), &record)?;
+        self.stage_put(&mut batch, TX_IDS_KEY, &tx_ids)?;
+        Ok(batch)
+    }
+
+    /// Add a record whose status has changed to `batch`
+    fn put_record(&mut self, batch: &mut WriteBatch, mut record: TransactionRecord) -> Result<(), WalletDbError> {
+        record.last_updated = Utc::now();
+        self.stage_put(batch, &record_key(record.tx_id), &record)
+    }
+
+    /// Store a record whose status has changed
+    fn update_record(&mut self, record: TransactionRecord) -> Result<(), WalletDbError> {
+        let mut batch = WriteBatch::new();
+        self.put_record(&mut batch, record)?;
+        self.write(batch)
+    }
+
+    //------------------------------------------      Outputs     ----------------------------------------------------//
+
+    /// Store an output owned by this wallet. Outputs are identified by their commitment.
+    pub fn add_unblinded_output(&mut self, output: &UnblindedOutput) -> Result<(), WalletDbError> {
+        let id = output_id(&commitment_of(output));
+        let mut batch = WriteBatch::new();
+        let mut ids = self.output_ids()?;
+        if !ids.contains(&id) {
+            ids.push(id.clone());
+            self.stage_put(&mut batch, OUTPUTS_KEY, &ids)?;
+        }
+        self.stage_put(&mut batch, &output_key(&id), output)?;
+        self.write(batch)
+    }
+
+    /// Remove an owned output, returning it if it was stored
+    pub fn remove_unblinded_output(
+        &mut self,
+        commitment: &Commitment,
+    ) -> Result<Option<UnblindedOutput>, WalletDbError>
+    {
+        let id = output_id(commitment);
+        let output = self.get(&output_key(&id))?;
+        if output.is_some() {
+            let ids: Vec<String> = self.output_ids()?.into_iter().filter(|i| i != &id).collect();
+            let mut batch = WriteBatch::new();
+            self.stage_put(&mut batch, OUTPUTS_KEY, &ids)?;
+            self.stage_delete(&mut batch, &output_key(&id));
+            self.write(batch)?;
+        }
+        Ok(output)
+    }
+
+    /// All the outputs owned by this wallet, in the order they were stored
+    pub fn fetch_unblinded_outputs(&self) -> Result<Vec<UnblindedOutput>, WalletDbError> {
+        let mut outputs = Vec::new();
+        for id in self.output_ids()? {
+            if let Some(output) = self.get(&output_key(&id))? {
+                outputs.push(output);
+            }
+        }
+        Ok(outputs)
+    }
+
+    fn output_ids(&self) -> Result<Vec<String>, WalletDbError> {
+        Ok(self.get(OUTPUTS_KEY)?.unwrap_or_default())
+    }
+
+    /// Store the state of the output manager
+    pub fn save_output_manager(&mut self, output_manager: &OutputManager) -> Result<(), WalletDbError> {
+        self.put(OUTPUT_MANAGER_KEY, output_manager)
+    }
+
+    /// The stored state of the output manager, if there is one
+    pub fn fetch_output_manager(&self) -> Result<Option<OutputManager>, WalletDbError> {
+        self.get(OUTPUT_MANAGER_KEY)
+    }
+
+    /// Store the state of the chain scanner
+    pub fn save_chain_scanner(&mut self, chain_scanner: &ChainScanner) -> Result<(), WalletDbError> {
+        self.put(CHAIN_SCANNER_KEY, chain_scanner)
+    }
+
+    /// The stored state of the chain scanner, if there is one
+    pub fn fetch_chain_scanner(&self) -> Result<Option<ChainScanner>, WalletDbError> {
+        self.get(CHAIN_SCANNER_KEY)
+    }
+
+    /// Store the fee rates of the recent blocks
+    pub fn save_fee_estimator(&mut self, fee_estimator: &FeeEstimator) -> Result<(), WalletDbError> {
+        self.put(FEE_ESTIMATOR_KEY, fee_estimator)
+    }
+
+    /// The stored fee rates of the recent blocks, if there are any
+    pub fn fetch_fee_estimator(&self) -> Result<Option<FeeEstimator>, WalletDbError> {
+        self.get(FEE_ESTIMATOR_KEY)
+    }
+
+    /// Store the progress of a wallet recovery
+    pub fn save_recovery_state(&mut self, state: &RecoveryState) -> Result<(), WalletDbError> {
+        self.put(RECOVERY_STATE_KEY, state)
+    }
+
+    /// The stored progress of a wallet recovery, if there is one
+    pub fn fetch_recovery_state(&self) -> Result<Option<RecoveryState>, WalletDbError> {
+        self.get(RECOVERY_STATE_KEY)
+    }
+
+    //------------------------------------------    Key indices   ----------------------------------------------------//
+
+    /// Store the next unused key index of a key manager branch
+    pub fn set_key_index(&mut self, branch: &str, index: usize) -> Result<(), WalletDbError> {
+        self.put(&key_index_key(branch), &(index as u64))
+    }
+
+    /// The stored key index of a key manager branch, if there is one
+    pub fn get_key_index(&self, branch: &str) -> Result<Option<usize>, WalletDbError> {
+        let index: Option<u64> = self.get(&key_index_key(branch))?;
+        Ok(index.map(|i| i as usize))
+    }
+
+    //------------------------------------------      Storage     ----------------------------------------------------//
+
+    /// Store `batch`, or add it to the staged changes if a batch has been started
+    fn write(&mut self, batch: WriteBatch) -> Result<(), WalletDbError> {
+        match self.staged.as_mut() {
+            Some(staged) => {
+                staged.append(batch);
+                Ok(())
+            },
+            None => Ok(self.store.write(batch)?),
+        }
+    }
+
+    /// The encrypted value stored for `id`, taking the staged changes into account
+    fn get_raw(&self, id: &str) -> Result<Option<Vec<u8>>, WalletDbError> {
+        let key = self.storage_key(id);
+        let staged = self
+            .staged
+            .as_ref()
+            .and_then(|batch| batch.operations().iter().rev().find(|op| op.key() == &key[..]));
+        match staged {
+            Some(WriteOperation::Put(_, value)) => Ok(Some(value.clone())),
+            Some(WriteOperation::Delete(_)) => Ok(None),
+            None => Ok(self.store.get_raw(&key)?),
+        }
+    }
+
+    fn put<T: Serialize>(&mut self, id: &str, value: &T) -> Result<(), WalletDbError> {
+        let mut batch = WriteBatch::new();
+        self.stage_put(&mut batch, id, value)?;
+        self.write(batch)
+    }
+
+    fn get<T: DeserializeOwned>(&self, id: &str) -> Result<Option<T>, WalletDbError> {
+        match self.get_raw(id)? {
+            Some(data) => Ok(Some(self.decrypt(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Add the encrypted `value` to `batch`, stored for `id`
+    fn stage_put<T: Serialize>(&mut self, batch: &mut WriteBatch, id: &str, value: &T) -> Result<(), WalletDbError> {
+        let data = self.encrypt(value)?;
+        batch.put_raw(&self.storage_key(id), data);
+        Ok(())
+    }
+
+    /// Add the deletion of the value stored for `id` to `batch`
+    fn stage_delete(&self, batch: &mut WriteBatch, id: &str) {
+        batch.delete_raw(&self.storage_key(id));
+    }
+
+    /// The key that the value with the given id is stored at, an HMAC of the id. Without the encryption key the ids
+    /// (tx_ids, output commitments and key branches) can't be learned from the keys.
+    fn storage_key(&self, id: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_varkey(&self.index_key).expect("HMAC accepts keys of any length");
+        mac.input(id.as_bytes());
+        mac.result().code().to_vec()
+    }
+
+    /// Check that the database was written with the same encryption key, by decrypting a value that is stored when
+    /// the database is first opened. Since every record is stored at a key derived from the encryption key, a wrong
+    /// key would otherwise make the database look empty.
+    fn check_key(&mut self) -> Result<(), WalletDbError> {
+        match self.store.get_raw(KEY_CHECK_KEY)? {
+            Some(data) => {
+                let value: String = self.decrypt(&data)?;
+                if value != KEY_CHECK_VALUE {
+                    return Err(WalletDbError::DecryptionError);
+                }
+                Ok(())
+            },
+            None => {
+                let data = self.encrypt(&KEY_CHECK_VALUE)?;
+                Ok(self.store.put_raw(KEY_CHECK_KEY, data)?)
+            },
+        }
+    }
+
+    //------------------------------------------    Encryption    ----------------------------------------------------//
+
+    /// Serialize and encrypt `value`. The encrypted bytes are `nonce | tag | ciphertext`.
+    fn encrypt<T: Serialize>(&mut self, value: &T) -> Result<Vec<u8>, WalletDbError> {
+        let plaintext = bincode::serialize(value)?;
+        let mut nonce_bytes = [0u8; NONCE_SIZE];
+        self.rng.fill_bytes(&mut nonce_bytes);
+        let nonce = nonce_from_bytes(&nonce_bytes);
+        let ciphertext = chacha20::encode_with_nonce(&plaintext, &self.cipher_key, &nonce);
+        let mut data = nonce_bytes.to_vec();
+        data.extend_from_slice(&self.tag(&nonce_bytes, &ciphertext));
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    fn decrypt<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, WalletDbError> {
+        if data.len() < NONCE_SIZE + TAG_SIZE {
+            return Err(WalletDbError::DecryptionError);
+        }
+        let (nonce_bytes, rest) = data.split_at(NONCE_SIZE);
+        let (tag, ciphertext) = rest.split_at(TAG_SIZE);
+        if self.mac(nonce_bytes, ciphertext).verify(tag).is_err() {
+            return Err(WalletDbError::DecryptionError);
+        }
+        let nonce = nonce_from_bytes(nonce_bytes);
+        let plaintext = chacha20::decode_with_nonce(ciphertext, &self.cipher_key, &nonce);
+        Ok(bincode::deserialize(&plaintext)?)
+    }
+
+    /// An HMAC-SHA256 tag over the nonce and ciphertext
+    fn tag(&self, nonce: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+        self.mac(nonce, ciphertext).result().code().to_vec()
+    }
+
+    fn mac(&self, nonce: &[u8], ciphertext: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_varkey(&self.mac_key).expect("HMAC accepts keys of any length");
+        mac.input(nonce);
+        mac.input(ciphertext);
+        mac
+    }
+}
+
+fn nonce_from_bytes(bytes: &[u8]) -> [u32; 3] {
+    let mut nonce = [0u32; 3];
+    for (i, n) in nonce.iter_mut().enumerate() {
+        let mut word = [0u8; 4];
+        word.copy_from_slice(&bytes[i * 4..i * 4 + 4]);
+        *n = u32::from_le_bytes(word);
+    }
+    nonce
+}
+
+/// Derive a key for a single purpose from the wallet encryption key
+fn derive_key(domain: &[u8], encryption_key: &[u8; 32]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&Blake256::new().chain(domain).chain(encryption_key).result());
+    key
+}
+
+fn commitment_of(output: &UnblindedOutput) -> Commitment {
+    CommitmentFactory::create(&output.spending_key, &SecretKey::from(output.value))
+}
+
+fn output_id(commitment: &Commitment) -> String {
+    to_hex(commitment.as_bytes())
+}
+
+fn record_key(tx_id: u64) -> String {
+    format!("tx_record/{}", tx_id)
+}
+
+fn pending_outbound_key(tx_id: u64) -> String {
+    format!("pending_outbound/{}", tx_id)
+}
+
+fn pending_inbound_key(tx_id: u64) -> String {
+    format!("pending_inbound/{}", tx_id)
+}
+
+fn completed_key(tx_id: u64) -> String {
+    format!("completed/{}", tx_id)
+}
+
+fn output_key(id: &str) -> String {
+    format!("output/{}", id)
+}
+
+fn key_index_key(branch: &str) -> String {
+    format!("key_index/{}", branch)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::wallet_db::{
+        commitment_of,
+        output_id,
+        output_key,
+        record_key,
+        TransactionDirection,
+        TransactionQuery,
+        TransactionStatus,
+        WalletDatabase,
+        WalletDbError,
+        OUTPUTS_KEY,
+        TX_IDS_KEY,
+    };
+    use chrono::{Duration, Utc};
+    use rand::OsRng;
+    use std::fs;
+    use tari_core::{
+        transaction::UnblindedOutput,
+        transaction_protocol::test_common::{make_input, TestParams},
+        types::{CommitmentFactory, PublicKey, SecretKey},
+        SenderTransactionProtocol,
+    };
+    use tari_crypto::{
+        commitment::HomomorphicCommitmentFactory,
+        common::Blake256,
+        keys::{PublicKey as PK, SecretKey as SK},
+    };
+    use tari_storage::{hashmap_store::HashmapStore, keyvalue_store::DataStore, lmdb::LMDBBuilder};
+
+    fn make_stp(rng: &mut OsRng) -> SenderTransactionProtocol {
+        let a = TestParams::new(rng);
+        let (utxo, input) = make_input(rng, 2500);
+        let mut builder = SenderTransactionProtocol::builder(1);
+        builder
+            .with_lock_height(0)
+            .with_fee_per_gram(20)
+            .with_offset(a.offset.clone())
+            .with_private_nonce(a.nonce.clone())
+            .with_change_secret(a.change_key.clone())
+            .with_input(utxo, input)
+            .with_amount(0, 500);
+        let mut stp = builder.build::<Blake256>().unwrap();
+        stp.build_single_round_message().unwrap();
+        stp
+    }
+
+    #[test]
+    fn transactions_are_queryable() {
+        let mut rng = OsRng::new().unwrap();
+        let mut db = WalletDatabase::new(HashmapStore::new(), [1u8; 32]).unwrap();
+        let start = Utc::now();
+        let stp1 = make_stp(&mut rng);
+        let stp2 = make_stp(&mut rng);
+        db.add_pending_outbound(1, &stp1, 500).unwrap();
+        db.add_pending_outbound(2, &stp2, 600).unwrap();
+        assert_eq!(
+            db.add_pending_outbound(2, &stp2, 600),
+            Err(WalletDbError::DuplicateTransaction)
+        );
+
+        let record = db.fetch_transaction(2).unwrap().unwrap();
+        assert_eq!(record.amount, 600);
+        assert_eq!(record.status, TransactionStatus::PendingOutbound);
+        assert!(db.fetch_transaction(3).unwrap().is_none());
+        let restored = db.fetch_pending_outbound(1).unwrap().unwrap();
+        assert_eq!(restored.get_tx_id(), stp1.get_tx_id());
+
+        assert_eq!(
+            db.fetch_transactions_by_status(TransactionStatus::PendingOutbound)
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(db
+            .fetch_transactions_by_status(TransactionStatus::Completed)
+            .unwrap()
+            .is_empty());
+        let end = Utc::now() + Duration::seconds(1);
+        assert_eq!(db.fetch_transactions_between(start, end).unwrap().len(), 2);
+        assert!(db
+            .fetch_transactions_between(end, end + Duration::days(1))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn transaction_history_query() {
+        let mut rng = OsRng::new().unwrap();
+        let mut db = WalletDatabase::new(HashmapStore::new(), [4u8; 32]).unwrap();
+        for tx_id in 1..5 {
+            db.add_pending_outbound(tx_id, &make_stp(&mut rng), 500).unwrap();
+        }
+        db.cancel_pending_outbound(2).unwrap();
+        let counterparty = PublicKey::from_secret_key(&SecretKey::random(&mut rng));
+        db.set_counterparty(3, counterparty.clone()).unwrap();
+
+        let record = db.fetch_transaction(2).unwrap().unwrap();
+        assert_eq!(record.direction, TransactionDirection::Outbound);
+        assert_eq!(record.status, TransactionStatus::Cancelled);
+        assert!(record.fee > 0);
+        assert!(record.last_updated >= record.timestamp);
+
+        let tx_ids = |query: TransactionQuery| -> Vec<u64> {
+            db.fetch_transactions(&query).unwrap().iter().map(|r| r.tx_id).collect()
+        };
+        assert_eq!(tx_ids(TransactionQuery::new()), vec![1, 2, 3, 4]);
+        assert_eq!(
+            tx_ids(TransactionQuery::new().with_status(TransactionStatus::PendingOutbound)),
+            vec![1, 3, 4]
+        );
+        assert_eq!(tx_ids(TransactionQuery::new().with_counterparty(counterparty)), vec![3]);
+        assert!(tx_ids(TransactionQuery::new().with_direction(TransactionDirection::Inbound)).is_empty());
+        assert_eq!(tx_ids(TransactionQuery::new().page(1, 2)), vec![2, 3]);
+        assert_eq!(tx_ids(TransactionQuery::new().page(3, 2)), vec![4]);
+    }
+
+    #[test]
+    fn outputs_and_key_indices() {
+        let mut rng = OsRng::new().unwrap();
+        let mut db = WalletDatabase::new(HashmapStore::new(), [2u8; 32]).unwrap();
+        let output1 = UnblindedOutput::new(100, SecretKey::random(&mut rng), None);
+        let output2 = UnblindedOutput::new(200, SecretKey::random(&mut rng), None);
+        db.add_unblinded_output(&output1).unwrap();
+        db.add_unblinded_output(&output2).unwrap();
+        let values: Vec<u64> = db.fetch_unblinded_outputs().unwrap().iter().map(|o| o.value).collect();
+        assert_eq!(values, vec![100, 200]);
+
+        let commitment = CommitmentFactory::create(&output1.spending_key, &SecretKey::from(100));
+        let removed = db.remove_unblinded_output(&commitment).unwrap().unwrap();
+        assert_eq!(removed.spending_key, output1.spending_key);
+        assert!(db.remove_unblinded_output(&commitment).unwrap().is_none());
+        assert_eq!(db.fetch_unblinded_outputs().unwrap().len(), 1);
+
+        assert_eq!(db.get_key_index("spend").unwrap(), None);
+        db.set_key_index("spend", 5).unwrap();
+        assert_eq!(db.get_key_index("spend").unwrap(), Some(5));
+    }
+
+    #[test]
+    fn staged_changes_are_stored_together() {
+        let mut rng = OsRng::new().unwrap();
+        let mut db = WalletDatabase::new(HashmapStore::new(), [5u8; 32]).unwrap();
+        let output = UnblindedOutput::new(100, SecretKey::random(&mut rng), None);
+        // Staged changes are visible, but dropped unless they are committed
+        db.begin_batch();
+        db.add_unblinded_output(&output).unwrap();
+        db.set_key_index("spend", 1).unwrap();
+        assert_eq!(db.fetch_unblinded_outputs().unwrap().len(), 1);
+        db.discard_batch();
+        assert!(db.fetch_unblinded_outputs().unwrap().is_empty());
+        assert_eq!(db.get_key_index("spend").unwrap(), None);
+
+        // A failed update stores none of its changes
+        let result = db.atomically(|db| {
+            db.set_key_index("spend", 2)?;
+            db.add_pending_outbound(1, &make_stp(&mut rng), 500)?;
+            db.add_pending_outbound(1, &make_stp(&mut rng), 500)
+        });
+        assert_eq!(result, Err(WalletDbError::DuplicateTransaction));
+        assert_eq!(db.get_key_index("spend").unwrap(), None);
+        assert!(db.fetch_transaction(1).unwrap().is_none());
+
+        db.atomically(|db| -> Result<(), WalletDbError> {
+            db.set_key_index("spend", 3)?;
+            db.add_unblinded_output(&output)
+        })
+        .unwrap();
+        assert_eq!(db.get_key_index("spend").unwrap(), Some(3));
+        assert_eq!(db.fetch_unblinded_outputs().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn ids_are_not_stored_in_plaintext() {
+        let mut rng = OsRng::new().unwrap();
+        let mut db = WalletDatabase::new(HashmapStore::new(), [6u8; 32]).unwrap();
+        let output = UnblindedOutput::new(100, SecretKey::random(&mut rng), None);
+        db.add_unblinded_output(&output).unwrap();
+        db.add_pending_outbound(1, &make_stp(&mut rng), 500).unwrap();
+        let ids = vec![
+            OUTPUTS_KEY.to_string(),
+            output_key(&output_id(&commitment_of(&output))),
+            TX_IDS_KEY.to_string(),
+            record_key(1),
+        ];
+        for id in ids {
+            assert!(!db.store.exists(id.as_bytes()).unwrap());
+            assert!(db.store.exists(&db.storage_key(&id)).unwrap());
+        }
+    }
+
+    #[test]
+    fn secrets_are_encrypted_at_rest() {
+        let test_dir = "./tests/wallet_db/";
+        if fs::metadata(test_dir).is_ok() {
+            fs::remove_dir_all(test_dir).unwrap();
+        }
+        fs::create_dir_all(test_dir).unwrap();
+        let mut rng = OsRng::new().unwrap();
+        let output = UnblindedOutput::new(100, SecretKey::random(&mut rng), None);
+        {
+            let store = LMDBBuilder::new().set_mapsize(5).set_path(test_dir).build().unwrap();
+            let mut db = WalletDatabase::new(store, [3u8; 32]).unwrap();
+            db.add_unblinded_output(&output).unwrap();
+            db.set_key_index("spend", 7).unwrap();
+            db.close().unwrap();
+        }
+        {
+            // Reopening with the wrong key is refused
+            let store = LMDBBuilder::new().set_mapsize(5).set_path(test_dir).build().unwrap();
+            assert_eq!(
+                WalletDatabase::new(store, [4u8; 32]).err(),
+                Some(WalletDbError::DecryptionError)
+            );
+        }
+        {
+            let store = LMDBBuilder::new().set_mapsize(5).set_path(test_dir).build().unwrap();
+            let db = WalletDatabase::new(store, [3u8; 32]).unwrap();
+            let outputs = db.fetch_unblinded_outputs().unwrap();
+            assert_eq!(outputs.len(), 1);
+            assert_eq!(outputs[0].spending_key, output.spending_key);
+            assert_eq!(db.get_key_index("spend").unwrap(), Some(7));
+            db.close().unwrap();
+        }
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// The changes to the state of the wallet's transactions that are published to subscribers
+#[derive(Clone, Debug, PartialEq)]
+pub enum WalletEvent {
+    /// A transaction was sent to a recipient
+    TransactionSent { tx_id: u64, amount: u64 },
+    /// A transaction was received from a sender, and a reply was created for it
+    TransactionReceived { tx_id: u64, amount: u64 },
+    /// The recipient's reply to an outbound transaction was accepted
+    ReplyAccepted { tx_id: u64 },
+    /// An outbound transaction was finalized and is ready to be broadcast
+    TransactionFinalized { tx_id: u64 },
+    /// A transaction was found in the block at `height`
+    TransactionMined { tx_id: u64, height: u64 },
+    /// The block a transaction was mined in was reorged out of the chain
+    TransactionReverted { tx_id: u64 },
+    /// A pending outbound transaction was cancelled
+    TransactionCancelled { tx_id: u64 },
+    /// An output spendable with one of the wallet's keys, but not created by a wallet transaction, was found in the
+    /// block at `height`
+    OutputReceived { value: u64, height: u64 },
+    /// An unspent output was spent in the block at `height` by a transaction the wallet did not create
+    OutputSpent { value: u64, height: u64 },
+}
+
+/// Publishes WalletEvents to every subscriber. Subscribers that have dropped their receiver are removed the next time
+/// an event is published.
+#[derive(Default)]
+pub struct WalletEventPublisher {
+    subscribers: Vec<Sender<WalletEvent>>,
+}
+
+impl WalletEventPublisher {
+    pub fn new() -> WalletEventPublisher {
+        Default::default()
+    }
+
+    /// Subscribe to all events published after this call
+    pub fn subscribe(&mut self) -> Receiver<WalletEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Send the event to every subscriber
+    pub fn publish(&mut self, event: WalletEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn events_are_published_to_subscribers() {
+        let mut publisher = WalletEventPublisher::new();
+        let receiver1 = publisher.subscribe();
+        publisher.publish(WalletEvent::ReplyAccepted { tx_id: 1 });
+        let receiver2 = publisher.subscribe();
+        publisher.publish(WalletEvent::TransactionCancelled { tx_id: 2 });
+        drop(receiver1);
+        publisher.publish(WalletEvent::TransactionFinalized { tx_id: 3 });
+
+        assert_eq!(publisher.subscribers.len(), 1);
+        assert_eq!(receiver2.try_recv().unwrap(), WalletEvent::TransactionCancelled {
+            tx_id: 2
+        });
+        assert_eq!(receiver2.try_recv().unwrap(), WalletEvent::TransactionFinalized {
+            tx_id: 3
+        });
+        assert!(receiver2.try_recv().is_err());
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use keymanager::keymanager::{KeyBranch, KeyManager};
+use rand::OsRng;
+use std::{
+    convert::TryFrom,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tari_comms::{
+    connection::{
+        connection::EstablishedConnection,
+        message::MessageEnvelope,
+        zmq::{Context, InprocAddress, ZmqEndpoint},
+        SocketType,
+    },
+    inbound_message_service::inbound_message_service::InboundMessageService,
+    outbound_message_service::{outbound_message::OutboundMessage, outbound_message_service::OutboundMessageService},
+    peer_manager::{manager::PeerManager, node_id::NodeId, node_identity::NodeIdentity},
+};
+use tari_core::{
+    transaction::UnblindedOutput,
+    types::{PublicKey, SecretKey},
+};
+use tari_crypto::keys::{PublicKey as PK, SecretKey as SK};
+use tari_storage::hashmap_store::HashmapStore;
+use wallet::{
+    transaction_manager::TransactionManager,
+    transaction_service::{construct_transaction_message_dispatcher, TransactionService},
+};
+
+/// Stands in for the outbound message pool and the connection between two nodes: messages written to the outbound
+/// address are unwrapped from their OutboundMessage and handed to the inbound message service at the inbound address
+fn start_relay(context: Context, outbound_address: InprocAddress, inbound_address: InprocAddress) {
+    thread::spawn(move || {
+        let pool_socket = context.socket(SocketType::Reply).unwrap();
+        pool_socket.bind(&outbound_address.to_zmq_endpoint()).unwrap();
+        let pool_connection = EstablishedConnection { socket: pool_socket };
+        let inbound_socket = context.socket(SocketType::Request).unwrap();
+        inbound_socket.connect(&inbound_address.to_zmq_endpoint()).unwrap();
+        let inbound_connection = EstablishedConnection { socket: inbound_socket };
+        loop {
+            if let Ok(mut frames) = pool_connection.receive(100) {
+                let outbound_message = OutboundMessage::<MessageEnvelope>::try_from(frames.remove(0)).unwrap();
+                let envelope = outbound_message.message_envelope;
+                let message_context_frames = vec![
+                    vec![0u8],
+                    vec![1u8],
+                    envelope.version().clone(),
+                    envelope.header().clone(),
+                    envelope.body().clone(),
+                ];
+                inbound_connection.send(&message_context_frames).unwrap();
+                inbound_connection.receive(2000).unwrap();
+                pool_connection.send(&[b"OK".to_vec()]).unwrap();
+            }
+        }
+    });
+}
+
+fn create_node_identity(rng: &mut OsRng) -> Arc<NodeIdentity<PublicKey, SecretKey>> {
+    let (sk, pk) = PublicKey::random_keypair(rng);
+    Arc::new(NodeIdentity::new(NodeId::from_key(&pk).unwrap(), pk, Some(sk)))
+}
+
+fn create_wallet(
+    context: &Context,
+    rng: &mut OsRng,
+    node_identity: Arc<NodeIdentity<PublicKey, SecretKey>>,
+    inbound_address: InprocAddress,
+    outbound_address: InprocAddress,
+) -> Arc<Mutex<TransactionService>>
+{
+    let peer_manager = Arc::new(PeerManager::new(node_identity.node_id.clone(), HashmapStore::new()).unwrap());
+    let outbound_message_service =
+        OutboundMessageService::new(context.clone(), outbound_address, node_identity.clone(), peer_manager);
+    let service = Arc::new(Mutex::new(
+        TransactionService::new(
+            TransactionManager::new().unwrap(),
+            KeyManager::new(rng),
+            node_identity.clone(),
+            outbound_message_service,
+        )
+        .unwrap(),
+    ));
+    InboundMessageService::new(
+        context.clone(),
+        inbound_address,
+        node_identity.public_key.clone(),
+        construct_transaction_message_dispatcher(node_identity.clone(), service.clone()),
+    )
+    .unwrap()
+    .start();
+    service
+}
+
+#[test]
+fn send_transaction_between_wallets() {
+    let mut rng = OsRng::new().unwrap();
+    let context = Context::new();
+    let alice_identity = create_node_identity(&mut rng);
+    let bob_identity = create_node_identity(&mut rng);
+    let (alice_inbound, alice_outbound) = (InprocAddress::random(), InprocAddress::random());
+    let (bob_inbound, bob_outbound) = (InprocAddress::random(), InprocAddress::random());
+
+    let alice = create_wallet(
+        &context,
+        &mut rng,
+        alice_identity.clone(),
+        alice_inbound.clone(),
+        alice_outbound.clone(),
+    );
+    let bob = create_wallet(
+        &context,
+        &mut rng,
+        bob_identity.clone(),
+        bob_inbound.clone(),
+        bob_outbound.clone(),
+    );
+    start_relay(context.clone(), alice_outbound, bob_inbound);
+    start_relay(context.clone(), bob_outbound, alice_inbound);
+
+    alice
+        .lock()
+        .unwrap()
+        .transaction_manager_mut()
+        .add_output(UnblindedOutput::new(5000, SecretKey::random(&mut rng), None))
+        .unwrap();
+    let tx_id = alice
+        .lock()
+        .unwrap()
+        .send_transaction(&bob_identity.public_key, 1000, None, 0)
+        .unwrap();
+
+    let mut completed = false;
+    for _ in 0..50 {
+        thread::sleep(Duration::from_millis(100));
+        if alice
+            .lock()
+            .unwrap()
+            .transaction_manager()
+            .database()
+            .fetch_completed(tx_id)
+            .unwrap()
+            .is_some()
+        {
+            completed = true;
+            break;
+        }
+    }
+    assert!(completed);
+    // Alice's change key was derived from the next unused index of the change branch
+    let change_index = alice
+        .lock()
+        .unwrap()
+        .transaction_manager()
+        .database()
+        .get_key_index(&KeyBranch::Change.label())
+        .unwrap();
+    assert_eq!(change_index, Some(1));
+
+    let bob = bob.lock().unwrap();
+    let database = bob.transaction_manager().database();
+    assert!(database.fetch_pending_inbound(tx_id).unwrap().is_some());
+    let record = database.fetch_transaction(tx_id).unwrap().unwrap();
+    assert_eq!(record.counterparty, Some(alice_identity.public_key.clone()));
+    // The spending key of Bob's new output was derived from the next unused key index
+    assert_eq!(database.get_key_index(&bob.key_manager().branch_seed).unwrap(), Some(1));
+}
@@ -29,7 +29,7 @@ use crate::connection::{
     Result,
     SocketEstablishment,
 };
-use std::{cmp, iter::IntoIterator};
+use std::{cmp, iter::IntoIterator, net::IpAddr};
 
 /// Represents a low-level connection which can be bound an address
 /// supported by [`ZeroMQ`] the `ZMQ_ROUTER` socket.
@@ -249,6 +249,10 @@ fn set_linger(socket: &zmq::Socket, linger: Linger) -> Result<()> {
     }
 }
 
+fn receive_error_mapper(e: zmq::Error) -> ConnectionError {
+    ConnectionError::SocketError(format!("Error receiving: {} ({})", e, e.to_raw()))
+}
+
 /// Represents an established connection.
 pub struct EstablishedConnection {
     pub socket: zmq::Socket,
@@ -259,6 +263,32 @@ impl EstablishedConnection {
     /// This method may be repeatably called, probably in a loop in a separate thread, to receive multiple multipart
     /// messages.
     pub fn receive(&self, timeout_ms: u32) -> Result<FrameSet> {
+        self.poll_receive(timeout_ms)?;
+        self.receive_multipart()
+    }
+
+    /// Receive a multipart message together with the IP address of the remote peer that sent it, or return a
+    /// `ConnectionError::Timeout` if the specified timeout has expired. The remote address is only known for messages
+    /// that were received over TCP.
+    pub fn receive_with_remote_address(&self, timeout_ms: u32) -> Result<(FrameSet, Option<IpAddr>)> {
+        self.poll_receive(timeout_ms)?;
+        let mut frames = Vec::new();
+        let mut remote_address = None;
+        loop {
+            let mut msg = self.socket.recv_msg(0).map_err(receive_error_mapper)?;
+            if remote_address.is_none() {
+                remote_address = msg.gets("Peer-Address").and_then(|address| address.parse().ok());
+            }
+            frames.push(msg.to_vec());
+            if !msg.get_more() {
+                break;
+            }
+        }
+        Ok((frames, remote_address))
+    }
+
+    /// Wait until a message is ready to be received
+    fn poll_receive(&self, timeout_ms: u32) -> Result<()> {
         match self.socket.poll(zmq::POLLIN, timeout_ms as i64) {
             Ok(rc) => {
                 match rc {
@@ -267,7 +297,7 @@ impl EstablishedConnection {
                     // Nothing to receive
                     0 => Err(ConnectionError::Timeout),
                     // Ready to receive
-                    _ => self.receive_multipart(),
+                    _ => Ok(()),
                 }
             },
 
@@ -277,9 +307,7 @@ impl EstablishedConnection {
 
     /// Read entire multipart message
     fn receive_multipart(&self) -> Result<FrameSet> {
-        self.socket
-            .recv_multipart(0)
-            .map_err(|e| ConnectionError::SocketError(format!("Error receiving: {} ({})", e, e.to_raw())))
+        self.socket.recv_multipart(0).map_err(receive_error_mapper)
     }
 
     /// Set the period the underling socket connection should
@@ -20,13 +20,27 @@
 //  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use crate::peer_manager::node_id::*;
+use crate::{
+    peer_manager::node_id::*,
+    types::{Challenge, DEFAULT_MESSAGE_TTL},
+};
 use bitflags::*;
 use derive_error::Error;
+use digest::Digest;
+use hmac::{Hmac, Mac};
+use rand::{CryptoRng, RngCore};
 use rmp_serde;
-use serde::{Deserialize, Serialize};
-use std::convert::TryFrom;
-use tari_crypto::keys::PublicKey;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    convert::TryFrom,
+    ops::{Add, Mul},
+};
+use tari_crypto::{
+    keys::{DiffieHellmanSharedSecret, PublicKey, SecretKey},
+    signatures::SchnorrSignature,
+};
+use tari_utilities::{chacha20, ByteArray, ByteArrayError};
 
 /// Represents a single message frame.
 pub type Frame = Vec<u8>;
@@ -45,6 +59,10 @@ pub enum MessageError {
     BinarySerializeError,
     /// An error occurred deserialising binary data into an object
     BinaryDeserializeError,
+    /// The shared secret used to encrypt or decrypt a message envelope body could not be derived
+    SharedSecretSerializationError(ByteArrayError),
+    /// The message envelope body was not encrypted for this node, or it has been corrupted
+    DecryptionFailed,
 }
 
 bitflags! {
@@ -68,10 +86,13 @@ pub struct MessageEnvelopeHeader<PubKey> {
     pub dest: NodeDestination<PubKey>,
     pub signature: Vec<u8>,
     pub flags: IdentityFlags,
+    /// The number of times the message may still be forwarded. The signature does not cover the header, so forwarding
+    /// nodes decrement it and discard the message when it reaches zero.
+    pub ttl: u8,
 }
 
 impl<PubKey: PublicKey> MessageEnvelopeHeader<PubKey> {
-    /// Construct a new MessageEnvelopeHeader from its member variables
+    /// Construct a new MessageEnvelopeHeader from its member variables, with the default time to live
     pub fn new(
         version: u8,
         source: PubKey,
@@ -86,9 +107,16 @@ impl<PubKey: PublicKey> MessageEnvelopeHeader<PubKey> {
             dest,
             signature,
             flags,
+            ttl: DEFAULT_MESSAGE_TTL,
         }
     }
 
+    /// Set the number of times the message may be forwarded
+    pub fn with_ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
     /// Serialize a MessageEnvelopeHeader into a single frame
     pub fn to_frame(&self) -> Result<Frame, MessageError> {
         let mut buf: Vec<u8> = Vec::new();
@@ -97,6 +125,22 @@ impl<PubKey: PublicKey> MessageEnvelopeHeader<PubKey> {
             Err(_) => Err(MessageError::SerializeFailed),
         }
     }
+
+    /// Verify that the signature in the header was made by the source of the message over the message envelope body
+    pub fn verify_signature(&self, message_envelope_body: &Frame) -> bool
+    where
+        PubKey::K: DeserializeOwned,
+        for<'a, 'b> &'a PubKey::K: Mul<&'b PubKey, Output = PubKey>,
+        for<'a> &'a PubKey: Add<PubKey, Output = PubKey>,
+    {
+        let mut de = rmp_serde::Deserializer::new(self.signature.as_slice());
+        let signature = match SchnorrSignature::<PubKey, PubKey::K>::deserialize(&mut de) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        let challenge = Challenge::new().chain(message_envelope_body).result().to_vec();
+        signature.verify_challenge(&self.source, &challenge)
+    }
 }
 
 impl<PubKey: PublicKey> TryFrom<Frame> for MessageEnvelopeHeader<PubKey> {
@@ -112,10 +156,119 @@ impl<PubKey: PublicKey> TryFrom<Frame> for MessageEnvelopeHeader<PubKey> {
     }
 }
 
+/// The size in bytes of the ChaCha20 nonce that precedes the ciphertext of an encrypted message envelope body
+const ENVELOPE_BODY_NONCE_SIZE: usize = 12;
+/// The size in bytes of the HMAC-SHA256 tag that follows the nonce of an encrypted message envelope body
+const ENVELOPE_BODY_TAG_SIZE: usize = 32;
+/// The domain of the ChaCha20 key derived from the ECDH shared secret
+const ENVELOPE_CIPHER_KEY_DOMAIN: &[u8] = b"comms.envelope_body.cipher_key";
+/// The domain of the HMAC key derived from the ECDH shared secret
+const ENVELOPE_MAC_KEY_DOMAIN: &[u8] = b"comms.envelope_body.mac_key";
+
+/// Encrypt a message envelope body for the node with the given public key. Separate ChaCha20 and HMAC-SHA256 keys are
+/// derived from the ECDH shared secret of the two nodes. The same keys are used for every message between the two
+/// nodes, so each body is encrypted with a random nonce. The encrypted body is `nonce | tag | ciphertext`, where the
+/// tag authenticates the nonce and ciphertext so that the recipient can tell whether the body was encrypted for it and
+/// has not been changed since.
+pub fn encrypt_envelope_body<PubKey, SecKey, R>(
+    secret_key: &SecKey,
+    public_key: &PubKey,
+    message_envelope_body: &Frame,
+    rng: &mut R,
+) -> Result<Frame, MessageError>
+where
+    PubKey: PublicKey<K = SecKey> + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>,
+    SecKey: SecretKey,
+    R: RngCore + CryptoRng,
+{
+    let shared_secret = shared_secret_key(secret_key, public_key)?;
+    let mut nonce_bytes = [0u8; ENVELOPE_BODY_NONCE_SIZE];
+    rng.fill_bytes(&mut nonce_bytes);
+    let ciphertext = chacha20::encode_with_nonce(
+        message_envelope_body,
+        &derive_key(ENVELOPE_CIPHER_KEY_DOMAIN, &shared_secret),
+        &nonce_from_bytes(&nonce_bytes),
+    );
+    let tag = envelope_body_mac(&shared_secret, &nonce_bytes, &ciphertext)
+        .result()
+        .code();
+    let mut encrypted_body = nonce_bytes.to_vec();
+    encrypted_body.extend_from_slice(&tag);
+    encrypted_body.extend_from_slice(&ciphertext);
+    Ok(encrypted_body)
+}
+
+/// Decrypt a message envelope body that was encrypted with `encrypt_envelope_body` by the node with the given public
+/// key. Returns `DecryptionFailed` if the body was not encrypted for this node.
+pub fn decrypt_envelope_body<PubKey, SecKey>(
+    secret_key: &SecKey,
+    public_key: &PubKey,
+    message_envelope_body: &Frame,
+) -> Result<Frame, MessageError>
+where
+    PubKey: PublicKey<K = SecKey> + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>,
+    SecKey: SecretKey,
+{
+    if message_envelope_body.len() < ENVELOPE_BODY_NONCE_SIZE + ENVELOPE_BODY_TAG_SIZE {
+        return Err(MessageError::DecryptionFailed);
+    }
+    let shared_secret = shared_secret_key(secret_key, public_key)?;
+    let (nonce_bytes, rest) = message_envelope_body.split_at(ENVELOPE_BODY_NONCE_SIZE);
+    let (tag, ciphertext) = rest.split_at(ENVELOPE_BODY_TAG_SIZE);
+    if envelope_body_mac(&shared_secret, nonce_bytes, ciphertext)
+        .verify(tag)
+        .is_err()
+    {
+        return Err(MessageError::DecryptionFailed);
+    }
+    Ok(chacha20::decode_with_nonce(
+        ciphertext,
+        &derive_key(ENVELOPE_CIPHER_KEY_DOMAIN, &shared_secret),
+        &nonce_from_bytes(nonce_bytes),
+    ))
+}
+
+/// The HMAC-SHA256 over the nonce and ciphertext of an encrypted message envelope body
+fn envelope_body_mac(shared_secret: &[u8; 32], nonce: &[u8], ciphertext: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(&derive_key(ENVELOPE_MAC_KEY_DOMAIN, shared_secret))
+        .expect("HMAC accepts keys of any length");
+    mac.input(nonce);
+    mac.input(ciphertext);
+    mac
+}
+
+/// Derive a key for a single purpose from the ECDH shared secret
+fn derive_key(domain: &[u8], shared_secret: &[u8; 32]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&Challenge::new().chain(domain).chain(shared_secret).result());
+    key
+}
+
+/// The ECDH shared secret of the node with the secret key and the node with the public key
+fn shared_secret_key<PubKey, SecKey>(secret_key: &SecKey, public_key: &PubKey) -> Result<[u8; 32], MessageError>
+where
+    PubKey: PublicKey<K = SecKey> + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>,
+    SecKey: SecretKey,
+{
+    let shared_secret = PubKey::shared_secret(secret_key, public_key).to_vec();
+    ByteArray::from_bytes(&shared_secret).map_err(MessageError::SharedSecretSerializationError)
+}
+
+/// Read a ChaCha20 nonce from its little-endian byte representation
+fn nonce_from_bytes(bytes: &[u8]) -> [u32; 3] {
+    let mut nonce = [0u32; 3];
+    for (i, n) in nonce.iter_mut().enumerate() {
+        let mut word = [0u8; 4];
+        word.copy_from_slice(&bytes[i * 4..i * 4 + 4]);
+        *n = u32::from_le_bytes(word);
+    }
+    nonce
+}
+
 const FRAMES_PER_MESSAGE: usize = 3;
 
 /// Represents a message which is about to go on or has just come off the wire.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct MessageEnvelope {
     frames: FrameSet,
 }
@@ -217,6 +370,7 @@ mod test {
             dest,
             signature,
             flags,
+            ttl: DEFAULT_MESSAGE_TTL,
         };
 
         let mut buf = Vec::new();
@@ -226,4 +380,58 @@ mod test {
         let deserialized: MessageEnvelopeHeader<RistrettoPublicKey> = Deserialize::deserialize(&mut de).unwrap();
         assert_eq!(deserialized, header);
     }
+
+    #[test]
+    fn test_encrypt_decrypt_envelope_body() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let (source_sk, source_pk) = RistrettoPublicKey::random_keypair(&mut rng);
+        let (dest_sk, dest_pk) = RistrettoPublicKey::random_keypair(&mut rng);
+        let (other_sk, _) = RistrettoPublicKey::random_keypair(&mut rng);
+        let body: Frame = vec![1, 2, 3, 4, 5];
+
+        let encrypted = encrypt_envelope_body(&source_sk, &dest_pk, &body, &mut rng).unwrap();
+        assert_ne!(encrypted, body);
+        assert_eq!(decrypt_envelope_body(&dest_sk, &source_pk, &encrypted).unwrap(), body);
+        // The same body is encrypted differently every time, as each message has its own nonce
+        let reencrypted = encrypt_envelope_body(&source_sk, &dest_pk, &body, &mut rng).unwrap();
+        assert_ne!(reencrypted, encrypted);
+        assert_eq!(decrypt_envelope_body(&dest_sk, &source_pk, &reencrypted).unwrap(), body);
+        // Only the destination can decrypt the body
+        match decrypt_envelope_body(&other_sk, &source_pk, &encrypted) {
+            Err(MessageError::DecryptionFailed) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        // A truncated body can't be decrypted
+        assert!(decrypt_envelope_body(&dest_sk, &source_pk, &encrypted[..10].to_vec()).is_err());
+        // A body that was changed after it was encrypted is rejected
+        let mut tampered = encrypted.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        match decrypt_envelope_body(&dest_sk, &source_pk, &tampered) {
+            Err(MessageError::DecryptionFailed) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let (sk, pk) = RistrettoPublicKey::random_keypair(&mut rng);
+        let body: Frame = vec![1, 2, 3];
+        let challenge = Challenge::digest(&body).to_vec();
+        let signature = SchnorrSignature::<RistrettoPublicKey, RistrettoSecretKey>::sign(
+            sk,
+            RistrettoSecretKey::random(&mut rng),
+            &challenge,
+        )
+        .unwrap();
+        let mut buf = Vec::new();
+        signature.serialize(&mut rmp_serde::Serializer::new(&mut buf)).unwrap();
+        let header = MessageEnvelopeHeader::new(0, pk, NodeDestination::Unknown, buf, IdentityFlags::empty());
+        assert!(header.verify_signature(&body));
+        assert!(!header.verify_signature(&vec![1, 2, 4]));
+        let mut invalid_header = header.clone();
+        invalid_header.signature = vec![0];
+        assert!(!invalid_header.verify_signature(&body));
+    }
 }
@@ -36,7 +36,7 @@ pub use self::{
     error::ConnectionError,
     message::MessageError,
     net_address::{NetAddress, NetAddressError},
-    peer_connection::{PeerConnection, PeerConnectionContextBuilder, PeerConnectionError},
+    peer_connection::{ConnectionFilter, PeerConnection, PeerConnectionContextBuilder, PeerConnectionError},
     types::*,
     zmq::{curve_keypair, Context, CurveEncryption, InprocAddress},
 };
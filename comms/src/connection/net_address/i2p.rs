@@ -23,9 +23,10 @@
 use std::{fmt, str::FromStr};
 
 use super::{parser::AddressParser, NetAddressError};
+use serde_derive::{Deserialize, Serialize};
 
 /// Represents an I2P address
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
 pub struct I2PAddress {
     pub name: String,
 }
@@ -1,5 +1,6 @@
 use crate::connection::NetAddress;
 use chrono::prelude::*;
+use serde_derive::{Deserialize, Serialize};
 use std::{
     cmp::{Ord, Ordering},
     time::Duration,
@@ -7,7 +8,7 @@ use std::{
 
 const MAX_LATENCY_SAMPLE_COUNT: u32 = 100;
 
-#[derive(Debug, Eq)]
+#[derive(Clone, Debug, Eq, Deserialize, Serialize)]
 pub struct NetAddressWithStats {
     pub net_address: NetAddress,
     pub last_seen: Option<DateTime<Utc>>,
@@ -3,12 +3,13 @@ use crate::connection::{
     NetAddress,
 };
 use chrono::prelude::*;
+use serde_derive::{Deserialize, Serialize};
 use std::time::Duration;
 
 const MAX_CONNECTION_ATTEMPTS: u32 = 3;
 
 /// This struct is used to store a set of different net addresses such as IPv4, IPv6, Tor or I2P for a single peer.
-#[derive(Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct NetAddresses {
     addresses: Vec<NetAddressWithStats>,
 }
@@ -29,6 +30,14 @@ impl NetAddresses {
         Err(NetAddressError::AddressNotFound)
     }
 
+    /// The net addresses in the set, without their usage stats
+    pub fn net_addresses(&self) -> Vec<NetAddress> {
+        self.addresses
+            .iter()
+            .map(|address| address.net_address.clone())
+            .collect()
+    }
+
     /// Provides the date and time of the last successful communication with this peer
     pub fn last_seen(&self) -> Option<DateTime<Utc>> {
         let mut latest_valid_datetime: Option<DateTime<Utc>> = None;
@@ -111,6 +120,18 @@ impl NetAddresses {
         Ok(())
     }
 
+    /// Mark that a rejected message was received from the peer when the net address it arrived on is not known. The
+    /// rejection counts against the net address that the peer was most recently seen on.
+    pub fn message_rejected_from_peer(&mut self) -> Result<(), NetAddressError> {
+        let updatable_address = self
+            .addresses
+            .iter_mut()
+            .max_by_key(|address| address.last_seen)
+            .ok_or(NetAddressError::NoValidAddresses)?;
+        updatable_address.message_rejected();
+        Ok(())
+    }
+
     /// Mark that a successful connection was established with the specified net address
     pub fn successful_connection_attempt(&mut self, address: &NetAddress) -> Result<(), NetAddressError> {
         let updatable_address = self.find_address_mut(address)?;
@@ -118,6 +139,14 @@ impl NetAddresses {
         Ok(())
     }
 
+    /// Reset the failed connection attempts of every net address, e.g. when the peer has been heard from after it was
+    /// found to be unreachable
+    pub fn reset_connection_attempts(&mut self) {
+        for address in &mut self.addresses {
+            address.connection_attempts = 0;
+        }
+    }
+
     /// Mark that a connection could not be established with the specified net address
     pub fn failed_connection_attempt(&mut self, address: &NetAddress) -> Result<(), NetAddressError> {
         let updatable_address = self.find_address_mut(address)?;
@@ -175,6 +204,11 @@ mod test {
         assert_eq!(net_addresses.addresses[0].net_address, net_address1);
         assert_eq!(net_addresses.addresses[1].net_address, net_address2);
         assert_eq!(net_addresses.addresses[2].net_address, net_address3);
+        assert_eq!(net_addresses.net_addresses(), vec![
+            net_address1,
+            net_address2,
+            net_address3
+        ]);
     }
 
     #[test]
@@ -214,6 +248,9 @@ mod test {
             assert!(net_addresses.failed_connection_attempt(&net_address3).is_ok());
         }
         assert!(net_addresses.get_best_net_address().is_err());
+
+        net_addresses.reset_connection_attempts();
+        assert!(net_addresses.get_best_net_address().is_ok());
     }
 
     #[test]
@@ -246,6 +283,10 @@ mod test {
         assert_eq!(net_addresses.addresses[0].rejected_message_count, 0);
         assert_eq!(net_addresses.addresses[1].rejected_message_count, 1);
         assert_eq!(net_addresses.addresses[2].rejected_message_count, 2);
+        // Without a known net address, a rejection counts against the most recently seen net address
+        assert!(net_addresses.message_rejected_from_peer().is_ok());
+        assert_eq!(net_addresses.addresses[2].rejected_message_count, 3);
+        assert!(NetAddresses::new(Vec::new()).message_rejected_from_peer().is_err());
 
         assert!(net_addresses.failed_connection_attempt(&net_address1).is_ok());
         assert!(net_addresses.failed_connection_attempt(&net_address2).is_ok());
@@ -20,9 +20,12 @@
 //  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use std::convert::{TryFrom, TryInto};
+use std::{
+    convert::{TryFrom, TryInto},
+    sync::Arc,
+};
 
-use super::{ConnectionId, PeerConnectionError};
+use super::{ConnectionFilter, ConnectionId, PeerConnectionError};
 
 use crate::connection::{
     net_address::ip::SocketAddress,
@@ -61,6 +64,7 @@ pub struct PeerConnectionContext {
     pub(crate) max_msg_size: u64,
     pub(crate) max_retry_attempts: u16,
     pub(crate) socks_address: Option<SocketAddress>,
+    pub(crate) connection_filter: Option<Arc<dyn ConnectionFilter>>,
 }
 
 impl<'a> TryFrom<PeerConnectionContextBuilder<'a>> for PeerConnectionContext {
@@ -79,8 +83,10 @@ impl<'a> TryFrom<PeerConnectionContextBuilder<'a>> for PeerConnectionContext {
         let max_retry_attempts = builder.max_retry_attempts.unwrap_or(DEFAULT_MAX_RETRY_ATTEMPTS);
         let peer_address = unwrap_prop(builder.address, "peer_address")?;
         let socks_address = builder.socks_address;
+        let connection_filter = builder.connection_filter;
 
         Ok(PeerConnectionContext {
+            connection_filter,
             consumer_address,
             context,
             curve_encryption,
@@ -143,6 +149,7 @@ pub struct PeerConnectionContextBuilder<'a> {
     pub(super) max_msg_size: Option<u64>,
     pub(super) max_retry_attempts: Option<u16>,
     pub(super) socks_address: Option<SocketAddress>,
+    pub(super) connection_filter: Option<Arc<dyn ConnectionFilter>>,
 }
 
 macro_rules! setter {
@@ -169,6 +176,8 @@ impl<'a> PeerConnectionContextBuilder<'a> {
 
     setter!(set_socks_proxy, socks_address, SocketAddress);
 
+    setter!(set_connection_filter, connection_filter, Arc<dyn ConnectionFilter>);
+
     /// Return a new PeerConnectionContextBuilder
     pub fn new() -> Self {
         Default::default()
@@ -41,6 +41,8 @@ pub enum PeerConnectionError {
     ShutdownError,
     /// Failed to establish a connection
     ConnectFailed,
+    /// The inbound connection was refused by the connection filter
+    ConnectionRefused,
     #[error(msg_embedded, non_std, no_from)]
     UnexpectedConnectionError(String),
 }
@@ -43,7 +43,9 @@
 /// 3. `control` - Contains the control messages which can be sent from the [PeerConnection] to
 ///                the [Worker], as well as a thin wrapper around [std::sync::mpsc::Sender].
 /// 4. `error` - Contains [PeerConnectionError]
-/// 5. `worker` - Where all the work is done. Contains the code responsible for establishing
+/// 5. `filter` - Contains the [ConnectionFilter] trait, which decides whether an inbound
+///               connection is accepted.
+/// 6. `worker` - Where all the work is done. Contains the code responsible for establishing
 ///               connections (peer and consumer), receiving messages to forward to the
 ///               consumer connection, updating the peer connection state from socket events
 ///               and receiving control messages and acting on them.
@@ -85,6 +87,7 @@ mod connection;
 mod context;
 mod control;
 mod error;
+mod filter;
 mod worker;
 
 pub type ConnectionId = super::message::Frame;
@@ -93,4 +96,5 @@ pub use self::{
     connection::PeerConnection,
     context::{PeerConnectionContext, PeerConnectionContextBuilder},
     error::PeerConnectionError,
+    filter::ConnectionFilter,
 };
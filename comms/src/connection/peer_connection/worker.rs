@@ -21,6 +21,7 @@
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use std::{
+    net::IpAddr,
     sync::{
         mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender},
         Arc,
@@ -241,7 +242,12 @@ impl Worker {
     /// Forwards frames from the source to the sink
     fn forward_frames(&mut self, frontend: &EstablishedConnection, backend: &EstablishedConnection) -> Result<()> {
         let context = &self.context;
-        if let Some(frames) = try_recv!(frontend.receive(10)) {
+        let received = match (&context.direction, &self.identity) {
+            // The remote address of an inbound connection is read from the first message of the peer
+            (Direction::Inbound, None) => try_recv!(frontend.receive_with_remote_address(10)),
+            _ => try_recv!(frontend.receive(10)).map(|frames| (frames, None)),
+        };
+        if let Some((frames, remote_address)) = received {
             match context.direction {
                 // For a ROUTER backend, the first frame is the identity
                 Direction::Inbound => match self.identity {
@@ -251,6 +257,7 @@ impl Worker {
                         }
                     },
                     None => {
+                        self.check_connection_filter(remote_address.as_ref())?;
                         self.identity = Some(frames[0].clone());
                     },
                 },
@@ -307,6 +314,18 @@ impl Worker {
         }
     }
 
+    /// Refuse an inbound connection that is not allowed by the connection filter from the remote address that the peer
+    /// connected from
+    fn check_connection_filter(&self, remote_address: Option<&IpAddr>) -> Result<()> {
+        let context = &self.context;
+        match &context.connection_filter {
+            Some(filter) if !filter.allow_connection(&context.id, remote_address) => {
+                Err(PeerConnectionError::ConnectionRefused.into())
+            },
+            _ => Ok(()),
+        }
+    }
+
     /// Establish the connection to the peer address
     fn establish_peer_connection(&self) -> Result<EstablishedConnection> {
         let context = &self.context;
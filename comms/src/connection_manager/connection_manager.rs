@@ -0,0 +1,148 @@
+pub mod connection;
+pub mod inbound_message_service;
+pub mod outbound_message_service;
+pub mod peer_manager;
+pub mod store_and_forward;
+pub mod types;
+
+use crate::{
+    connection::{
+        connection::EstablishedConnection,
+        message::FrameSet,
+        types::SocketType,
+        zmq::{Context, InprocAddress, ZmqEndpoint},
+        Connection,
+        ConnectionError,
+        Direction,
+        NetAddress,
+        NetAddressError,
+    },
+    peer_manager::{
+        manager::{PeerManager, PeerManagerError},
+        node_id::NodeId,
+    },
+};
+use derive_error::Error;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread,
+};
+use tari_crypto::keys::PublicKey;
+use tari_storage::{hashmap_store::HashmapStore, keyvalue_store::DataStore};
+
+/// The time, in milliseconds, that the listener waits for a message from a peer before it checks its connections
+const LISTENER_RECEIVE_TIMEOUT_MS: u32 = 100;
+/// The time, in milliseconds, that the listener waits for the inbound message service to accept a message
+const INBOUND_REPLY_TIMEOUT_MS: u32 = 2000;
+
+#[derive(Debug, Error)]
+pub enum ConnectionManagerError {
+    /// A connection could not be established, or a message could not be sent on it
+    ConnectionError(ConnectionError),
+    /// The peer could not be found in the peer manager
+    PeerManagerError(PeerManagerError),
+    /// The peer has no net address that can be connected to
+    NetAddressError(NetAddressError),
+    /// The lock on the peer connections has been poisoned by a thread that panicked while holding it
+    PoisonedAccess,
+}
+
+/// The ConnectionManager maintains the network connections of a node. It listens for peers on the listen address and
+/// hands the messages they send to the inbound message service, and it connects to peers, at the best net address the
+/// peer manager holds for them, to send them messages. Connections to peers are kept open and reused.
+///
+/// Connections are not curve encrypted as the curve keys of peers are not known. Message envelopes are signed by their
+/// source, and can be encrypted for their destination.
+pub struct ConnectionManager<PubKey, DS = HashmapStore>
+where PubKey: PublicKey
+{
+    context: Context,
+    peer_manager: Arc<PeerManager<PubKey, DS>>,
+    connections: Mutex<HashMap<NodeId, EstablishedConnection>>,
+}
+
+impl<PubKey, DS> ConnectionManager<PubKey, DS>
+where
+    PubKey: PublicKey,
+    DS: DataStore,
+{
+    /// Construct a ConnectionManager that connects to the peers in the peer manager
+    pub fn new(context: Context, peer_manager: Arc<PeerManager<PubKey, DS>>) -> ConnectionManager<PubKey, DS> {
+        ConnectionManager {
+            context,
+            peer_manager,
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Listen for peers on the listen address, and hand the messages they send to the inbound message service at the
+    /// inbound address. The listen address is bound before this returns, and messages are received in a new thread.
+    /// Messages are identified by the connection they arrived on.
+    pub fn listen(
+        &self,
+        listen_address: &NetAddress,
+        inbound_address: InprocAddress,
+    ) -> Result<(), ConnectionManagerError>
+    {
+        let listener = Connection::new(&self.context, Direction::Inbound).establish(listen_address)?;
+        let context = self.context.clone();
+        thread::spawn(move || {
+            let mut inbound_connection = None;
+            loop {
+                let frames = match listener.receive(LISTENER_RECEIVE_TIMEOUT_MS) {
+                    Ok(frames) => frames,
+                    Err(_) => continue,
+                };
+                // The first frame is the identity of the connection, followed by the frames of the message envelope
+                if frames.len() < 2 {
+                    continue;
+                }
+                let mut message_context_frames = vec![frames[0].clone()];
+                message_context_frames.extend(frames);
+                if inbound_connection.is_none() {
+                    inbound_connection = connect_inbound(&context, &inbound_address);
+                }
+                let delivered = inbound_connection.as_ref().map_or(false, |connection| {
+                    connection.send(&message_context_frames).is_ok() &&
+                        connection.receive(INBOUND_REPLY_TIMEOUT_MS).is_ok()
+                });
+                // A request socket that did not receive its reply can't be used again
+                if !delivered {
+                    inbound_connection = None;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Send the frames to the peer with the node id, connecting to the peer first if there is no connection to it.
+    /// A connection that fails to send is closed, so that the next message to the peer connects again.
+    pub fn send_to(&self, node_id: &NodeId, frames: &FrameSet) -> Result<(), ConnectionManagerError> {
+        let mut connections = self
+            .connections
+            .lock()
+            .map_err(|_| ConnectionManagerError::PoisonedAccess)?;
+        if !connections.contains_key(node_id) {
+            let net_address = self
+                .peer_manager
+                .find_with_node_id(node_id)?
+                .addresses
+                .get_best_net_address()?;
+            let connection = Connection::new(&self.context, Direction::Outbound).establish(&net_address)?;
+            connections.insert(node_id.clone(), connection);
+        }
+        let result = connections[node_id].send(frames);
+        if result.is_err() {
+            connections.remove(node_id);
+        }
+        Ok(result?)
+    }
+}
+
+/// Connect a request socket to the inbound message service
+fn connect_inbound(context: &Context, inbound_address: &InprocAddress) -> Option<EstablishedConnection> {
+    let socket = context.socket(SocketType::Request).ok()?;
+    socket.connect(&inbound_address.to_zmq_endpoint()).ok()?;
+    Some(EstablishedConnection { socket })
+}
@@ -0,0 +1,8 @@
+pub mod connection;
+pub mod inbound_message_service;
+pub mod outbound_message_service;
+pub mod peer_manager;
+pub mod store_and_forward;
+pub mod types;
+
+pub mod connection_manager;
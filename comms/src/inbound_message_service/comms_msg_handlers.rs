@@ -20,11 +20,30 @@
 //  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use crate::inbound_message_service::{
-    message_context::MessageContext,
-    message_dispatcher::{DispatchError, MessageDispatcher},
+use crate::{
+    connection::message::{decrypt_envelope_body, IdentityFlags, NodeDestination},
+    inbound_message_service::{
+        message_context::MessageContext,
+        message_dispatcher::{DispatchError, MessageDispatcher},
+        message_forwarder::{ForwardError, MessageForwarder},
+    },
+    peer_manager::{
+        ban_list::{BanList, Misbehaviour},
+        manager::PeerManagerError,
+        node_id::NodeId,
+        node_identity::NodeIdentity,
+    },
+    store_and_forward::store_and_forward_service::StoreAndForwardService,
 };
-use tari_crypto::keys::PublicKey;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    convert::TryFrom,
+    ops::{Add, Mul},
+    sync::{Arc, Mutex},
+};
+use tari_crypto::keys::{DiffieHellmanSharedSecret, PublicKey, SecretKey};
+use tari_storage::keyvalue_store::DataStore;
+use tari_utilities::Hashable;
 
 /// The comms_msg_dispatcher will determine the type of message and forward it to the the correct handler
 pub enum CommsDispatchType {
@@ -36,56 +55,387 @@ pub enum CommsDispatchType {
     Discard,
 }
 
-/// Specify what handler function should be called for messages with different comms level dispatch types
-pub fn construct_comms_msg_dispatcher<PubKey: PublicKey>() -> MessageDispatcher<MessageContext<PubKey>> {
-    MessageDispatcher::<MessageContext<PubKey>>::new()
-        .route(CommsDispatchType::Handle as u32, handler_handle)
-        .route(CommsDispatchType::Forward as u32, handler_forward)
-        .route(CommsDispatchType::Discard as u32, handler_discard)
+/// Specify what handler function should be called for messages with different comms level dispatch types. The dispatch
+/// type of messages is determined for the node with the given identity. Messages that must be handled are passed to
+/// the message handler, which is the second dispatch stage that routes messages to the domain services, messages for
+/// other nodes are relayed by the message forwarder and held by the store and forward service when this node is
+/// responsible for the destination, and discarded messages with an invalid signature count against the peer they
+/// arrived from and are reported as misbehaviour of that peer to the ban list.
+pub fn construct_comms_msg_dispatcher<PubKey, SecKey, DS, F>(
+    node_identity: Arc<NodeIdentity<PubKey, SecKey>>,
+    ban_list: Arc<BanList<PubKey, DS>>,
+    message_forwarder: Arc<MessageForwarder<PubKey, SecKey, DS>>,
+    store_and_forward_service: Arc<Mutex<StoreAndForwardService<PubKey, SecKey, DS>>>,
+    message_handler: F,
+) -> MessageDispatcher<MessageContext<PubKey>>
+where
+    PubKey:
+        PublicKey<K = SecKey> + Hashable + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey> + Send + Sync + 'static,
+    SecKey: SecretKey
+        + Mul<PubKey, Output = PubKey>
+        + Mul<Output = SecKey>
+        + Serialize
+        + DeserializeOwned
+        + Send
+        + Sync
+        + 'static,
+    DS: DataStore + Send + Sync + 'static,
+    F: Fn(MessageContext<PubKey>) -> Result<(), DispatchError> + Send + Sync + 'static,
+    for<'a, 'b> &'a SecKey: Mul<&'b PubKey, Output = PubKey>,
+    for<'a> &'a PubKey: Add<PubKey, Output = PubKey>,
+{
+    MessageDispatcher::with_dispatch_type(move |message_context| {
+        determine_comms_msg_dispatch_type(message_context, &node_identity)
+    })
+    .route(CommsDispatchType::Handle as u32, message_handler)
+    .route(CommsDispatchType::Forward as u32, move |message_context| {
+        handler_forward(message_context, &message_forwarder, &store_and_forward_service)
+    })
+    .route(CommsDispatchType::Discard as u32, move |message_context| {
+        handler_discard(message_context, &ban_list)
+    })
 }
 
-/// The dispatch type is determined from the content of the MessageContext, which is used to dispatch the message to the
-/// correct handler
-pub fn determine_comms_msg_dispatch_type<PubKey: PublicKey>(_message_context: &MessageContext<PubKey>) -> u32 {
-    // TODO:
-    // if signature is correct {
-    //     if message_context.message_envelope_header.flags.contains(IdentityFlags::ENCRYPTED)  {
-    //         if msg can be decrypted {
-    //             if msg meant for curr node {
-    //                 DispatchType::Handle
-    //             }
-    //             else {
-    //                 CommsDispatchType::Forward
-    //             }
-    //         }
-    //         else {
-    //             CommsDispatchType::Forward
-    //         }
-    //     }
-    //     else {
-    //         CommsDispatchType::Handle
-    //     }
-    // }
-    // else {
-    //     CommsDispatchType::Discard
-    // }
-    CommsDispatchType::Handle as u32
+/// The dispatch type is determined from the content of the MessageContext and the identity of the node that received
+/// it, which is used to dispatch the message to the correct handler. Messages with an invalid signature are discarded.
+/// Messages destined for this node, or with an unknown destination, are handled if they are unencrypted or can be
+/// decrypted by this node, and all other messages are forwarded.
+pub fn determine_comms_msg_dispatch_type<PubKey, SecKey>(
+    message_context: &MessageContext<PubKey>,
+    node_identity: &NodeIdentity<PubKey, SecKey>,
+) -> u32
+where
+    PubKey: PublicKey<K = SecKey> + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>,
+    SecKey: SecretKey + DeserializeOwned,
+    for<'a, 'b> &'a SecKey: Mul<&'b PubKey, Output = PubKey>,
+    for<'a> &'a PubKey: Add<PubKey, Output = PubKey>,
+{
+    let header = &message_context.message_envelope_header;
+    let body = &message_context.message_envelope_body;
+    if !header.verify_signature(body) {
+        return CommsDispatchType::Discard as u32;
+    }
+    let for_this_node = match &header.dest {
+        NodeDestination::Unknown => true,
+        NodeDestination::PublicKey(public_key) => *public_key == node_identity.public_key,
+        NodeDestination::NodeId(node_id) => *node_id == node_identity.node_id,
+    };
+    let readable = !header.flags.contains(IdentityFlags::ENCRYPTED) ||
+        node_identity.secret_key.as_ref().map_or(false, |secret_key| {
+            decrypt_envelope_body(secret_key, &header.source, body).is_ok()
+        });
+    if for_this_node && readable {
+        CommsDispatchType::Handle as u32
+    } else {
+        CommsDispatchType::Forward as u32
+    }
 }
 
-fn handler_handle<PubKey: PublicKey>(_message_context: MessageContext<PubKey>) -> Result<(), DispatchError> {
-    // TODO: Pass message to DHT and/or Domain Dispatcher
+/// Relay the message towards its destination, and hold it for the destination if this node is one of the closest
+/// known nodes to the destination. Messages that have reached their time to live, have already been forwarded, or
+/// that arrived on a connection that has exceeded its rate limit are dropped by the message forwarder and are not held.
+/// A held message that could not be relayed is delivered when the destination requests its stored messages.
+fn handler_forward<PubKey, SecKey, DS>(
+    message_context: MessageContext<PubKey>,
+    message_forwarder: &MessageForwarder<PubKey, SecKey, DS>,
+    store_and_forward_service: &Mutex<StoreAndForwardService<PubKey, SecKey, DS>>,
+) -> Result<(), DispatchError>
+where
+    PubKey: PublicKey<K = SecKey> + Hashable + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>,
+    SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize,
+    DS: DataStore,
+{
+    let forward_result = message_forwarder.forward(&message_context);
+    let stored = match forward_result {
+        Ok(_) | Err(ForwardError::NoForwardPeers) => store_and_forward_service
+            .lock()
+            .map_err(|_| DispatchError::HandlerError("Store and forward service lock poisoned".to_string()))?
+            .store_forwarded_message(&message_context)
+            .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))?,
+        Err(_) => false,
+    };
+    match forward_result {
+        Ok(_) => Ok(()),
+        Err(_) if stored => Ok(()),
+        Err(e) => Err(DispatchError::HandlerError(format!("{:?}", e))),
+    }
+}
 
-    Ok(())
+/// Discard the message. The source in the header of a message with an invalid signature can't be trusted, as it can
+/// be set to the public key of any node, so the rejected message counts against the peer of the connection that it
+/// arrived on instead, and adds to its misbehaviour score. Inbound peer connections use the node id of the peer as the
+/// connection id; messages that arrived on any other connection are dropped without counting against a peer.
+fn handler_discard<PubKey, DS>(
+    message_context: MessageContext<PubKey>,
+    ban_list: &BanList<PubKey, DS>,
+) -> Result<(), DispatchError>
+where
+    PubKey: PublicKey,
+    DS: DataStore,
+{
+    let node_id = match NodeId::try_from(message_context.connection_id.as_slice()) {
+        Ok(node_id) => node_id,
+        Err(_) => return Ok(()),
+    };
+    match ban_list
+        .peer_manager()
+        .modify_peer(&node_id, |peer| peer.addresses.message_rejected_from_peer())
+    {
+        Ok(result) => result.map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))?,
+        Err(PeerManagerError::PeerNotFoundError) => return Ok(()),
+        Err(e) => return Err(DispatchError::HandlerError(format!("{:?}", e))),
+    }
+    ban_list
+        .report_misbehaviour(&node_id, Misbehaviour::InvalidSignature)
+        .map(|_| ())
+        .map_err(|e| DispatchError::HandlerError(format!("{:?}", e)))
 }
 
-fn handler_forward<PubKey: PublicKey>(_message_context: MessageContext<PubKey>) -> Result<(), DispatchError> {
-    // TODO: Add logic for message forwarding
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        connection::{
+            message::{encrypt_envelope_body, Frame, MessageEnvelopeHeader},
+            net_address::net_addresses::NetAddresses,
+            zmq::{Context, InprocAddress},
+            NetAddress,
+        },
+        inbound_message_service::message_forwarder::ForwardingConfig,
+        outbound_message_service::outbound_message_service::OutboundMessageService,
+        peer_manager::{
+            ban_list::BanListConfig,
+            manager::PeerManager,
+            peer::{Peer, PeerFlags},
+        },
+        store_and_forward::message_store::MessageStoreConfig,
+        types::Challenge,
+    };
+    use digest::Digest;
+    use tari_crypto::{
+        ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+        signatures::SchnorrSignature,
+    };
+    use tari_storage::hashmap_store::HashmapStore;
 
-    Ok(())
-}
+    type TestNodeIdentity = NodeIdentity<RistrettoPublicKey, RistrettoSecretKey>;
+
+    fn create_node_identity(rng: &mut rand::OsRng) -> Arc<TestNodeIdentity> {
+        let (sk, pk) = RistrettoPublicKey::random_keypair(rng);
+        Arc::new(NodeIdentity::new(NodeId::from_key(&pk).unwrap(), pk, Some(sk)))
+    }
+
+    // Construct a message from the source node, arriving on the connection of the source node, that is signed over the
+    // body, which is encrypted for the given public key if there is one
+    fn create_message_context(
+        source: &TestNodeIdentity,
+        dest: NodeDestination<RistrettoPublicKey>,
+        encrypt_for: Option<&RistrettoPublicKey>,
+        rng: &mut rand::OsRng,
+    ) -> MessageContext<RistrettoPublicKey>
+    {
+        let secret_key = source.secret_key.clone().unwrap();
+        let body: Frame = vec![1, 2, 3, 4];
+        let (body, flags) = match encrypt_for {
+            Some(public_key) => (
+                encrypt_envelope_body(&secret_key, public_key, &body, rng).unwrap(),
+                IdentityFlags::ENCRYPTED,
+            ),
+            None => (body, IdentityFlags::empty()),
+        };
+        let challenge = Challenge::digest(&body).to_vec();
+        let signature = SchnorrSignature::<RistrettoPublicKey, RistrettoSecretKey>::sign(
+            secret_key,
+            RistrettoSecretKey::random(rng),
+            &challenge,
+        )
+        .unwrap();
+        let mut buf = Vec::new();
+        signature.serialize(&mut rmp_serde::Serializer::new(&mut buf)).unwrap();
+        let header = MessageEnvelopeHeader::new(0, source.public_key.clone(), dest, buf, flags);
+        MessageContext::new(source.node_id.as_bytes().to_vec(), vec![1], vec![0], None, header, body)
+    }
+
+    fn create_ban_list(node_identity: &TestNodeIdentity) -> Arc<BanList<RistrettoPublicKey, HashmapStore>> {
+        let peer_manager = Arc::new(PeerManager::new(node_identity.node_id.clone(), HashmapStore::new()).unwrap());
+        Arc::new(BanList::new(peer_manager, HashmapStore::new(), BanListConfig::default()).unwrap())
+    }
+
+    fn create_message_dispatcher(
+        node_identity: &Arc<TestNodeIdentity>,
+        ban_list: &Arc<BanList<RistrettoPublicKey, HashmapStore>>,
+    ) -> (
+        MessageDispatcher<MessageContext<RistrettoPublicKey>>,
+        Arc<Mutex<StoreAndForwardService<RistrettoPublicKey, RistrettoSecretKey>>>,
+    ) {
+        let context = Context::new();
+        let peer_manager = ban_list.peer_manager();
+        let message_forwarder = Arc::new(MessageForwarder::new(
+            node_identity.clone(),
+            OutboundMessageService::new(
+                context.clone(),
+                InprocAddress::random(),
+                node_identity.clone(),
+                peer_manager.clone(),
+            ),
+            ForwardingConfig::default(),
+        ));
+        let store_and_forward_service = Arc::new(Mutex::new(StoreAndForwardService::new(
+            node_identity.clone(),
+            MessageStoreConfig::default(),
+            OutboundMessageService::new(
+                context,
+                InprocAddress::random(),
+                node_identity.clone(),
+                peer_manager.clone(),
+            ),
+        )));
+        let message_dispatcher = construct_comms_msg_dispatcher(
+            node_identity.clone(),
+            ban_list.clone(),
+            message_forwarder,
+            store_and_forward_service.clone(),
+            |_| Ok(()),
+        );
+        (message_dispatcher, store_and_forward_service)
+    }
+
+    #[test]
+    fn test_determine_comms_msg_dispatch_type() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let node_identity = create_node_identity(&mut rng);
+        let source_identity = create_node_identity(&mut rng);
+        let other_identity = create_node_identity(&mut rng);
+        let handle = CommsDispatchType::Handle as u32;
+        let forward = CommsDispatchType::Forward as u32;
+        let mut dispatch_type = |dest: NodeDestination<RistrettoPublicKey>,
+                                 encrypt_for: Option<&RistrettoPublicKey>| {
+            let message_context = create_message_context(&source_identity, dest, encrypt_for, &mut rng);
+            determine_comms_msg_dispatch_type(&message_context, &node_identity)
+        };
+
+        // Encrypted messages are handled if this node can decrypt them and they are destined for it
+        let node_id_dest = NodeDestination::NodeId(node_identity.node_id.clone());
+        let public_key_dest = NodeDestination::PublicKey(node_identity.public_key.clone());
+        assert_eq!(dispatch_type(node_id_dest, Some(&node_identity.public_key)), handle);
+        assert_eq!(dispatch_type(public_key_dest, Some(&node_identity.public_key)), handle);
+        assert_eq!(
+            dispatch_type(NodeDestination::Unknown, Some(&node_identity.public_key)),
+            handle
+        );
+        let other_dest = NodeDestination::NodeId(other_identity.node_id.clone());
+        assert_eq!(
+            dispatch_type(other_dest.clone(), Some(&other_identity.public_key)),
+            forward
+        );
+        assert_eq!(
+            dispatch_type(NodeDestination::Unknown, Some(&other_identity.public_key)),
+            forward
+        );
+
+        // Unencrypted messages are handled unless they are destined for another node
+        assert_eq!(dispatch_type(NodeDestination::Unknown, None), handle);
+        assert_eq!(dispatch_type(other_dest, None), forward);
+    }
+
+    #[test]
+    fn test_discard_invalid_signature() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let node_identity = create_node_identity(&mut rng);
+        let source_identity = create_node_identity(&mut rng);
+        let other_identity = create_node_identity(&mut rng);
+        let address = "127.0.0.1:9000".parse::<NetAddress>().unwrap();
+        let ban_list = create_ban_list(&node_identity);
+        let peer_manager = ban_list.peer_manager();
+        for identity in &[&source_identity, &other_identity] {
+            peer_manager
+                .add_peer(Peer::new(
+                    identity.public_key.clone(),
+                    identity.node_id.clone(),
+                    NetAddresses::from(address.clone()),
+                    PeerFlags::default(),
+                ))
+                .unwrap();
+        }
+        let invalid_signature_score = Misbehaviour::InvalidSignature.score();
+        let rejected_message_count = |node_id: &NodeId| {
+            let mut peer = peer_manager.find_with_node_id(node_id).unwrap();
+            peer.addresses
+                .find_address_mut(&address)
+                .unwrap()
+                .rejected_message_count
+        };
+        let (message_dispatcher, _) = create_message_dispatcher(&node_identity, &ban_list);
+
+        // A message with a valid signature is not rejected
+        let message_context = create_message_context(&source_identity, NodeDestination::Unknown, None, &mut rng);
+        assert!(message_dispatcher.dispatch(message_context.clone()).is_ok());
+        assert_eq!(rejected_message_count(&source_identity.node_id), 0);
+        assert_eq!(ban_list.misbehaviour_score(&source_identity.node_id).unwrap(), 0);
+
+        // A message that was changed after it was signed is discarded, and counts against the peer it arrived from
+        let mut tampered_message_context = message_context;
+        tampered_message_context.message_envelope_body.push(5);
+        assert_eq!(
+            determine_comms_msg_dispatch_type(&tampered_message_context, &node_identity),
+            CommsDispatchType::Discard as u32
+        );
+        assert!(message_dispatcher.dispatch(tampered_message_context.clone()).is_ok());
+        assert_eq!(rejected_message_count(&source_identity.node_id), 1);
+        assert_eq!(
+            ban_list.misbehaviour_score(&source_identity.node_id).unwrap(),
+            invalid_signature_score
+        );
+
+        // A peer that relays a message with an invalid signature is charged for it, not the source named in the header
+        tampered_message_context.connection_id = other_identity.node_id.as_bytes().to_vec();
+        assert!(message_dispatcher.dispatch(tampered_message_context.clone()).is_ok());
+        assert_eq!(rejected_message_count(&other_identity.node_id), 1);
+        assert_eq!(rejected_message_count(&source_identity.node_id), 1);
+        assert_eq!(
+            ban_list.misbehaviour_score(&other_identity.node_id).unwrap(),
+            invalid_signature_score
+        );
+        assert_eq!(
+            ban_list.misbehaviour_score(&source_identity.node_id).unwrap(),
+            invalid_signature_score
+        );
+
+        // Messages on connections that don't belong to a known peer are dropped without counting against any peer
+        tampered_message_context.connection_id = vec![1, 2, 3];
+        assert!(message_dispatcher.dispatch(tampered_message_context).is_ok());
+        assert_eq!(rejected_message_count(&other_identity.node_id), 1);
+        assert_eq!(rejected_message_count(&source_identity.node_id), 1);
+    }
+
+    #[test]
+    fn test_forward_stores_message() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let node_identity = create_node_identity(&mut rng);
+        let source_identity = create_node_identity(&mut rng);
+        let dest_identity = create_node_identity(&mut rng);
+        let ban_list = create_ban_list(&node_identity);
+        let (message_dispatcher, store_and_forward_service) = create_message_dispatcher(&node_identity, &ban_list);
+        let stored_count = || store_and_forward_service.lock().unwrap().message_store().len();
+
+        // An encrypted message for an offline destination is held when there are no peers to forward it to
+        let dest = NodeDestination::NodeId(dest_identity.node_id.clone());
+        let message_context = create_message_context(
+            &source_identity,
+            dest.clone(),
+            Some(&dest_identity.public_key),
+            &mut rng,
+        );
+        assert!(message_dispatcher.dispatch(message_context.clone()).is_ok());
+        assert_eq!(stored_count(), 1);
 
-fn handler_discard<PubKey: PublicKey>(_message_context: MessageContext<PubKey>) -> Result<(), DispatchError> {
-    // TODO: Add logic for discarding a message
+        // A message that has already been forwarded is not held again
+        assert!(message_dispatcher.dispatch(message_context).is_err());
+        assert_eq!(stored_count(), 1);
 
-    Ok(())
+        // Unencrypted messages are not held
+        let message_context = create_message_context(&source_identity, dest, None, &mut rng);
+        assert!(message_dispatcher.dispatch(message_context).is_err());
+        assert_eq!(stored_count(), 1);
+    }
 }
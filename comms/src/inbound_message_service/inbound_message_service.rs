@@ -105,6 +105,7 @@ mod test {
             SocketType,
         },
         inbound_message_service::{comms_msg_handlers::*, message_dispatcher::DispatchError},
+        types::DEFAULT_MESSAGE_TTL,
     };
     use std::thread::ThreadId;
     use tari_crypto::{
@@ -136,7 +137,10 @@ mod test {
             }
             Ok(())
         }
-        let message_dispatcher = MessageDispatcher::<MessageContext<RistrettoPublicKey>>::new()
+        let message_dispatcher =
+            MessageDispatcher::<MessageContext<RistrettoPublicKey>>::with_dispatch_type(|_message_context| {
+                CommsDispatchType::Handle as u32
+            })
             .route(CommsDispatchType::Handle as u32, test_fn)
             .route(CommsDispatchType::Forward as u32, test_fn)
             .route(CommsDispatchType::Discard as u32, test_fn);
@@ -162,6 +166,7 @@ mod test {
             dest,
             signature: vec![0],
             flags: IdentityFlags::ENCRYPTED,
+            ttl: DEFAULT_MESSAGE_TTL,
         };
         let message_envelope_body: Vec<u8> = vec![11, 12, 13, 14, 15];
         let message_context = MessageContext::<RistrettoPublicKey>::new(
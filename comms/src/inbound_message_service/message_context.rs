@@ -20,13 +20,7 @@
 //  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use crate::{
-    connection::message::{FrameSet, MessageEnvelopeHeader, MessageError},
-    inbound_message_service::{
-        comms_msg_handlers::determine_comms_msg_dispatch_type,
-        message_dispatcher::Dispatchable,
-    },
-};
+use crate::connection::message::{FrameSet, MessageEnvelopeHeader, MessageError};
 use serde_derive::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use tari_crypto::keys::PublicKey;
@@ -99,16 +93,13 @@ impl<PubKey: PublicKey> TryFrom<FrameSet> for MessageContext<PubKey> {
     }
 }
 
-impl<PubKey: PublicKey> Dispatchable for MessageContext<PubKey> {
-    fn dispatch_type(&self) -> u32 {
-        determine_comms_msg_dispatch_type(&self)
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::connection::message::{IdentityFlags, MessageEnvelopeHeader, NodeDestination};
+    use crate::{
+        connection::message::{IdentityFlags, MessageEnvelopeHeader, NodeDestination},
+        types::DEFAULT_MESSAGE_TTL,
+    };
     use tari_crypto::{
         keys::{PublicKey, SecretKey},
         ristretto::{RistrettoPublicKey, RistrettoSecretKey},
@@ -128,6 +119,7 @@ mod test {
             dest,
             signature: vec![0],
             flags: IdentityFlags::ENCRYPTED,
+            ttl: DEFAULT_MESSAGE_TTL,
         };
         let message_envelope_body: Vec<u8> = vec![11, 12, 13, 14, 15];
         let desired_message_context = MessageContext::<RistrettoPublicKey>::new(
@@ -21,12 +21,15 @@
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use derive_error::Error;
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, sync::Arc};
 
 #[derive(Debug, Error)]
 pub enum DispatchError {
     /// A dispatch route was not defined for the specific message type
     MessageHandlerUndefined,
+    /// The handler could not process the message
+    #[error(msg_embedded, no_from, non_std)]
+    HandlerError(String),
 }
 
 /// Each message struct that will be dispatched should implement Dispatchable. The dispatch_type function is used by the
@@ -36,34 +39,68 @@ pub trait Dispatchable {
     fn dispatch_type(&self) -> u32;
 }
 
-/// Format required of handler functions specified by dispatch routes
-type HandlerFunctionFormat<DispMsg> = fn(msg_data: DispMsg) -> Result<(), DispatchError>;
+/// Format required of handler functions specified by dispatch routes. Handlers can be plain functions or closures that
+/// capture the state they need, e.g. a service behind an `Arc<Mutex<_>>`.
+type HandlerFunctionFormat<DispMsg> = Arc<dyn Fn(DispMsg) -> Result<(), DispatchError> + Send + Sync>;
+
+/// Format required of the function that determines the dispatch type of a message
+type DispatchTypeFunctionFormat<DispMsg> = Arc<dyn Fn(&DispMsg) -> u32 + Send + Sync>;
 
-#[derive(Clone, Debug)]
 pub struct MessageDispatcher<DispMsg> {
+    dispatch_type_function: DispatchTypeFunctionFormat<DispMsg>,
     handlers: HashMap<u32, HandlerFunctionFormat<DispMsg>>,
 }
 
+impl<DispMsg> Clone for MessageDispatcher<DispMsg> {
+    fn clone(&self) -> Self {
+        MessageDispatcher {
+            dispatch_type_function: self.dispatch_type_function.clone(),
+            handlers: self.handlers.clone(),
+        }
+    }
+}
+
+impl<DispMsg> fmt::Debug for MessageDispatcher<DispMsg> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut routes: Vec<&u32> = self.handlers.keys().collect();
+        routes.sort();
+        f.debug_struct("MessageDispatcher").field("routes", &routes).finish()
+    }
+}
+
 impl<DispMsg> MessageDispatcher<DispMsg>
-where DispMsg: Dispatchable
+where DispMsg: Dispatchable + 'static
 {
-    /// Construct a new MessageDispatcher with no defined dispatch routes
+    /// Construct a new MessageDispatcher with no defined dispatch routes, that uses the Dispatchable dispatch_type of
+    /// messages to select their handler
     pub fn new() -> MessageDispatcher<DispMsg> {
+        MessageDispatcher::with_dispatch_type(|msg_data: &DispMsg| msg_data.dispatch_type())
+    }
+}
+
+impl<DispMsg> MessageDispatcher<DispMsg> {
+    /// Construct a new MessageDispatcher with no defined dispatch routes, that uses the dispatch_type_function to
+    /// determine the dispatch type of messages. This allows the dispatch type to depend on state that is not part of
+    /// the message, such as the identity of the node that received it.
+    pub fn with_dispatch_type<F>(dispatch_type_function: F) -> MessageDispatcher<DispMsg>
+    where F: Fn(&DispMsg) -> u32 + Send + Sync + 'static {
         MessageDispatcher {
+            dispatch_type_function: Arc::new(dispatch_type_function),
             handlers: HashMap::new(),
         }
     }
 
     /// This function allows a new dispatch route to be specified and added to the handlers, all received messaged that
     /// are of the dispatch type will be routed to the specified handler_function
-    pub fn route(mut self, dispatch_type: u32, handler_function: HandlerFunctionFormat<DispMsg>) -> Self {
-        self.handlers.insert(dispatch_type, handler_function);
+    pub fn route<F>(mut self, dispatch_type: u32, handler_function: F) -> Self
+    where F: Fn(DispMsg) -> Result<(), DispatchError> + Send + Sync + 'static {
+        self.handlers.insert(dispatch_type, Arc::new(handler_function));
         self
     }
 
     /// This function can be used to forward a message to the correct function handler
     pub fn dispatch(&self, msg_data: DispMsg) -> Result<(), DispatchError> {
-        match self.handlers.get(&msg_data.dispatch_type()) {
+        match self.handlers.get(&(self.dispatch_type_function)(&msg_data)) {
             Some(dispatch_function) => {
                 dispatch_function(msg_data)?;
                 Ok(())
@@ -143,4 +180,44 @@ mod test {
             assert_eq!(CALLED_FN_TYPE, DispatchType::Type2);
         }
     }
+
+    #[test]
+    fn test_closure_handler() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct Message;
+        impl Dispatchable for Message {
+            fn dispatch_type(&self) -> u32 {
+                1
+            }
+        }
+
+        // Handlers can capture state, and that state is shared by clones of the dispatcher
+        let counter = Arc::new(AtomicUsize::new(0));
+        let handler_counter = counter.clone();
+        let message_dispatcher = MessageDispatcher::<Message>::new().route(1, move |_msg_data| {
+            handler_counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        let cloned_dispatcher = message_dispatcher.clone();
+        assert!(message_dispatcher.dispatch(Message).is_ok());
+        assert!(cloned_dispatcher.dispatch(Message).is_ok());
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_dispatch_type_function() {
+        struct Message {
+            value: u32,
+        }
+
+        // The dispatch type can be determined from state that isn't part of the message
+        let threshold = 10;
+        let message_dispatcher =
+            MessageDispatcher::<Message>::with_dispatch_type(move |msg_data| (msg_data.value > threshold) as u32)
+                .route(0, |_msg_data| Ok(()))
+                .route(1, |_msg_data| Err(DispatchError::HandlerError("Too large".to_string())));
+        assert!(message_dispatcher.dispatch(Message { value: 5 }).is_ok());
+        assert!(message_dispatcher.dispatch(Message { value: 15 }).is_err());
+    }
 }
@@ -0,0 +1,539 @@
+//  Copyright 2019 The Tari Project
+//
+//  Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+//  following conditions are met:
+//
+//  1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+//  disclaimer.
+//
+//  2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+//  following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+//  3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+//  products derived from this software without specific prior written permission.
+//
+//  THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+//  INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+//  DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+//  SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+//  SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+//  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+//  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    connection::message::{MessageEnvelope, MessageError, NodeDestination},
+    inbound_message_service::message_context::MessageContext,
+    outbound_message_service::outbound_message_service::{OutboundError, OutboundMessageService},
+    peer_manager::{
+        manager::PeerManagerError,
+        node_id::{NodeId, NodeIdError},
+        node_identity::NodeIdentity,
+    },
+    types::{Challenge, DEFAULT_MESSAGE_TTL},
+};
+use chrono::{prelude::*, Duration};
+use derive_error::Error;
+use digest::Digest;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ops::Mul,
+    sync::{Arc, Mutex},
+};
+use tari_crypto::keys::{DiffieHellmanSharedSecret, PublicKey, SecretKey};
+use tari_storage::{hashmap_store::HashmapStore, keyvalue_store::DataStore};
+use tari_utilities::Hashable;
+
+/// The default number of forwarded messages that are remembered to detect duplicates
+const DEFAULT_SEEN_CACHE_SIZE: usize = 10_000;
+/// The default period for which a forwarded message is remembered to detect duplicates
+const DEFAULT_SEEN_PERIOD_MINS: i64 = 10;
+/// The default number of peers that a message is forwarded to
+const DEFAULT_FORWARD_PEER_COUNT: usize = 3;
+/// The default number of messages received on a single connection that are forwarded in a rate limit period
+const DEFAULT_MAX_FORWARDS_PER_CONNECTION: usize = 100;
+/// The default period in which the messages forwarded for a connection are counted
+const DEFAULT_RATE_LIMIT_PERIOD_SECS: i64 = 60;
+
+#[derive(Debug, Error)]
+pub enum ForwardError {
+    /// The message has been forwarded the maximum number of times
+    TtlExpired,
+    /// The message has already been forwarded by this node
+    DuplicateMessage,
+    /// Too many messages received on the connection of the message have been forwarded in the rate limit period
+    RateLimitExceeded,
+    /// There are no peers that are closer to the destination of the message
+    NoForwardPeers,
+    /// The node id of the message destination or source could not be derived
+    NodeIdError(NodeIdError),
+    /// The peers to forward the message to could not be selected
+    PeerManagerError(PeerManagerError),
+    /// The header of the forwarded message could not be serialized
+    MessageError(MessageError),
+    /// The message could not be sent to a peer
+    OutboundError(OutboundError),
+    /// The forwarding state lock has been poisoned by a thread that panicked while holding it
+    PoisonedAccess,
+}
+
+/// The limits placed on the messages forwarded by a MessageForwarder
+#[derive(Clone, Debug)]
+pub struct ForwardingConfig {
+    /// The maximum number of forwarded messages that are remembered to detect duplicates
+    pub seen_cache_size: usize,
+    /// The period for which a forwarded message is remembered to detect duplicates
+    pub seen_period: Duration,
+    /// The maximum number of peers that a message is forwarded to
+    pub forward_peer_count: usize,
+    /// The maximum number of messages received on a single connection that are forwarded in a rate limit period
+    pub max_forwards_per_connection: usize,
+    /// The period in which the messages forwarded for a connection are counted
+    pub rate_limit_period: Duration,
+}
+
+impl Default for ForwardingConfig {
+    fn default() -> Self {
+        ForwardingConfig {
+            seen_cache_size: DEFAULT_SEEN_CACHE_SIZE,
+            seen_period: Duration::minutes(DEFAULT_SEEN_PERIOD_MINS),
+            forward_peer_count: DEFAULT_FORWARD_PEER_COUNT,
+            max_forwards_per_connection: DEFAULT_MAX_FORWARDS_PER_CONNECTION,
+            rate_limit_period: Duration::seconds(DEFAULT_RATE_LIMIT_PERIOD_SECS),
+        }
+    }
+}
+
+/// Remembers the hashes of the signatures of recently forwarded messages, so that a message that loops back to this
+/// node, or that arrives from several peers, is forwarded only once
+struct SeenMessageCache {
+    capacity: usize,
+    period: Duration,
+    hashes: HashSet<Vec<u8>>,
+    order: VecDeque<(DateTime<Utc>, Vec<u8>)>,
+}
+
+impl SeenMessageCache {
+    fn new(capacity: usize, period: Duration) -> SeenMessageCache {
+        SeenMessageCache {
+            capacity,
+            period,
+            hashes: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns true if the hash has been seen in the seen period
+    fn contains_at(&mut self, hash: &[u8], now: DateTime<Utc>) -> bool {
+        self.remove_expired(now);
+        self.hashes.contains(hash)
+    }
+
+    /// Remember the hash, returning false if it has already been seen
+    fn insert_at(&mut self, hash: Vec<u8>, now: DateTime<Utc>) -> bool {
+        if self.contains_at(&hash, now) {
+            return false;
+        }
+        while !self.order.is_empty() && self.order.len() >= self.capacity {
+            self.remove_oldest();
+        }
+        self.hashes.insert(hash.clone());
+        self.order.push_back((now, hash));
+        true
+    }
+
+    fn remove_expired(&mut self, now: DateTime<Utc>) {
+        let period = self.period;
+        while self
+            .order
+            .front()
+            .map_or(false, |(seen_at, _)| *seen_at + period <= now)
+        {
+            self.remove_oldest();
+        }
+    }
+
+    fn remove_oldest(&mut self) {
+        if let Some((_, hash)) = self.order.pop_front() {
+            self.hashes.remove(&hash);
+        }
+    }
+}
+
+/// Counts the messages forwarded for each inbound connection in fixed periods, and refuses to forward more than the
+/// maximum. Messages are counted against the connection they arrived on, as the source in the header of a message is
+/// chosen by the sender and could be used to exhaust the limit of another node.
+struct RateLimiter {
+    max_messages: usize,
+    period: Duration,
+    periods: HashMap<Vec<u8>, (DateTime<Utc>, usize)>,
+}
+
+impl RateLimiter {
+    fn new(max_messages: usize, period: Duration) -> RateLimiter {
+        RateLimiter {
+            max_messages,
+            period,
+            periods: HashMap::new(),
+        }
+    }
+
+    /// Count a message received on the connection, returning false if the connection has reached its limit for the
+    /// current period
+    fn allow_at(&mut self, connection_id: &[u8], now: DateTime<Utc>) -> bool {
+        let period = self.period;
+        if !self.periods.contains_key(connection_id) {
+            self.periods.retain(|_, (started_at, _)| *started_at + period > now);
+        }
+        let (started_at, count) = self.periods.entry(connection_id.to_vec()).or_insert((now, 0));
+        if *started_at + period <= now {
+            *started_at = now;
+            *count = 0;
+        }
+        if *count >= self.max_messages {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+struct ForwardingState {
+    seen_messages: SeenMessageCache,
+    rate_limiter: RateLimiter,
+}
+
+/// The MessageForwarder relays messages that are not addressed to this node towards their destination. A message is
+/// sent, unchanged except for its decremented time to live, to the known peers that are closer to the destination
+/// NodeId than this node, or directly to the destination if it is a known peer. Messages with an unknown destination
+/// are relayed to the peers closest to this node.
+///
+/// Messages are not forwarded once their time to live reaches zero, when they have already been forwarded, or when the
+/// connection that the message arrived on has exceeded its rate limit. The time to live of a received message is
+/// capped at the default, so that a sender can't make its messages travel further than the network allows. The
+/// MessageForwarder can be shared between the threads that
+/// dispatch messages.
+pub struct MessageForwarder<PubKey, SecKey, DS = HashmapStore>
+where PubKey: PublicKey
+{
+    node_identity: Arc<NodeIdentity<PubKey, SecKey>>,
+    outbound_message_service: OutboundMessageService<PubKey, SecKey, DS>,
+    config: ForwardingConfig,
+    state: Mutex<ForwardingState>,
+}
+
+impl<PubKey, SecKey, DS> MessageForwarder<PubKey, SecKey, DS>
+where
+    PubKey: PublicKey<K = SecKey> + Hashable + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>,
+    SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize,
+    DS: DataStore,
+{
+    /// Construct a MessageForwarder that sends forwarded messages with the OutboundMessageService, to peers selected
+    /// from its peer manager
+    pub fn new(
+        node_identity: Arc<NodeIdentity<PubKey, SecKey>>,
+        outbound_message_service: OutboundMessageService<PubKey, SecKey, DS>,
+        config: ForwardingConfig,
+    ) -> MessageForwarder<PubKey, SecKey, DS>
+    {
+        let state = ForwardingState {
+            seen_messages: SeenMessageCache::new(config.seen_cache_size, config.seen_period),
+            rate_limiter: RateLimiter::new(config.max_forwards_per_connection, config.rate_limit_period),
+        };
+        MessageForwarder {
+            node_identity,
+            outbound_message_service,
+            config,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// The limits placed on forwarded messages
+    pub fn config(&self) -> &ForwardingConfig {
+        &self.config
+    }
+
+    /// Forward the message towards its destination
+    /// # Returns
+    /// The number of peers the message was sent to
+    pub fn forward(&self, message_context: &MessageContext<PubKey>) -> Result<usize, ForwardError> {
+        self.forward_at(message_context, Utc::now())
+    }
+
+    fn forward_at(&self, message_context: &MessageContext<PubKey>, now: DateTime<Utc>) -> Result<usize, ForwardError> {
+        let header = &message_context.message_envelope_header;
+        let ttl = header.ttl.min(DEFAULT_MESSAGE_TTL);
+        if ttl == 0 {
+            return Err(ForwardError::TtlExpired);
+        }
+        let source_node_id = NodeId::from_key(&header.source)?;
+        {
+            let mut state = self.state.lock().map_err(|_| ForwardError::PoisonedAccess)?;
+            let signature_hash = Challenge::digest(&header.signature).to_vec();
+            if state.seen_messages.contains_at(&signature_hash, now) {
+                return Err(ForwardError::DuplicateMessage);
+            }
+            // A message that is dropped by the rate limiter is not remembered, so that it is still forwarded when it
+            // arrives on a connection that is within its limit
+            if !state.rate_limiter.allow_at(&message_context.connection_id, now) {
+                return Err(ForwardError::RateLimitExceeded);
+            }
+            state.seen_messages.insert_at(signature_hash, now);
+        }
+
+        let peer_manager = self.outbound_message_service.peer_manager();
+        let node_id = &self.node_identity.node_id;
+        let dest_node_id = match &header.dest {
+            NodeDestination::Unknown => None,
+            NodeDestination::PublicKey(public_key) => Some(NodeId::from_key(public_key)?),
+            NodeDestination::NodeId(dest_node_id) => Some(dest_node_id.clone()),
+        };
+        // One extra peer is selected in case the source of the message is among the closest peers
+        let peer_count = self.config.forward_peer_count + 1;
+        let mut peers = match &dest_node_id {
+            Some(dest_node_id) => {
+                let distance = node_id.distance(dest_node_id);
+                peer_manager
+                    .closest_peers(dest_node_id, peer_count)?
+                    .into_iter()
+                    .filter(|peer| peer.node_id.distance(dest_node_id) < distance)
+                    .collect()
+            },
+            None => peer_manager.closest_peers(node_id, peer_count)?,
+        };
+        peers.retain(|peer| peer.node_id != source_node_id);
+        peers.truncate(self.config.forward_peer_count);
+        let dest_index = dest_node_id
+            .as_ref()
+            .and_then(|dest_node_id| peers.iter().position(|peer| peer.node_id == *dest_node_id));
+        if let Some(dest_index) = dest_index {
+            peers = vec![peers.swap_remove(dest_index)];
+        }
+        if peers.is_empty() {
+            return Err(ForwardError::NoForwardPeers);
+        }
+
+        let mut forwarded_header = header.clone();
+        forwarded_header.ttl = ttl - 1;
+        let forwarded_header_frame = forwarded_header.to_frame()?;
+        for peer in &peers {
+            let message_envelope = MessageEnvelope::new(
+                message_context.version.clone(),
+                forwarded_header_frame.clone(),
+                message_context.message_envelope_body.clone(),
+            );
+            self.outbound_message_service
+                .forward(peer.node_id.clone(), message_envelope)?;
+        }
+        Ok(peers.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        connection::{
+            message::{IdentityFlags, MessageEnvelopeHeader},
+            net_address::net_addresses::NetAddresses,
+            types::SocketType,
+            zmq::{Context, InprocAddress, ZmqEndpoint},
+            NetAddress,
+        },
+        outbound_message_service::outbound_message::OutboundMessage,
+        peer_manager::{
+            manager::PeerManager,
+            peer::{Peer, PeerFlags},
+        },
+    };
+    use rand::{CryptoRng, Rng};
+    use std::convert::TryFrom;
+    use tari_crypto::ristretto::{RistrettoPublicKey, RistrettoSecretKey};
+
+    type TestNodeIdentity = NodeIdentity<RistrettoPublicKey, RistrettoSecretKey>;
+
+    fn create_node_identity<R: Rng + CryptoRng>(rng: &mut R) -> Arc<TestNodeIdentity> {
+        let (sk, pk) = RistrettoPublicKey::random_keypair(rng);
+        Arc::new(NodeIdentity::new(NodeId::from_key(&pk).unwrap(), pk, Some(sk)))
+    }
+
+    fn create_peer(node_identity: &TestNodeIdentity) -> Peer<RistrettoPublicKey> {
+        let addresses = NetAddresses::from("127.0.0.1:9000".parse::<NetAddress>().unwrap());
+        Peer::new(
+            node_identity.public_key.clone(),
+            node_identity.node_id.clone(),
+            addresses,
+            PeerFlags::default(),
+        )
+    }
+
+    // The forwarder does not verify signatures, so any unique signature bytes identify the message
+    fn create_message_context(
+        source: &TestNodeIdentity,
+        dest: NodeDestination<RistrettoPublicKey>,
+        signature: Vec<u8>,
+    ) -> MessageContext<RistrettoPublicKey>
+    {
+        let header = MessageEnvelopeHeader::new(0, source.public_key.clone(), dest, signature, IdentityFlags::empty());
+        MessageContext::new(vec![0], vec![1], vec![0], None, header, vec![1, 2, 3, 4])
+    }
+
+    // Receive the messages written to the outbound message pool
+    fn receive_messages(omp_socket: &zmq::Socket, count: usize) -> Vec<OutboundMessage<MessageEnvelope>> {
+        (0..count)
+            .map(|_| {
+                let msg_bytes = omp_socket.recv_multipart(0).unwrap();
+                assert!(omp_socket.send("OK".as_bytes(), 0).is_ok());
+                OutboundMessage::<MessageEnvelope>::try_from(msg_bytes).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_seen_message_cache() {
+        let now = Utc::now();
+        let mut cache = SeenMessageCache::new(2, Duration::minutes(10));
+        assert!(cache.insert_at(vec![1], now));
+        assert!(!cache.insert_at(vec![1], now));
+        assert!(cache.insert_at(vec![2], now));
+
+        assert!(cache.contains_at(&[2], now));
+        assert!(!cache.contains_at(&[3], now));
+
+        // The oldest hash is forgotten when the cache is full
+        assert!(cache.insert_at(vec![3], now));
+        assert!(cache.insert_at(vec![1], now));
+        assert!(!cache.insert_at(vec![3], now));
+
+        // Hashes are forgotten once the seen period has elapsed
+        assert!(!cache.insert_at(vec![3], now + Duration::minutes(9)));
+        assert!(cache.insert_at(vec![3], now + Duration::minutes(10)));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let now = Utc::now();
+        let connection1 = vec![1];
+        let connection2 = vec![2];
+        let mut rate_limiter = RateLimiter::new(2, Duration::seconds(60));
+        assert!(rate_limiter.allow_at(&connection1, now));
+        assert!(rate_limiter.allow_at(&connection1, now));
+        assert!(!rate_limiter.allow_at(&connection1, now + Duration::seconds(59)));
+        // Each connection is limited separately
+        assert!(rate_limiter.allow_at(&connection2, now));
+        // The count is reset once the rate limit period has elapsed
+        assert!(rate_limiter.allow_at(&connection1, now + Duration::seconds(60)));
+    }
+
+    #[test]
+    fn test_forward() {
+        let context = Context::new();
+        let mut rng = rand::OsRng::new().unwrap();
+        let outbound_address = InprocAddress::random();
+        let omp_socket = context.socket(SocketType::Reply).unwrap();
+        omp_socket.bind(&outbound_address.to_zmq_endpoint()).unwrap();
+
+        let node_identity = create_node_identity(&mut rng);
+        let source_identity = create_node_identity(&mut rng);
+        let peer_manager = Arc::new(PeerManager::new(node_identity.node_id.clone(), HashmapStore::new()).unwrap());
+        let peer_identities: Vec<_> = (0..5).map(|_| create_node_identity(&mut rng)).collect();
+        for peer_identity in &peer_identities {
+            peer_manager.add_peer(create_peer(peer_identity)).unwrap();
+        }
+        peer_manager.add_peer(create_peer(&source_identity)).unwrap();
+        let outbound_message_service = OutboundMessageService::<RistrettoPublicKey, RistrettoSecretKey>::new(
+            context,
+            outbound_address,
+            node_identity.clone(),
+            peer_manager.clone(),
+        );
+        let config = ForwardingConfig {
+            max_forwards_per_connection: 3,
+            ..Default::default()
+        };
+        let message_forwarder = MessageForwarder::new(node_identity.clone(), outbound_message_service, config);
+        let now = Utc::now();
+
+        // A message destined for a known peer is sent directly to it, with a decremented time to live
+        let dest_node_id = peer_identities[0].node_id.clone();
+        let message_context =
+            create_message_context(&source_identity, NodeDestination::NodeId(dest_node_id.clone()), vec![1]);
+        assert_eq!(message_forwarder.forward_at(&message_context, now).unwrap(), 1);
+        let outbound_messages = receive_messages(&omp_socket, 1);
+        assert_eq!(outbound_messages[0].destination_node_id, dest_node_id);
+        let message_envelope = &outbound_messages[0].message_envelope;
+        let header = MessageEnvelopeHeader::<RistrettoPublicKey>::try_from(message_envelope.header().clone()).unwrap();
+        assert_eq!(header.ttl, DEFAULT_MESSAGE_TTL - 1);
+        assert_eq!(header.signature, message_context.message_envelope_header.signature);
+        assert_eq!(*message_envelope.body(), message_context.message_envelope_body);
+
+        // The same message is not forwarded again
+        match message_forwarder.forward_at(&message_context, now) {
+            Err(ForwardError::DuplicateMessage) => {},
+            result => panic!("Unexpected forward result: {:?}", result),
+        }
+
+        // A message with an unknown destination is sent to the peers closest to this node, except its source
+        let message_context = create_message_context(&source_identity, NodeDestination::Unknown, vec![2]);
+        assert_eq!(
+            message_forwarder.forward_at(&message_context, now).unwrap(),
+            DEFAULT_FORWARD_PEER_COUNT
+        );
+        let closest_node_ids: Vec<_> = peer_manager
+            .closest_peers(&node_identity.node_id, DEFAULT_FORWARD_PEER_COUNT + 1)
+            .unwrap()
+            .into_iter()
+            .map(|peer| peer.node_id)
+            .filter(|node_id| *node_id != source_identity.node_id)
+            .take(DEFAULT_FORWARD_PEER_COUNT)
+            .collect();
+        for outbound_message in receive_messages(&omp_socket, DEFAULT_FORWARD_PEER_COUNT) {
+            assert!(closest_node_ids.contains(&outbound_message.destination_node_id));
+            assert_ne!(outbound_message.destination_node_id, source_identity.node_id);
+        }
+
+        // A message that has reached its time to live is not forwarded
+        let mut message_context = create_message_context(&source_identity, NodeDestination::Unknown, vec![3]);
+        message_context.message_envelope_header.ttl = 0;
+        match message_forwarder.forward_at(&message_context, now) {
+            Err(ForwardError::TtlExpired) => {},
+            result => panic!("Unexpected forward result: {:?}", result),
+        }
+
+        // The connection is rate limited until the rate limit period has elapsed
+        let dest = NodeDestination::NodeId(dest_node_id.clone());
+        let message_context = create_message_context(&source_identity, dest.clone(), vec![4]);
+        assert_eq!(message_forwarder.forward_at(&message_context, now).unwrap(), 1);
+        receive_messages(&omp_socket, 1);
+        let mut message_context = create_message_context(&source_identity, dest.clone(), vec![5]);
+        match message_forwarder.forward_at(&message_context, now) {
+            Err(ForwardError::RateLimitExceeded) => {},
+            result => panic!("Unexpected forward result: {:?}", result),
+        }
+        // A message that was dropped by the rate limiter is forwarded when it arrives on a connection within its limit
+        message_context.connection_id = vec![2];
+        assert_eq!(message_forwarder.forward_at(&message_context, now).unwrap(), 1);
+        receive_messages(&omp_socket, 1);
+        let message_context = create_message_context(&source_identity, dest.clone(), vec![6]);
+        let later = now + Duration::seconds(DEFAULT_RATE_LIMIT_PERIOD_SECS);
+        assert_eq!(message_forwarder.forward_at(&message_context, later).unwrap(), 1);
+        receive_messages(&omp_socket, 1);
+
+        // Messages are counted against the connection they arrived on, not the source in their header
+        let mut message_context = create_message_context(&source_identity, dest.clone(), vec![7]);
+        message_context.connection_id = vec![1];
+        assert_eq!(message_forwarder.forward_at(&message_context, now).unwrap(), 1);
+        receive_messages(&omp_socket, 1);
+
+        // The time to live of a received message is capped at the default
+        let mut message_context = create_message_context(&source_identity, dest, vec![8]);
+        message_context.message_envelope_header.ttl = u8::max_value();
+        assert_eq!(message_forwarder.forward_at(&message_context, later).unwrap(), 1);
+        let outbound_messages = receive_messages(&omp_socket, 1);
+        let header = MessageEnvelopeHeader::<RistrettoPublicKey>::try_from(
+            outbound_messages[0].message_envelope.header().clone(),
+        )
+        .unwrap();
+        assert_eq!(header.ttl, DEFAULT_MESSAGE_TTL - 1);
+    }
+}
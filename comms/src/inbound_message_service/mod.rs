@@ -24,4 +24,5 @@ pub mod comms_msg_handlers;
 pub mod inbound_message_service;
 pub mod message_context;
 pub mod message_dispatcher;
+pub mod message_forwarder;
 pub mod msg_processing_worker;
@@ -26,7 +26,7 @@ use crate::{
         types::SocketType,
         zmq::{Context, InprocAddress, ZmqEndpoint, ZmqError},
     },
-    inbound_message_service::message_dispatcher::{Dispatchable, MessageDispatcher},
+    inbound_message_service::message_dispatcher::MessageDispatcher,
 };
 use std::{convert::TryFrom, marker::Send, thread};
 use tari_crypto::keys::PublicKey;
@@ -47,9 +47,7 @@ pub struct MsgProcessingWorker<PubKey, DispMsg> {
     message_dispatcher: MessageDispatcher<DispMsg>,
 }
 
-impl<PubKey: PublicKey + Send + 'static, DispMsg: Dispatchable + TryFrom<FrameSet> + 'static>
-    MsgProcessingWorker<PubKey, DispMsg>
-{
+impl<PubKey: PublicKey + Send + 'static, DispMsg: TryFrom<FrameSet> + 'static> MsgProcessingWorker<PubKey, DispMsg> {
     /// Setup a new MsgProcessingWorker that will read incoming messages and dispatch them using the message_dispatcher
     pub fn new(
         context: Context,
@@ -97,7 +95,7 @@ impl<PubKey: PublicKey + Send + 'static, DispMsg: Dispatchable + TryFrom<FrameSe
                             inbound_socket.send("DISCARD_MSG".as_bytes(), 0).unwrap_or_else(|_e| {
                                 (/*TODO Log Warning: message could not be deserialised*/)
                             })
-                        }
+                        },
                     }
                 },
                 Err(_e) => {
@@ -1,5 +1,7 @@
 pub mod connection;
+pub mod connection_manager;
 pub mod inbound_message_service;
 pub mod outbound_message_service;
 pub mod peer_manager;
+pub mod store_and_forward;
 pub mod types;
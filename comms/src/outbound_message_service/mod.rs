@@ -22,4 +22,5 @@
 
 pub mod broadcast_strategy;
 pub mod outbound_message;
+pub mod outbound_message_pool;
 pub mod outbound_message_service;
@@ -0,0 +1,100 @@
+pub mod connection;
+pub mod inbound_message_service;
+pub mod outbound_message_service;
+pub mod peer_manager;
+pub mod store_and_forward;
+pub mod types;
+
+use crate::{
+    connection::{
+        message::MessageEnvelope,
+        zmq::{Context, InprocAddress},
+        Connection,
+        ConnectionError,
+        Direction,
+    },
+    connection_manager::connection_manager::ConnectionManager,
+    outbound_message_service::outbound_message::OutboundMessage,
+};
+use std::{collections::VecDeque, convert::TryFrom, sync::Arc, thread};
+use tari_crypto::keys::PublicKey;
+use tari_storage::{hashmap_store::HashmapStore, keyvalue_store::DataStore};
+
+/// The maximum number of times that the pool attempts to send a message before the message is discarded
+const MAX_SEND_ATTEMPTS: u32 = 3;
+/// The time, in milliseconds, that the pool waits for new messages before it retries the messages that failed
+const POOL_RECEIVE_TIMEOUT_MS: u32 = 100;
+
+/// The OutboundMessagePool receives the messages that the OutboundMessageService writes to the pool address, and sends
+/// them to their destination peers using the ConnectionManager. Messages that could not be sent are retried, up to
+/// MAX_SEND_ATTEMPTS times.
+pub struct OutboundMessagePool<PubKey, DS = HashmapStore>
+where PubKey: PublicKey
+{
+    context: Context,
+    pool_address: InprocAddress,
+    connection_manager: Arc<ConnectionManager<PubKey, DS>>,
+}
+
+impl<PubKey, DS> OutboundMessagePool<PubKey, DS>
+where
+    PubKey: PublicKey + Send + Sync + 'static,
+    DS: DataStore + Send + Sync + 'static,
+{
+    /// Construct an OutboundMessagePool that receives messages on the pool address
+    pub fn new(
+        context: Context,
+        pool_address: InprocAddress,
+        connection_manager: Arc<ConnectionManager<PubKey, DS>>,
+    ) -> OutboundMessagePool<PubKey, DS>
+    {
+        OutboundMessagePool {
+            context,
+            pool_address,
+            connection_manager,
+        }
+    }
+
+    /// Bind the pool address, then send the messages written to it from a new thread
+    pub fn start(self) -> Result<(), ConnectionError> {
+        let pool_connection = Connection::new(&self.context, Direction::Inbound).establish(&self.pool_address)?;
+        thread::spawn(move || {
+            let mut retry_queue = VecDeque::new();
+            loop {
+                // The last frame written by a request socket is the outbound message
+                if let Ok(mut frames) = pool_connection.receive(POOL_RECEIVE_TIMEOUT_MS) {
+                    if let Some(Ok(outbound_message)) = frames.pop().map(OutboundMessage::<MessageEnvelope>::try_from) {
+                        self.send(outbound_message, &mut retry_queue);
+                    }
+                    continue;
+                }
+                for outbound_message in retry_queue.split_off(0) {
+                    self.send(outbound_message, &mut retry_queue);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn send(
+        &self,
+        mut outbound_message: OutboundMessage<MessageEnvelope>,
+        retry_queue: &mut VecDeque<OutboundMessage<MessageEnvelope>>,
+    ) {
+        let sent = outbound_message
+            .message_envelope
+            .to_frame_set()
+            .map(|frames| {
+                self.connection_manager
+                    .send_to(&outbound_message.destination_node_id, &frames)
+                    .is_ok()
+            })
+            .unwrap_or(false);
+        if !sent {
+            outbound_message.mark_transmission_attempt();
+            if outbound_message.retry_count < MAX_SEND_ATTEMPTS {
+                retry_queue.push_back(outbound_message);
+            }
+        }
+    }
+}
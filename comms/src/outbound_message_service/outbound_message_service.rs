@@ -24,12 +24,25 @@ use crate::{
     connection::{
         connection::EstablishedConnection,
         error::ConnectionError,
-        message::{Frame, IdentityFlags, MessageEnvelope, MessageEnvelopeHeader, MessageError, NodeDestination},
+        message::{
+            encrypt_envelope_body,
+            Frame,
+            IdentityFlags,
+            MessageEnvelope,
+            MessageEnvelopeHeader,
+            MessageError,
+            NodeDestination,
+        },
         types::SocketType,
         zmq::{Context, InprocAddress, ZmqEndpoint, ZmqError},
     },
     outbound_message_service::{broadcast_strategy::BroadcastStrategy, outbound_message::OutboundMessage},
-    peer_manager::node_identity::NodeIdentity,
+    peer_manager::{
+        manager::{PeerManager, PeerManagerError},
+        node_id::NodeId,
+        node_identity::NodeIdentity,
+        peer::Peer,
+    },
     types::{Challenge, MESSAGE_PROTOCOL_VERSION, WIRE_PROTOCOL_VERSION},
 };
 use derive_error::Error;
@@ -42,7 +55,8 @@ use tari_crypto::{
     keys::{DiffieHellmanSharedSecret, PublicKey, SecretKey},
     signatures::{SchnorrSignature, SchnorrSignatureError},
 };
-use tari_utilities::{chacha20, ByteArray, ByteArrayError, Hashable};
+use tari_storage::{hashmap_store::HashmapStore, keyvalue_store::DataStore};
+use tari_utilities::{ByteArrayError, Hashable};
 
 #[derive(Debug, Error)]
 pub enum OutboundError {
@@ -62,53 +76,110 @@ pub enum OutboundError {
     MessageSerializationError(MessageError),
     /// Could not successfully sign the message
     SignatureError(SchnorrSignatureError),
+    /// The peers to send the message to could not be selected from the peer manager
+    PeerManagerError(PeerManagerError),
+    /// The broadcast strategy did not select any peers to send the message to
+    NoPeersSelected,
 }
 
 /// Handler functions use the OutboundMessageService to send messages to peers. The OutboundMessage service will receive
 /// messages from handlers, apply a broadcasting strategy, encrypted and serialized the messages into OutboundMessages
 /// and write them to the outbound message pool.
-pub struct OutboundMessageService<PubKey, SecKey> {
+///
+/// The peers that a message is sent to are selected from the PeerManager, which can be shared with other services.
+pub struct OutboundMessageService<PubKey, SecKey, DS = HashmapStore>
+where PubKey: PublicKey
+{
     context: Context,
     outbound_address: InprocAddress,
     node_identity: Arc<NodeIdentity<PubKey, SecKey>>,
+    peer_manager: Arc<PeerManager<PubKey, DS>>,
 }
 
-impl<PubKey, SecKey> OutboundMessageService<PubKey, SecKey>
+impl<PubKey, SecKey, DS> OutboundMessageService<PubKey, SecKey, DS>
 where
     PubKey: PublicKey<K = SecKey> + Hashable + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>,
     SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize,
+    DS: DataStore,
 {
-    /// Constructs a new OutboundMessageService from the context, node_identity and outbound_address
+    /// Constructs a new OutboundMessageService from the context, node_identity, outbound_address and the peer manager
+    /// that destination peers are selected from
     pub fn new(
         context: Context,
         outbound_address: InprocAddress, /* The outbound_address is an inproc that connects the OutboundMessagePool
                                           * and the OutboundMessageService */
         node_identity: Arc<NodeIdentity<PubKey, SecKey>>,
-    ) -> OutboundMessageService<PubKey, SecKey>
+        peer_manager: Arc<PeerManager<PubKey, DS>>,
+    ) -> OutboundMessageService<PubKey, SecKey, DS>
     {
         OutboundMessageService {
             context,
             outbound_address,
             node_identity,
+            peer_manager,
         }
     }
 
-    /// Encrypt the message_envelope_body with the generated shared secret if the Encrypted IdentityFlag is set
-    fn encrypt_envelope_body(
+    /// The peer manager that destination peers are selected from
+    pub fn peer_manager(&self) -> Arc<PeerManager<PubKey, DS>> {
+        self.peer_manager.clone()
+    }
+
+    /// Select the peers that a message must be sent to using the BroadcastStrategy. Banned peers are never selected,
+    /// they are excluded before the closest or random peers are chosen so that `Closest(n)` and `Random(n)` still
+    /// select `n` peers when enough peers are not banned.
+    fn select_peers<R: Rng + CryptoRng>(
+        &self,
+        broadcast_strategy: BroadcastStrategy,
+        rng: &mut R,
+    ) -> Result<Vec<Peer<PubKey>>, OutboundError>
+    {
+        Ok(match broadcast_strategy {
+            BroadcastStrategy::Direct(node_id) => {
+                let peer = self.peer_manager.find_with_node_id(&node_id)?;
+                if peer.is_banned() {
+                    Vec::new()
+                } else {
+                    vec![peer]
+                }
+            },
+            BroadcastStrategy::Flood => self.unbanned_peers()?,
+            BroadcastStrategy::Closest(n) => self
+                .peer_manager
+                .closest_peers(&self.node_identity.node_id, n as usize)?,
+            BroadcastStrategy::Random(n) => {
+                let mut peers = self.unbanned_peers()?;
+                rng.shuffle(&mut peers);
+                peers.truncate(n as usize);
+                peers
+            },
+        })
+    }
+
+    /// The known peers that are not banned
+    fn unbanned_peers(&self) -> Result<Vec<Peer<PubKey>>, OutboundError> {
+        let mut peers = self.peer_manager.peers()?;
+        peers.retain(|peer| !peer.is_banned());
+        Ok(peers)
+    }
+
+    /// Encrypt the message_envelope_body for the destination node with the generated shared secret
+    fn encrypt_envelope_body<R: Rng + CryptoRng>(
         &self,
         message_envelope_body: &Frame,
         dest_node_public_key: &PubKey,
+        rng: &mut R,
     ) -> Result<Frame, OutboundError>
     {
         let node_secret_key = self
             .node_identity
             .secret_key
-            .clone()
+            .as_ref()
             .ok_or(OutboundError::UndefinedSecretKey)?;
-        let ecdh_shared_secret = PubKey::shared_secret(&node_secret_key, &dest_node_public_key).to_vec();
-        let ecdh_shared_secret_bytes: [u8; 32] =
-            ByteArray::from_bytes(&ecdh_shared_secret).map_err(|e| OutboundError::SharedSecretSerializationError(e))?;
-        Ok(chacha20::encode(message_envelope_body, &ecdh_shared_secret_bytes))
+        encrypt_envelope_body(node_secret_key, dest_node_public_key, message_envelope_body, rng).map_err(|e| match e {
+            MessageError::SharedSecretSerializationError(e) => OutboundError::SharedSecretSerializationError(e),
+            e => OutboundError::MessageSerializationError(e),
+        })
     }
 
     /// Generate a signature for the MessageEnvelopeHeader from the MessageEnvelopeBody
@@ -137,26 +208,25 @@ where
     }
 
     /// Handler functions use the send function to transmit a message to a peer or set of peers based on the
-    /// BroadcastStrategy
+    /// BroadcastStrategy. A personalised message, encrypted for and addressed to the peer, is constructed for each
+    /// selected peer.
     pub fn send<R: Rng + CryptoRng>(
         &self,
-        _broadcast_strategy: BroadcastStrategy, /* TODO: make use of BroadcastStrategy once the PeerManager has been
-                                                 * implemented */
+        broadcast_strategy: BroadcastStrategy,
         flags: IdentityFlags,
         message_envelope_body: &Frame,
         rng: &mut R,
-        dest_node_identity: Arc<NodeIdentity<PubKey, SecKey>>, /* TODO: This field is temporary until routing table
-                                                                * has been implemented */
     ) -> Result<(), OutboundError>
     {
-        // Todo: use the BroadcastStrategy to select appropriate peer(s) from PeerManager and then construct and send a
-        // personalised message to each selected peer
-        let selected_node_identities = vec![Arc::clone(&dest_node_identity)]; // TODO: Remove with PeerManager call after PeerManager has been implemented
+        let selected_peers = self.select_peers(broadcast_strategy, rng)?;
+        if selected_peers.is_empty() {
+            return Err(OutboundError::NoPeersSelected);
+        }
 
-        for dest_node_identity in &selected_node_identities {
+        for dest_peer in &selected_peers {
             // Constructing a MessageEnvelope
             let message_envelope_body = if flags.contains(IdentityFlags::ENCRYPTED) {
-                self.encrypt_envelope_body(message_envelope_body, &dest_node_identity.public_key)?
+                self.encrypt_envelope_body(message_envelope_body, &dest_peer.public_key, rng)?
             } else {
                 message_envelope_body.clone()
             };
@@ -164,7 +234,7 @@ where
             let message_envelope_header = MessageEnvelopeHeader::<PubKey>::new(
                 MESSAGE_PROTOCOL_VERSION,
                 self.node_identity.public_key.clone(),
-                NodeDestination::<PubKey>::NodeId(dest_node_identity.node_id.clone()),
+                NodeDestination::<PubKey>::NodeId(dest_peer.node_id.clone()),
                 signature,
                 flags,
             );
@@ -176,30 +246,39 @@ where
                 message_envelope_header_frame,
                 message_envelope_body,
             );
-            // Construct an OutboundMessage
-            let outbound_message =
-                OutboundMessage::<MessageEnvelope>::new(dest_node_identity.node_id.clone(), message_envelope);
-            let outbound_message_buffer = vec![outbound_message
-                .to_frame()
-                .map_err(|e| OutboundError::MessageSerializationError(e))?];
-
-            // Send message to outbound message pool
-            let outbound_socket = self
-                .context
-                .socket(SocketType::Request)
-                .map_err(|e| OutboundError::SocketError(e))?;
-            outbound_socket
-                .connect(&self.outbound_address.to_zmq_endpoint())
-                .map_err(|e| OutboundError::SocketConnectionError(e))?;
-            let outbound_connection = EstablishedConnection {
-                socket: outbound_socket,
-            };
-            outbound_connection
-                .send(&outbound_message_buffer)
-                .map_err(|e| OutboundError::SendError(e))?;
+            self.send_to_pool(dest_peer.node_id.clone(), message_envelope)?;
         }
         Ok(())
     }
+
+    /// Forward an existing MessageEnvelope, unchanged, to the peer with the given node id. The envelope keeps the
+    /// signature and encryption of its original source, which allows messages held for other nodes to be delivered.
+    pub fn forward(&self, dest_node_id: NodeId, message_envelope: MessageEnvelope) -> Result<(), OutboundError> {
+        self.send_to_pool(dest_node_id, message_envelope)
+    }
+
+    /// Construct an OutboundMessage and write it to the outbound message pool
+    fn send_to_pool(&self, dest_node_id: NodeId, message_envelope: MessageEnvelope) -> Result<(), OutboundError> {
+        let outbound_message = OutboundMessage::<MessageEnvelope>::new(dest_node_id, message_envelope);
+        let outbound_message_buffer = vec![outbound_message
+            .to_frame()
+            .map_err(|e| OutboundError::MessageSerializationError(e))?];
+
+        // Send message to outbound message pool
+        let outbound_socket = self
+            .context
+            .socket(SocketType::Request)
+            .map_err(|e| OutboundError::SocketError(e))?;
+        outbound_socket
+            .connect(&self.outbound_address.to_zmq_endpoint())
+            .map_err(|e| OutboundError::SocketConnectionError(e))?;
+        let outbound_connection = EstablishedConnection {
+            socket: outbound_socket,
+        };
+        outbound_connection
+            .send(&outbound_message_buffer)
+            .map_err(|e| OutboundError::SendError(e))
+    }
 }
 
 #[cfg(test)]
@@ -207,13 +286,49 @@ mod test {
     use super::*;
 
     use crate::{
-        connection::zmq::{Context, InprocAddress, ZmqEndpoint},
-        peer_manager::node_id::NodeId,
+        connection::{
+            message::decrypt_envelope_body,
+            net_address::net_addresses::NetAddresses,
+            zmq::{Context, InprocAddress, ZmqEndpoint},
+            NetAddress,
+        },
+        peer_manager::{node_id::NodeId, peer::PeerFlags},
     };
     use serde::Deserialize;
-    use std::{convert::TryFrom, sync::Arc};
+    use std::{collections::HashSet, convert::TryFrom, sync::Arc};
     use tari_crypto::ristretto::{RistrettoPublicKey, RistrettoSecretKey};
 
+    fn create_node_identity<R: Rng + CryptoRng>(
+        rng: &mut R,
+    ) -> Arc<NodeIdentity<RistrettoPublicKey, RistrettoSecretKey>>
+    {
+        let (sk, pk) = RistrettoPublicKey::random_keypair(rng);
+        Arc::new(NodeIdentity::new(NodeId::from_key(&pk).unwrap(), pk, Some(sk)))
+    }
+
+    fn create_peer(node_identity: &NodeIdentity<RistrettoPublicKey, RistrettoSecretKey>) -> Peer<RistrettoPublicKey> {
+        let addresses = NetAddresses::from("127.0.0.1:9000".parse::<NetAddress>().unwrap());
+        Peer::new(
+            node_identity.public_key.clone(),
+            node_identity.node_id.clone(),
+            addresses,
+            PeerFlags::default(),
+        )
+    }
+
+    // Receive the messages written to the outbound message pool and return the node ids they are destined for
+    fn receive_destinations(omp_socket: &zmq::Socket, count: usize) -> HashSet<NodeId> {
+        (0..count)
+            .map(|_| {
+                let msg_bytes = omp_socket.recv_multipart(0).unwrap();
+                assert!(omp_socket.send("OK".as_bytes(), 0).is_ok());
+                OutboundMessage::<MessageEnvelope>::try_from(msg_bytes)
+                    .unwrap()
+                    .destination_node_id
+            })
+            .collect()
+    }
+
     #[test]
     fn test_outbound_send() {
         let context = Context::new();
@@ -230,26 +345,18 @@ mod test {
             .map_err(|e| OutboundError::SocketConnectionError(e))
             .unwrap();
 
-        // Create an identity for the current node and the destination node
-        let (sk, pk) = RistrettoPublicKey::random_keypair(&mut rng);
-        let node_identity = Arc::new(NodeIdentity::<RistrettoPublicKey, RistrettoSecretKey>::new(
-            NodeId::from_key(&pk).unwrap(),
-            pk,
-            Some(sk),
-        ));
-
-        let (sk, pk) = RistrettoPublicKey::random_keypair(&mut rng);
-        let dest_node_identity = Arc::new(NodeIdentity::<RistrettoPublicKey, RistrettoSecretKey>::new(
-            NodeId::from_key(&pk).unwrap(),
-            pk,
-            Some(sk),
-        ));
+        // Create an identity for the current node and the destination node, and make the destination a known peer
+        let node_identity = create_node_identity(&mut rng);
+        let dest_node_identity = create_node_identity(&mut rng);
+        let peer_manager = Arc::new(PeerManager::new(node_identity.node_id.clone(), HashmapStore::new()).unwrap());
+        peer_manager.add_peer(create_peer(&dest_node_identity)).unwrap();
 
         // Setup OutboundMessageService and transmit a message to the destination
         let outbound_message_service = OutboundMessageService::<RistrettoPublicKey, RistrettoSecretKey>::new(
             context,
             outbound_address,
             node_identity.clone(),
+            peer_manager,
         );
 
         let message_envelope_body: Vec<u8> = vec![0, 1, 2, 3];
@@ -259,7 +366,6 @@ mod test {
                 IdentityFlags::ENCRYPTED,
                 &message_envelope_body,
                 &mut rng,
-                dest_node_identity.clone(), // TODO Remove, this is temporary until the routing table is implemented
             )
             .is_ok());
 
@@ -286,16 +392,99 @@ mod test {
         assert!(signature.verify_challenge(&node_identity.public_key, &challenge));
         // Check Encryption
         assert_eq!(message_envelope_header.flags, IdentityFlags::ENCRYPTED);
-        let ecdh_shared_secret = RistrettoPublicKey::shared_secret(
-            &dest_node_identity.secret_key.clone().unwrap(),
+        let decoded_message_envelope_body = decrypt_envelope_body(
+            dest_node_identity.secret_key.as_ref().unwrap(),
             &node_identity.public_key,
+            outbound_message.message_envelope.body(),
         )
-        .to_vec();
-        let ecdh_shared_secret_bytes: [u8; 32] = ByteArray::from_bytes(&ecdh_shared_secret).unwrap();
-        let decoded_message_envelope_body =
-            chacha20::decode(outbound_message.message_envelope.body(), &ecdh_shared_secret_bytes);
+        .unwrap();
         assert_eq!(message_envelope_body, decoded_message_envelope_body);
 
         assert!(omp_socket.send("OK".as_bytes(), 0).is_ok());
     }
+
+    #[test]
+    fn test_broadcast_strategies() {
+        let context = Context::new();
+        let mut rng = rand::OsRng::new().unwrap();
+        let outbound_address = InprocAddress::random();
+        let omp_socket = context.socket(SocketType::Reply).unwrap();
+        omp_socket.bind(&outbound_address.to_zmq_endpoint()).unwrap();
+
+        let node_identity = create_node_identity(&mut rng);
+        let peer_manager = Arc::new(PeerManager::new(node_identity.node_id.clone(), HashmapStore::new()).unwrap());
+        let peer_identities: Vec<_> = (0..5).map(|_| create_node_identity(&mut rng)).collect();
+        for peer_identity in &peer_identities {
+            peer_manager.add_peer(create_peer(peer_identity)).unwrap();
+        }
+        // The peer closest to our node id is banned, so that it would be among the peers selected by Closest
+        let banned_node_id = peer_manager.closest_peers(&node_identity.node_id, 1).unwrap()[0]
+            .node_id
+            .clone();
+        peer_manager
+            .modify_peer(&banned_node_id, |peer| peer.set_banned(true))
+            .unwrap();
+        let outbound_message_service = OutboundMessageService::<RistrettoPublicKey, RistrettoSecretKey>::new(
+            context,
+            outbound_address,
+            node_identity.clone(),
+            peer_manager.clone(),
+        );
+        let body: Vec<u8> = vec![0, 1, 2, 3];
+
+        // Flood sends to every peer that is not banned
+        outbound_message_service
+            .send(BroadcastStrategy::Flood, IdentityFlags::ENCRYPTED, &body, &mut rng)
+            .unwrap();
+        let destinations = receive_destinations(&omp_socket, 4);
+        let expected: HashSet<NodeId> = peer_identities
+            .iter()
+            .map(|p| p.node_id.clone())
+            .filter(|node_id| node_id != &banned_node_id)
+            .collect();
+        assert_eq!(destinations, expected);
+
+        // Closest sends to the peers nearest to our node id, skipping the banned peer instead of sending to fewer peers
+        outbound_message_service
+            .send(BroadcastStrategy::Closest(2), IdentityFlags::ENCRYPTED, &body, &mut rng)
+            .unwrap();
+        let destinations = receive_destinations(&omp_socket, 2);
+        let node_ids: Vec<NodeId> = peer_identities.iter().map(|p| p.node_id.clone()).collect();
+        let expected: HashSet<NodeId> = node_identity
+            .node_id
+            .closest(&node_ids, 3)
+            .unwrap()
+            .into_iter()
+            .filter(|node_id| node_id != &banned_node_id)
+            .collect();
+        assert_eq!(destinations, expected);
+
+        // Random sends to a subset of the peers that are not banned
+        outbound_message_service
+            .send(BroadcastStrategy::Random(3), IdentityFlags::ENCRYPTED, &body, &mut rng)
+            .unwrap();
+        let destinations = receive_destinations(&omp_socket, 3);
+        assert_eq!(destinations.len(), 3);
+        assert!(!destinations.contains(&banned_node_id));
+
+        // Banned and unknown peers can't be sent to directly
+        match outbound_message_service.send(
+            BroadcastStrategy::Direct(banned_node_id),
+            IdentityFlags::ENCRYPTED,
+            &body,
+            &mut rng,
+        ) {
+            Err(OutboundError::NoPeersSelected) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match outbound_message_service.send(
+            BroadcastStrategy::Direct(NodeId::new()),
+            IdentityFlags::ENCRYPTED,
+            &body,
+            &mut rng,
+        ) {
+            Err(OutboundError::PeerManagerError(PeerManagerError::PeerNotFoundError)) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
 }
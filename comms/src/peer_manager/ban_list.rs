@@ -19,3 +19,750 @@
 //  SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
 //  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    connection::{peer_connection::ConnectionFilter, NetAddress},
+    peer_manager::{
+        manager::{PeerManager, PeerManagerError},
+        node_id::NodeId,
+    },
+};
+use chrono::{prelude::*, Duration};
+use derive_error::Error;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+};
+use tari_crypto::keys::PublicKey;
+use tari_storage::keyvalue_store::{DataStore, DatastoreError};
+
+const DEFAULT_BAN_THRESHOLD: u32 = 100;
+const DEFAULT_BAN_DURATION_HOURS: i64 = 24;
+const DEFAULT_SCORE_PERIOD_HOURS: i64 = 1;
+const DEFAULT_EXPIRY_INTERVAL_SECS: i64 = 60;
+
+/// The datastore key of the list of bans
+const BANS_KEY: &str = "bans";
+
+const INVALID_SIGNATURE_SCORE: u32 = 25;
+const INVALID_BLOCK_SCORE: u32 = 100;
+const INVALID_TRANSACTION_SCORE: u32 = 50;
+const PROTOCOL_VIOLATION_SCORE: u32 = 10;
+
+#[derive(Debug, Error)]
+pub enum BanListError {
+    /// The banned peer could not be updated in the peer manager
+    PeerManagerError(PeerManagerError),
+    /// The bans could not be read from or written to the datastore
+    DatastoreError(DatastoreError),
+    /// The ban list lock has been poisoned by a thread that panicked while holding it
+    PoisonedAccess,
+}
+
+/// The kinds of misbehaviour that count towards the misbehaviour score of a peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Misbehaviour {
+    /// The peer sent a message with a signature that does not match the message
+    InvalidSignature,
+    /// The peer sent a block that failed validation
+    InvalidBlock,
+    /// The peer sent a transaction that failed validation
+    InvalidTransaction,
+    /// The peer sent a message that is not allowed by the protocol, such as a malformed message or an unsolicited
+    /// response
+    ProtocolViolation,
+}
+
+impl Misbehaviour {
+    /// The score that the misbehaviour adds to the misbehaviour score of the peer
+    pub fn score(&self) -> u32 {
+        match self {
+            Misbehaviour::InvalidSignature => INVALID_SIGNATURE_SCORE,
+            Misbehaviour::InvalidBlock => INVALID_BLOCK_SCORE,
+            Misbehaviour::InvalidTransaction => INVALID_TRANSACTION_SCORE,
+            Misbehaviour::ProtocolViolation => PROTOCOL_VIOLATION_SCORE,
+        }
+    }
+}
+
+/// The reason that a peer or net address was banned
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum BanReason {
+    /// The misbehaviour score of the peer reached the ban threshold, the misbehaviour is the one that was reported
+    /// last
+    Misbehaviour(Misbehaviour),
+    /// The ban was placed by the node operator
+    Manual(String),
+}
+
+/// A banned peer is identified by its node id, and a banned host by its net address
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum BanTarget {
+    NodeId(NodeId),
+    NetAddress(NetAddress),
+}
+
+/// A ban of a peer or net address, bans without an expiry time are permanent
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Ban {
+    pub target: BanTarget,
+    pub reason: BanReason,
+    pub banned_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Ban {
+    /// Returns true if the ban has expired at the given time
+    fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.map_or(false, |expires_at| expires_at <= now)
+    }
+
+    /// Returns true if the ban is of an IP net address of the host
+    fn is_of_host(&self, ip: &IpAddr) -> bool {
+        match &self.target {
+            BanTarget::NetAddress(NetAddress::IP(socket_address)) => socket_address.ip() == *ip,
+            _ => false,
+        }
+    }
+}
+
+/// The configuration of the ban list
+#[derive(Clone, Debug)]
+pub struct BanListConfig {
+    /// Peers are banned once their misbehaviour score reaches this threshold
+    pub ban_threshold: u32,
+    /// The duration of the bans that are placed when the ban threshold is reached
+    pub ban_duration: Duration,
+    /// Misbehaviour is forgotten if the peer did not misbehave again within this period
+    pub score_period: Duration,
+    /// The interval at which the expiry service removes expired bans
+    pub expiry_interval: Duration,
+}
+
+impl Default for BanListConfig {
+    fn default() -> Self {
+        BanListConfig {
+            ban_threshold: DEFAULT_BAN_THRESHOLD,
+            ban_duration: Duration::hours(DEFAULT_BAN_DURATION_HOURS),
+            score_period: Duration::hours(DEFAULT_SCORE_PERIOD_HOURS),
+            expiry_interval: Duration::seconds(DEFAULT_EXPIRY_INTERVAL_SECS),
+        }
+    }
+}
+
+/// The accumulated misbehaviour score of a peer
+struct MisbehaviourScore {
+    score: u32,
+    last_reported_at: DateTime<Utc>,
+}
+
+struct BanListState<DS> {
+    datastore: DS,
+    bans: Vec<Ban>,
+    scores: HashMap<NodeId, MisbehaviourScore>,
+}
+
+impl<DS: DataStore> BanListState<DS> {
+    fn is_banned_at(&self, target: &BanTarget, now: DateTime<Utc>) -> bool {
+        self.bans
+            .iter()
+            .any(|ban| ban.target == *target && !ban.is_expired_at(now))
+    }
+
+    fn is_host_banned_at(&self, ip: &IpAddr, now: DateTime<Utc>) -> bool {
+        self.bans
+            .iter()
+            .any(|ban| ban.is_of_host(ip) && !ban.is_expired_at(now))
+    }
+
+    /// Replace the bans, writing them through to the datastore
+    fn set_bans(&mut self, bans: Vec<Ban>) -> Result<(), BanListError> {
+        self.datastore.put(BANS_KEY, &bans)?;
+        self.bans = bans;
+        Ok(())
+    }
+
+    /// Add the bans to the ban list, replacing any existing bans of the same targets
+    fn insert_bans(&mut self, new_bans: Vec<Ban>) -> Result<(), BanListError> {
+        let mut bans: Vec<Ban> = self
+            .bans
+            .iter()
+            .filter(|ban| !new_bans.iter().any(|new_ban| new_ban.target == ban.target))
+            .cloned()
+            .collect();
+        bans.extend(new_bans);
+        self.set_bans(bans)
+    }
+}
+
+/// The BanList keeps track of the peers and net addresses that are banned, together with the reason and the expiry
+/// time of each ban. Misbehaviour that is reported for a peer adds to its misbehaviour score, and the peer is banned
+/// for the configured ban duration once its score reaches the ban threshold. Banning a peer also bans the net
+/// addresses that are known for it and sets the banned flag of the peer in the peer manager, so that messages are no
+/// longer sent to it. Inbound connections from the hosts of banned net addresses, including the net addresses of banned
+/// peers, are refused by using the BanList as the ConnectionFilter of the inbound peer connections.
+///
+/// Bans are kept in memory and written through to a DataStore, so that they survive a restart of the node. Misbehaviour
+/// scores are not stored. Expired bans are removed by the expiry service.
+pub struct BanList<PubKey, DS>
+where PubKey: PublicKey
+{
+    peer_manager: Arc<PeerManager<PubKey, DS>>,
+    config: BanListConfig,
+    state: Mutex<BanListState<DS>>,
+}
+
+impl<PubKey, DS> BanList<PubKey, DS>
+where
+    PubKey: PublicKey,
+    DS: DataStore,
+{
+    /// Construct a BanList that bans the peers in the peer manager, loading any bans that were stored in the datastore
+    pub fn new(
+        peer_manager: Arc<PeerManager<PubKey, DS>>,
+        datastore: DS,
+        config: BanListConfig,
+    ) -> Result<BanList<PubKey, DS>, BanListError>
+    {
+        let bans: Vec<Ban> = datastore.get(BANS_KEY)?.unwrap_or_default();
+        Ok(BanList {
+            peer_manager,
+            config,
+            state: Mutex::new(BanListState {
+                datastore,
+                bans,
+                scores: HashMap::new(),
+            }),
+        })
+    }
+
+    /// The peer manager of the banned peers
+    pub fn peer_manager(&self) -> &Arc<PeerManager<PubKey, DS>> {
+        &self.peer_manager
+    }
+
+    /// The configuration of the ban list
+    pub fn config(&self) -> &BanListConfig {
+        &self.config
+    }
+
+    /// Add the score of the misbehaviour to the misbehaviour score of the peer, and ban the peer if its score has
+    /// reached the ban threshold
+    /// # Returns
+    /// True if the peer was banned because of the misbehaviour
+    pub fn report_misbehaviour(&self, node_id: &NodeId, misbehaviour: Misbehaviour) -> Result<bool, BanListError> {
+        self.report_misbehaviour_at(node_id, misbehaviour, Utc::now())
+    }
+
+    fn report_misbehaviour_at(
+        &self,
+        node_id: &NodeId,
+        misbehaviour: Misbehaviour,
+        now: DateTime<Utc>,
+    ) -> Result<bool, BanListError>
+    {
+        {
+            let mut state = self.acquire_state_lock()?;
+            if state.is_banned_at(&BanTarget::NodeId(node_id.clone()), now) {
+                return Ok(false);
+            }
+            let score_period = self.config.score_period;
+            let score = state.scores.entry(node_id.clone()).or_insert(MisbehaviourScore {
+                score: 0,
+                last_reported_at: now,
+            });
+            if score.last_reported_at + score_period <= now {
+                score.score = 0;
+            }
+            score.score = score.score.saturating_add(misbehaviour.score());
+            score.last_reported_at = now;
+            if score.score < self.config.ban_threshold {
+                return Ok(false);
+            }
+        }
+        let ban_duration = self.config.ban_duration;
+        self.ban_peer_at(node_id, BanReason::Misbehaviour(misbehaviour), Some(ban_duration), now)?;
+        Ok(true)
+    }
+
+    /// The current misbehaviour score of the peer
+    pub fn misbehaviour_score(&self, node_id: &NodeId) -> Result<u32, BanListError> {
+        self.misbehaviour_score_at(node_id, Utc::now())
+    }
+
+    fn misbehaviour_score_at(&self, node_id: &NodeId, now: DateTime<Utc>) -> Result<u32, BanListError> {
+        let state = self.acquire_state_lock()?;
+        Ok(match state.scores.get(node_id) {
+            Some(score) if score.last_reported_at + self.config.score_period > now => score.score,
+            _ => 0,
+        })
+    }
+
+    /// Ban the peer, and the net addresses that are known for it, for the given duration. The ban is permanent if no
+    /// duration is given. An existing ban of the peer or its net addresses is replaced.
+    pub fn ban_peer(
+        &self,
+        node_id: &NodeId,
+        reason: BanReason,
+        duration: Option<Duration>,
+    ) -> Result<(), BanListError>
+    {
+        self.ban_peer_at(node_id, reason, duration, Utc::now())
+    }
+
+    fn ban_peer_at(
+        &self,
+        node_id: &NodeId,
+        reason: BanReason,
+        duration: Option<Duration>,
+        now: DateTime<Utc>,
+    ) -> Result<(), BanListError>
+    {
+        let net_addresses = match self.peer_manager.modify_peer(node_id, |peer| {
+            peer.set_banned(true);
+            peer.addresses.net_addresses()
+        }) {
+            Ok(net_addresses) => net_addresses,
+            // Peers that are not known yet can still be banned by their node id
+            Err(PeerManagerError::PeerNotFoundError) => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut state = self.acquire_state_lock()?;
+        state.scores.remove(node_id);
+        let bans = Some(BanTarget::NodeId(node_id.clone()))
+            .into_iter()
+            .chain(net_addresses.into_iter().map(BanTarget::NetAddress))
+            .map(|target| Ban {
+                target,
+                reason: reason.clone(),
+                banned_at: now,
+                expires_at: duration.map(|duration| now + duration),
+            })
+            .collect();
+        state.insert_bans(bans)
+    }
+
+    /// Ban the net address for the given duration. The ban is permanent if no duration is given. An existing ban of the
+    /// net address is replaced.
+    pub fn ban_net_address(
+        &self,
+        net_address: &NetAddress,
+        reason: BanReason,
+        duration: Option<Duration>,
+    ) -> Result<(), BanListError>
+    {
+        let now = Utc::now();
+        let mut state = self.acquire_state_lock()?;
+        state.insert_bans(vec![Ban {
+            target: BanTarget::NetAddress(net_address.clone()),
+            reason,
+            banned_at: now,
+            expires_at: duration.map(|duration| now + duration),
+        }])
+    }
+
+    /// Lift the ban of the peer or net address. Lifting the ban of a peer clears its banned flag in the peer manager,
+    /// the bans of its net addresses have to be lifted separately.
+    /// # Returns
+    /// False if the target was not banned
+    pub fn lift_ban(&self, target: &BanTarget) -> Result<bool, BanListError> {
+        let lifted = {
+            let mut state = self.acquire_state_lock()?;
+            let bans: Vec<Ban> = state.bans.iter().filter(|ban| ban.target != *target).cloned().collect();
+            let lifted = bans.len() < state.bans.len();
+            if lifted {
+                state.set_bans(bans)?;
+            }
+            lifted
+        };
+        if let BanTarget::NodeId(node_id) = target {
+            self.clear_banned_flag(node_id)?;
+        }
+        Ok(lifted)
+    }
+
+    /// The bans that have not expired yet
+    pub fn bans(&self) -> Result<Vec<Ban>, BanListError> {
+        self.bans_at(Utc::now())
+    }
+
+    fn bans_at(&self, now: DateTime<Utc>) -> Result<Vec<Ban>, BanListError> {
+        let state = self.acquire_state_lock()?;
+        Ok(state
+            .bans
+            .iter()
+            .filter(|ban| !ban.is_expired_at(now))
+            .cloned()
+            .collect())
+    }
+
+    /// Returns true if the peer is currently banned
+    pub fn is_banned(&self, node_id: &NodeId) -> Result<bool, BanListError> {
+        let state = self.acquire_state_lock()?;
+        Ok(state.is_banned_at(&BanTarget::NodeId(node_id.clone()), Utc::now()))
+    }
+
+    /// Returns true if the net address is currently banned
+    pub fn is_net_address_banned(&self, net_address: &NetAddress) -> Result<bool, BanListError> {
+        let state = self.acquire_state_lock()?;
+        Ok(state.is_banned_at(&BanTarget::NetAddress(net_address.clone()), Utc::now()))
+    }
+
+    /// Remove the bans that have expired, and clear the banned flag of the peers whose ban has expired
+    /// # Returns
+    /// The bans that were removed
+    pub fn expire_bans(&self) -> Result<Vec<Ban>, BanListError> {
+        self.expire_bans_at(Utc::now())
+    }
+
+    fn expire_bans_at(&self, now: DateTime<Utc>) -> Result<Vec<Ban>, BanListError> {
+        let expired_bans: Vec<Ban> = {
+            let mut state = self.acquire_state_lock()?;
+            let (expired_bans, bans): (Vec<Ban>, Vec<Ban>) =
+                state.bans.iter().cloned().partition(|ban| ban.is_expired_at(now));
+            if !expired_bans.is_empty() {
+                state.set_bans(bans)?;
+            }
+            expired_bans
+        };
+        for ban in &expired_bans {
+            if let BanTarget::NodeId(node_id) = &ban.target {
+                self.clear_banned_flag(node_id)?;
+            }
+        }
+        Ok(expired_bans)
+    }
+
+    /// Returns true if an inbound connection from the peer with the node id, if it is known, may be accepted from the
+    /// remote address. The connection is refused if the peer is banned or has the banned flag set in the peer manager,
+    /// or if an IP net address of the remote host is banned. Inbound connections come from an ephemeral port, so the
+    /// ban of an IP net address refuses connections from any port of its host.
+    pub fn allow_connection(
+        &self,
+        node_id: Option<&NodeId>,
+        remote_address: Option<&IpAddr>,
+    ) -> Result<bool, BanListError>
+    {
+        if let Some(node_id) = node_id {
+            match self.peer_manager.find_with_node_id(node_id) {
+                Ok(peer) if peer.is_banned() => return Ok(false),
+                Ok(_) | Err(PeerManagerError::PeerNotFoundError) => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let state = self.acquire_state_lock()?;
+        let now = Utc::now();
+        let node_id_banned = node_id.map_or(false, |node_id| {
+            state.is_banned_at(&BanTarget::NodeId(node_id.clone()), now)
+        });
+        let host_banned = remote_address.map_or(false, |ip| state.is_host_banned_at(ip, now));
+        Ok(!node_id_banned && !host_banned)
+    }
+
+    /// Release the underlying datastore
+    pub fn into_datastore(self) -> Result<DS, BanListError> {
+        Ok(self
+            .state
+            .into_inner()
+            .map_err(|_| BanListError::PoisonedAccess)?
+            .datastore)
+    }
+
+    fn clear_banned_flag(&self, node_id: &NodeId) -> Result<(), BanListError> {
+        match self.peer_manager.modify_peer(node_id, |peer| peer.set_banned(false)) {
+            Ok(()) | Err(PeerManagerError::PeerNotFoundError) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn acquire_state_lock(&self) -> Result<MutexGuard<BanListState<DS>>, BanListError> {
+        self.state.lock().map_err(|_| BanListError::PoisonedAccess)
+    }
+}
+
+impl<PubKey, DS> BanList<PubKey, DS>
+where
+    PubKey: PublicKey + Send + Sync + 'static,
+    DS: DataStore + Send + Sync + 'static,
+{
+    /// Remove expired bans from a new thread at the configured expiry interval. The thread stops once the ban list has
+    /// been dropped everywhere else.
+    pub fn start_expiry_service(ban_list: &Arc<BanList<PubKey, DS>>) -> JoinHandle<()> {
+        let interval = ban_list
+            .config
+            .expiry_interval
+            .to_std()
+            .unwrap_or_else(|_| std::time::Duration::from_secs(DEFAULT_EXPIRY_INTERVAL_SECS as u64));
+        let ban_list = Arc::downgrade(ban_list);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match ban_list.upgrade() {
+                // A failed expiry is retried at the next interval
+                Some(ban_list) => {
+                    let _ = ban_list.expire_bans();
+                },
+                None => break,
+            }
+        })
+    }
+}
+
+impl<PubKey, DS> ConnectionFilter for BanList<PubKey, DS>
+where
+    PubKey: PublicKey + Send + Sync,
+    DS: DataStore + Send + Sync,
+{
+    /// The connection id is not authenticated, as the peer that connects can claim any identity, so only the remote
+    /// address of the connection is checked. A banned peer is refused when it connects from the host of one of the net
+    /// addresses that were banned with it, but not from a host that the ban list doesn't know about. The connection is
+    /// refused if the ban list can't be accessed.
+    fn allow_connection(&self, _connection_id: &[u8], remote_address: Option<&IpAddr>) -> bool {
+        BanList::allow_connection(self, None, remote_address).unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        connection::net_address::{net_address_with_stats::NetAddressWithStats, net_addresses::NetAddresses},
+        peer_manager::peer::{Peer, PeerFlags},
+    };
+    use tari_crypto::ristretto::RistrettoPublicKey;
+    use tari_storage::hashmap_store::HashmapStore;
+
+    fn create_peer(net_addresses: &[NetAddress]) -> Peer<RistrettoPublicKey> {
+        let (_sk, pk) = RistrettoPublicKey::random_keypair(&mut rand::OsRng::new().unwrap());
+        let node_id = NodeId::from_key(&pk).unwrap();
+        let addresses = NetAddresses::new(net_addresses.iter().cloned().map(NetAddressWithStats::from).collect());
+        Peer::new(pk, node_id, addresses, PeerFlags::default())
+    }
+
+    fn create_ban_list(peers: &[Peer<RistrettoPublicKey>]) -> BanList<RistrettoPublicKey, HashmapStore> {
+        let node_id = create_peer(&[]).node_id;
+        let peer_manager = Arc::new(PeerManager::new(node_id, HashmapStore::new()).unwrap());
+        for peer in peers {
+            peer_manager.add_peer(peer.clone()).unwrap();
+        }
+        BanList::new(peer_manager, HashmapStore::new(), BanListConfig::default()).unwrap()
+    }
+
+    fn is_peer_flagged(ban_list: &BanList<RistrettoPublicKey, HashmapStore>, node_id: &NodeId) -> bool {
+        ban_list.peer_manager().find_with_node_id(node_id).unwrap().is_banned()
+    }
+
+    #[test]
+    fn test_misbehaviour_ban() {
+        let net_address = "123.0.0.123:8000".parse::<NetAddress>().unwrap();
+        let peer = create_peer(&[net_address.clone()]);
+        let ban_list = create_ban_list(&[peer.clone()]);
+        let now = Utc::now();
+
+        // Misbehaviour adds up until the ban threshold is reached
+        for i in 0..3 {
+            assert!(!ban_list
+                .report_misbehaviour_at(&peer.node_id, Misbehaviour::InvalidSignature, now)
+                .unwrap());
+            assert_eq!(
+                ban_list.misbehaviour_score_at(&peer.node_id, now).unwrap(),
+                (i + 1) * INVALID_SIGNATURE_SCORE
+            );
+        }
+        assert!(!is_peer_flagged(&ban_list, &peer.node_id));
+        assert!(ban_list
+            .report_misbehaviour_at(&peer.node_id, Misbehaviour::InvalidSignature, now)
+            .unwrap());
+        assert_eq!(ban_list.misbehaviour_score_at(&peer.node_id, now).unwrap(), 0);
+
+        // The peer and its net address are banned for the configured duration
+        assert!(is_peer_flagged(&ban_list, &peer.node_id));
+        assert!(ban_list.is_banned(&peer.node_id).unwrap());
+        assert!(ban_list.is_net_address_banned(&net_address).unwrap());
+        let bans = ban_list.bans_at(now).unwrap();
+        assert_eq!(bans.len(), 2);
+        for ban in &bans {
+            assert_eq!(ban.reason, BanReason::Misbehaviour(Misbehaviour::InvalidSignature));
+            assert_eq!(ban.expires_at, Some(now + ban_list.config().ban_duration));
+        }
+        let ip = "123.0.0.123".parse::<IpAddr>().unwrap();
+        let other_ip = "125.1.54.254".parse::<IpAddr>().unwrap();
+        assert!(!ban_list.allow_connection(Some(&peer.node_id), Some(&ip)).unwrap());
+        assert!(!ban_list.allow_connection(Some(&peer.node_id), Some(&other_ip)).unwrap());
+        assert!(!ban_list.allow_connection(None, Some(&ip)).unwrap());
+        assert!(ban_list.allow_connection(None, Some(&other_ip)).unwrap());
+
+        // Banned peers don't accumulate misbehaviour
+        assert!(!ban_list
+            .report_misbehaviour_at(&peer.node_id, Misbehaviour::InvalidBlock, now)
+            .unwrap());
+        assert_eq!(ban_list.misbehaviour_score_at(&peer.node_id, now).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_misbehaviour_score_period() {
+        let peer = create_peer(&[]);
+        let ban_list = create_ban_list(&[peer.clone()]);
+        let now = Utc::now();
+        let score_period = ban_list.config().score_period;
+
+        ban_list
+            .report_misbehaviour_at(&peer.node_id, Misbehaviour::InvalidTransaction, now)
+            .unwrap();
+        assert_eq!(
+            ban_list.misbehaviour_score_at(&peer.node_id, now).unwrap(),
+            INVALID_TRANSACTION_SCORE
+        );
+        assert_eq!(
+            ban_list
+                .misbehaviour_score_at(&peer.node_id, now + score_period)
+                .unwrap(),
+            0
+        );
+        // Misbehaviour that was reported before the score period is forgotten
+        assert!(!ban_list
+            .report_misbehaviour_at(&peer.node_id, Misbehaviour::InvalidTransaction, now + score_period)
+            .unwrap());
+        assert_eq!(
+            ban_list
+                .misbehaviour_score_at(&peer.node_id, now + score_period)
+                .unwrap(),
+            INVALID_TRANSACTION_SCORE
+        );
+    }
+
+    #[test]
+    fn test_expire_and_lift_bans() {
+        let net_address = "123.0.0.123:8000".parse::<NetAddress>().unwrap();
+        let peer = create_peer(&[net_address.clone()]);
+        let unknown_peer = create_peer(&[]);
+        let ban_list = create_ban_list(&[peer.clone()]);
+        let now = Utc::now();
+
+        ban_list
+            .ban_peer_at(
+                &peer.node_id,
+                BanReason::Manual("Spam".to_string()),
+                Some(Duration::hours(1)),
+                now,
+            )
+            .unwrap();
+        ban_list
+            .ban_peer_at(&unknown_peer.node_id, BanReason::Manual("Spam".to_string()), None, now)
+            .unwrap();
+        assert_eq!(ban_list.bans_at(now).unwrap().len(), 3);
+
+        // Only the temporary bans expire, and the banned flag of the peer is cleared
+        assert!(ban_list.expire_bans_at(now + Duration::minutes(59)).unwrap().is_empty());
+        assert!(is_peer_flagged(&ban_list, &peer.node_id));
+        assert_eq!(ban_list.expire_bans_at(now + Duration::hours(1)).unwrap().len(), 2);
+        assert!(!is_peer_flagged(&ban_list, &peer.node_id));
+        assert_eq!(ban_list.bans_at(now + Duration::hours(1)).unwrap(), vec![Ban {
+            target: BanTarget::NodeId(unknown_peer.node_id.clone()),
+            reason: BanReason::Manual("Spam".to_string()),
+            banned_at: now,
+            expires_at: None,
+        }]);
+
+        // Bans can be lifted manually
+        ban_list
+            .ban_net_address(&net_address, BanReason::Manual("Spam".to_string()), None)
+            .unwrap();
+        assert!(ban_list.is_net_address_banned(&net_address).unwrap());
+        assert!(ban_list.lift_ban(&BanTarget::NetAddress(net_address.clone())).unwrap());
+        assert!(!ban_list.is_net_address_banned(&net_address).unwrap());
+        assert!(ban_list
+            .lift_ban(&BanTarget::NodeId(unknown_peer.node_id.clone()))
+            .unwrap());
+        assert!(!ban_list
+            .lift_ban(&BanTarget::NodeId(unknown_peer.node_id.clone()))
+            .unwrap());
+        assert!(ban_list.bans().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_connection_filter() {
+        let net_address = "123.0.0.123:8000".parse::<NetAddress>().unwrap();
+        let peer = create_peer(&[net_address.clone()]);
+        let other_peer = create_peer(&[]);
+        let ban_list = create_ban_list(&[peer.clone()]);
+        let filter: &dyn ConnectionFilter = &ban_list;
+        // The peer connects from an ephemeral port of the host of its net address
+        let ip = "123.0.0.123".parse::<IpAddr>().unwrap();
+        let other_ip = "125.1.54.254".parse::<IpAddr>().unwrap();
+
+        ban_list
+            .ban_peer(&peer.node_id, BanReason::Manual("Spam".to_string()), None)
+            .unwrap();
+        // Connections are refused on the host they come from, whatever identity they claim
+        assert!(!filter.allow_connection(peer.node_id.as_bytes(), Some(&ip)));
+        assert!(!filter.allow_connection(other_peer.node_id.as_bytes(), Some(&ip)));
+        assert!(!filter.allow_connection(&[1, 2, 3], Some(&ip)));
+        // The connection id is not trusted, so a banned peer that connects from another host is not refused
+        assert!(filter.allow_connection(peer.node_id.as_bytes(), Some(&other_ip)));
+        assert!(filter.allow_connection(other_peer.node_id.as_bytes(), Some(&other_ip)));
+        assert!(filter.allow_connection(peer.node_id.as_bytes(), None));
+    }
+
+    #[test]
+    fn test_restore_from_datastore() {
+        let net_address = "123.0.0.123:8000".parse::<NetAddress>().unwrap();
+        let peer = create_peer(&[]);
+        let ban_list = create_ban_list(&[peer.clone()]);
+        ban_list
+            .ban_peer(&peer.node_id, BanReason::Manual("Spam".to_string()), None)
+            .unwrap();
+        ban_list
+            .ban_net_address(
+                &net_address,
+                BanReason::Manual("Spam".to_string()),
+                Some(Duration::hours(1)),
+            )
+            .unwrap();
+        let bans = ban_list.bans().unwrap();
+        let peer_manager = ban_list.peer_manager().clone();
+
+        let ban_list = BanList::new(
+            peer_manager,
+            ban_list.into_datastore().unwrap(),
+            BanListConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(ban_list.bans().unwrap(), bans);
+        assert!(ban_list.is_banned(&peer.node_id).unwrap());
+        assert!(ban_list.is_net_address_banned(&net_address).unwrap());
+    }
+
+    #[test]
+    fn test_expiry_service() {
+        let peer = create_peer(&[]);
+        let node_id = create_peer(&[]).node_id;
+        let peer_manager = Arc::new(PeerManager::new(node_id, HashmapStore::new()).unwrap());
+        peer_manager.add_peer(peer.clone()).unwrap();
+        let config = BanListConfig {
+            expiry_interval: Duration::milliseconds(10),
+            ..Default::default()
+        };
+        let ban_list = Arc::new(BanList::new(peer_manager, HashmapStore::new(), config).unwrap());
+        let expiry_service = BanList::start_expiry_service(&ban_list);
+        ban_list
+            .ban_peer(
+                &peer.node_id,
+                BanReason::Manual("Spam".to_string()),
+                Some(Duration::milliseconds(50)),
+            )
+            .unwrap();
+        assert!(is_peer_flagged(&ban_list, &peer.node_id));
+
+        // The expired ban is removed and the banned flag of the peer is cleared without calling expire_bans
+        thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(ban_list.state.lock().unwrap().bans.len(), 0);
+        assert!(!is_peer_flagged(&ban_list, &peer.node_id));
+
+        // The service stops once the ban list is dropped
+        drop(ban_list);
+        expiry_service.join().unwrap();
+    }
+}
@@ -19,3 +19,310 @@
 //  SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
 //  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    connection::{net_address::NetAddressError, NetAddress},
+    peer_manager::{
+        manager::{PeerManager, PeerManagerError},
+        node_id::NodeId,
+    },
+};
+use chrono::{prelude::*, Duration};
+use derive_error::Error;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tari_crypto::keys::PublicKey;
+use tari_storage::keyvalue_store::DataStore;
+
+#[derive(Debug, Error)]
+pub enum LivenessError {
+    /// The peer could not be found or updated in the peer manager
+    PeerManagerError(PeerManagerError),
+    /// The stats of a net address of the peer could not be updated
+    NetAddressError(NetAddressError),
+    /// A ping with the same nonce is already pending
+    DuplicateNonce,
+    /// The pending pings lock has been poisoned by a thread that panicked while holding it
+    PoisonedAccess,
+}
+
+/// A ping that has been sent to a peer and has not been answered yet
+struct PendingPing {
+    node_id: NodeId,
+    net_address: NetAddress,
+    sent_at: DateTime<Utc>,
+}
+
+/// PeerLiveness keeps track of the pings that are sent to peers, and feeds the outcome of each ping into the usage
+/// stats of the net address it was sent to. The pong that answers a ping updates the latency and last seen time of
+/// the address, and a ping that is not answered within the ping timeout counts as a failed connection attempt. As
+/// `NetAddresses::get_best_net_address` ranks addresses by these stats, pings keep the best net address of a peer up
+/// to date.
+///
+/// A peer is marked as offline once all of its net addresses have failed repeatedly. Offline peers are not probed
+/// until they are heard from again.
+pub struct PeerLiveness<PubKey, DS>
+where PubKey: PublicKey
+{
+    peer_manager: Arc<PeerManager<PubKey, DS>>,
+    ping_timeout: Duration,
+    pending_pings: Mutex<HashMap<u64, PendingPing>>,
+}
+
+impl<PubKey, DS> PeerLiveness<PubKey, DS>
+where
+    PubKey: PublicKey,
+    DS: DataStore,
+{
+    /// Construct a PeerLiveness that updates the peers in the peer manager, and that considers pings that are not
+    /// answered within the ping timeout as failed
+    pub fn new(peer_manager: Arc<PeerManager<PubKey, DS>>, ping_timeout: Duration) -> PeerLiveness<PubKey, DS> {
+        PeerLiveness {
+            peer_manager,
+            ping_timeout,
+            pending_pings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The node ids of the peers that should be probed, which are the peers that are neither banned nor offline
+    pub fn probe_targets(&self) -> Result<Vec<NodeId>, LivenessError> {
+        Ok(self
+            .peer_manager
+            .peers()?
+            .into_iter()
+            .filter(|peer| !peer.is_banned() && !peer.is_offline())
+            .map(|peer| peer.node_id)
+            .collect())
+    }
+
+    /// Record that a ping with the nonce is about to be sent to the peer. The ping is attributed to the best net
+    /// address of the peer.
+    /// # Returns
+    /// False if the peer has no net address that can be probed, in which case the ping should not be sent
+    pub fn ping_sent(&self, node_id: &NodeId, nonce: u64) -> Result<bool, LivenessError> {
+        self.ping_sent_at(node_id, nonce, Utc::now())
+    }
+
+    fn ping_sent_at(&self, node_id: &NodeId, nonce: u64, now: DateTime<Utc>) -> Result<bool, LivenessError> {
+        let net_address = match self
+            .peer_manager
+            .modify_peer(node_id, |peer| peer.addresses.get_best_net_address())?
+        {
+            Ok(net_address) => net_address,
+            Err(_) => return Ok(false),
+        };
+        let mut pending_pings = self.pending_pings.lock().map_err(|_| LivenessError::PoisonedAccess)?;
+        if pending_pings.contains_key(&nonce) {
+            return Err(LivenessError::DuplicateNonce);
+        }
+        pending_pings.insert(nonce, PendingPing {
+            node_id: node_id.clone(),
+            net_address,
+            sent_at: now,
+        });
+        Ok(true)
+    }
+
+    /// Record the pong with the nonce that was received from the peer. The round trip time of the ping is included in
+    /// the average latency of the net address it was sent to, and the peer is no longer offline.
+    /// # Returns
+    /// False if the pong does not answer a pending ping that was sent to the peer
+    pub fn pong_received(&self, node_id: &NodeId, nonce: u64) -> Result<bool, LivenessError> {
+        self.pong_received_at(node_id, nonce, Utc::now())
+    }
+
+    fn pong_received_at(&self, node_id: &NodeId, nonce: u64, now: DateTime<Utc>) -> Result<bool, LivenessError> {
+        let ping = {
+            let mut pending_pings = self.pending_pings.lock().map_err(|_| LivenessError::PoisonedAccess)?;
+            match pending_pings.get(&nonce) {
+                Some(ping) if ping.node_id == *node_id => pending_pings.remove(&nonce),
+                _ => None,
+            }
+        };
+        let ping = match ping {
+            Some(ping) => ping,
+            None => return Ok(false),
+        };
+        let latency = now.signed_duration_since(ping.sent_at).to_std().unwrap_or_default();
+        self.peer_manager
+            .modify_peer(node_id, |peer| -> Result<(), NetAddressError> {
+                peer.set_offline(false);
+                peer.addresses.update_latency(&ping.net_address, latency)?;
+                peer.addresses.successful_connection_attempt(&ping.net_address)
+            })??;
+        Ok(true)
+    }
+
+    /// Count the pings that have not been answered within the ping timeout as failed connection attempts, and mark the
+    /// peers whose net addresses have all failed repeatedly as offline
+    /// # Returns
+    /// The node ids of the peers that were marked as offline
+    pub fn expire_pings(&self) -> Result<Vec<NodeId>, LivenessError> {
+        self.expire_pings_at(Utc::now())
+    }
+
+    fn expire_pings_at(&self, now: DateTime<Utc>) -> Result<Vec<NodeId>, LivenessError> {
+        let expired_pings: Vec<PendingPing> = {
+            let mut pending_pings = self.pending_pings.lock().map_err(|_| LivenessError::PoisonedAccess)?;
+            let ping_timeout = self.ping_timeout;
+            let expired_nonces: Vec<u64> = pending_pings
+                .iter()
+                .filter(|(_, ping)| ping.sent_at + ping_timeout <= now)
+                .map(|(nonce, _)| *nonce)
+                .collect();
+            expired_nonces
+                .iter()
+                .filter_map(|nonce| pending_pings.remove(nonce))
+                .collect()
+        };
+        let mut offline_node_ids = Vec::new();
+        for ping in expired_pings {
+            let result = self
+                .peer_manager
+                .modify_peer(&ping.node_id, |peer| -> Result<bool, NetAddressError> {
+                    peer.addresses.failed_connection_attempt(&ping.net_address)?;
+                    match peer.addresses.get_best_net_address() {
+                        Err(NetAddressError::ConnectionAttemptsExceeded) if !peer.is_offline() => {
+                            peer.set_offline(true);
+                            Ok(true)
+                        },
+                        _ => Ok(false),
+                    }
+                });
+            match result {
+                Ok(Ok(true)) => offline_node_ids.push(ping.node_id),
+                Ok(Ok(false)) => {},
+                // The peer, or the net address the ping was sent to, has been removed since the ping was sent
+                Err(PeerManagerError::PeerNotFoundError) | Ok(Err(NetAddressError::AddressNotFound)) => {},
+                Err(e) => return Err(e.into()),
+                Ok(Err(e)) => return Err(e.into()),
+            }
+        }
+        Ok(offline_node_ids)
+    }
+
+    /// Record that the peer was heard from, e.g. because it sent a ping. An offline peer is no longer offline, and its
+    /// net addresses can be probed again.
+    pub fn peer_seen(&self, node_id: &NodeId) -> Result<(), LivenessError> {
+        Ok(self.peer_manager.modify_peer(node_id, |peer| {
+            if peer.is_offline() {
+                peer.set_offline(false);
+                peer.addresses.reset_connection_attempts();
+            }
+        })?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        connection::net_address::{net_address_with_stats::NetAddressWithStats, net_addresses::NetAddresses},
+        peer_manager::peer::{Peer, PeerFlags},
+    };
+    use tari_crypto::ristretto::RistrettoPublicKey;
+    use tari_storage::hashmap_store::HashmapStore;
+
+    fn create_peer(net_addresses: &[NetAddress]) -> Peer<RistrettoPublicKey> {
+        let (_sk, pk) = RistrettoPublicKey::random_keypair(&mut rand::OsRng::new().unwrap());
+        let node_id = NodeId::from_key(&pk).unwrap();
+        let addresses = NetAddresses::new(net_addresses.iter().cloned().map(NetAddressWithStats::from).collect());
+        Peer::new(pk, node_id, addresses, PeerFlags::default())
+    }
+
+    fn create_peer_liveness(peers: &[Peer<RistrettoPublicKey>]) -> PeerLiveness<RistrettoPublicKey, HashmapStore> {
+        let node_id = create_peer(&[]).node_id;
+        let peer_manager = Arc::new(PeerManager::new(node_id, HashmapStore::new()).unwrap());
+        for peer in peers {
+            peer_manager.add_peer(peer.clone()).unwrap();
+        }
+        PeerLiveness::new(peer_manager, Duration::seconds(30))
+    }
+
+    #[test]
+    fn test_ping_pong() {
+        let net_address = "123.0.0.123:8000".parse::<NetAddress>().unwrap();
+        let peer = create_peer(&[net_address.clone()]);
+        let other_peer = create_peer(&[]);
+        let peer_liveness = create_peer_liveness(&[peer.clone(), other_peer.clone()]);
+        let now = Utc::now();
+
+        // Peers without a net address can't be probed
+        assert_eq!(peer_liveness.probe_targets().unwrap().len(), 2);
+        assert!(!peer_liveness.ping_sent_at(&other_peer.node_id, 1, now).unwrap());
+
+        assert!(peer_liveness.ping_sent_at(&peer.node_id, 2, now).unwrap());
+        match peer_liveness.ping_sent_at(&peer.node_id, 2, now) {
+            Err(LivenessError::DuplicateNonce) => {},
+            result => panic!("Unexpected ping result: {:?}", result),
+        }
+        // Pongs from another peer, or with an unknown nonce, don't answer the ping
+        assert!(!peer_liveness.pong_received_at(&other_peer.node_id, 2, now).unwrap());
+        assert!(!peer_liveness.pong_received_at(&peer.node_id, 3, now).unwrap());
+
+        let pong_at = now + Duration::milliseconds(250);
+        assert!(peer_liveness.pong_received_at(&peer.node_id, 2, pong_at).unwrap());
+        assert!(!peer_liveness.pong_received_at(&peer.node_id, 2, pong_at).unwrap());
+        let mut peer = peer_liveness.peer_manager.find_with_node_id(&peer.node_id).unwrap();
+        let address = peer.addresses.find_address_mut(&net_address).unwrap();
+        assert_eq!(address.avg_latency, std::time::Duration::from_millis(250));
+        assert!(address.last_seen.is_some());
+        assert_eq!(address.connection_attempts, 0);
+    }
+
+    #[test]
+    fn test_expire_pings() {
+        let net_address1 = "123.0.0.123:8000".parse::<NetAddress>().unwrap();
+        let net_address2 = "125.1.54.254:7999".parse::<NetAddress>().unwrap();
+        let peer = create_peer(&[net_address1.clone(), net_address2.clone()]);
+        let peer_liveness = create_peer_liveness(&[peer.clone()]);
+        let now = Utc::now();
+        let net_address_attempts = |net_address: &NetAddress| {
+            let mut peer = peer_liveness.peer_manager.find_with_node_id(&peer.node_id).unwrap();
+            peer.addresses
+                .find_address_mut(net_address)
+                .unwrap()
+                .connection_attempts
+        };
+
+        // Unanswered pings only fail once the ping timeout has elapsed
+        assert!(peer_liveness.ping_sent_at(&peer.node_id, 1, now).unwrap());
+        assert!(peer_liveness
+            .expire_pings_at(now + Duration::seconds(29))
+            .unwrap()
+            .is_empty());
+        assert_eq!(net_address_attempts(&net_address1), 0);
+        assert!(peer_liveness
+            .expire_pings_at(now + Duration::seconds(30))
+            .unwrap()
+            .is_empty());
+        assert_eq!(net_address_attempts(&net_address1), 1);
+        assert!(!peer_liveness.pong_received_at(&peer.node_id, 1, now).unwrap());
+
+        // The failed ping makes the other net address the best one, so it is probed next
+        assert!(peer_liveness.ping_sent_at(&peer.node_id, 2, now).unwrap());
+        assert!(peer_liveness
+            .expire_pings_at(now + Duration::seconds(30))
+            .unwrap()
+            .is_empty());
+        assert_eq!(net_address_attempts(&net_address2), 1);
+
+        // The peer is offline once all of its net addresses have failed repeatedly
+        let mut offline_node_ids = Vec::new();
+        for nonce in 3..7 {
+            assert!(peer_liveness.ping_sent_at(&peer.node_id, nonce, now).unwrap());
+            offline_node_ids.extend(peer_liveness.expire_pings_at(now + Duration::seconds(30)).unwrap());
+        }
+        assert_eq!(offline_node_ids, vec![peer.node_id.clone()]);
+        assert!(peer_liveness.probe_targets().unwrap().is_empty());
+        assert!(!peer_liveness.ping_sent_at(&peer.node_id, 7, now).unwrap());
+
+        // Hearing from an offline peer makes it reachable again
+        peer_liveness.peer_seen(&peer.node_id).unwrap();
+        assert_eq!(peer_liveness.probe_targets().unwrap(), vec![peer.node_id.clone()]);
+        assert_eq!(net_address_attempts(&net_address1), 0);
+        assert!(peer_liveness.ping_sent_at(&peer.node_id, 8, now).unwrap());
+    }
+}
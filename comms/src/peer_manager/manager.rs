@@ -19,3 +19,407 @@
 //  SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
 //  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::peer_manager::{
+    node_id::NodeId,
+    peer::Peer,
+    routing_table::{RoutingTable, RoutingTableConfig},
+    storage::PeerStorage,
+};
+use derive_error::Error;
+use rand::Rng;
+use std::sync::RwLock;
+use tari_crypto::keys::PublicKey;
+use tari_storage::keyvalue_store::{DataStore, DatastoreError};
+
+#[derive(Debug, Error)]
+pub enum PeerManagerError {
+    /// The requested peer does not exist
+    PeerNotFoundError,
+    /// A peer with the same node id or public key already exists
+    DuplicatePeerError,
+    /// The peer database could not be read or written
+    DatastoreError(DatastoreError),
+    /// The list of stored peers refers to a peer record that does not exist
+    DataInconsistencyError,
+    /// The peer database lock has been poisoned by a thread that panicked while holding it
+    PoisonedAccess,
+}
+
+/// The PeerManager is the thread-safe store of the peers that a node knows about, including the usage stats of their
+/// net addresses and their flags. It can be shared between services with an `Arc`. Peers are kept in memory and
+/// persisted in a pluggable DataStore backend.
+///
+/// The peers that are not banned are also organised in a Kademlia RoutingTable around our own node id, which is used
+/// to find the peers closest to a node id. The routing table is kept up to date as peers are added, updated and
+/// deleted. Locks are always taken in the order peer storage, then routing table.
+pub struct PeerManager<PubKey, DS>
+where PubKey: PublicKey
+{
+    peer_storage: RwLock<PeerStorage<PubKey, DS>>,
+    routing_table: RwLock<RoutingTable>,
+}
+
+impl<PubKey, DS> PeerManager<PubKey, DS>
+where
+    PubKey: PublicKey,
+    DS: DataStore,
+{
+    /// Construct a PeerManager for the node with the given node id on top of the datastore, restoring any peers that
+    /// were stored in it
+    pub fn new(node_id: NodeId, datastore: DS) -> Result<PeerManager<PubKey, DS>, PeerManagerError> {
+        PeerManager::with_routing_table_config(node_id, datastore, RoutingTableConfig::default())
+    }
+
+    /// Construct a PeerManager with the given routing table limits and timeouts
+    pub fn with_routing_table_config(
+        node_id: NodeId,
+        datastore: DS,
+        config: RoutingTableConfig,
+    ) -> Result<PeerManager<PubKey, DS>, PeerManagerError>
+    {
+        let peer_storage = PeerStorage::new(datastore)?;
+        let mut routing_table = RoutingTable::new(node_id, config);
+        for peer in peer_storage.peers().filter(|peer| !peer.is_banned()) {
+            routing_table.add_node(peer.node_id.clone(), peer.last_seen());
+        }
+        Ok(PeerManager {
+            peer_storage: RwLock::new(peer_storage),
+            routing_table: RwLock::new(routing_table),
+        })
+    }
+
+    /// Add a new peer. A peer with the same node id or public key must not already exist.
+    pub fn add_peer(&self, peer: Peer<PubKey>) -> Result<(), PeerManagerError> {
+        let mut peer_storage = self
+            .peer_storage
+            .write()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?;
+        peer_storage.add_peer(peer.clone())?;
+        self.update_routing_table(&peer)
+    }
+
+    /// Replace the record of an existing peer
+    pub fn update_peer(&self, peer: Peer<PubKey>) -> Result<(), PeerManagerError> {
+        let mut peer_storage = self
+            .peer_storage
+            .write()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?;
+        peer_storage.update_peer(peer.clone())?;
+        self.update_routing_table(&peer)
+    }
+
+    /// Apply `f` to the record of the peer with the given node id and store the result. The peer database is locked
+    /// while `f` runs, so concurrent changes to the same peer can't be lost.
+    pub fn modify_peer<F, T>(&self, node_id: &NodeId, f: F) -> Result<T, PeerManagerError>
+    where F: FnOnce(&mut Peer<PubKey>) -> T {
+        let mut peer_storage = self
+            .peer_storage
+            .write()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?;
+        let mut peer = peer_storage.find_with_node_id(node_id)?.clone();
+        let result = f(&mut peer);
+        peer_storage.update_peer(peer.clone())?;
+        self.update_routing_table(&peer)?;
+        Ok(result)
+    }
+
+    /// Remove the peer with the given node id, returning its record
+    pub fn delete_peer(&self, node_id: &NodeId) -> Result<Peer<PubKey>, PeerManagerError> {
+        let mut peer_storage = self
+            .peer_storage
+            .write()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?;
+        let peer = peer_storage.delete_peer(node_id)?;
+        self.routing_table
+            .write()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?
+            .remove_node(node_id);
+        Ok(peer)
+    }
+
+    /// Find the peer with the given node id
+    pub fn find_with_node_id(&self, node_id: &NodeId) -> Result<Peer<PubKey>, PeerManagerError> {
+        Ok(self
+            .peer_storage
+            .read()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?
+            .find_with_node_id(node_id)?
+            .clone())
+    }
+
+    /// Find the peer with the given public key
+    pub fn find_with_public_key(&self, public_key: &PubKey) -> Result<Peer<PubKey>, PeerManagerError> {
+        Ok(self
+            .peer_storage
+            .read()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?
+            .find_with_public_key(public_key)?
+            .clone())
+    }
+
+    /// Check whether a peer with the given node id exists
+    pub fn exists(&self, node_id: &NodeId) -> Result<bool, PeerManagerError> {
+        Ok(self
+            .peer_storage
+            .read()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?
+            .find_with_node_id(node_id)
+            .is_ok())
+    }
+
+    /// A snapshot of all the peers, in the order they were added
+    pub fn peers(&self) -> Result<Vec<Peer<PubKey>>, PeerManagerError> {
+        Ok(self
+            .peer_storage
+            .read()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?
+            .peers()
+            .cloned()
+            .collect())
+    }
+
+    /// Call `f` with each peer, in the order they were added, without copying the peer records. The peer database
+    /// can't be modified while `f` runs.
+    pub fn for_each<F>(&self, f: F) -> Result<(), PeerManagerError>
+    where F: FnMut(&Peer<PubKey>) {
+        self.peer_storage
+            .read()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?
+            .peers()
+            .for_each(f);
+        Ok(())
+    }
+
+    /// The number of known peers
+    pub fn peer_count(&self) -> Result<usize, PeerManagerError> {
+        Ok(self
+            .peer_storage
+            .read()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?
+            .len())
+    }
+
+    /// The (at most) `n` peers in the routing table that are closest to the node id, ordered by distance. Banned peers
+    /// are skipped while the closest peers are selected, so they don't take the place of the peers after them.
+    pub fn closest_peers(&self, node_id: &NodeId, n: usize) -> Result<Vec<Peer<PubKey>>, PeerManagerError> {
+        let peer_storage = self.peer_storage.read().map_err(|_| PeerManagerError::PoisonedAccess)?;
+        let closest = self
+            .routing_table
+            .read()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?
+            .closest_where(node_id, n, |node_id| {
+                peer_storage
+                    .find_with_node_id(node_id)
+                    .map(|peer| !peer.is_banned())
+                    .unwrap_or(false)
+            });
+        Ok(closest
+            .iter()
+            .filter_map(|node_id| peer_storage.find_with_node_id(node_id).ok())
+            .cloned()
+            .collect())
+    }
+
+    /// Replace the peers in the routing table that have not been seen for the stale period with newer candidates,
+    /// returning the node ids of the replaced peers. The peers remain in the peer database.
+    pub fn replace_stale_routing_entries(&self) -> Result<Vec<NodeId>, PeerManagerError> {
+        Ok(self
+            .routing_table
+            .write()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?
+            .replace_stale_entries())
+    }
+
+    /// Node ids to look up to refresh the routing table buckets that have not been used for the refresh interval. The
+    /// buckets are marked as refreshed.
+    pub fn routing_refresh_targets<R: Rng>(&self, rng: &mut R) -> Result<Vec<NodeId>, PeerManagerError> {
+        let mut routing_table = self
+            .routing_table
+            .write()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?;
+        let buckets = routing_table.buckets_to_refresh();
+        let targets = buckets.iter().map(|i| routing_table.refresh_target(*i, rng)).collect();
+        for i in buckets {
+            routing_table.mark_refreshed(i);
+        }
+        Ok(targets)
+    }
+
+    /// Bring the routing table entry of the peer in line with its record: banned peers are removed, and other peers
+    /// are added or marked as seen
+    fn update_routing_table(&self, peer: &Peer<PubKey>) -> Result<(), PeerManagerError> {
+        let mut routing_table = self
+            .routing_table
+            .write()
+            .map_err(|_| PeerManagerError::PoisonedAccess)?;
+        if peer.is_banned() {
+            routing_table.remove_node(&peer.node_id);
+            return Ok(());
+        }
+        let known = match peer.last_seen() {
+            Some(last_seen) => routing_table.node_seen(&peer.node_id, last_seen),
+            None => routing_table.contains(&peer.node_id),
+        };
+        if !known {
+            routing_table.add_node(peer.node_id.clone(), peer.last_seen());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        connection::{
+            net_address::{net_address_with_stats::NetAddressWithStats, net_addresses::NetAddresses},
+            NetAddress,
+        },
+        peer_manager::peer::PeerFlags,
+    };
+    use std::{sync::Arc, thread, time::Duration};
+    use tari_crypto::ristretto::RistrettoPublicKey;
+    use tari_storage::hashmap_store::HashmapStore;
+
+    fn create_test_peer(address: &str) -> Peer<RistrettoPublicKey> {
+        let mut rng = rand::OsRng::new().unwrap();
+        let (_sk, pk) = RistrettoPublicKey::random_keypair(&mut rng);
+        let node_id = NodeId::from_key(&pk).unwrap();
+        let addresses = NetAddresses::from(address.parse::<NetAddress>().unwrap());
+        Peer::new(pk, node_id, addresses, PeerFlags::default())
+    }
+
+    #[test]
+    fn test_add_find_update_delete() {
+        let peer_manager = PeerManager::new(NodeId::new(), HashmapStore::new()).unwrap();
+        let peer1 = create_test_peer("127.0.0.1:8000");
+        let peer2 = create_test_peer("127.0.0.1:8001");
+        peer_manager.add_peer(peer1.clone()).unwrap();
+        peer_manager.add_peer(peer2.clone()).unwrap();
+        match peer_manager.add_peer(peer1.clone()) {
+            Err(PeerManagerError::DuplicatePeerError) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert_eq!(peer_manager.peer_count().unwrap(), 2);
+
+        let found = peer_manager.find_with_node_id(&peer2.node_id).unwrap();
+        assert_eq!(found.public_key, peer2.public_key);
+        let found = peer_manager.find_with_public_key(&peer1.public_key).unwrap();
+        assert_eq!(found.node_id, peer1.node_id);
+
+        let mut updated = peer1.clone();
+        updated.set_banned(true);
+        peer_manager.update_peer(updated).unwrap();
+        assert!(peer_manager.find_with_node_id(&peer1.node_id).unwrap().is_banned());
+        let address = "127.0.0.1:8000".parse::<NetAddress>().unwrap();
+        peer_manager
+            .modify_peer(&peer1.node_id, |peer| {
+                peer.addresses
+                    .update_latency(&address, Duration::from_millis(100))
+                    .unwrap()
+            })
+            .unwrap();
+        assert!(peer_manager
+            .find_with_node_id(&peer1.node_id)
+            .unwrap()
+            .last_seen()
+            .is_some());
+
+        let node_ids: Vec<NodeId> = peer_manager.peers().unwrap().into_iter().map(|p| p.node_id).collect();
+        assert_eq!(node_ids, vec![peer1.node_id.clone(), peer2.node_id.clone()]);
+
+        peer_manager.delete_peer(&peer1.node_id).unwrap();
+        assert!(!peer_manager.exists(&peer1.node_id).unwrap());
+        assert!(peer_manager.exists(&peer2.node_id).unwrap());
+        match peer_manager.delete_peer(&peer1.node_id) {
+            Err(PeerManagerError::PeerNotFoundError) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match peer_manager.update_peer(peer1.clone()) {
+            Err(PeerManagerError::PeerNotFoundError) => {},
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_restore_from_datastore() {
+        let mut peer = create_test_peer("127.0.0.1:8000");
+        let address = "propub3r6espa33w.onion:1234".parse::<NetAddress>().unwrap();
+        peer.addresses.add_net_address(&address).unwrap();
+        peer.addresses.failed_connection_attempt(&address).unwrap();
+        peer.set_banned(true);
+        let peer_storage = {
+            let mut peer_storage = PeerStorage::new(HashmapStore::new()).unwrap();
+            peer_storage.add_peer(peer.clone()).unwrap();
+            peer_storage.add_peer(create_test_peer("127.0.0.1:8001")).unwrap();
+            peer_storage
+        };
+
+        let peer_manager: PeerManager<RistrettoPublicKey, _> =
+            PeerManager::new(NodeId::new(), peer_storage.into_datastore()).unwrap();
+        assert_eq!(peer_manager.peer_count().unwrap(), 2);
+        let mut restored = peer_manager.find_with_node_id(&peer.node_id).unwrap();
+        assert_eq!(restored.public_key, peer.public_key);
+        assert!(restored.is_banned());
+        let stats: &mut NetAddressWithStats = restored.addresses.find_address_mut(&address).unwrap();
+        assert_eq!(stats.connection_attempts, 1);
+    }
+
+    #[test]
+    fn test_closest_peers() {
+        let peer_manager = PeerManager::new(NodeId::new(), HashmapStore::new()).unwrap();
+        let peers: Vec<Peer<RistrettoPublicKey>> = (0..20)
+            .map(|i| create_test_peer(&format!("127.0.0.1:{}", 8000 + i)))
+            .collect();
+        for peer in &peers {
+            peer_manager.add_peer(peer.clone()).unwrap();
+        }
+        let node_ids: Vec<NodeId> = peers.iter().map(|p| p.node_id.clone()).collect();
+        let target = peers[7].node_id.clone();
+        let closest: Vec<NodeId> = peer_manager
+            .closest_peers(&target, 5)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.node_id)
+            .collect();
+        assert_eq!(closest, target.closest(&node_ids, 5).unwrap());
+        assert_eq!(closest[0], target);
+
+        // Banned and deleted peers are no longer routed to
+        peer_manager.modify_peer(&target, |peer| peer.set_banned(true)).unwrap();
+        peer_manager.delete_peer(&closest[1]).unwrap();
+        let closest: Vec<NodeId> = peer_manager
+            .closest_peers(&target, 5)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.node_id)
+            .collect();
+        assert!(!closest.contains(&node_ids[7]));
+        assert_eq!(closest.len(), 5);
+    }
+
+    #[test]
+    fn test_concurrent_access() {
+        let peer_manager = Arc::new(PeerManager::new(NodeId::new(), HashmapStore::new()).unwrap());
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let peer_manager = Arc::clone(&peer_manager);
+                thread::spawn(move || {
+                    for j in 0..10 {
+                        let peer = create_test_peer(&format!("127.0.0.1:{}", 8000 + i * 10 + j));
+                        peer_manager.add_peer(peer.clone()).unwrap();
+                        assert!(peer_manager.exists(&peer.node_id).unwrap());
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(peer_manager.peer_count().unwrap(), 40);
+        let mut count = 0;
+        peer_manager.for_each(|_| count += 1).unwrap();
+        assert_eq!(count, 40);
+    }
+}
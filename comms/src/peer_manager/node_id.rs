@@ -52,6 +52,19 @@ impl NodeDistance {
         }
         nd
     }
+
+    /// The number of leading zero bits of the distance. The routing table of a node places the nodes whose distances
+    /// from it have the same number of leading zero bits in the same bucket.
+    pub fn leading_zeros(&self) -> usize {
+        let mut count = 0;
+        for byte in self.0.iter() {
+            if *byte != 0 {
+                return count + byte.leading_zeros() as usize;
+            }
+            count += 8;
+        }
+        count
+    }
 }
 
 impl PartialEq for NodeDistance {
@@ -75,7 +88,7 @@ impl TryFrom<&[u8]> for NodeDistance {
     }
 }
 
-#[derive(Clone, Debug, Eq, Deserialize, Serialize)]
+#[derive(Clone, Debug, Eq, Hash, Deserialize, Serialize)]
 pub struct NodeId(NodeIdArray);
 
 impl NodeId {
@@ -94,6 +107,11 @@ impl NodeId {
     // TODO: NodeId=hash(blockhash(with block_height),public key?)
     // }
 
+    /// The bytes of the node id
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
     /// Calculate the distance between the current node id and the provided node id using the XOR metric
     pub fn distance(&self, node_id: &NodeId) -> NodeDistance {
         NodeDistance::from_node_ids(&self, &node_id)
@@ -353,4 +371,15 @@ mod test {
         };
         assert!(node_id.closest(&node_ids, node_ids.len() + 1).is_err());
     }
+
+    #[test]
+    fn test_leading_zeros() {
+        assert_eq!(NodeDistance::new().leading_zeros(), 256);
+        let mut bytes = [0u8; 32];
+        bytes[0] = 0b1000_0000;
+        assert_eq!(NodeDistance::try_from(&bytes[..]).unwrap().leading_zeros(), 0);
+        bytes[0] = 0;
+        bytes[2] = 0b0001_0110;
+        assert_eq!(NodeDistance::try_from(&bytes[..]).unwrap().leading_zeros(), 19);
+    }
 }
@@ -23,18 +23,20 @@
 use crate::{connection::net_address::net_addresses::NetAddresses, peer_manager::node_id::NodeId};
 use bitflags::*;
 use chrono::prelude::*;
+use serde_derive::{Deserialize, Serialize};
 use tari_crypto::keys::PublicKey;
 
 // TODO reputation metric?
 
 bitflags! {
-    #[derive(Default)]
+    #[derive(Default, Deserialize, Serialize)]
     pub struct PeerFlags: u8 {
         const BANNED = 0b00000001;
+        const OFFLINE = 0b00000010;
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Peer<K: PublicKey> {
     pub public_key: K,
     pub node_id: NodeId,
@@ -69,6 +71,16 @@ where K: PublicKey
     pub fn set_banned(&mut self, ban_flag: bool) {
         self.flags.set(PeerFlags::BANNED, ban_flag);
     }
+
+    /// Returns the offline status of the peer
+    pub fn is_offline(&self) -> bool {
+        self.flags.contains(PeerFlags::OFFLINE)
+    }
+
+    /// Changes the offline flag bit of the peer
+    pub fn set_offline(&mut self, offline_flag: bool) {
+        self.flags.set(PeerFlags::OFFLINE, offline_flag);
+    }
 }
 
 #[cfg(test)]
@@ -98,4 +110,22 @@ mod test {
         peer.set_banned(false);
         assert_eq!(peer.is_banned(), false);
     }
+
+    #[test]
+    fn test_is_and_set_offline() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let sk = RistrettoSecretKey::random(&mut rng);
+        let pk = RistrettoPublicKey::from_secret_key(&sk);
+        let node_id = NodeId::from_key(&pk).unwrap();
+        let addresses = NetAddresses::from("123.0.0.123:8000".parse::<NetAddress>().unwrap());
+        let mut peer: Peer<RistrettoPublicKey> =
+            Peer::<RistrettoPublicKey>::new(pk, node_id, addresses, PeerFlags::default());
+        assert_eq!(peer.is_offline(), false);
+        peer.set_offline(true);
+        peer.set_banned(true);
+        assert_eq!(peer.is_offline(), true);
+        peer.set_offline(false);
+        assert_eq!(peer.is_offline(), false);
+        assert_eq!(peer.is_banned(), true);
+    }
 }
@@ -19,3 +19,522 @@
 //  SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
 //  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::peer_manager::node_id::NodeId;
+use chrono::{prelude::*, Duration};
+use rand::Rng;
+use std::{collections::VecDeque, convert::TryFrom};
+
+/// There is one bucket for every bit of a NodeId
+const BUCKET_COUNT: usize = 256;
+/// The default number of nodes held in a bucket, Kademlia's _k_
+const DEFAULT_BUCKET_SIZE: usize = 20;
+/// The default number of candidates kept for a full bucket
+const DEFAULT_REPLACEMENT_CACHE_SIZE: usize = 10;
+/// The default period after which a bucket that has not been used should be refreshed
+const DEFAULT_REFRESH_INTERVAL_MINS: i64 = 60;
+/// The default period after which a node that has not been seen can be replaced
+const DEFAULT_STALE_PERIOD_MINS: i64 = 60;
+
+/// The limits and timeouts of a RoutingTable
+#[derive(Clone, Debug)]
+pub struct RoutingTableConfig {
+    /// The maximum number of nodes held in a bucket
+    pub bucket_size: usize,
+    /// The maximum number of candidates kept for a full bucket; they take the place of nodes that are removed
+    pub replacement_cache_size: usize,
+    /// A bucket that has not been used for this period should be refreshed with a lookup
+    pub refresh_interval: Duration,
+    /// A node that has not been seen for this period is replaced when a candidate is available
+    pub stale_period: Duration,
+}
+
+impl Default for RoutingTableConfig {
+    fn default() -> Self {
+        RoutingTableConfig {
+            bucket_size: DEFAULT_BUCKET_SIZE,
+            replacement_cache_size: DEFAULT_REPLACEMENT_CACHE_SIZE,
+            refresh_interval: Duration::minutes(DEFAULT_REFRESH_INTERVAL_MINS),
+            stale_period: Duration::minutes(DEFAULT_STALE_PERIOD_MINS),
+        }
+    }
+}
+
+/// The outcome of adding a node to the RoutingTable
+#[derive(Clone, Debug, PartialEq)]
+pub enum AddNodeResult {
+    /// The node was added to its bucket
+    Added,
+    /// The node was already in its bucket and has been moved to the most recently seen position
+    Updated,
+    /// The bucket is full, so the node was kept as a replacement candidate. The given least recently seen node of the
+    /// bucket should be checked, and removed if it is no longer alive.
+    Pending(NodeId),
+    /// The node id is that of the table's own node
+    Ignored,
+}
+
+/// A node held in a bucket of the RoutingTable
+#[derive(Clone, Debug)]
+pub struct RoutingEntry {
+    pub node_id: NodeId,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub added_at: DateTime<Utc>,
+}
+
+impl RoutingEntry {
+    fn is_stale(&self, now: DateTime<Utc>, stale_period: Duration) -> bool {
+        self.last_seen.unwrap_or(self.added_at) + stale_period <= now
+    }
+}
+
+/// A k-bucket, with its nodes ordered from least to most recently seen
+#[derive(Clone, Debug)]
+struct KBucket {
+    entries: Vec<RoutingEntry>,
+    replacements: VecDeque<RoutingEntry>,
+    last_refreshed: DateTime<Utc>,
+}
+
+impl KBucket {
+    fn new(now: DateTime<Utc>) -> KBucket {
+        KBucket {
+            entries: Vec::new(),
+            replacements: VecDeque::new(),
+            last_refreshed: now,
+        }
+    }
+
+    fn position(&self, node_id: &NodeId) -> Option<usize> {
+        self.entries.iter().position(|e| &e.node_id == node_id)
+    }
+
+    fn replacement_position(&self, node_id: &NodeId) -> Option<usize> {
+        self.replacements.iter().position(|e| &e.node_id == node_id)
+    }
+
+    /// Move the most recently seen replacement candidate into the bucket
+    fn promote_replacement(&mut self) -> Option<NodeId> {
+        let entry = self.replacements.pop_back()?;
+        let node_id = entry.node_id.clone();
+        self.entries.push(entry);
+        Some(node_id)
+    }
+}
+
+/// A Kademlia routing table. Nodes are placed in buckets by their XOR distance from our own NodeId: bucket _i_ holds
+/// the nodes whose distance has _i_ leading zero bits, so each bucket covers half the distance of the one before it.
+/// Every bucket holds at most `bucket_size` nodes, with older nodes preferred over new ones because nodes that have
+/// been up for a long time are likely to stay up. New nodes for a full bucket are kept as replacement candidates, and
+/// take the place of nodes that are removed or have not been seen for the stale period.
+pub struct RoutingTable {
+    node_id: NodeId,
+    config: RoutingTableConfig,
+    buckets: Vec<KBucket>,
+    len: usize,
+}
+
+impl RoutingTable {
+    /// Construct an empty routing table for the node with the given node id
+    pub fn new(node_id: NodeId, config: RoutingTableConfig) -> RoutingTable {
+        let now = Utc::now();
+        RoutingTable {
+            node_id,
+            config,
+            buckets: (0..BUCKET_COUNT).map(|_| KBucket::new(now)).collect(),
+            len: 0,
+        }
+    }
+
+    /// The node id of the node the table belongs to
+    pub fn node_id(&self) -> &NodeId {
+        &self.node_id
+    }
+
+    /// The limits and timeouts of the table
+    pub fn config(&self) -> &RoutingTableConfig {
+        &self.config
+    }
+
+    /// The index of the bucket the node id belongs in, or None for our own node id
+    pub fn bucket_index(&self, node_id: &NodeId) -> Option<usize> {
+        let index = self.node_id.distance(node_id).leading_zeros();
+        if index < BUCKET_COUNT {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// The number of nodes in the buckets, not counting replacement candidates
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the buckets hold no nodes
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns true if the node is in one of the buckets
+    pub fn contains(&self, node_id: &NodeId) -> bool {
+        self.bucket_index(node_id)
+            .map(|i| self.buckets[i].position(node_id).is_some())
+            .unwrap_or(false)
+    }
+
+    /// Iterate over the nodes in the buckets, from the furthest bucket to the closest
+    pub fn entries(&self) -> impl Iterator<Item = &RoutingEntry> {
+        self.buckets.iter().flat_map(|b| b.entries.iter())
+    }
+
+    /// Add a node, or mark a node that is already in its bucket as the most recently seen
+    pub fn add_node(&mut self, node_id: NodeId, last_seen: Option<DateTime<Utc>>) -> AddNodeResult {
+        self.add_node_at(node_id, last_seen, Utc::now())
+    }
+
+    fn add_node_at(&mut self, node_id: NodeId, last_seen: Option<DateTime<Utc>>, now: DateTime<Utc>) -> AddNodeResult {
+        let index = match self.bucket_index(&node_id) {
+            Some(index) => index,
+            None => return AddNodeResult::Ignored,
+        };
+        let bucket_size = self.config.bucket_size;
+        let replacement_cache_size = self.config.replacement_cache_size;
+        let bucket = &mut self.buckets[index];
+        bucket.last_refreshed = now;
+        if let Some(position) = bucket.position(&node_id) {
+            let mut entry = bucket.entries.remove(position);
+            entry.last_seen = last_seen.or(entry.last_seen);
+            bucket.entries.push(entry);
+            return AddNodeResult::Updated;
+        }
+        let entry = match bucket.replacement_position(&node_id) {
+            Some(position) => {
+                let mut entry = bucket.replacements.remove(position).unwrap();
+                entry.last_seen = last_seen.or(entry.last_seen);
+                entry
+            },
+            None => RoutingEntry {
+                node_id,
+                last_seen,
+                added_at: now,
+            },
+        };
+        if bucket.entries.len() < bucket_size {
+            bucket.entries.push(entry);
+            self.len += 1;
+            return AddNodeResult::Added;
+        }
+        if bucket.replacements.len() >= replacement_cache_size {
+            bucket.replacements.pop_front();
+        }
+        if replacement_cache_size > 0 {
+            bucket.replacements.push_back(entry);
+        }
+        AddNodeResult::Pending(bucket.entries[0].node_id.clone())
+    }
+
+    /// Record that a node was seen at the given time. If the time is later than the node was last seen, the node is
+    /// moved to the most recently seen position of its bucket. Returns false if the node is neither in its bucket nor
+    /// a replacement candidate.
+    pub fn node_seen(&mut self, node_id: &NodeId, last_seen: DateTime<Utc>) -> bool {
+        let index = match self.bucket_index(node_id) {
+            Some(index) => index,
+            None => return false,
+        };
+        let bucket = &mut self.buckets[index];
+        if let Some(position) = bucket.position(node_id) {
+            if bucket.entries[position].last_seen < Some(last_seen) {
+                let mut entry = bucket.entries.remove(position);
+                entry.last_seen = Some(last_seen);
+                bucket.entries.push(entry);
+            }
+            true
+        } else if let Some(position) = bucket.replacement_position(node_id) {
+            if bucket.replacements[position].last_seen < Some(last_seen) {
+                let mut entry = bucket.replacements.remove(position).unwrap();
+                entry.last_seen = Some(last_seen);
+                bucket.replacements.push_back(entry);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Remove a node from the table, e.g. because it failed to respond. If the node was in its bucket, the most
+    /// recently seen replacement candidate takes its place and its node id is returned.
+    pub fn remove_node(&mut self, node_id: &NodeId) -> Option<NodeId> {
+        let index = self.bucket_index(node_id)?;
+        let bucket = &mut self.buckets[index];
+        if let Some(position) = bucket.replacement_position(node_id) {
+            bucket.replacements.remove(position);
+            return None;
+        }
+        let position = bucket.position(node_id)?;
+        bucket.entries.remove(position);
+        let replacement = bucket.promote_replacement();
+        if replacement.is_none() {
+            self.len -= 1;
+        }
+        replacement
+    }
+
+    /// Replace the nodes that have not been seen for the stale period with replacement candidates. Stale nodes are
+    /// only evicted while candidates are available. The evicted node ids are returned.
+    pub fn replace_stale_entries(&mut self) -> Vec<NodeId> {
+        self.replace_stale_entries_at(Utc::now())
+    }
+
+    fn replace_stale_entries_at(&mut self, now: DateTime<Utc>) -> Vec<NodeId> {
+        let stale_period = self.config.stale_period;
+        let mut evicted = Vec::new();
+        for bucket in self.buckets.iter_mut() {
+            while !bucket.replacements.is_empty() {
+                match bucket.entries.iter().position(|e| e.is_stale(now, stale_period)) {
+                    Some(position) => {
+                        evicted.push(bucket.entries.remove(position).node_id);
+                        bucket.promote_replacement();
+                    },
+                    None => break,
+                }
+            }
+        }
+        evicted
+    }
+
+    /// The indices of the buckets that have not been used for the refresh interval. Only the buckets up to the
+    /// closest non-empty bucket are considered, as closer buckets cover too small a distance to be populated.
+    pub fn buckets_to_refresh(&self) -> Vec<usize> {
+        self.buckets_to_refresh_at(Utc::now())
+    }
+
+    fn buckets_to_refresh_at(&self, now: DateTime<Utc>) -> Vec<usize> {
+        let closest = match self.buckets.iter().rposition(|b| !b.entries.is_empty()) {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        (0..=closest)
+            .filter(|i| self.buckets[*i].last_refreshed + self.config.refresh_interval <= now)
+            .collect()
+    }
+
+    /// A random node id that falls in the given bucket. A lookup of this node id refreshes the bucket.
+    pub fn refresh_target<R: Rng>(&self, index: usize, rng: &mut R) -> NodeId {
+        let mut distance = [0u8; BUCKET_COUNT / 8];
+        rng.fill(&mut distance[..]);
+        // Clear the first `index` bits and set the next one, so that the distance has `index` leading zeros
+        let index = index.min(BUCKET_COUNT - 1);
+        for (i, byte) in distance.iter_mut().enumerate() {
+            let start = i * 8;
+            if index >= start + 8 {
+                *byte = 0;
+            } else if index >= start {
+                let bit = index - start;
+                *byte &= 0xff >> bit;
+                *byte |= 0x80 >> bit;
+            }
+        }
+        let bytes: Vec<u8> = self
+            .node_id
+            .as_bytes()
+            .iter()
+            .zip(distance.iter())
+            .map(|(a, b)| a ^ b)
+            .collect();
+        NodeId::try_from(bytes.as_slice()).expect("A node id is the same size as a distance")
+    }
+
+    /// Record that a lookup has refreshed the bucket with the given index
+    pub fn mark_refreshed(&mut self, index: usize) {
+        self.mark_refreshed_at(index, Utc::now())
+    }
+
+    fn mark_refreshed_at(&mut self, index: usize, now: DateTime<Utc>) {
+        if let Some(bucket) = self.buckets.get_mut(index) {
+            bucket.last_refreshed = now;
+        }
+    }
+
+    /// The (at most) `n` nodes in the table that are closest to the target, ordered by distance. Only the buckets that
+    /// can hold the closest nodes are visited: if the target falls in bucket _b_, the nodes in bucket _b_ are closer to
+    /// it than the nodes in the buckets after _b_, which in turn are closer than the nodes in bucket _b - 1_, _b - 2_
+    /// and so on.
+    pub fn closest(&self, target: &NodeId, n: usize) -> Vec<NodeId> {
+        self.closest_where(target, n, |_| true)
+    }
+
+    /// The (at most) `n` nodes in the table that are closest to the target and satisfy the predicate, ordered by
+    /// distance. Nodes that don't satisfy the predicate are skipped, so they don't take the place of the nodes after
+    /// them.
+    pub fn closest_where<P>(&self, target: &NodeId, n: usize, predicate: P) -> Vec<NodeId>
+    where P: Fn(&NodeId) -> bool {
+        let b = self.node_id.distance(target).leading_zeros();
+        let mut closest = Vec::with_capacity(n);
+        if b < BUCKET_COUNT {
+            self.extend_closest(&mut closest, target, b..=b, &predicate);
+        }
+        if closest.len() < n && b + 1 < BUCKET_COUNT {
+            self.extend_closest(&mut closest, target, b + 1..=BUCKET_COUNT - 1, &predicate);
+        }
+        for i in (0..b.min(BUCKET_COUNT)).rev() {
+            if closest.len() >= n {
+                break;
+            }
+            self.extend_closest(&mut closest, target, i..=i, &predicate);
+        }
+        closest.truncate(n);
+        closest
+    }
+
+    /// Append the nodes in the range of buckets that satisfy the predicate to `closest`, ordered by their distance to
+    /// the target
+    fn extend_closest<I, P>(&self, closest: &mut Vec<NodeId>, target: &NodeId, indices: I, predicate: &P)
+    where
+        I: Iterator<Item = usize>,
+        P: Fn(&NodeId) -> bool,
+    {
+        let mut node_ids: Vec<NodeId> = indices
+            .flat_map(|i| self.buckets[i].entries.iter().map(|e| &e.node_id))
+            .filter(|node_id| predicate(node_id))
+            .cloned()
+            .collect();
+        node_ids.sort_by_key(|node_id| target.distance(node_id));
+        closest.extend(node_ids);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{OsRng, RngCore};
+
+    fn random_node_id<R: RngCore>(rng: &mut R) -> NodeId {
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        NodeId::try_from(&bytes[..]).unwrap()
+    }
+
+    fn config(bucket_size: usize, replacement_cache_size: usize) -> RoutingTableConfig {
+        RoutingTableConfig {
+            bucket_size,
+            replacement_cache_size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut rng = OsRng::new().unwrap();
+        let own_id = random_node_id(&mut rng);
+        let mut table = RoutingTable::new(own_id.clone(), config(2, 1));
+        assert_eq!(table.add_node(own_id.clone(), None), AddNodeResult::Ignored);
+
+        // Fill bucket 3 and overflow it
+        let node_ids: Vec<NodeId> = (0..4).map(|_| table.refresh_target(3, &mut rng)).collect();
+        assert!(node_ids.iter().all(|id| table.bucket_index(id) == Some(3)));
+        assert_eq!(table.add_node(node_ids[0].clone(), None), AddNodeResult::Added);
+        assert_eq!(table.add_node(node_ids[1].clone(), None), AddNodeResult::Added);
+        assert_eq!(table.add_node(node_ids[0].clone(), None), AddNodeResult::Updated);
+        // node 1 is now the least recently seen
+        assert_eq!(
+            table.add_node(node_ids[2].clone(), None),
+            AddNodeResult::Pending(node_ids[1].clone())
+        );
+        // The replacement cache only holds one candidate, so node 3 displaces node 2
+        assert_eq!(
+            table.add_node(node_ids[3].clone(), None),
+            AddNodeResult::Pending(node_ids[1].clone())
+        );
+        assert_eq!(table.len(), 2);
+        assert!(!table.contains(&node_ids[2]));
+
+        // Removing a node promotes the candidate
+        assert_eq!(table.remove_node(&node_ids[1]), Some(node_ids[3].clone()));
+        assert!(table.contains(&node_ids[3]));
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.remove_node(&node_ids[3]), None);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.remove_node(&node_ids[3]), None);
+        assert_eq!(table.entries().count(), 1);
+    }
+
+    #[test]
+    fn test_replace_stale_entries() {
+        let mut rng = OsRng::new().unwrap();
+        let mut table = RoutingTable::new(random_node_id(&mut rng), config(2, 2));
+        let now = Utc::now();
+        let long_ago = now - Duration::hours(2);
+        let node_ids: Vec<NodeId> = (0..4).map(|_| table.refresh_target(0, &mut rng)).collect();
+        table.add_node_at(node_ids[0].clone(), Some(long_ago), long_ago);
+        table.add_node_at(node_ids[1].clone(), Some(now), now);
+        // No candidates yet, so the stale node stays
+        assert!(table.replace_stale_entries_at(now).is_empty());
+        table.add_node_at(node_ids[2].clone(), Some(now), now);
+        assert_eq!(table.replace_stale_entries_at(now), vec![node_ids[0].clone()]);
+        assert!(table.contains(&node_ids[2]));
+        assert!(!table.contains(&node_ids[0]));
+        // Seeing a node keeps it from going stale
+        table.add_node_at(node_ids[3].clone(), None, now);
+        assert!(table.node_seen(&node_ids[1], now + Duration::hours(2)));
+        assert!(table.node_seen(&node_ids[2], now + Duration::hours(2)));
+        assert!(table.replace_stale_entries_at(now + Duration::hours(2)).is_empty());
+    }
+
+    #[test]
+    fn test_bucket_refresh() {
+        let mut rng = OsRng::new().unwrap();
+        let mut table = RoutingTable::new(random_node_id(&mut rng), RoutingTableConfig::default());
+        let later = Utc::now() + Duration::hours(2);
+        assert!(table.buckets_to_refresh_at(later).is_empty());
+        for i in 0..BUCKET_COUNT {
+            let target = table.refresh_target(i, &mut rng);
+            assert_eq!(table.bucket_index(&target), Some(i));
+        }
+        let node_id = table.refresh_target(4, &mut rng);
+        table.add_node(node_id, None);
+        assert_eq!(table.buckets_to_refresh_at(later), vec![0, 1, 2, 3, 4]);
+        table.mark_refreshed_at(2, later);
+        assert_eq!(table.buckets_to_refresh_at(later), vec![0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn test_closest() {
+        let mut rng = OsRng::new().unwrap();
+        let own_id = random_node_id(&mut rng);
+        let mut table = RoutingTable::new(own_id.clone(), config(1000, 0));
+        let mut node_ids = Vec::new();
+        for i in 0..200 {
+            // Include nodes in the closer buckets as well as random ones, which mostly land in the first few buckets
+            let node_id = if i % 2 == 0 {
+                table.refresh_target(i % 16, &mut rng)
+            } else {
+                random_node_id(&mut rng)
+            };
+            table.add_node(node_id.clone(), None);
+            node_ids.push(node_id);
+        }
+        for _ in 0..20 {
+            let target = random_node_id(&mut rng);
+            assert_eq!(table.closest(&target, 8), target.closest(&node_ids, 8).unwrap());
+        }
+        let target = table.refresh_target(6, &mut rng);
+        assert_eq!(table.closest(&target, 8), target.closest(&node_ids, 8).unwrap());
+        assert_eq!(table.closest(&own_id, 8), own_id.closest(&node_ids, 8).unwrap());
+        assert_eq!(table.closest(&own_id, 500).len(), 200);
+
+        // Nodes that don't satisfy the predicate are skipped instead of shortening the result
+        let excluded = table.closest(&target, 4);
+        let expected: Vec<NodeId> = target
+            .closest(&node_ids, 12)
+            .unwrap()
+            .into_iter()
+            .filter(|node_id| !excluded.contains(node_id))
+            .take(8)
+            .collect();
+        assert_eq!(
+            table.closest_where(&target, 8, |node_id| !excluded.contains(node_id)),
+            expected
+        );
+    }
+}
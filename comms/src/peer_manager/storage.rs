@@ -19,3 +19,120 @@
 //  SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
 //  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::peer_manager::{manager::PeerManagerError, node_id::NodeId, peer::Peer};
+use std::collections::HashMap;
+use tari_crypto::keys::PublicKey;
+use tari_storage::keyvalue_store::DataStore;
+use tari_utilities::hex::to_hex;
+
+/// The key of the list of node ids of the stored peers
+const PEER_IDS_KEY: &str = "peer_ids";
+
+/// PeerStorage keeps the peers in memory and writes every change through to a DataStore, so that the peer database
+/// can be restored when the node restarts. Any DataStore backend, such as the in-memory HashmapStore or the
+/// LMDBStore, can be used. Peers are iterated in the order they were added.
+pub struct PeerStorage<PubKey, DS>
+where PubKey: PublicKey
+{
+    datastore: DS,
+    node_ids: Vec<NodeId>,
+    peers: HashMap<NodeId, Peer<PubKey>>,
+}
+
+impl<PubKey, DS> PeerStorage<PubKey, DS>
+where
+    PubKey: PublicKey,
+    DS: DataStore,
+{
+    /// Construct a PeerStorage on top of the datastore, loading any peers that were stored in it
+    pub fn new(datastore: DS) -> Result<PeerStorage<PubKey, DS>, PeerManagerError> {
+        let node_ids: Vec<NodeId> = datastore.get(PEER_IDS_KEY)?.unwrap_or_default();
+        let mut peers = HashMap::with_capacity(node_ids.len());
+        for node_id in &node_ids {
+            let peer: Peer<PubKey> = datastore
+                .get(&peer_key(node_id))?
+                .ok_or(PeerManagerError::DataInconsistencyError)?;
+            peers.insert(node_id.clone(), peer);
+        }
+        Ok(PeerStorage {
+            datastore,
+            node_ids,
+            peers,
+        })
+    }
+
+    /// Add a new peer. The peer is rejected if a peer with the same node id or public key has already been added.
+    pub fn add_peer(&mut self, peer: Peer<PubKey>) -> Result<(), PeerManagerError> {
+        if self.peers.contains_key(&peer.node_id) || self.find_with_public_key(&peer.public_key).is_ok() {
+            return Err(PeerManagerError::DuplicatePeerError);
+        }
+        self.datastore.put(&peer_key(&peer.node_id), &peer)?;
+        self.node_ids.push(peer.node_id.clone());
+        if let Err(e) = self.datastore.put(PEER_IDS_KEY, &self.node_ids) {
+            self.node_ids.pop();
+            return Err(e.into());
+        }
+        self.peers.insert(peer.node_id.clone(), peer);
+        Ok(())
+    }
+
+    /// Replace the stored record of an existing peer, e.g. after its addresses or flags have changed
+    pub fn update_peer(&mut self, peer: Peer<PubKey>) -> Result<(), PeerManagerError> {
+        if !self.peers.contains_key(&peer.node_id) {
+            return Err(PeerManagerError::PeerNotFoundError);
+        }
+        self.datastore.put(&peer_key(&peer.node_id), &peer)?;
+        self.peers.insert(peer.node_id.clone(), peer);
+        Ok(())
+    }
+
+    /// Remove the peer with the given node id, returning its record
+    pub fn delete_peer(&mut self, node_id: &NodeId) -> Result<Peer<PubKey>, PeerManagerError> {
+        if !self.peers.contains_key(node_id) {
+            return Err(PeerManagerError::PeerNotFoundError);
+        }
+        let node_ids: Vec<NodeId> = self.node_ids.iter().filter(|id| *id != node_id).cloned().collect();
+        self.datastore.put(PEER_IDS_KEY, &node_ids)?;
+        self.datastore.delete_raw(peer_key(node_id).as_bytes())?;
+        self.node_ids = node_ids;
+        self.peers.remove(node_id).ok_or(PeerManagerError::PeerNotFoundError)
+    }
+
+    /// Find the peer with the given node id
+    pub fn find_with_node_id(&self, node_id: &NodeId) -> Result<&Peer<PubKey>, PeerManagerError> {
+        self.peers.get(node_id).ok_or(PeerManagerError::PeerNotFoundError)
+    }
+
+    /// Find the peer with the given public key
+    pub fn find_with_public_key(&self, public_key: &PubKey) -> Result<&Peer<PubKey>, PeerManagerError> {
+        self.peers()
+            .find(|peer| &peer.public_key == public_key)
+            .ok_or(PeerManagerError::PeerNotFoundError)
+    }
+
+    /// Iterate over the stored peers, in the order they were added
+    pub fn peers(&self) -> impl Iterator<Item = &Peer<PubKey>> {
+        self.node_ids.iter().filter_map(move |node_id| self.peers.get(node_id))
+    }
+
+    /// The number of stored peers
+    pub fn len(&self) -> usize {
+        self.node_ids.len()
+    }
+
+    /// Returns true if no peers are stored
+    pub fn is_empty(&self) -> bool {
+        self.node_ids.is_empty()
+    }
+
+    /// Release the underlying datastore
+    pub fn into_datastore(self) -> DS {
+        self.datastore
+    }
+}
+
+/// The datastore key of a peer record
+fn peer_key(node_id: &NodeId) -> String {
+    format!("peer_{}", to_hex(node_id.as_bytes()))
+}
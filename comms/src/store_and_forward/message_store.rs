@@ -0,0 +1,273 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{connection::message::MessageEnvelope, peer_manager::node_id::NodeId};
+use chrono::{prelude::*, Duration};
+use derive_error::Error;
+use std::collections::{HashMap, VecDeque};
+
+/// The default period that a message is held for an offline destination
+const DEFAULT_MESSAGE_TTL_HOURS: i64 = 72;
+/// The default number of messages that can be held for all destinations
+const DEFAULT_MAX_MESSAGES: usize = 10_000;
+/// The default number of messages that can be held for a single destination
+const DEFAULT_MAX_MESSAGES_PER_DESTINATION: usize = 100;
+/// The default number of nodes closest to a destination that hold its messages
+const DEFAULT_CLOSEST_NODE_COUNT: usize = 8;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum MessageStoreError {
+    /// The store has reached its capacity
+    StoreFull,
+}
+
+/// Limits placed on the messages held by a MessageStore
+#[derive(Clone, Debug)]
+pub struct MessageStoreConfig {
+    /// The period after which a held message is discarded
+    pub ttl: Duration,
+    /// The maximum number of messages held for all destinations
+    pub max_messages: usize,
+    /// The maximum number of messages held for a single destination, the oldest message is discarded when it is
+    /// reached
+    pub max_messages_per_destination: usize,
+    /// A node holds messages for the destinations it is one of the closest known nodes to
+    pub closest_node_count: usize,
+}
+
+impl Default for MessageStoreConfig {
+    fn default() -> Self {
+        MessageStoreConfig {
+            ttl: Duration::hours(DEFAULT_MESSAGE_TTL_HOURS),
+            max_messages: DEFAULT_MAX_MESSAGES,
+            max_messages_per_destination: DEFAULT_MAX_MESSAGES_PER_DESTINATION,
+            closest_node_count: DEFAULT_CLOSEST_NODE_COUNT,
+        }
+    }
+}
+
+/// A MessageEnvelope held for a destination, with the time it was stored
+#[derive(Clone, Debug)]
+pub struct StoredMessage {
+    pub stored_at: DateTime<Utc>,
+    pub message_envelope: MessageEnvelope,
+}
+
+impl StoredMessage {
+    fn is_expired(&self, now: DateTime<Utc>, ttl: Duration) -> bool {
+        self.stored_at + ttl <= now
+    }
+}
+
+/// The MessageStore holds MessageEnvelopes for destination nodes that are offline, so that they can be delivered when
+/// the destination comes online and requests them. Messages are held until they are fetched or their time to live
+/// expires.
+pub struct MessageStore {
+    config: MessageStoreConfig,
+    messages: HashMap<NodeId, VecDeque<StoredMessage>>,
+    message_count: usize,
+}
+
+impl MessageStore {
+    /// Construct a new empty MessageStore with the given limits
+    pub fn new(config: MessageStoreConfig) -> MessageStore {
+        MessageStore {
+            config,
+            messages: HashMap::new(),
+            message_count: 0,
+        }
+    }
+
+    /// The limits of the store
+    pub fn config(&self) -> &MessageStoreConfig {
+        &self.config
+    }
+
+    /// Determine if the node with node_id should hold messages for the destination. The node is responsible if it is
+    /// one of the closest_node_count nodes, of itself and its neighbours, to the destination.
+    pub fn is_responsible_for(&self, node_id: &NodeId, destination: &NodeId, neighbours: &[NodeId]) -> bool {
+        let mut node_ids = neighbours.to_vec();
+        if !node_ids.contains(node_id) {
+            node_ids.push(node_id.clone());
+        }
+        let k = self.config.closest_node_count.min(node_ids.len());
+        match destination.closest(&node_ids, k) {
+            Ok(closest) => closest.contains(node_id),
+            Err(_) => false,
+        }
+    }
+
+    /// Hold a message for the destination node
+    pub fn store(&mut self, destination: NodeId, message_envelope: MessageEnvelope) -> Result<(), MessageStoreError> {
+        self.store_at(destination, message_envelope, Utc::now())
+    }
+
+    fn store_at(
+        &mut self,
+        destination: NodeId,
+        message_envelope: MessageEnvelope,
+        now: DateTime<Utc>,
+    ) -> Result<(), MessageStoreError>
+    {
+        self.remove_expired_at(now);
+        let destination_count = self.messages.get(&destination).map(|m| m.len()).unwrap_or(0);
+        if destination_count < self.config.max_messages_per_destination &&
+            self.message_count >= self.config.max_messages
+        {
+            return Err(MessageStoreError::StoreFull);
+        }
+        let messages = self.messages.entry(destination).or_insert_with(VecDeque::new);
+        if messages.len() >= self.config.max_messages_per_destination && messages.pop_front().is_some() {
+            self.message_count -= 1;
+        }
+        messages.push_back(StoredMessage {
+            stored_at: now,
+            message_envelope,
+        });
+        self.message_count += 1;
+        Ok(())
+    }
+
+    /// Remove and return the unexpired messages held for the destination node, oldest first
+    pub fn fetch(&mut self, destination: &NodeId) -> Vec<MessageEnvelope> {
+        self.fetch_at(destination, Utc::now())
+    }
+
+    fn fetch_at(&mut self, destination: &NodeId, now: DateTime<Utc>) -> Vec<MessageEnvelope> {
+        let ttl = self.config.ttl;
+        match self.messages.remove(destination) {
+            Some(messages) => {
+                self.message_count -= messages.len();
+                messages
+                    .into_iter()
+                    .filter(|m| !m.is_expired(now, ttl))
+                    .map(|m| m.message_envelope)
+                    .collect()
+            },
+            None => Vec::new(),
+        }
+    }
+
+    /// Discard all messages whose time to live has expired
+    pub fn remove_expired(&mut self) {
+        self.remove_expired_at(Utc::now())
+    }
+
+    fn remove_expired_at(&mut self, now: DateTime<Utc>) {
+        let ttl = self.config.ttl;
+        let mut removed = 0;
+        for messages in self.messages.values_mut() {
+            let count = messages.len();
+            messages.retain(|m| !m.is_expired(now, ttl));
+            removed += count - messages.len();
+        }
+        self.messages.retain(|_, messages| !messages.is_empty());
+        self.message_count -= removed;
+    }
+
+    /// The number of messages held for all destinations
+    pub fn len(&self) -> usize {
+        self.message_count
+    }
+
+    /// Returns true if no messages are held
+    pub fn is_empty(&self) -> bool {
+        self.message_count == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn node_id(first_byte: u8) -> NodeId {
+        let mut bytes = [0u8; 32];
+        bytes[0] = first_byte;
+        NodeId::try_from(&bytes[..]).unwrap()
+    }
+
+    fn envelope(body: u8) -> MessageEnvelope {
+        MessageEnvelope::new(vec![0], vec![1], vec![body])
+    }
+
+    #[test]
+    fn test_store_and_fetch() {
+        let mut store = MessageStore::new(MessageStoreConfig::default());
+        store.store(node_id(1), envelope(1)).unwrap();
+        store.store(node_id(1), envelope(2)).unwrap();
+        store.store(node_id(2), envelope(3)).unwrap();
+        assert_eq!(store.len(), 3);
+
+        assert_eq!(store.fetch(&node_id(1)), vec![envelope(1), envelope(2)]);
+        assert_eq!(store.len(), 1);
+        assert!(store.fetch(&node_id(1)).is_empty());
+        assert_eq!(store.fetch(&node_id(2)), vec![envelope(3)]);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut store = MessageStore::new(MessageStoreConfig::default());
+        let now = Utc::now();
+        let ttl = store.config().ttl;
+        store.store_at(node_id(1), envelope(1), now - ttl).unwrap();
+        store.store_at(node_id(1), envelope(2), now).unwrap();
+        assert_eq!(store.len(), 1);
+        store
+            .store_at(node_id(2), envelope(3), now - ttl + Duration::seconds(1))
+            .unwrap();
+        assert_eq!(store.fetch_at(&node_id(2), now + Duration::seconds(1)), Vec::new());
+        assert_eq!(store.fetch_at(&node_id(1), now), vec![envelope(2)]);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut store = MessageStore::new(MessageStoreConfig {
+            max_messages: 3,
+            max_messages_per_destination: 2,
+            ..Default::default()
+        });
+        store.store(node_id(1), envelope(1)).unwrap();
+        store.store(node_id(1), envelope(2)).unwrap();
+        // The oldest message for a destination is replaced once the destination's limit is reached
+        store.store(node_id(1), envelope(3)).unwrap();
+        assert_eq!(store.len(), 2);
+        store.store(node_id(2), envelope(4)).unwrap();
+        assert_eq!(store.store(node_id(3), envelope(5)), Err(MessageStoreError::StoreFull));
+        assert_eq!(store.fetch(&node_id(1)), vec![envelope(2), envelope(3)]);
+    }
+
+    #[test]
+    fn test_is_responsible_for() {
+        let store = MessageStore::new(MessageStoreConfig {
+            closest_node_count: 2,
+            ..Default::default()
+        });
+        let neighbours = vec![node_id(1), node_id(64), node_id(96)];
+        assert!(store.is_responsible_for(&node_id(3), &node_id(0), &neighbours));
+        assert!(!store.is_responsible_for(&node_id(128), &node_id(0), &neighbours));
+        // A node without neighbours is the closest node it knows of
+        assert!(store.is_responsible_for(&node_id(128), &node_id(0), &[]));
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE
+
+use crate::{
+    connection::message::{IdentityFlags, MessageEnvelope, MessageError, NodeDestination},
+    inbound_message_service::message_context::MessageContext,
+    outbound_message_service::outbound_message_service::{OutboundError, OutboundMessageService},
+    peer_manager::{
+        manager::PeerManagerError,
+        node_id::{NodeId, NodeIdError},
+        node_identity::NodeIdentity,
+    },
+    store_and_forward::message_store::{MessageStore, MessageStoreConfig, MessageStoreError},
+};
+use derive_error::Error;
+use serde::Serialize;
+use std::{ops::Mul, sync::Arc};
+use tari_crypto::keys::{DiffieHellmanSharedSecret, PublicKey, SecretKey};
+use tari_storage::{hashmap_store::HashmapStore, keyvalue_store::DataStore};
+use tari_utilities::Hashable;
+
+#[derive(Debug, Error)]
+pub enum StoreAndForwardError {
+    /// The message could not be held
+    MessageStoreError(MessageStoreError),
+    /// A held message could not be forwarded to its destination
+    OutboundError(OutboundError),
+    /// The message header could not be serialized
+    MessageSerializationError(MessageError),
+    /// The node id of the requesting node could not be determined
+    NodeIdError(NodeIdError),
+    /// The neighbours of this node could not be retrieved from the peer manager
+    PeerManagerError(PeerManagerError),
+}
+
+/// The StoreAndForwardService allows messages to be delivered to nodes that are offline. Encrypted messages that are
+/// addressed to a NodeId are held by the nodes closest to that NodeId, and the destination node requests them from
+/// those nodes when it comes online. Held messages are forwarded unchanged, so only the destination can decrypt them
+/// and the signature of the original source can still be verified.
+pub struct StoreAndForwardService<PubKey, SecKey, DS = HashmapStore>
+where PubKey: PublicKey
+{
+    node_identity: Arc<NodeIdentity<PubKey, SecKey>>,
+    message_store: MessageStore,
+    outbound_message_service: OutboundMessageService<PubKey, SecKey, DS>,
+}
+
+impl<PubKey, SecKey, DS> StoreAndForwardService<PubKey, SecKey, DS>
+where
+    PubKey: PublicKey<K = SecKey> + Hashable + DiffieHellmanSharedSecret<K = SecKey, PK = PubKey>,
+    SecKey: SecretKey + Mul<PubKey, Output = PubKey> + Mul<Output = SecKey> + Serialize,
+    DS: DataStore,
+{
+    /// Construct a new StoreAndForwardService that forwards held messages using the OutboundMessageService
+    pub fn new(
+        node_identity: Arc<NodeIdentity<PubKey, SecKey>>,
+        config: MessageStoreConfig,
+        outbound_message_service: OutboundMessageService<PubKey, SecKey, DS>,
+    ) -> StoreAndForwardService<PubKey, SecKey, DS>
+    {
+        StoreAndForwardService {
+            node_identity,
+            message_store: MessageStore::new(config),
+            outbound_message_service,
+        }
+    }
+
+    /// The messages held by this node
+    pub fn message_store(&self) -> &MessageStore {
+        &self.message_store
+    }
+
+    /// Hold the received message if it is an encrypted message addressed to another node, and this node is one of the
+    /// closest nodes, of itself and its neighbours, to the destination. Returns true if the message was stored.
+    pub fn store_message(
+        &mut self,
+        message_context: &MessageContext<PubKey>,
+        neighbours: &[NodeId],
+    ) -> Result<bool, StoreAndForwardError>
+    {
+        let header = &message_context.message_envelope_header;
+        let destination = match &header.dest {
+            NodeDestination::NodeId(node_id) if *node_id != self.node_identity.node_id => node_id,
+            _ => return Ok(false),
+        };
+        if !header.flags.contains(IdentityFlags::ENCRYPTED) ||
+            !self
+                .message_store
+                .is_responsible_for(&self.node_identity.node_id, destination, neighbours)
+        {
+            return Ok(false);
+        }
+        let message_envelope = MessageEnvelope::new(
+            message_context.version.clone(),
+            header.to_frame()?,
+            message_context.message_envelope_body.clone(),
+        );
+        self.message_store.store(destination.clone(), message_envelope)?;
+        Ok(true)
+    }
+
+    /// Hold a message that is being forwarded through this node, using the known peers closest to the destination as
+    /// the neighbours of this node. Returns true if the message was stored.
+    pub fn store_forwarded_message(
+        &mut self,
+        message_context: &MessageContext<PubKey>,
+    ) -> Result<bool, StoreAndForwardError>
+    {
+        let neighbours = match &message_context.message_envelope_header.dest {
+            NodeDestination::NodeId(node_id) => self
+                .outbound_message_service
+                .peer_manager()
+                .closest_peers(node_id, self.message_store.config().closest_node_count)?
+                .into_iter()
+                .map(|peer| peer.node_id)
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.store_message(message_context, &neighbours)
+    }
+
+    /// Handle a request for held messages by forwarding the messages held for the requesting node. Returns the number
+    /// of messages that were forwarded.
+    pub fn handle_stored_messages_request(&mut self, requester: &PubKey) -> Result<usize, StoreAndForwardError> {
+        let requester = NodeId::from_key(requester)?;
+        self.forward_stored_messages(&requester)
+    }
+
+    /// Forward the messages held for the destination node. Messages that could not be forwarded are held again.
+    pub fn forward_stored_messages(&mut self, destination: &NodeId) -> Result<usize, StoreAndForwardError> {
+        let mut message_envelopes = self.message_store.fetch(destination).into_iter();
+        let mut forward_count = 0;
+        while let Some(message_envelope) = message_envelopes.next() {
+            if let Err(e) = self
+                .outbound_message_service
+                .forward(destination.clone(), message_envelope.clone())
+            {
+                for message_envelope in std::iter::once(message_envelope).chain(message_envelopes) {
+                    self.message_store.store(destination.clone(), message_envelope)?;
+                }
+                return Err(StoreAndForwardError::OutboundError(e));
+            }
+            forward_count += 1;
+        }
+        Ok(forward_count)
+    }
+
+    /// Discard held messages whose time to live has expired
+    pub fn remove_expired(&mut self) {
+        self.message_store.remove_expired();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        connection::{
+            message::MessageEnvelopeHeader,
+            zmq::{Context, InprocAddress, ZmqEndpoint},
+            SocketType,
+        },
+        outbound_message_service::outbound_message::OutboundMessage,
+        peer_manager::manager::PeerManager,
+    };
+    use std::convert::TryFrom;
+    use tari_crypto::ristretto::{RistrettoPublicKey, RistrettoSecretKey};
+    use tari_storage::hashmap_store::HashmapStore;
+
+    fn create_node_identity(rng: &mut rand::OsRng) -> Arc<NodeIdentity<RistrettoPublicKey, RistrettoSecretKey>> {
+        let (sk, pk) = RistrettoPublicKey::random_keypair(rng);
+        Arc::new(NodeIdentity::new(NodeId::from_key(&pk).unwrap(), pk, Some(sk)))
+    }
+
+    fn create_message_context(
+        source: &RistrettoPublicKey,
+        dest: NodeDestination<RistrettoPublicKey>,
+        flags: IdentityFlags,
+    ) -> MessageContext<RistrettoPublicKey>
+    {
+        let header = MessageEnvelopeHeader::new(0, source.clone(), dest, vec![0, 1, 2], flags);
+        MessageContext::new(vec![0], vec![1], vec![0], None, header, vec![3, 4, 5])
+    }
+
+    #[test]
+    fn test_store_and_forward() {
+        let context = Context::new();
+        let mut rng = rand::OsRng::new().unwrap();
+        let outbound_address = InprocAddress::random();
+        let omp_socket = context.socket(SocketType::Reply).unwrap();
+        omp_socket.bind(&outbound_address.to_zmq_endpoint()).unwrap();
+
+        let node_identity = create_node_identity(&mut rng);
+        let source_identity = create_node_identity(&mut rng);
+        let dest_identity = create_node_identity(&mut rng);
+        let peer_manager = Arc::new(PeerManager::new(node_identity.node_id.clone(), HashmapStore::new()).unwrap());
+        let outbound_message_service =
+            OutboundMessageService::new(context.clone(), outbound_address, node_identity.clone(), peer_manager);
+        let mut service = StoreAndForwardService::new(
+            node_identity.clone(),
+            MessageStoreConfig::default(),
+            outbound_message_service,
+        );
+
+        // Only encrypted messages for other nodes are held
+        let dest = NodeDestination::NodeId(dest_identity.node_id.clone());
+        let message_context =
+            create_message_context(&source_identity.public_key, dest.clone(), IdentityFlags::ENCRYPTED);
+        assert!(service.store_message(&message_context, &[]).unwrap());
+        let unencrypted_message_context =
+            create_message_context(&source_identity.public_key, dest.clone(), IdentityFlags::empty());
+        assert!(!service.store_message(&unencrypted_message_context, &[]).unwrap());
+        let own_message_context = create_message_context(
+            &source_identity.public_key,
+            NodeDestination::NodeId(node_identity.node_id.clone()),
+            IdentityFlags::ENCRYPTED,
+        );
+        assert!(!service.store_message(&own_message_context, &[]).unwrap());
+        assert_eq!(service.message_store().len(), 1);
+
+        // The destination requests its messages when it comes online
+        assert_eq!(
+            service
+                .handle_stored_messages_request(&dest_identity.public_key)
+                .unwrap(),
+            1
+        );
+        assert!(service.message_store().is_empty());
+
+        let msg_bytes = omp_socket.recv_multipart(0).unwrap();
+        let outbound_message = OutboundMessage::<MessageEnvelope>::try_from(msg_bytes).unwrap();
+        assert_eq!(outbound_message.destination_node_id, dest_identity.node_id);
+        let message_envelope = outbound_message.message_envelope;
+        assert_eq!(message_envelope.body(), &message_context.message_envelope_body);
+        let header = MessageEnvelopeHeader::<RistrettoPublicKey>::try_from(message_envelope.header().clone()).unwrap();
+        assert_eq!(header, message_context.message_envelope_header);
+        assert!(omp_socket.send("OK".as_bytes(), 0).is_ok());
+    }
+}
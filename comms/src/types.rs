@@ -28,5 +28,8 @@ pub const MESSAGE_PROTOCOL_VERSION: u8 = 0;
 /// The wire protocol version for the MessageEnvelope wire format
 pub const WIRE_PROTOCOL_VERSION: u8 = 0;
 
+/// The number of times a message may be forwarded towards its destination before it is discarded
+pub const DEFAULT_MESSAGE_TTL: u8 = 10;
+
 /// Specify the digest type for the signature challenges
 pub type Challenge = Blake256;
@@ -0,0 +1,187 @@
+//  Copyright 2019 The Tari Project
+//
+//  Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+//  following conditions are met:
+//
+//  1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+//  disclaimer.
+//
+//  2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+//  following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+//  3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+//  products derived from this software without specific prior written permission.
+//
+//  THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+//  INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+//  DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+//  SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+//  SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+//  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+//  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::support::utils::find_available_tcp_net_address;
+use rand::OsRng;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tari_comms::{
+    connection::{
+        message::{IdentityFlags, MessageEnvelopeHeader, NodeDestination},
+        net_address::net_addresses::NetAddresses,
+        Connection,
+        ConnectionError,
+        Context,
+        Direction,
+        InprocAddress,
+        Linger,
+        NetAddress,
+        PeerConnection,
+        PeerConnectionContextBuilder,
+        PeerConnectionError,
+    },
+    inbound_message_service::{
+        comms_msg_handlers::construct_comms_msg_dispatcher,
+        message_context::MessageContext,
+        message_forwarder::{ForwardingConfig, MessageForwarder},
+    },
+    outbound_message_service::outbound_message_service::OutboundMessageService,
+    peer_manager::{
+        ban_list::{BanList, BanListConfig},
+        manager::PeerManager,
+        node_id::NodeId,
+        node_identity::NodeIdentity,
+        peer::{Peer, PeerFlags},
+    },
+    store_and_forward::{message_store::MessageStoreConfig, store_and_forward_service::StoreAndForwardService},
+};
+use tari_crypto::{keys::PublicKey, ristretto::RistrettoPublicKey};
+use tari_storage::hashmap_store::HashmapStore;
+
+type TestBanList = BanList<RistrettoPublicKey, HashmapStore>;
+
+// Start an inbound peer connection on the address that is filtered by the ban list, and connect to it from the
+// loopback host
+fn start_filtered_connection(
+    ctx: &Context,
+    ban_list: &Arc<TestBanList>,
+    consumer_address: &InprocAddress,
+) -> (PeerConnection, Connection)
+{
+    let addr = find_available_tcp_net_address("127.0.0.1").unwrap();
+    let context = PeerConnectionContextBuilder::new()
+        .set_id("123")
+        .set_direction(Direction::Inbound)
+        .set_context(ctx)
+        .set_consumer_address(consumer_address.clone())
+        .set_address(addr.clone())
+        .set_connection_filter(ban_list.clone())
+        .build()
+        .unwrap();
+
+    let conn = PeerConnection::new();
+    conn.start(context).unwrap();
+    conn.wait_connected_or_failure(Duration::from_millis(2000)).unwrap();
+
+    let sender = Connection::new(ctx, Direction::Outbound)
+        .set_linger(Linger::Never)
+        .establish(&addr)
+        .unwrap();
+    (conn, sender)
+}
+
+#[test]
+fn misbehaving_peer_is_banned_and_refused() {
+    let mut rng = OsRng::new().unwrap();
+    let ctx = Context::new();
+
+    let (sk, pk) = RistrettoPublicKey::random_keypair(&mut rng);
+    let node_identity = Arc::new(NodeIdentity::new(NodeId::from_key(&pk).unwrap(), pk, Some(sk)));
+
+    // The misbehaving peer is known by an address on the loopback host that its connections come from
+    let (_, peer_pk) = RistrettoPublicKey::random_keypair(&mut rng);
+    let peer_node_id = NodeId::from_key(&peer_pk).unwrap();
+    let peer_address: NetAddress = "127.0.0.1:9000".parse().unwrap();
+    let peer_manager = Arc::new(PeerManager::new(node_identity.node_id.clone(), HashmapStore::new()).unwrap());
+    peer_manager
+        .add_peer(Peer::new(
+            peer_pk.clone(),
+            peer_node_id.clone(),
+            NetAddresses::from(peer_address),
+            PeerFlags::default(),
+        ))
+        .unwrap();
+    let ban_list = Arc::new(BanList::new(peer_manager.clone(), HashmapStore::new(), BanListConfig::default()).unwrap());
+
+    let message_forwarder = Arc::new(MessageForwarder::new(
+        node_identity.clone(),
+        OutboundMessageService::new(
+            ctx.clone(),
+            InprocAddress::random(),
+            node_identity.clone(),
+            peer_manager.clone(),
+        ),
+        ForwardingConfig::default(),
+    ));
+    let store_and_forward_service = Arc::new(Mutex::new(StoreAndForwardService::new(
+        node_identity.clone(),
+        MessageStoreConfig::default(),
+        OutboundMessageService::new(
+            ctx.clone(),
+            InprocAddress::random(),
+            node_identity.clone(),
+            peer_manager.clone(),
+        ),
+    )));
+    let message_dispatcher = construct_comms_msg_dispatcher(
+        node_identity.clone(),
+        ban_list.clone(),
+        message_forwarder,
+        store_and_forward_service,
+        |_| Ok(()),
+    );
+
+    // Connections from the host of the peer are accepted while it is not banned
+    let consumer_address = InprocAddress::random();
+    let consumer = Connection::new(&ctx, Direction::Inbound)
+        .establish(&consumer_address)
+        .unwrap();
+    let (conn, sender) = start_filtered_connection(&ctx, &ban_list, &consumer_address);
+    sender.send(&[&[1u8]]).unwrap();
+    consumer.receive(2000).unwrap();
+    assert!(!conn.is_failed());
+
+    // Messages with an invalid signature that arrive on the connection of the peer are discarded and counted against
+    // it, until its misbehaviour score reaches the ban threshold
+    for _ in 0..4 {
+        assert!(!ban_list.is_banned(&peer_node_id).unwrap());
+        let header = MessageEnvelopeHeader::new(
+            0,
+            peer_pk.clone(),
+            NodeDestination::Unknown,
+            vec![0],
+            IdentityFlags::empty(),
+        );
+        let message_context =
+            MessageContext::new(peer_node_id.as_bytes().to_vec(), vec![1], vec![0], None, header, vec![
+                1, 2, 3, 4,
+            ]);
+        message_dispatcher.dispatch(message_context).unwrap();
+    }
+    assert!(ban_list.is_banned(&peer_node_id).unwrap());
+
+    // The next inbound connection from the host of the banned peer is refused before its first message is passed on
+    let (conn, sender) = start_filtered_connection(&ctx, &ban_list, &consumer_address);
+    sender.send(&[&[1u8]]).unwrap();
+    assert!(consumer.receive(500).unwrap_err().is_timeout());
+
+    assert!(conn.is_failed());
+    match conn.failure().unwrap() {
+        ConnectionError::PeerError(err) => match err {
+            PeerConnectionError::ConnectionRefused => {},
+            _ => panic!("Unexpected connection error '{}'", err),
+        },
+        err => panic!("Unexpected connection error '{}'", err),
+    }
+}
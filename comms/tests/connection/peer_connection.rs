@@ -21,11 +21,12 @@
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use crate::support::utils::find_available_tcp_net_address;
-use std::time::Duration;
+use std::{net::IpAddr, sync::Arc, time::Duration};
 use tari_comms::connection::{
     curve_keypair,
     Connection,
     ConnectionError,
+    ConnectionFilter,
     Context,
     CurveEncryption,
     Direction,
@@ -216,6 +217,82 @@ fn connection_wait_connect_failed() {
     }
 }
 
+struct DenyConnectionId(&'static str);
+
+impl ConnectionFilter for DenyConnectionId {
+    fn allow_connection(&self, connection_id: &[u8], _remote_address: Option<&IpAddr>) -> bool {
+        connection_id != self.0.as_bytes()
+    }
+}
+
+#[test]
+fn connection_refused_by_filter() {
+    let ctx = Context::new();
+
+    let consumer_addr = InprocAddress::random();
+    let consumer = Connection::new(&ctx, Direction::Inbound)
+        .establish(&consumer_addr)
+        .unwrap();
+
+    let addr = find_available_tcp_net_address("127.0.0.1").unwrap();
+    let context = PeerConnectionContextBuilder::new()
+        .set_id("123")
+        .set_direction(Direction::Inbound)
+        .set_context(&ctx)
+        .set_consumer_address(consumer_addr.clone())
+        .set_address(addr.clone())
+        .set_connection_filter(Arc::new(DenyConnectionId("123")))
+        .build()
+        .unwrap();
+
+    let conn = PeerConnection::new();
+    conn.start(context).unwrap();
+    conn.wait_connected_or_failure(Duration::from_millis(2000)).unwrap();
+
+    // The connection is refused when the first message of the peer arrives, before it is passed to the consumer
+    let sender = Connection::new(&ctx, Direction::Outbound)
+        .set_linger(Linger::Never)
+        .establish(&addr)
+        .unwrap();
+    sender.send(&[&[1u8]]).unwrap();
+    assert!(consumer.receive(500).unwrap_err().is_timeout());
+
+    assert!(conn.is_failed());
+    match conn.failure().unwrap() {
+        ConnectionError::PeerError(err) => match err {
+            PeerConnectionError::ConnectionRefused => {},
+            _ => panic!("Unexpected connection error '{}'", err),
+        },
+        err => panic!("Unexpected connection error '{}'", err),
+    }
+
+    // Connections that are allowed by the filter are accepted
+    let addr = find_available_tcp_net_address("127.0.0.1").unwrap();
+    let context = PeerConnectionContextBuilder::new()
+        .set_id("456")
+        .set_direction(Direction::Inbound)
+        .set_context(&ctx)
+        .set_consumer_address(consumer_addr.clone())
+        .set_address(addr.clone())
+        .set_connection_filter(Arc::new(DenyConnectionId("123")))
+        .build()
+        .unwrap();
+
+    let conn = PeerConnection::new();
+    conn.start(context).unwrap();
+    conn.wait_connected_or_failure(Duration::from_millis(1000)).unwrap();
+
+    let sender = Connection::new(&ctx, Direction::Outbound)
+        .set_linger(Linger::Never)
+        .establish(&addr)
+        .unwrap();
+    sender.send(&[&[1u8]]).unwrap();
+    let frames = consumer.receive(2000).unwrap();
+    assert_eq!("456".as_bytes().to_vec(), frames[1]);
+    assert_eq!(vec![1u8], frames[2]);
+    assert!(!conn.is_failed());
+}
+
 #[test]
 fn connection_pause_resume() {
     let addr = find_available_tcp_net_address("127.0.0.1").unwrap();
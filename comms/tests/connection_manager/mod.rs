@@ -0,0 +1,84 @@
+pub mod connection;
+pub mod inbound_message_service;
+pub mod outbound_message_service;
+pub mod peer_manager;
+pub mod store_and_forward;
+pub mod types;
+
+use crate::support::utils::find_available_tcp_net_address;
+use std::sync::Arc;
+use tari_comms::{
+    connection::{
+        message::{FrameSet, MessageEnvelope},
+        net_address::net_addresses::NetAddresses,
+        types::SocketType,
+        zmq::{Context, InprocAddress, ZmqEndpoint},
+    },
+    connection_manager::connection_manager::ConnectionManager,
+    outbound_message_service::{
+        outbound_message_pool::OutboundMessagePool,
+        outbound_message_service::OutboundMessageService,
+    },
+    peer_manager::{
+        manager::PeerManager,
+        node_id::NodeId,
+        node_identity::NodeIdentity,
+        peer::{Peer, PeerFlags},
+    },
+};
+use tari_crypto::{keys::PublicKey, ristretto::RistrettoPublicKey};
+use tari_storage::hashmap_store::HashmapStore;
+
+#[test]
+fn send_to_listening_peer() {
+    let context = Context::new();
+    let mut rng = rand::OsRng::new().unwrap();
+    let (sender_sk, sender_pk) = RistrettoPublicKey::random_keypair(&mut rng);
+    let sender_identity = Arc::new(NodeIdentity::new(
+        NodeId::from_key(&sender_pk).unwrap(),
+        sender_pk,
+        Some(sender_sk),
+    ));
+    let (_, receiver_pk) = RistrettoPublicKey::random_keypair(&mut rng);
+    let receiver_node_id = NodeId::from_key(&receiver_pk).unwrap();
+    let listen_address = find_available_tcp_net_address("127.0.0.1").unwrap();
+
+    // The receiver listens for peers and hands their messages to its inbound message service
+    let inbound_address = InprocAddress::random();
+    let inbound_socket = context.socket(SocketType::Reply).unwrap();
+    inbound_socket.bind(&inbound_address.to_zmq_endpoint()).unwrap();
+    let receiver_peer_manager = Arc::new(PeerManager::new(receiver_node_id.clone(), HashmapStore::new()).unwrap());
+    ConnectionManager::new(context.clone(), receiver_peer_manager)
+        .listen(&listen_address, inbound_address)
+        .unwrap();
+
+    // The sender writes a message for the receiver to its outbound message pool
+    let peer_manager = Arc::new(PeerManager::new(sender_identity.node_id.clone(), HashmapStore::new()).unwrap());
+    peer_manager
+        .add_peer(Peer::new(
+            receiver_pk,
+            receiver_node_id.clone(),
+            NetAddresses::from(listen_address),
+            PeerFlags::default(),
+        ))
+        .unwrap();
+    let connection_manager = Arc::new(ConnectionManager::new(context.clone(), peer_manager.clone()));
+    let pool_address = InprocAddress::random();
+    OutboundMessagePool::new(context.clone(), pool_address.clone(), connection_manager)
+        .start()
+        .unwrap();
+    let outbound_message_service =
+        OutboundMessageService::new(context.clone(), pool_address, sender_identity, peer_manager);
+    let message_envelope = MessageEnvelope::new(vec![0], vec![1, 2], vec![3, 4, 5]);
+    outbound_message_service
+        .forward(receiver_node_id, message_envelope.clone())
+        .unwrap();
+
+    // The message arrives with the identity of the connection it was received on
+    inbound_socket.set_rcvtimeo(5000).unwrap();
+    let frames: FrameSet = inbound_socket.recv_multipart(0).unwrap();
+    assert_eq!(frames.len(), 5);
+    assert_eq!(frames[0], frames[1]);
+    assert_eq!(frames[2..].to_vec(), message_envelope.to_frame_set().unwrap());
+    inbound_socket.send("OK".as_bytes(), 0).unwrap();
+}
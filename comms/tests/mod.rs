@@ -23,5 +23,7 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod ban_list;
 mod connection;
+mod connection_manager;
 mod support;
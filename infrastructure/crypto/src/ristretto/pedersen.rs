@@ -31,6 +31,7 @@ use curve25519_dalek::{
 
 use crate::{commitment::HomomorphicCommitmentFactory, ristretto::RistrettoSecretKey};
 use curve25519_dalek::scalar::Scalar;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
     borrow::Borrow,
     cmp::Ordering,
@@ -73,6 +74,25 @@ impl PedersenOnRistretto255 {
     }
 }
 
+/// Only the commitment point is serialized. Commitments are always deserialized onto the default Pedersen base.
+impl Serialize for PedersenOnRistretto255 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        self.commitment.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PedersenOnRistretto255 {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: Deserializer<'de> {
+        let commitment = RistrettoPublicKey::deserialize(deserializer)?;
+        Ok(PedersenOnRistretto255 {
+            base: &DEFAULT_RISTRETTO_PEDERSON_BASE,
+            commitment,
+        })
+    }
+}
+
 impl HomomorphicCommitmentFactory for PedersenBaseOnRistretto255 {
     type C = PedersenOnRistretto255;
     type K = RistrettoSecretKey;
@@ -293,4 +313,15 @@ mod test {
         assert_eq!(c_sum, commitments.iter().sum());
     }
 
+    #[test]
+    fn serialize_deserialize() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let k = RistrettoSecretKey::random(&mut rng);
+        let v = RistrettoSecretKey::from(100);
+        let c = PedersenBaseOnRistretto255::create(&k, &v);
+        let ser_c = serde_json::to_string(&c).unwrap();
+        let c2: PedersenOnRistretto255 = serde_json::from_str(&ser_c).unwrap();
+        assert_eq!(c, c2);
+        assert!(c2.open(&k, &v));
+    }
 }
@@ -0,0 +1,131 @@
+//! An in-memory implementation of [DataStore](../keyvalue_store/trait.DataStore.html), backed by `HashMap`s. Nothing
+//! is persisted, which makes it useful for tests and for ephemeral instances of components that require a store.
+
+use crate::keyvalue_store::{DataStore, DatastoreError, WriteBatch, WriteOperation};
+use std::collections::HashMap;
+
+/// The name of the logical database that a new `HashmapStore` is connected to
+pub const DEFAULT_DATABASE: &str = "default";
+
+/// A `DataStore` that keeps all values in memory. Logical databases are created on demand when `connect` is called.
+#[derive(Debug)]
+pub struct HashmapStore {
+    databases: HashMap<String, HashMap<Vec<u8>, Vec<u8>>>,
+    curr_db: String,
+}
+
+impl HashmapStore {
+    pub fn new() -> HashmapStore {
+        let mut databases = HashMap::new();
+        databases.insert(DEFAULT_DATABASE.to_string(), HashMap::new());
+        HashmapStore {
+            databases,
+            curr_db: DEFAULT_DATABASE.to_string(),
+        }
+    }
+
+    fn db(&self) -> Result<&HashMap<Vec<u8>, Vec<u8>>, DatastoreError> {
+        self.databases.get(&self.curr_db).ok_or(DatastoreError::DatabaseNotOpen)
+    }
+
+    fn db_mut(&mut self) -> Result<&mut HashMap<Vec<u8>, Vec<u8>>, DatastoreError> {
+        self.databases
+            .get_mut(&self.curr_db)
+            .ok_or(DatastoreError::DatabaseNotOpen)
+    }
+}
+
+impl Default for HashmapStore {
+    fn default() -> Self {
+        HashmapStore::new()
+    }
+}
+
+impl DataStore for HashmapStore {
+    fn connect(&mut self, name: &str) -> Result<(), DatastoreError> {
+        self.databases.entry(name.to_string()).or_insert_with(HashMap::new);
+        self.curr_db = name.to_string();
+        Ok(())
+    }
+
+    fn get_raw(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatastoreError> {
+        Ok(self.db()?.get(key).cloned())
+    }
+
+    fn exists(&self, key: &[u8]) -> Result<bool, DatastoreError> {
+        Ok(self.db()?.contains_key(key))
+    }
+
+    fn put_raw(&mut self, key: &[u8], value: Vec<u8>) -> Result<(), DatastoreError> {
+        self.db_mut()?.insert(key.to_vec(), value);
+        Ok(())
+    }
+
+    fn delete_raw(&mut self, key: &[u8]) -> Result<(), DatastoreError> {
+        self.db_mut()?.remove(key);
+        Ok(())
+    }
+
+    fn write(&mut self, batch: WriteBatch) -> Result<(), DatastoreError> {
+        let db = self.db_mut()?;
+        for operation in batch.operations() {
+            match operation {
+                WriteOperation::Put(key, value) => db.insert(key.clone(), value.clone()),
+                WriteOperation::Delete(key) => db.remove(key),
+            };
+        }
+        Ok(())
+    }
+
+    fn close(self) -> Result<(), DatastoreError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        hashmap_store::HashmapStore,
+        keyvalue_store::{DataStore, WriteBatch},
+    };
+
+    #[test]
+    fn crud() {
+        let mut store = HashmapStore::new();
+        assert_eq!(store.get_raw(b"a").unwrap(), None);
+        store.put_raw(b"a", b"apple".to_vec()).unwrap();
+        assert!(store.exists(b"a").unwrap());
+        assert_eq!(store.get_raw(b"a").unwrap(), Some(b"apple".to_vec()));
+        store.put::<u32>("b", &42).unwrap();
+        assert_eq!(store.get::<u32>("b").unwrap(), Some(42));
+        store.delete_raw(b"a").unwrap();
+        assert!(!store.exists(b"a").unwrap());
+    }
+
+    #[test]
+    fn write_batch() {
+        let mut store = HashmapStore::new();
+        store.put_raw(b"a", b"apple".to_vec()).unwrap();
+        let mut batch = WriteBatch::new();
+        batch
+            .put_raw(b"b", b"banana".to_vec())
+            .delete_raw(b"a")
+            .delete_raw(b"c");
+        batch.put::<u32>("d", &42).unwrap();
+        store.write(batch).unwrap();
+        assert!(!store.exists(b"a").unwrap());
+        assert_eq!(store.get_raw(b"b").unwrap(), Some(b"banana".to_vec()));
+        assert_eq!(store.get::<u32>("d").unwrap(), Some(42));
+    }
+
+    #[test]
+    fn logical_databases() {
+        let mut store = HashmapStore::new();
+        store.put_raw(b"a", b"apple".to_vec()).unwrap();
+        store.connect("fruit").unwrap();
+        assert!(!store.exists(b"a").unwrap());
+        store.put_raw(b"a", b"avocado".to_vec()).unwrap();
+        store.connect("default").unwrap();
+        assert_eq!(store.get_raw(b"a").unwrap(), Some(b"apple".to_vec()));
+    }
+}
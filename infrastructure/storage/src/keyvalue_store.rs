@@ -6,7 +6,7 @@ use derive_error::Error;
 use serde::{de::DeserializeOwned, Serialize};
 use std::error::Error;
 
-#[derive(Debug, Error)]
+#[derive(Debug, Error, PartialEq)]
 pub enum DatastoreError {
     /// An error occurred with the underlying data store implementation
     #[error(embedded_msg, no_from, non_std)]
@@ -77,11 +77,77 @@ pub trait DataStore {
         self.put_raw(key, val)
     }
 
+    /// Apply all the puts and deletes in `batch` in a single transaction, so that either all of them are stored or
+    /// none of them are. Deleting a key that does not exist is not an error.
+    fn write(&mut self, batch: WriteBatch) -> Result<(), DatastoreError>;
+
     /// Close and release any underlying resources associated with the datastore. The instance is no longer
     /// accessible from this point
     fn close(self) -> Result<(), DatastoreError>;
 }
 
+/// A single change in a `WriteBatch`
+#[derive(Clone, Debug, PartialEq)]
+pub enum WriteOperation {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+impl WriteOperation {
+    /// The key that the operation changes
+    pub fn key(&self) -> &[u8] {
+        match self {
+            WriteOperation::Put(key, _) => key,
+            WriteOperation::Delete(key) => key,
+        }
+    }
+}
+
+/// A set of puts and deletes that are applied to a `DataStore` atomically with `DataStore::write`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WriteBatch {
+    operations: Vec<WriteOperation>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        Default::default()
+    }
+
+    /// Save a value at the given key when the batch is written. Existing values are overwritten
+    pub fn put_raw(&mut self, key: &[u8], value: Vec<u8>) -> &mut Self {
+        self.operations.push(WriteOperation::Put(key.to_vec(), value));
+        self
+    }
+
+    /// Serialize a value using Bincode and save it at the given key when the batch is written
+    pub fn put<T: Serialize>(&mut self, key: &str, value: &T) -> Result<&mut Self, DatastoreError> {
+        let val = serialize(value)?;
+        Ok(self.put_raw(key.as_bytes(), val))
+    }
+
+    /// Delete the given key when the batch is written
+    pub fn delete_raw(&mut self, key: &[u8]) -> &mut Self {
+        self.operations.push(WriteOperation::Delete(key.to_vec()));
+        self
+    }
+
+    /// Add all the changes in `other` after the changes in this batch
+    pub fn append(&mut self, mut other: WriteBatch) -> &mut Self {
+        self.operations.append(&mut other.operations);
+        self
+    }
+
+    /// The changes in the batch, in the order they were added
+    pub fn operations(&self) -> &[WriteOperation] {
+        &self.operations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
 /// BatchWrite is implemented on Datastores if it supports batch writes, or transactions, to efficiently write
 /// multiple puts to the Datastore.
 pub trait BatchWrite {
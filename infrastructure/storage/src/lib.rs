@@ -1,2 +1,3 @@
+pub mod hashmap_store;
 pub mod keyvalue_store;
 pub mod lmdb;
@@ -1,6 +1,6 @@
 //! An implementation of [KVStore](trait.KVStore.html) using [LMDB](http://www.lmdb.tech)
 
-use crate::keyvalue_store::{BatchWrite, DataStore, DatastoreError};
+use crate::keyvalue_store::{BatchWrite, DataStore, DatastoreError, WriteBatch, WriteOperation};
 use lmdb_zero as lmdb;
 use lmdb_zero::error::LmdbResultExt;
 use std::{collections::HashMap, sync::Arc};
@@ -157,6 +157,25 @@ impl DataStore for LMDBStore {
         tx.commit().map_err(|e| e.into())
     }
 
+    fn write(&mut self, batch: WriteBatch) -> Result<(), DatastoreError> {
+        let tx = lmdb::WriteTransaction::new(self.env.clone())?;
+        {
+            let mut accessor = tx.access();
+            for operation in batch.operations() {
+                match operation {
+                    WriteOperation::Put(key, value) => {
+                        accessor.put(&self.curr_db, &key[..], &value[..], lmdb::put::Flags::empty())?;
+                    },
+                    WriteOperation::Delete(key) => {
+                        accessor.del_key(&self.curr_db, &key[..]).to_opt()?;
+                    },
+                }
+            }
+        }
+        // The transaction is aborted when it is dropped, so nothing is stored if any of the operations failed
+        tx.commit().map_err(|e| e.into())
+    }
+
     fn close(self) -> Result<(), DatastoreError> {
         self.delete_db_from_scope()
             .map_err(|e| DatastoreError::InternalError(e.to_string()))
@@ -207,7 +226,7 @@ impl From<lmdb::error::Error> for DatastoreError {
 mod test {
     use super::{LMDBBuilder, LMDBStore};
     use crate::{
-        keyvalue_store::{BatchWrite, DataStore, DatastoreError},
+        keyvalue_store::{BatchWrite, DataStore, DatastoreError, WriteBatch},
         lmdb::LMDBBatch,
     };
     use bincode::{deserialize, serialize};
@@ -282,6 +301,35 @@ mod test {
         }
     }
 
+    #[test]
+    fn write_batch() {
+        let test_dir = "./tests/test_write_batch/";
+        if std::fs::metadata(test_dir).is_ok() {
+            assert!(fs::remove_dir_all(test_dir).is_ok());
+        }
+        assert!(fs::create_dir(test_dir).is_ok());
+        let mut store = LMDBBuilder::new().set_mapsize(5).set_path(test_dir).build().unwrap();
+        store.put_raw(b"a", b"apple".to_vec()).unwrap();
+        let mut batch = WriteBatch::new();
+        batch
+            .put_raw(b"b", b"banana".to_vec())
+            .put_raw(b"c", b"carrot".to_vec())
+            .delete_raw(b"a")
+            // Deleting a key that does not exist does not abort the batch
+            .delete_raw(b"d");
+        store.write(batch).unwrap();
+        assert!(!store.exists(b"a").unwrap());
+        assert_eq!(&store.get_raw(b"b").unwrap().unwrap(), b"banana");
+        assert_eq!(&store.get_raw(b"c").unwrap().unwrap(), b"carrot");
+        // Clean up
+        assert!(store.close().is_ok());
+        let _no_val = fs::remove_dir_all(test_dir);
+        if std::fs::metadata(test_dir).is_ok() {
+            println!("Database file handles not released, still open in {:?}!", test_dir);
+            assert!(fs::remove_dir_all(test_dir).is_ok());
+        }
+    }
+
     #[test]
     fn writes_to_default_db() {
         let test_dir = "./tests/test_default";